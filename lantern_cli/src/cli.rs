@@ -1,6 +1,10 @@
 use clap::{Parser, Subcommand};
 use lantern_cli::daemon::cli::DaemonArgs;
-use lantern_cli::embeddings::cli::{EmbeddingArgs, MeasureModelSpeedArgs, ShowModelsArgs};
+#[cfg(feature = "embedding-server")]
+use lantern_cli::embedding_server::cli::EmbeddingServerArgs;
+use lantern_cli::embeddings::cli::{
+    EmbeddingArgs, MeasureModelSpeedArgs, ShowModelsArgs, ShowRuntimesArgs,
+};
 use lantern_cli::external_index::cli::IndexServerArgs;
 use lantern_cli::http_server::cli::HttpServerArgs;
 use lantern_cli::index_autotune::cli::IndexAutotuneArgs;
@@ -10,8 +14,8 @@ use lantern_cli::pq::cli::PQArgs;
 pub enum Commands {
     /// Create embeddings
     CreateEmbeddings(EmbeddingArgs),
-    /// Show embedding models
-    ShowRuntimes,
+    /// Show embedding runtimes
+    ShowRuntimes(ShowRuntimesArgs),
     /// Show embedding models
     ShowModels(ShowModelsArgs),
     /// Measure embedding geneartion speed
@@ -26,6 +30,9 @@ pub enum Commands {
     StartServer(HttpServerArgs),
     /// Start external index server
     StartIndexingServer(IndexServerArgs),
+    /// Start embedding server
+    #[cfg(feature = "embedding-server")]
+    StartEmbeddingServer(EmbeddingServerArgs),
 }
 
 #[derive(Parser, Debug)]
@@ -78,4 +78,16 @@ pub struct DaemonArgs {
     /// Is being run inside postgres
     #[arg(long, default_value_t = false)]
     pub inside_postgres: bool,
+
+    /// Reuse embeddings for the same input, model and dimensions across embedding jobs
+    #[arg(long, default_value_t = false)]
+    pub embedding_cache: bool,
+
+    /// Remove embedding cache entries which were not used for this many seconds
+    #[arg(long)]
+    pub embedding_cache_max_age: Option<u64>,
+
+    /// Maximum number of entries to keep in embedding cache (least recently used are removed)
+    #[arg(long)]
+    pub embedding_cache_max_entries: Option<u64>,
 }
@@ -4,8 +4,8 @@ use super::helpers::{
     remove_job_handle, schedule_job_retry, set_job_handle, startup_hook,
 };
 use super::types::{
-    ClientJobsMap, EmbeddingProcessorArgs, JobBatchingHashMap, JobEvent, JobEventHandlersMap,
-    JobInsertNotification, JobRunArgs, JobUpdateNotification,
+    ClientJobsMap, EmbeddingCacheArgs, EmbeddingProcessorArgs, JobBatchingHashMap, JobEvent,
+    JobEventHandlersMap, JobInsertNotification, JobRunArgs, JobUpdateNotification,
 };
 use crate::daemon::helpers::anyhow_wrap_connection;
 use crate::embeddings::cli::{EmbeddingArgs, EmbeddingJobType, Runtime};
@@ -42,6 +42,7 @@ pub const JOB_TABLE_DEFINITION: &'static str = r#"
 "batch_size" int NULL,
 "src_column" text NOT NULL,
 "dst_column" text NOT NULL,
+"dst_json_columns" jsonb,
 "embedding_model" text NOT NULL,
 "created_at" timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP,
 "updated_at" timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP,
@@ -50,7 +51,8 @@ pub const JOB_TABLE_DEFINITION: &'static str = r#"
 "init_finished_at" timestamp,
 "init_failed_at" timestamp,
 "init_failure_reason" text,
-"init_progress" int2 DEFAULT 0
+"init_progress" int2 DEFAULT 0,
+"batch_ids" jsonb
 "#;
 
 pub const USAGE_TABLE_DEFINITION: &'static str = r#"
@@ -58,6 +60,7 @@ pub const USAGE_TABLE_DEFINITION: &'static str = r#"
 "job_id" INT NOT NULL,
 "rows" INT NOT NULL,
 "tokens" INT NOT NULL,
+"cache_hits" INT NOT NULL DEFAULT 0,
 "failed" BOOL NOT NULL DEFAULT FALSE,
 "created_at" timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP
 "#;
@@ -65,14 +68,33 @@ pub const USAGE_TABLE_DEFINITION: &'static str = r#"
 pub const FAILURE_TABLE_DEFINITION: &'static str = r#"
 "id" SERIAL PRIMARY KEY,
 "job_id" INT NOT NULL,
-"row_id" INT NOT NULL,
+"row_id" TEXT NOT NULL,
 "value" TEXT,
+"truncated" BOOL NOT NULL DEFAULT FALSE,
 "created_at" timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP
 "#;
 
 const EMB_USAGE_TABLE_NAME: &'static str = "embedding_usage_info";
 const EMB_FAILURE_TABLE_NAME: &'static str = "embedding_failure_info";
 const EMB_LOCK_TABLE_NAME: &'static str = "_lantern_emb_job_locks";
+const EMB_CACHE_TABLE_NAME: &'static str = "embedding_cache";
+
+// Columns added after the initial table definition, so job tables
+// created by older versions will be updated on startup
+fn get_job_table_migration(schema: &str, table: &str) -> String {
+    format!(
+        r#"
+        ALTER TABLE {full_table_name} ADD COLUMN IF NOT EXISTS "dst_json_columns" jsonb;
+        ALTER TABLE {full_table_name} ADD COLUMN IF NOT EXISTS "batch_ids" jsonb;
+        ALTER TABLE IF EXISTS {usage_table_name} ADD COLUMN IF NOT EXISTS "cache_hits" INT NOT NULL DEFAULT 0;
+        ALTER TABLE IF EXISTS {failure_table_name} ADD COLUMN IF NOT EXISTS "truncated" BOOL NOT NULL DEFAULT FALSE;
+        ALTER TABLE IF EXISTS {failure_table_name} ALTER COLUMN "row_id" TYPE TEXT;
+        "#,
+        full_table_name = get_full_table_name(schema, table),
+        usage_table_name = get_full_table_name(schema, EMB_USAGE_TABLE_NAME),
+        failure_table_name = get_full_table_name(schema, EMB_FAILURE_TABLE_NAME)
+    )
+}
 
 #[derive(Debug, Clone)]
 pub struct EmbeddingJob {
@@ -88,6 +110,7 @@ pub struct EmbeddingJob {
     pub job_type: EmbeddingJobType,
     pub column_type: String,
     pub out_column: String,
+    pub out_json_columns: Option<String>,
     pub model: String,
     pub runtime_params: String,
     pub runtime: Runtime,
@@ -98,11 +121,20 @@ pub struct EmbeddingJob {
 impl EmbeddingJob {
     pub fn new(row: Row, data_path: &str, db_uri: &str) -> Result<EmbeddingJob, anyhow::Error> {
         let runtime = Runtime::try_from(row.get::<&str, Option<&str>>("runtime").unwrap_or("ort"))?;
+        let runtime_params = row
+            .get::<&str, Option<String>>("runtime_params")
+            .unwrap_or("{}".to_owned());
         let runtime_params = if runtime == Runtime::Ort {
-            format!(r#"{{ "data_path": "{data_path}" }}"#)
+            // data_path is always set by daemon, other params (e.g s3 credentials)
+            // are taken from the job
+            let mut params: serde_json::Value = serde_json::from_str(&runtime_params)?;
+            if !params.is_object() {
+                params = serde_json::json!({});
+            }
+            params["data_path"] = serde_json::json!(data_path);
+            params.to_string()
         } else {
-            row.get::<&str, Option<String>>("runtime_params")
-                .unwrap_or("{}".to_owned())
+            runtime_params
         };
 
         let batch_size = if let Some(batch_size) = row.get::<&str, Option<i32>>("batch_size") {
@@ -120,6 +152,7 @@ impl EmbeddingJob {
             table: row.get::<&str, String>("table"),
             column: row.get::<&str, String>("column"),
             out_column: row.get::<&str, String>("dst_column"),
+            out_json_columns: row.get::<&str, Option<String>>("dst_json_columns"),
             model: get_clean_model_name(row.get::<&str, &str>("model"), runtime),
             runtime,
             runtime_params,
@@ -170,6 +203,24 @@ impl EmbeddingJob {
             common_filter = get_common_embedding_ignore_filters(&self.column)
         ));
     }
+
+    // Batch API is used only for the initial backfill
+    // as new rows should be processed with low latency
+    pub fn get_runtime_params(&self) -> String {
+        if self.is_init {
+            return self.runtime_params.clone();
+        }
+
+        match serde_json::from_str::<serde_json::Value>(&self.runtime_params) {
+            Ok(serde_json::Value::Object(mut params)) => {
+                params.remove("batch_api");
+                params.remove("batch_poll_interval");
+                params.remove("batch_max_wait");
+                serde_json::Value::Object(params).to_string()
+            }
+            _ => self.runtime_params.clone(),
+        }
+    }
 }
 
 async fn lock_row(
@@ -531,7 +582,9 @@ async fn embedding_worker(
     embedding_processor_tx: Sender<EmbeddingProcessorArgs>,
     db_uri: String,
     schema: String,
+    jobs_table: String,
     jobs_map: Arc<JobEventHandlersMap>,
+    cache: Option<EmbeddingCacheArgs>,
     logger: Arc<Logger>,
 ) -> AnyhowVoidResult {
     let schema = Arc::new(schema);
@@ -552,12 +605,13 @@ async fn embedding_worker(
                 logger.level.clone(),
             );
             let job_clone = job.clone();
-            let mut failed_rows_table = None;
+            // Rows which failed to be processed (e.g image could not be loaded)
+            // will be inserted into failure table for all jobs
+            let failed_rows_table = Some(EMB_FAILURE_TABLE_NAME.to_owned());
             let mut check_column_type = false;
 
             match job_clone.job_type {
                 EmbeddingJobType::Completion => {
-                    failed_rows_table = Some(EMB_FAILURE_TABLE_NAME.to_owned());
                     check_column_type = true;
                 },
                 _ => {}
@@ -578,7 +632,7 @@ async fn embedding_worker(
                     out_column: job_clone.out_column.clone(),
                     batch_size: job_clone.batch_size,
                     runtime: job_clone.runtime.clone(),
-                    runtime_params: job_clone.runtime_params.clone(),
+                    runtime_params: job_clone.get_runtime_params(),
                     visual: false,
                     stream: true,
                     create_column: false,
@@ -586,11 +640,27 @@ async fn embedding_worker(
                     limit: None,
                     job_type: Some(job_clone.job_type.clone()),
                     column_type: Some(job_clone.column_type.clone()),
+                    out_json_columns: job_clone.out_json_columns.clone(),
                     create_cast_fn: false,
                     check_column_type,
                     job_id: job_clone.id,
                     internal_schema: schema.deref().clone(),
-                    failed_rows_table
+                    failed_rows_table,
+                    // Batch API is used only for init jobs
+                    batch_state_table: if job_clone.is_init {
+                        Some(jobs_table.clone())
+                    } else {
+                        None
+                    },
+                    cache: cache.is_some(),
+                    cache_table: EMB_CACHE_TABLE_NAME.to_owned(),
+                    cache_max_age: cache.as_ref().and_then(|c| c.max_age),
+                    cache_max_entries: cache.as_ref().and_then(|c| c.max_entries),
+                    input_type: None,
+                    input_file: None,
+                    input_format: None,
+                    output_file: None,
+                    output_format: None,
                 },
                 tx,
                 task_logger
@@ -604,14 +674,14 @@ async fn embedding_worker(
             }
 
             match result.unwrap() {
-                Ok((processed_rows, processed_tokens)) => {
-                    if processed_tokens > 0 {
+                Ok((processed_rows, processed_tokens, cache_hits)) => {
+                    if processed_tokens > 0 || cache_hits > 0 {
                         let res = client_ref
                             .execute(
                                 &format!(
-                                    "INSERT INTO {usage_table_name} (job_id, rows, tokens) VALUES ($1, $2, $3)",
+                                    "INSERT INTO {usage_table_name} (job_id, rows, tokens, cache_hits) VALUES ($1, $2, $3, $4)",
                                 ),
-                                &[&job.id, &(processed_rows as i32), &(processed_tokens as i32)],
+                                &[&job.id, &(processed_rows as i32), &(processed_tokens as i32), &(cache_hits as i32)],
                             )
                             .await;
 
@@ -750,7 +820,7 @@ async fn job_insert_processor(
     // batch jobs for the rows. This will optimize embedding generation as if there will be lots of
     // inserts to the table between 10 seconds all that rows will be batched.
     let full_table_name = Arc::new(get_full_table_name(&schema, &table));
-    let job_query_sql = Arc::new(format!("SELECT id, pk, label, src_column as \"column\", dst_column, \"table\", \"schema\", embedding_model as model, runtime, runtime_params::text, init_finished_at, job_type, column_type, batch_size FROM {0}", &full_table_name));
+    let job_query_sql = Arc::new(format!("SELECT id, pk, label, src_column as \"column\", dst_column, dst_json_columns::text, \"table\", \"schema\", embedding_model as model, runtime, runtime_params::text, init_finished_at, job_type, column_type, batch_size FROM {0}", &full_table_name));
 
     let db_uri_r1 = db_uri.clone();
     let full_table_name_r1 = full_table_name.clone();
@@ -1170,6 +1240,7 @@ pub async fn start(
     let (job_queue_tx, job_queue_rx): (Sender<EmbeddingJob>, Receiver<EmbeddingJob>) =
         mpsc::channel(1);
     let table = args.table_name;
+    let migration = get_job_table_migration(&args.schema, &table);
 
     startup_hook(
         &mut main_db_client,
@@ -1183,7 +1254,7 @@ pub async fn start(
         Some(USAGE_TABLE_DEFINITION),
         Some(EMB_FAILURE_TABLE_NAME),
         Some(FAILURE_TABLE_DEFINITION),
-        None,
+        Some(&migration),
         &notification_channel,
         logger.clone(),
     )
@@ -1265,7 +1336,9 @@ pub async fn start(
             embedding_processor_tx,
             main_db_uri.clone(),
             schema.clone(),
+            table.clone(),
             jobs_map.clone(),
+            args.embedding_cache.clone(),
             logger.clone(),
         ),
         collect_pending_jobs(
@@ -21,7 +21,7 @@ use tokio_util::sync::CancellationToken;
 
 use crate::types::AnyhowVoidResult;
 use crate::{logger::Logger, utils::get_full_table_name};
-use types::{DaemonJobHandlerMap, JobRunArgs, TargetDB};
+use types::{DaemonJobHandlerMap, EmbeddingCacheArgs, JobRunArgs, TargetDB};
 
 use types::{AutotuneProcessorArgs, EmbeddingProcessorArgs, JobType};
 
@@ -131,6 +131,14 @@ async fn spawn_job(
                         log_level: args.log_level.value(),
                         data_path: args.data_path.clone(),
                         table_name: "embedding_generation_jobs".to_owned(),
+                        embedding_cache: if args.embedding_cache {
+                            Some(EmbeddingCacheArgs {
+                                max_age: args.embedding_cache_max_age,
+                                max_entries: args.embedding_cache_max_entries,
+                            })
+                        } else {
+                            None
+                        },
                     },
                     processor_tx.clone(),
                     logger.clone(),
@@ -152,6 +160,7 @@ async fn spawn_job(
                         log_level: args.log_level.value(),
                         data_path: None,
                         table_name: "autotune_jobs".to_owned(),
+                        embedding_cache: None,
                     },
                     processor_tx.clone(),
                     logger.clone(),
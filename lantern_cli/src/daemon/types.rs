@@ -13,6 +13,13 @@ pub struct JobRunArgs {
     pub table_name: String,
     pub label: Option<String>,
     pub data_path: Option<String>,
+    pub embedding_cache: Option<EmbeddingCacheArgs>,
+}
+
+#[derive(Clone, Debug)]
+pub struct EmbeddingCacheArgs {
+    pub max_age: Option<u64>,
+    pub max_entries: Option<u64>,
 }
 
 #[derive(Clone, Debug)]
@@ -78,7 +85,7 @@ pub enum ClientJobSignal {
 #[cfg(feature = "embeddings")]
 pub type EmbeddingProcessorArgs = (
     crate::embeddings::cli::EmbeddingArgs,
-    Sender<Result<(usize, usize), anyhow::Error>>,
+    Sender<Result<(usize, usize, usize), anyhow::Error>>,
     crate::logger::Logger,
 );
 #[cfg(not(feature = "embeddings"))]
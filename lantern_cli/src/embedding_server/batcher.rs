@@ -0,0 +1,269 @@
+use std::{
+    collections::HashMap,
+    ops::Range,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{timeout, timeout_at, Instant};
+
+use crate::embeddings::{
+    core::{
+        runtime::{BatchCompletionResult, EmbeddingResult},
+        EmbeddingRuntime, Runtime,
+    },
+    process_isolating_failures,
+};
+use crate::logger::Logger;
+
+// Workers without requests for this long are stopped
+const WORKER_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RequestKind {
+    Embedding,
+    Completion,
+}
+
+// Requests are batched together only if they can be processed with the same runtime instance
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BatchKey {
+    pub kind: RequestKind,
+    pub runtime: Runtime,
+    pub model: String,
+    pub runtime_params: String,
+}
+
+pub enum BatchOutput {
+    Embeddings(EmbeddingResult),
+    Completions(BatchCompletionResult),
+}
+
+// Splits the token count of the batch between the requests proportionally
+// to their input count, so the parts sum up to the total
+fn get_range_tokens(processed_tokens: usize, input_cnt: usize, range: &Range<usize>) -> usize {
+    if input_cnt == 0 {
+        return 0;
+    }
+    processed_tokens * range.end / input_cnt - processed_tokens * range.start / input_cnt
+}
+
+impl BatchOutput {
+    // Returns the part of the output for inputs [offset, offset + count)
+    fn slice(&self, offset: usize, count: usize) -> BatchOutput {
+        let range = offset..offset + count;
+        let remap = |items: &Vec<(usize, String)>| {
+            items
+                .iter()
+                .filter(|(idx, _)| range.contains(idx))
+                .map(|(idx, message)| (idx - offset, message.clone()))
+                .collect()
+        };
+
+        match self {
+            BatchOutput::Embeddings(result) => BatchOutput::Embeddings(EmbeddingResult {
+                embeddings: result.embeddings[range.clone()].to_vec(),
+                processed_tokens: get_range_tokens(
+                    result.processed_tokens,
+                    result.embeddings.len(),
+                    &range,
+                ),
+                failed_inputs: remap(&result.failed_inputs),
+                truncated_inputs: remap(&result.truncated_inputs),
+            }),
+            BatchOutput::Completions(result) => BatchOutput::Completions(BatchCompletionResult {
+                messages: result.messages[range.clone()].to_vec(),
+                processed_tokens: get_range_tokens(
+                    result.processed_tokens,
+                    result.messages.len(),
+                    &range,
+                ),
+                failed_inputs: remap(&result.failed_inputs),
+            }),
+        }
+    }
+}
+
+struct PendingRequest {
+    inputs: Vec<String>,
+    sender: oneshot::Sender<Result<BatchOutput, String>>,
+}
+
+type WorkerMap = HashMap<BatchKey, mpsc::UnboundedSender<PendingRequest>>;
+
+// Collects concurrent requests with the same key and processes them as one batch
+// Each key has its own worker task which is started on the first request
+pub struct Batcher {
+    max_batch_size: usize,
+    batch_wait: Duration,
+    workers: Mutex<WorkerMap>,
+    logger: Logger,
+}
+
+impl Batcher {
+    pub fn new(max_batch_size: usize, batch_wait: Duration, logger: Logger) -> Self {
+        Self {
+            max_batch_size: max_batch_size.max(1),
+            batch_wait,
+            workers: Mutex::new(HashMap::new()),
+            logger,
+        }
+    }
+
+    pub async fn submit(
+        self: &Arc<Self>,
+        key: BatchKey,
+        inputs: Vec<String>,
+    ) -> Result<BatchOutput, anyhow::Error> {
+        let (sender, receiver) = oneshot::channel();
+        let mut request = PendingRequest { inputs, sender };
+
+        loop {
+            let worker = self.get_worker(&key)?;
+            match worker.send(request) {
+                Ok(()) => break,
+                Err(mpsc::error::SendError(returned)) => {
+                    // Worker has stopped, remove it if it was not replaced already
+                    let mut workers = self.workers.lock().unwrap();
+                    if let Some(current) = workers.get(&key) {
+                        if current.same_channel(&worker) {
+                            workers.remove(&key);
+                        }
+                    }
+                    request = returned;
+                }
+            }
+        }
+
+        match receiver.await {
+            Ok(result) => result.map_err(|e| anyhow::anyhow!(e)),
+            Err(_) => anyhow::bail!("Request was dropped by the embedding server"),
+        }
+    }
+
+    fn get_worker(
+        self: &Arc<Self>,
+        key: &BatchKey,
+    ) -> Result<mpsc::UnboundedSender<PendingRequest>, anyhow::Error> {
+        let mut workers = self.workers.lock().unwrap();
+        if let Some(worker) = workers.get(key) {
+            return Ok(worker.clone());
+        }
+
+        let (sender, receiver) = mpsc::unbounded_channel();
+        if key.runtime == Runtime::Ort {
+            // ORT inference is CPU bound, so the worker is run on its own thread
+            // to keep serving the other requests of the server worker meanwhile
+            let worker_runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()?;
+            let batcher = self.clone();
+            let worker_key = key.clone();
+            std::thread::Builder::new()
+                .name(format!("embedding-worker-{}", key.model))
+                .spawn(move || worker_runtime.block_on(batcher.run_worker(worker_key, receiver)))?;
+        } else {
+            actix_web::rt::spawn(self.clone().run_worker(key.clone(), receiver));
+        }
+        workers.insert(key.clone(), sender.clone());
+        Ok(sender)
+    }
+
+    async fn run_worker(
+        self: Arc<Self>,
+        key: BatchKey,
+        mut receiver: mpsc::UnboundedReceiver<PendingRequest>,
+    ) {
+        // The runtime is created once per worker, so the loaded model sessions
+        // and HTTP clients are reused between the batches
+        let runtime = EmbeddingRuntime::new(&key.runtime, None, &key.runtime_params)
+            .map_err(|e| e.to_string());
+
+        loop {
+            let first_request = match timeout(WORKER_IDLE_TIMEOUT, receiver.recv()).await {
+                Ok(Some(request)) => request,
+                Ok(None) => return,
+                Err(_) => {
+                    // Unregister the worker and process the requests
+                    // which were sent before the channel was closed
+                    self.workers.lock().unwrap().remove(&key);
+                    receiver.close();
+                    let mut batch = Vec::new();
+                    while let Ok(request) = receiver.try_recv() {
+                        batch.push(request);
+                    }
+                    if !batch.is_empty() {
+                        self.process_batch(&key, &runtime, batch).await;
+                    }
+                    return;
+                }
+            };
+
+            let mut input_cnt = first_request.inputs.len();
+            let mut batch = vec![first_request];
+            let deadline = Instant::now() + self.batch_wait;
+
+            while input_cnt < self.max_batch_size {
+                match timeout_at(deadline, receiver.recv()).await {
+                    Ok(Some(request)) => {
+                        input_cnt += request.inputs.len();
+                        batch.push(request);
+                    }
+                    _ => break,
+                }
+            }
+
+            self.process_batch(&key, &runtime, batch).await;
+        }
+    }
+
+    async fn process_batch(
+        &self,
+        key: &BatchKey,
+        runtime: &Result<EmbeddingRuntime<'_>, String>,
+        batch: Vec<PendingRequest>,
+    ) {
+        let inputs: Vec<&str> = batch
+            .iter()
+            .flat_map(|request| request.inputs.iter().map(|input| input.as_str()))
+            .collect();
+
+        let result = match runtime {
+            Ok(runtime) => run(key, runtime, &inputs, &self.logger)
+                .await
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e.clone()),
+        };
+
+        let mut offset = 0;
+        for request in batch {
+            let count = request.inputs.len();
+            let output = match &result {
+                Ok(output) => Ok(output.slice(offset, count)),
+                Err(e) => Err(e.clone()),
+            };
+            // The client may have disconnected already
+            let _ = request.sender.send(output);
+            offset += count;
+        }
+    }
+}
+
+// Inputs of different requests are processed together, so the failed inputs are isolated
+// and reported to their requests instead of failing every request of the batch
+async fn run(
+    key: &BatchKey,
+    runtime: &EmbeddingRuntime<'_>,
+    inputs: &Vec<&str>,
+    logger: &Logger,
+) -> Result<BatchOutput, anyhow::Error> {
+    match key.kind {
+        RequestKind::Embedding => Ok(BatchOutput::Embeddings(
+            process_isolating_failures(runtime, &key.model, inputs, logger).await?,
+        )),
+        RequestKind::Completion => Ok(BatchOutput::Completions(
+            runtime.batch_completion(&key.model, inputs).await?,
+        )),
+    }
+}
@@ -0,0 +1,37 @@
+use clap::Parser;
+
+#[derive(Parser, Debug, Clone)]
+#[command(version, about, long_about = None)]
+pub struct EmbeddingServerArgs {
+    /// Host to listen
+    #[arg(long, default_value = "127.0.0.1")]
+    pub host: String,
+
+    /// Port to bind
+    #[arg(long, default_value_t = 8090)]
+    pub port: u16,
+
+    /// Listen on Unix socket instead of TCP
+    #[arg(long)]
+    pub socket_path: Option<String>,
+
+    /// ORT models to load on startup and keep in memory (comma separated)
+    #[arg(long, value_delimiter = ',')]
+    pub models: Vec<String>,
+
+    /// Data path for ORT models
+    #[arg(long)]
+    pub data_path: Option<String>,
+
+    /// Max number of inputs processed together from concurrent requests
+    #[arg(long, default_value_t = 256)]
+    pub max_batch_size: usize,
+
+    /// Time to wait for concurrent requests before processing the batch
+    #[arg(long, default_value_t = 5)]
+    pub batch_wait_ms: u64,
+
+    /// Number of HTTP workers
+    #[arg(long)]
+    pub workers: Option<usize>,
+}
@@ -0,0 +1,171 @@
+use std::{str::FromStr, sync::Arc, time::Duration};
+
+use actix_web::{
+    error::{ErrorBadRequest, ErrorInternalServerError},
+    get, post, web, App, HttpResponse, HttpServer, Result,
+};
+use batcher::{BatchKey, BatchOutput, Batcher, RequestKind};
+use cli::EmbeddingServerArgs;
+
+use crate::{
+    embeddings::{
+        client::{CompletionResponse, EmbeddingRequest, EmbeddingResponse},
+        core::{EmbeddingRuntime, Runtime},
+    },
+    logger::{LogLevel, Logger},
+    types::AnyhowVoidResult,
+};
+
+mod batcher;
+pub mod cli;
+
+struct ServerState {
+    batcher: Arc<Batcher>,
+    data_path: Option<String>,
+}
+
+type ServerData = web::Data<ServerState>;
+
+impl ServerState {
+    fn get_batch_key(
+        &self,
+        kind: RequestKind,
+        request: &EmbeddingRequest,
+    ) -> Result<BatchKey, actix_web::Error> {
+        let runtime = Runtime::from_str(&request.runtime).map_err(ErrorBadRequest)?;
+        let mut runtime_params = match &request.runtime_params {
+            serde_json::Value::Null => serde_json::json!({}),
+            serde_json::Value::Object(_) => request.runtime_params.clone(),
+            _ => return Err(ErrorBadRequest("runtime_params should be a JSON object")),
+        };
+
+        if runtime == Runtime::Ort {
+            // Keep the models loaded between requests
+            runtime_params["cache"] = serde_json::json!(true);
+            if let Some(data_path) = &self.data_path {
+                runtime_params["data_path"] = serde_json::json!(data_path);
+            }
+        }
+
+        Ok(BatchKey {
+            kind,
+            runtime,
+            model: request.model.clone(),
+            runtime_params: runtime_params.to_string(),
+        })
+    }
+}
+
+#[post("/embeddings")]
+async fn embeddings(
+    data: ServerData,
+    body: web::Json<EmbeddingRequest>,
+) -> Result<web::Json<EmbeddingResponse>> {
+    let request = body.into_inner();
+    let key = data.get_batch_key(RequestKind::Embedding, &request)?;
+
+    match data.batcher.submit(key, request.inputs).await {
+        Ok(BatchOutput::Embeddings(result)) => Ok(web::Json(EmbeddingResponse {
+            embeddings: result.embeddings,
+            failed_inputs: result.failed_inputs,
+            truncated_inputs: result.truncated_inputs,
+            processed_tokens: result.processed_tokens,
+        })),
+        Ok(BatchOutput::Completions(_)) => Err(ErrorInternalServerError("Unexpected output")),
+        Err(e) => Err(ErrorInternalServerError(e)),
+    }
+}
+
+#[post("/completions")]
+async fn completions(
+    data: ServerData,
+    body: web::Json<EmbeddingRequest>,
+) -> Result<web::Json<CompletionResponse>> {
+    let request = body.into_inner();
+    let key = data.get_batch_key(RequestKind::Completion, &request)?;
+
+    match data.batcher.submit(key, request.inputs).await {
+        Ok(BatchOutput::Completions(result)) => Ok(web::Json(CompletionResponse {
+            messages: result.messages,
+            failed_inputs: result.failed_inputs,
+            processed_tokens: result.processed_tokens,
+        })),
+        Ok(BatchOutput::Embeddings(_)) => Err(ErrorInternalServerError("Unexpected output")),
+        Err(e) => Err(ErrorInternalServerError(e)),
+    }
+}
+
+#[get("/health")]
+async fn health() -> HttpResponse {
+    HttpResponse::Ok().body("ok")
+}
+
+// Loads the models into memory, so the first requests will not wait for it
+async fn warm_up_models(args: &EmbeddingServerArgs, logger: &Logger) -> AnyhowVoidResult {
+    let mut runtime_params = serde_json::json!({ "cache": true });
+    if let Some(data_path) = &args.data_path {
+        runtime_params["data_path"] = serde_json::json!(data_path);
+    }
+    let runtime_params = runtime_params.to_string();
+    let runtime = EmbeddingRuntime::new(&Runtime::Ort, None, &runtime_params)?;
+
+    for model in &args.models {
+        logger.info(&format!("Loading model {model}"));
+        runtime.process(model, &vec!["warmup"]).await?;
+    }
+
+    Ok(())
+}
+
+#[actix_web::main]
+pub async fn start(args: EmbeddingServerArgs, logger: Option<Logger>) -> AnyhowVoidResult {
+    let logger = logger.unwrap_or(Logger::new("Lantern Embedding Server", LogLevel::Debug));
+
+    warm_up_models(&args, &logger).await?;
+
+    let state = web::Data::new(ServerState {
+        batcher: Arc::new(Batcher::new(
+            args.max_batch_size,
+            Duration::from_millis(args.batch_wait_ms),
+            Logger::new(&logger.label, logger.level.clone()),
+        )),
+        data_path: args.data_path.clone(),
+    });
+
+    let mut server = HttpServer::new(move || {
+        App::new()
+            .app_data(state.clone())
+            .app_data(
+                web::JsonConfig::default()
+                    // limit request payload size to 100MB
+                    .limit(100 * 1024 * 1024),
+            )
+            .service(embeddings)
+            .service(completions)
+            .service(health)
+    });
+
+    if let Some(workers) = args.workers {
+        server = server.workers(workers);
+    }
+
+    let server = match &args.socket_path {
+        Some(socket_path) => {
+            // Remove the socket file left from the previous run
+            let _ = std::fs::remove_file(socket_path);
+            logger.info(&format!("Starting embedding server on unix:{socket_path}"));
+            server.bind_uds(socket_path)?
+        }
+        None => {
+            logger.info(&format!(
+                "Starting embedding server on http://{host}:{port}",
+                host = args.host,
+                port = args.port,
+            ));
+            server.bind((args.host.clone(), args.port))?
+        }
+    };
+
+    server.run().await?;
+    Ok(())
+}
@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+
+use itertools::Itertools;
+use sha2::{Digest, Sha256};
+use tokio_postgres::{Client, NoTls};
+
+use super::cli::Runtime;
+use super::core::post_process::PostProcessParams;
+use super::core::truncation::TruncationPolicy;
+use crate::types::*;
+use crate::utils::{get_full_table_name, quote_ident};
+
+pub const CACHE_TABLE_DEFINITION: &'static str = r#"
+"runtime" text NOT NULL,
+"model" text NOT NULL,
+"dimensions" int NOT NULL DEFAULT 0,
+"input_hash" text NOT NULL,
+"embedding" REAL[] NOT NULL,
+"truncation" text,
+"created_at" timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP,
+"last_used_at" timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP,
+PRIMARY KEY ("runtime", "model", "dimensions", "input_hash")
+"#;
+
+pub fn get_input_hash(input: &str) -> String {
+    format!("{:x}", Sha256::digest(input.as_bytes()))
+}
+
+// Content addressed cache for generated embeddings
+// Entries are keyed by (runtime, model, dimensions, sha256(input))
+// so the same text will not be embedded twice with the same model
+// The truncation message of the input is stored along with the embedding
+pub struct EmbeddingCache {
+    client: Client,
+    full_table_name: String,
+    runtime: String,
+    model: String,
+    dimensions: i32,
+}
+
+impl EmbeddingCache {
+    pub async fn new(
+        uri: &str,
+        schema: &str,
+        table: &str,
+        runtime: &Runtime,
+        model: &str,
+        runtime_params: &str,
+    ) -> Result<Self, anyhow::Error> {
+        let (client, connection) = tokio_postgres::connect(uri, NoTls).await?;
+        tokio::spawn(async move { connection.await });
+
+        let full_table_name = get_full_table_name(schema, table);
+        client
+            .batch_execute(&format!(
+                "
+                CREATE SCHEMA IF NOT EXISTS {schema};
+                CREATE TABLE IF NOT EXISTS {full_table_name} ({CACHE_TABLE_DEFINITION});
+                ALTER TABLE {full_table_name} ADD COLUMN IF NOT EXISTS \"truncation\" text;
+                ",
+                schema = quote_ident(schema)
+            ))
+            .await?;
+
+        // Models which support custom dimensions will produce different
+        // embeddings for the same input, so dimensions are part of the key
+        let params: serde_json::Value = serde_json::from_str(runtime_params)?;
+        let dimensions = params["dimensions"].as_i64().unwrap_or(0) as i32;
+        Ok(Self {
+            client,
+            full_table_name,
+            runtime: runtime.to_string(),
+            model: format!(
+                "{model}{}",
+                Self::get_model_key_suffix(&params, runtime_params)?
+            ),
+            dimensions,
+        })
+    }
+
+    // Embeddings generated with different settings are stored under separate model key:
+    // input type, ORT model variant, truncation policy, post processing and
+    // the server url (e.g. Azure deployment or OpenAI compatible server)
+    fn get_model_key_suffix(
+        params: &serde_json::Value,
+        runtime_params: &str,
+    ) -> Result<String, anyhow::Error> {
+        let mut suffix = String::new();
+        if let Some(input_type) = params["input_type"].as_str() {
+            suffix.push_str(&format!(":{input_type}"));
+        }
+        if let Some(model_variant) = params["model_variant"].as_str() {
+            suffix.push_str(&format!(":variant={model_variant}"));
+        }
+        let truncation_policy = TruncationPolicy::from_params(runtime_params)?;
+        if truncation_policy != TruncationPolicy::default() {
+            suffix.push_str(&format!(":{}", truncation_policy.to_string()));
+        }
+        suffix.push_str(&PostProcessParams::from_params(runtime_params)?.cache_key_suffix());
+        if let Some(base_url) = params["base_url"].as_str() {
+            suffix.push_str(&format!("@{base_url}"));
+        }
+        Ok(suffix)
+    }
+
+    // Returns cached embeddings and truncation messages mapped by input hash
+    // The last_used_at of the returned entries will be updated
+    pub async fn get(
+        &self,
+        input_hashes: &Vec<String>,
+    ) -> Result<HashMap<String, (Vec<f32>, Option<String>)>, anyhow::Error> {
+        let rows = self
+            .client
+            .query(
+                &format!(
+                    "UPDATE {table} SET last_used_at = CURRENT_TIMESTAMP WHERE runtime = $1 AND model = $2 AND dimensions = $3 AND input_hash = ANY($4) RETURNING input_hash, embedding, truncation",
+                    table = self.full_table_name
+                ),
+                &[&self.runtime, &self.model, &self.dimensions, input_hashes],
+            )
+            .await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| {
+                (
+                    row.get::<usize, String>(0),
+                    (
+                        row.get::<usize, Vec<f32>>(1),
+                        row.get::<usize, Option<String>>(2),
+                    ),
+                )
+            })
+            .collect())
+    }
+
+    pub async fn insert(
+        &self,
+        entries: Vec<(String, &Vec<f32>, Option<&String>)>,
+    ) -> AnyhowVoidResult {
+        if entries.is_empty() {
+            return Ok(());
+        }
+
+        let mut input_hashes = Vec::with_capacity(entries.len());
+        let mut embeddings = Vec::with_capacity(entries.len());
+        let mut truncations = Vec::with_capacity(entries.len());
+        for (input_hash, embedding, truncation) in entries {
+            input_hashes.push(input_hash);
+            embeddings.push(format!(
+                "{{{}}}",
+                embedding.iter().map(|v| v.to_string()).join(",")
+            ));
+            truncations.push(truncation.cloned());
+        }
+
+        self.client
+            .execute(
+                &format!(
+                    "INSERT INTO {table} (runtime, model, dimensions, input_hash, embedding, truncation) SELECT $1, $2, $3, unnest($4::text[]), unnest($5::text[])::REAL[], unnest($6::text[]) ON CONFLICT DO NOTHING",
+                    table = self.full_table_name
+                ),
+                &[
+                    &self.runtime,
+                    &self.model,
+                    &self.dimensions,
+                    &input_hashes,
+                    &embeddings,
+                    &truncations,
+                ],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    // Removes the entries which were not used for max_age seconds
+    // and keeps at most max_entries most recently used entries
+    pub async fn evict(&self, max_age: Option<u64>, max_entries: Option<u64>) -> AnyhowVoidResult {
+        let table = &self.full_table_name;
+
+        if let Some(max_age) = max_age {
+            self.client
+                .execute(
+                    &format!("DELETE FROM {table} WHERE last_used_at < CURRENT_TIMESTAMP - make_interval(secs => $1)"),
+                    &[&(max_age as f64)],
+                )
+                .await?;
+        }
+
+        if let Some(max_entries) = max_entries {
+            self.client
+                .execute(
+                    &format!("DELETE FROM {table} WHERE ctid IN (SELECT ctid FROM {table} ORDER BY last_used_at DESC OFFSET $1)"),
+                    &[&(max_entries as i64)],
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+}
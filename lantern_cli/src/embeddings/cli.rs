@@ -1,5 +1,8 @@
+use super::core::runtime::InputType;
 pub use super::core::Runtime;
 use clap::{Parser, ValueEnum};
+use std::path::Path;
+use std::str::FromStr;
 
 #[derive(ValueEnum, Debug, Clone)]
 pub enum EmbeddingJobType {
@@ -7,6 +10,42 @@ pub enum EmbeddingJobType {
     Completion,
 }
 
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum FileFormat {
+    Csv,
+    Jsonl,
+    Parquet,
+}
+
+impl FileFormat {
+    // Returns explicitly specified format or infers it from the file extension
+    pub fn from_path(path: &str, format: Option<FileFormat>) -> Result<FileFormat, anyhow::Error> {
+        if let Some(format) = format {
+            return Ok(format);
+        }
+
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_lowercase());
+
+        match extension.as_deref() {
+            Some("csv") => Ok(FileFormat::Csv),
+            Some("jsonl") | Some("ndjson") => Ok(FileFormat::Jsonl),
+            Some("parquet") => Ok(FileFormat::Parquet),
+            _ => anyhow::bail!(
+                "Could not infer file format for {path}, please specify it explicitly (csv, jsonl, parquet)"
+            ),
+        }
+    }
+}
+
 impl TryFrom<&str> for EmbeddingJobType {
     type Error = anyhow::Error;
     fn try_from(value: &str) -> Result<Self, Self::Error> {
@@ -26,11 +65,13 @@ pub struct EmbeddingArgs {
     pub model: String,
 
     /// Fully associated database connection string including db name
-    #[arg(short, long)]
+    /// Not required if both input and output are files
+    #[arg(short, long, default_value = "")]
     pub uri: String,
 
     /// Table name
-    #[arg(short, long)]
+    /// Not required if both input and output are files
+    #[arg(short, long, default_value = "")]
     pub table: String,
 
     /// Schema name
@@ -69,6 +110,12 @@ pub struct EmbeddingArgs {
     #[arg(long)]
     pub column_type: Option<String>,
 
+    /// JSON object mapping completion response properties to destination column types
+    /// e.g '{"summary": "TEXT", "score": "INT"}'. The columns will be filled from the
+    /// JSON response stored in out_column
+    #[arg(long)]
+    pub out_json_columns: Option<String>,
+
     /// Runtime
     #[arg(long, default_value_t = Runtime::Ort)]
     pub runtime: Runtime,
@@ -116,6 +163,50 @@ pub struct EmbeddingArgs {
     /// Job ID is only needed when run from daemon
     #[arg(long, default_value_t = 0)]
     pub job_id: i32,
+
+    /// Jobs table under internal schema where the ids of submitted Batch API batches
+    /// are stored on the job row, so they are resumed after restart
+    #[arg(long)]
+    pub batch_state_table: Option<String>,
+
+    /// Reuse embeddings for the same input, model and dimensions from cache table
+    #[arg(long, default_value_t = false)]
+    pub cache: bool,
+
+    /// Cache table name, it will be created under internal schema if not exists
+    #[arg(long, default_value = "embedding_cache")]
+    pub cache_table: String,
+
+    /// Remove cache entries which were not used for this many seconds
+    #[arg(long)]
+    pub cache_max_age: Option<u64>,
+
+    /// Maximum number of entries to keep in cache (least recently used are removed)
+    #[arg(long)]
+    pub cache_max_entries: Option<u64>,
+
+    /// Whether the inputs are search queries or documents (query, document)
+    /// Model specific instruction prefixes will be applied based on this
+    #[arg(long)]
+    pub input_type: Option<String>,
+
+    /// Read inputs from local file instead of the table. `--pk` and `--column`
+    /// are used as the field names in the file
+    #[arg(long)]
+    pub input_file: Option<String>,
+
+    /// Input file format, inferred from the file extension if not specified
+    #[arg(long)]
+    pub input_format: Option<FileFormat>,
+
+    /// Write results to local file instead of the table. `--pk` and `--out-column`
+    /// are used as the field names in the file
+    #[arg(long)]
+    pub output_file: Option<String>,
+
+    /// Output file format, inferred from the file extension if not specified
+    #[arg(long)]
+    pub output_format: Option<FileFormat>,
 }
 
 impl EmbeddingArgs {
@@ -126,6 +217,35 @@ impl EmbeddingArgs {
             ..self
         }
     }
+
+    // Merges input_type argument into runtime params, so the runtime
+    // will apply the instruction template of the model
+    pub fn with_input_type(self) -> Result<Self, anyhow::Error> {
+        let input_type = match &self.input_type {
+            Some(input_type) => InputType::from_str(input_type)?,
+            None => return Ok(self),
+        };
+
+        let mut params: serde_json::Value = serde_json::from_str(&self.runtime_params)?;
+        params["input_type"] = serde_json::json!(input_type.to_string());
+
+        Ok(EmbeddingArgs {
+            runtime_params: params.to_string(),
+            ..self
+        })
+    }
+
+    // Values of bytea columns are passed to the runtime in Postgres hex format (\x...)
+    // so the runtime will decode the inputs instead of treating them as paths or URLs
+    pub fn with_bytea_input(self) -> Result<Self, anyhow::Error> {
+        let mut params: serde_json::Value = serde_json::from_str(&self.runtime_params)?;
+        params["bytea_input"] = serde_json::json!(true);
+
+        Ok(EmbeddingArgs {
+            runtime_params: params.to_string(),
+            ..self
+        })
+    }
 }
 
 #[derive(Parser, Debug)]
@@ -145,6 +265,18 @@ pub struct ShowModelsArgs {
     /// Generate embeddings or get chat completion
     #[arg(long)]
     pub job_type: Option<EmbeddingJobType>,
+
+    /// Output format
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+}
+
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct ShowRuntimesArgs {
+    /// Output format
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
 }
 
 #[derive(Parser, Debug)]
@@ -177,4 +309,8 @@ pub struct MeasureModelSpeedArgs {
     /// Runtime Params JSON string
     #[arg(long, default_value = "{}")]
     pub runtime_params: String,
+
+    /// Output format
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
 }
@@ -0,0 +1,187 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, UnixStream};
+use url::Url;
+
+use super::core::Runtime;
+
+// Client and request/response types for the embedding server (lantern-cli start-embedding-server)
+// The server can listen on TCP (http://host:port) or on Unix socket (unix:/path/to/socket)
+// so the client speaks plain HTTP/1.1 over both kinds of streams
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct EmbeddingRequest {
+    pub runtime: String,
+    pub model: String,
+    #[serde(default)]
+    pub runtime_params: serde_json::Value,
+    pub inputs: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct EmbeddingResponse {
+    pub embeddings: Vec<Vec<f32>>,
+    // Index of the input and the error message
+    #[serde(default)]
+    pub failed_inputs: Vec<(usize, String)>,
+    // Index of the input and the info message
+    #[serde(default)]
+    pub truncated_inputs: Vec<(usize, String)>,
+    // Share of the batch tokens for the inputs of this request
+    #[serde(default)]
+    pub processed_tokens: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CompletionResponse {
+    // Failed completions are returned as "Error: <message>" like in completion jobs
+    pub messages: Vec<String>,
+    // Index of the input and the error message
+    #[serde(default)]
+    pub failed_inputs: Vec<(usize, String)>,
+    // Share of the batch tokens for the inputs of this request
+    #[serde(default)]
+    pub processed_tokens: usize,
+}
+
+enum ServerAddress {
+    Tcp { host: String, port: u16 },
+    Unix(PathBuf),
+}
+
+pub struct EmbeddingServerClient {
+    address: ServerAddress,
+}
+
+impl EmbeddingServerClient {
+    pub fn new(server_url: &str) -> Result<Self, anyhow::Error> {
+        if let Some(path) = server_url.strip_prefix("unix:") {
+            return Ok(Self {
+                address: ServerAddress::Unix(PathBuf::from(path)),
+            });
+        }
+
+        let url = Url::parse(server_url)?;
+        if url.scheme() != "http" {
+            anyhow::bail!("Invalid embedding server url {server_url}, expected http://host:port or unix:/path/to/socket");
+        }
+
+        Ok(Self {
+            address: ServerAddress::Tcp {
+                host: url
+                    .host_str()
+                    .ok_or(anyhow::anyhow!("Invalid embedding server url {server_url}"))?
+                    .to_owned(),
+                port: url.port().unwrap_or(80),
+            },
+        })
+    }
+
+    pub async fn embeddings(
+        &self,
+        runtime: &Runtime,
+        model: &str,
+        runtime_params: &str,
+        inputs: Vec<String>,
+    ) -> Result<EmbeddingResponse, anyhow::Error> {
+        let request = EmbeddingRequest {
+            runtime: runtime.to_string(),
+            model: model.to_owned(),
+            runtime_params: serde_json::from_str(runtime_params)?,
+            inputs,
+        };
+        let response = self.post("/embeddings", &request).await?;
+        Ok(serde_json::from_slice(&response)?)
+    }
+
+    pub async fn completions(
+        &self,
+        runtime: &Runtime,
+        model: &str,
+        runtime_params: &str,
+        inputs: Vec<String>,
+    ) -> Result<CompletionResponse, anyhow::Error> {
+        let request = EmbeddingRequest {
+            runtime: runtime.to_string(),
+            model: model.to_owned(),
+            runtime_params: serde_json::from_str(runtime_params)?,
+            inputs,
+        };
+        let response = self.post("/completions", &request).await?;
+        Ok(serde_json::from_slice(&response)?)
+    }
+
+    async fn post<T: Serialize>(&self, path: &str, body: &T) -> Result<Vec<u8>, anyhow::Error> {
+        let body = serde_json::to_vec(body)?;
+        match &self.address {
+            ServerAddress::Tcp { host, port } => {
+                let stream = TcpStream::connect((host.as_str(), *port)).await?;
+                send_request(stream, host, path, body).await
+            }
+            ServerAddress::Unix(socket_path) => {
+                let stream = UnixStream::connect(socket_path).await.map_err(|e| {
+                    anyhow::anyhow!(
+                        "Could not connect to embedding server at {}: {e}",
+                        socket_path.display()
+                    )
+                })?;
+                send_request(stream, "localhost", path, body).await
+            }
+        }
+    }
+}
+
+async fn send_request<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    host: &str,
+    path: &str,
+    body: Vec<u8>,
+) -> Result<Vec<u8>, anyhow::Error> {
+    let head = format!(
+        "POST {path} HTTP/1.1\r\nHost: {host}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(&body).await?;
+    stream.flush().await?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await?;
+
+    let header_end = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or(anyhow::anyhow!("Invalid response from embedding server"))?;
+    let head = String::from_utf8_lossy(&response[..header_end]).to_string();
+    let mut body = response.split_off(header_end + 4);
+
+    let status: u16 = head
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or(anyhow::anyhow!("Invalid response from embedding server"))?;
+
+    let content_length = head.lines().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        if name.trim().eq_ignore_ascii_case("content-length") {
+            value.trim().parse::<usize>().ok()
+        } else {
+            None
+        }
+    });
+
+    if let Some(content_length) = content_length {
+        body.truncate(content_length);
+    }
+
+    if status != 200 {
+        anyhow::bail!(
+            "Embedding server returned status {status}: {}",
+            String::from_utf8_lossy(&body)
+        );
+    }
+
+    Ok(body)
+}
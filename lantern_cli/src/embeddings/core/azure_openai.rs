@@ -0,0 +1,227 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::embeddings::cli::EmbeddingJobType;
+
+// Helpers for Azure OpenAI (https://learn.microsoft.com/en-us/azure/ai-services/openai/reference)
+// Requests are sent to {endpoint}/openai/deployments/{deployment}/{embeddings|chat/completions}?api-version={version}
+// and authorized either with api-key header or with Microsoft Entra ID token
+
+pub static DEFAULT_API_VERSION: &'static str = "2024-10-21";
+static DEFAULT_AUTHORITY_HOST: &'static str = "https://login.microsoftonline.com";
+static COGNITIVE_SERVICES_SCOPE: &'static str = "https://cognitiveservices.azure.com/.default";
+static AZURE_OPENAI_REGEX: &'static str =
+    r"^https:\/\/[a-zA-Z0-9_\-]+\.(openai|cognitiveservices)\.azure\.com(\/|\?|$)";
+
+// Tokens are refreshed this long before they expire
+const TOKEN_REFRESH_MARGIN: Duration = Duration::from_secs(300);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct AzureParams {
+    // Overrides the api-version from base_url
+    pub api_version: Option<String>,
+    // Deployment name to OpenAI model name mapping (e.g. {"my-embeddings": "text-embedding-3-small"})
+    // used to get the token limits and dimensions of the deployed model
+    #[serde(default)]
+    pub deployments: HashMap<String, String>,
+    // Service principal credentials to get Entra ID tokens,
+    // the token is refreshed automatically before it expires
+    pub tenant_id: Option<String>,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+    // Authority host for sovereign clouds (default: https://login.microsoftonline.com)
+    pub authority_host: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AzureDeployment {
+    endpoint: String,
+    // Deployment from base_url, if not set the model name is used as deployment name
+    deployment: Option<String>,
+    api_version: String,
+    deployments: HashMap<String, String>,
+}
+
+impl AzureDeployment {
+    pub fn is_azure_url(base_url: &str) -> bool {
+        Regex::new(AZURE_OPENAI_REGEX).unwrap().is_match(base_url)
+    }
+
+    // Accepts both resource endpoint (https://{resource}.openai.azure.com)
+    // and full deployment url (https://{resource}.openai.azure.com/openai/deployments/{deployment}/embeddings?api-version={version})
+    pub fn new(base_url: &str, params: &AzureParams) -> Result<Self, anyhow::Error> {
+        let url = Url::parse(base_url)?;
+        let path = url.path();
+
+        let (prefix, deployment) = match path.find("/openai/deployments/") {
+            Some(idx) => {
+                let deployment = path[idx + "/openai/deployments/".len()..]
+                    .split('/')
+                    .next()
+                    .filter(|d| !d.is_empty())
+                    .map(|d| d.to_owned());
+                (&path[..idx], deployment)
+            }
+            None => (path.trim_end_matches("/openai").trim_end_matches('/'), None),
+        };
+
+        let url_api_version = url
+            .query_pairs()
+            .find(|(key, _)| key == "api-version")
+            .map(|(_, value)| value.to_string());
+
+        let api_version = params
+            .api_version
+            .clone()
+            .or(url_api_version)
+            .unwrap_or(DEFAULT_API_VERSION.to_owned());
+
+        Ok(Self {
+            endpoint: format!("{}{prefix}", url.origin().ascii_serialization()),
+            deployment,
+            api_version,
+            deployments: params.deployments.clone(),
+        })
+    }
+
+    pub fn get_url(&self, model_name: &str, job_type: &EmbeddingJobType) -> String {
+        let deployment = self.deployment.as_deref().unwrap_or(model_name);
+        let path = match job_type {
+            EmbeddingJobType::EmbeddingGeneration => "embeddings",
+            EmbeddingJobType::Completion => "chat/completions",
+        };
+
+        format!(
+            "{}/openai/deployments/{deployment}/{path}?api-version={}",
+            self.endpoint, self.api_version
+        )
+    }
+
+    // Returns the OpenAI model name for the deployment
+    pub fn get_model_name<'a>(&'a self, model_name: &'a str) -> &'a str {
+        if let Some(model) = self.deployments.get(model_name) {
+            return model;
+        }
+
+        if let Some(model) = self
+            .deployment
+            .as_ref()
+            .and_then(|deployment| self.deployments.get(deployment))
+        {
+            return model;
+        }
+
+        model_name
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct EntraTokenResponse {
+    access_token: String,
+    expires_in: u64,
+}
+
+struct CachedToken {
+    token: String,
+    expires_at: Instant,
+}
+
+lazy_static! {
+    // Tokens are shared between runtime instances, so the jobs with the same
+    // credentials will not request a new token for each batch
+    static ref ENTRA_TOKEN_CACHE: RwLock<HashMap<String, CachedToken>> =
+        RwLock::new(HashMap::new());
+}
+
+#[derive(Debug, Clone)]
+pub struct EntraCredentials {
+    tenant_id: String,
+    client_id: String,
+    client_secret: String,
+    authority_host: String,
+}
+
+impl EntraCredentials {
+    pub fn from_params(params: &AzureParams) -> Result<Option<Self>, anyhow::Error> {
+        match (&params.tenant_id, &params.client_id, &params.client_secret) {
+            (None, None, None) => Ok(None),
+            (Some(tenant_id), Some(client_id), Some(client_secret)) => Ok(Some(Self {
+                tenant_id: tenant_id.clone(),
+                client_id: client_id.clone(),
+                client_secret: client_secret.clone(),
+                authority_host: params
+                    .authority_host
+                    .clone()
+                    .unwrap_or(DEFAULT_AUTHORITY_HOST.to_owned()),
+            })),
+            _ => anyhow::bail!(
+                "'tenant_id', 'client_id' and 'client_secret' are required to get Azure Entra token"
+            ),
+        }
+    }
+
+    fn cache_key(&self) -> String {
+        format!(
+            "{}/{}/{}",
+            self.authority_host, self.tenant_id, self.client_id
+        )
+    }
+
+    // Returns the cached token if it will be valid for at least min_validity
+    pub fn get_cached_token(&self, min_validity: Duration) -> Option<String> {
+        let cache = ENTRA_TOKEN_CACHE.read().unwrap();
+        cache
+            .get(&self.cache_key())
+            .filter(|cached| cached.expires_at > Instant::now() + min_validity)
+            .map(|cached| cached.token.clone())
+    }
+
+    // Requests a new token with client credentials flow if the cached one is missing or about to expire
+    // https://learn.microsoft.com/en-us/entra/identity-platform/v2-oauth2-client-creds-grant-flow
+    pub async fn refresh_token(&self) -> Result<String, anyhow::Error> {
+        if let Some(token) = self.get_cached_token(TOKEN_REFRESH_MARGIN) {
+            return Ok(token);
+        }
+
+        let url = format!(
+            "{}/{}/oauth2/v2.0/token",
+            self.authority_host.trim_end_matches('/'),
+            self.tenant_id
+        );
+        let response = reqwest::Client::new()
+            .post(&url)
+            .form(&[
+                ("grant_type", "client_credentials"),
+                ("client_id", self.client_id.as_str()),
+                ("client_secret", self.client_secret.as_str()),
+                ("scope", COGNITIVE_SERVICES_SCOPE),
+            ])
+            .send()
+            .await?;
+
+        let status = response.status();
+        let body = response.bytes().await?;
+        if !status.is_success() {
+            anyhow::bail!(
+                "Could not get Azure Entra token, status {status}: {}",
+                String::from_utf8_lossy(&body)
+            );
+        }
+
+        let response: EntraTokenResponse = serde_json::from_slice(&body)?;
+        ENTRA_TOKEN_CACHE.write().unwrap().insert(
+            self.cache_key(),
+            CachedToken {
+                token: response.access_token.clone(),
+                expires_at: Instant::now() + Duration::from_secs(response.expires_in),
+            },
+        );
+
+        Ok(response.access_token)
+    }
+}
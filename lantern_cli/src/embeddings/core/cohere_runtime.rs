@@ -1,12 +1,24 @@
 use itertools::Itertools;
+use serde_json::json;
 use std::collections::HashMap;
+use std::str::FromStr;
 use tokio::sync::RwLock;
 
+use super::post_process::PostProcessParams;
+use super::truncation::TruncationPolicy;
+use super::utils::{get_response_validator, validate_json_response};
 use super::{
-    runtime::{EmbeddingResult, EmbeddingRuntimeT},
+    runtime::{
+        AvailableModel, BatchCompletionResult, CompletionResult, EmbeddingResult,
+        EmbeddingRuntimeT, InputType,
+    },
     LoggerFn,
 };
-use crate::{check_and_get_model, embeddings::cli::EmbeddingJobType, HTTPRuntime};
+use crate::{
+    check_and_get_model,
+    embeddings::{cli::EmbeddingJobType, get_default_batch_size},
+    HTTPRuntime,
+};
 use serde::{Deserialize, Serialize};
 
 struct ModelInfo {
@@ -17,7 +29,10 @@ struct ModelInfo {
 
 #[derive(Deserialize)]
 struct CohereMetaBilledUnits {
+    #[serde(default)]
     input_tokens: usize,
+    #[serde(default)]
+    output_tokens: usize,
 }
 
 #[derive(Deserialize)]
@@ -31,46 +46,71 @@ struct CohereResponse {
     meta: CohereMeta,
 }
 
+#[derive(Deserialize)]
+struct CohereChatResponse {
+    text: String,
+    meta: CohereMeta,
+}
+
 impl ModelInfo {
-    pub fn new(model_name: &str) -> Result<Self, anyhow::Error> {
+    pub fn new(model_name: &str, job_type: EmbeddingJobType) -> Result<Self, anyhow::Error> {
         let name = model_name.split("/").last().unwrap().to_owned();
-        match model_name {
-            "embed-english-v3.0" => Ok(Self {
-                name,
-                sequence_len: 512,
-                dimensions: 1024,
-            }),
-            "embed-multilingual-v3.0" => Ok(Self {
-                name,
-                sequence_len: 512,
-                dimensions: 1024,
-            }),
-            "embed-english-light-v3.0" => Ok(Self {
-                name,
-                sequence_len: 512,
-                dimensions: 384,
-            }),
-            "embed-multilingual-light-v3.0" => Ok(Self {
-                name,
-                sequence_len: 512,
-                dimensions: 384,
-            }),
-            "embed-english-v2.0" => Ok(Self {
-                name,
-                sequence_len: 512,
-                dimensions: 4096,
-            }),
-            "embed-english-light-v2.0" => Ok(Self {
-                name,
-                sequence_len: 512,
-                dimensions: 1024,
-            }),
-            "embed-multilingual-v2.0" => Ok(Self {
-                name,
-                sequence_len: 512,
-                dimensions: 768,
-            }),
-            _ => anyhow::bail!("Unsupported model {model_name}"),
+        match job_type {
+            EmbeddingJobType::EmbeddingGeneration => match model_name {
+                "embed-english-v3.0" => Ok(Self {
+                    name,
+                    sequence_len: 512,
+                    dimensions: 1024,
+                }),
+                "embed-multilingual-v3.0" => Ok(Self {
+                    name,
+                    sequence_len: 512,
+                    dimensions: 1024,
+                }),
+                "embed-english-light-v3.0" => Ok(Self {
+                    name,
+                    sequence_len: 512,
+                    dimensions: 384,
+                }),
+                "embed-multilingual-light-v3.0" => Ok(Self {
+                    name,
+                    sequence_len: 512,
+                    dimensions: 384,
+                }),
+                "embed-english-v2.0" => Ok(Self {
+                    name,
+                    sequence_len: 512,
+                    dimensions: 4096,
+                }),
+                "embed-english-light-v2.0" => Ok(Self {
+                    name,
+                    sequence_len: 512,
+                    dimensions: 1024,
+                }),
+                "embed-multilingual-v2.0" => Ok(Self {
+                    name,
+                    sequence_len: 512,
+                    dimensions: 768,
+                }),
+                _ => anyhow::bail!("Unsupported model {model_name}"),
+            },
+            EmbeddingJobType::Completion => match model_name {
+                "command-r"
+                | "command-r-plus"
+                | "command-r-08-2024"
+                | "command-r-plus-08-2024"
+                | "command-r7b-12-2024" => Ok(Self {
+                    name,
+                    sequence_len: 128000,
+                    dimensions: 0,
+                }),
+                "command" | "command-light" => Ok(Self {
+                    name,
+                    sequence_len: 4096,
+                    dimensions: 0,
+                }),
+                _ => anyhow::bail!("Unsupported model {model_name}"),
+            },
         }
     }
 }
@@ -80,31 +120,84 @@ lazy_static! {
         RwLock::new(HashMap::from([
             (
                 "embed-english-v3.0",
-                ModelInfo::new("embed-english-v3.0").unwrap()
+                ModelInfo::new("embed-english-v3.0", EmbeddingJobType::EmbeddingGeneration)
+                    .unwrap()
             ),
             (
                 "embed-multilingual-v3.0",
-                ModelInfo::new("embed-multilingual-v3.0").unwrap()
+                ModelInfo::new(
+                    "embed-multilingual-v3.0",
+                    EmbeddingJobType::EmbeddingGeneration
+                )
+                .unwrap()
             ),
             (
                 "embed-multilingual-light-v3.0",
-                ModelInfo::new("embed-multilingual-light-v3.0").unwrap()
+                ModelInfo::new(
+                    "embed-multilingual-light-v3.0",
+                    EmbeddingJobType::EmbeddingGeneration
+                )
+                .unwrap()
             ),
             (
                 "embed-english-light-v3.0",
-                ModelInfo::new("embed-english-light-v3.0").unwrap()
+                ModelInfo::new(
+                    "embed-english-light-v3.0",
+                    EmbeddingJobType::EmbeddingGeneration
+                )
+                .unwrap()
             ),
             (
                 "embed-english-v2.0",
-                ModelInfo::new("embed-english-v2.0").unwrap()
+                ModelInfo::new("embed-english-v2.0", EmbeddingJobType::EmbeddingGeneration)
+                    .unwrap()
             ),
             (
                 "embed-english-light-v2.0",
-                ModelInfo::new("embed-english-light-v2.0").unwrap()
+                ModelInfo::new(
+                    "embed-english-light-v2.0",
+                    EmbeddingJobType::EmbeddingGeneration
+                )
+                .unwrap()
             ),
             (
                 "embed-multilingual-v2.0",
-                ModelInfo::new("embed-multilingual-v2.0").unwrap()
+                ModelInfo::new(
+                    "embed-multilingual-v2.0",
+                    EmbeddingJobType::EmbeddingGeneration
+                )
+                .unwrap()
+            ),
+        ]));
+    static ref COMPLETION_MODEL_INFO_MAP: RwLock<HashMap<&'static str, ModelInfo>> =
+        RwLock::new(HashMap::from([
+            (
+                "command-r",
+                ModelInfo::new("command-r", EmbeddingJobType::Completion).unwrap()
+            ),
+            (
+                "command-r-plus",
+                ModelInfo::new("command-r-plus", EmbeddingJobType::Completion).unwrap()
+            ),
+            (
+                "command-r-08-2024",
+                ModelInfo::new("command-r-08-2024", EmbeddingJobType::Completion).unwrap()
+            ),
+            (
+                "command-r-plus-08-2024",
+                ModelInfo::new("command-r-plus-08-2024", EmbeddingJobType::Completion).unwrap()
+            ),
+            (
+                "command-r7b-12-2024",
+                ModelInfo::new("command-r7b-12-2024", EmbeddingJobType::Completion).unwrap()
+            ),
+            (
+                "command",
+                ModelInfo::new("command", EmbeddingJobType::Completion).unwrap()
+            ),
+            (
+                "command-light",
+                ModelInfo::new("command-light", EmbeddingJobType::Completion).unwrap()
             ),
         ]));
 }
@@ -115,6 +208,11 @@ pub struct CohereRuntime<'a> {
     base_url: String,
     headers: Vec<(String, String)>,
     input_type: String,
+    system_prompt: Option<String>,
+    response_schema: Option<serde_json::Value>,
+    response_validator: Option<Arc<jsonschema::Validator>>,
+    pub(super) post_process: PostProcessParams,
+    pub(super) truncation_policy: TruncationPolicy,
     #[allow(dead_code)]
     logger: &'a LoggerFn,
 }
@@ -123,24 +221,38 @@ pub struct CohereRuntime<'a> {
 pub struct CohereRuntimeParams {
     pub api_token: Option<String>,
     pub input_type: Option<String>,
+    pub system_prompt: Option<String>,
+    pub response_schema: Option<serde_json::Value>,
 }
 
 impl<'a> CohereRuntime<'a> {
+    fn get_headers(&self) -> Vec<(String, String)> {
+        self.headers.clone()
+    }
+
     pub fn new(logger: &'a LoggerFn, params: &'a str) -> Result<Self, anyhow::Error> {
         let runtime_params: CohereRuntimeParams = serde_json::from_str(&params)?;
 
         if runtime_params.api_token.is_none() {
-            anyhow::bail!("'api_token' is required for OpenAi runtime");
+            anyhow::bail!("'api_token' is required for Cohere runtime");
         }
 
+        let response_validator = get_response_validator(&runtime_params.response_schema)?;
+
         Ok(Self {
             base_url: "https://api.cohere.ai".to_owned(),
             logger,
             request_timeout: 120,
             max_batch_size: 96,
-            input_type: runtime_params
-                .input_type
-                .unwrap_or("search_document".to_owned()),
+            input_type: Self::get_input_type(runtime_params.input_type),
+            system_prompt: runtime_params
+                .system_prompt
+                .filter(|prompt| prompt.trim() != ""),
+            response_schema: runtime_params.response_schema,
+            response_validator,
+            post_process: PostProcessParams::from_params(params)?,
+            truncation_policy: TruncationPolicy::from_params(params)?
+                .require_tokenizer("cohere runtime")?,
             headers: vec![
                 ("Content-Type".to_owned(), "application/json".to_owned()),
                 (
@@ -151,6 +263,19 @@ impl<'a> CohereRuntime<'a> {
         })
     }
 
+    // Maps generic query/document input types to Cohere's search_query/search_document
+    // Other Cohere input types (e.g. classification, clustering) are passed as is
+    fn get_input_type(input_type: Option<String>) -> String {
+        match input_type {
+            Some(input_type) => match InputType::from_str(&input_type) {
+                Ok(InputType::Query) => "search_query".to_owned(),
+                Ok(InputType::Document) => "search_document".to_owned(),
+                Err(_) => input_type,
+            },
+            None => "search_document".to_owned(),
+        }
+    }
+
     async fn chunk_inputs(
         &self,
         model_name: &str,
@@ -182,6 +307,96 @@ impl<'a> CohereRuntime<'a> {
         Ok(batch_tokens)
     }
 
+    pub async fn completion(
+        &self,
+        model_name: &str,
+        query: &str,
+        retries: Option<usize>,
+    ) -> Result<CompletionResult, anyhow::Error> {
+        let client = Arc::new(self.get_client()?);
+        let url = Url::parse(&self.base_url)?.join("/v1/chat")?.to_string();
+
+        let mut body = json!({
+            "model": model_name,
+            "message": query,
+        });
+
+        if let Some(system_prompt) = &self.system_prompt {
+            // Cohere chat API accepts system prompt as "preamble"
+            body["preamble"] = json!(system_prompt);
+        }
+
+        if let Some(response_schema) = &self.response_schema {
+            // https://docs.cohere.com/docs/structured-outputs
+            body["response_format"] = json!({ "type": "json_object", "schema": response_schema });
+        }
+
+        // The response is validated against the schema locally as well,
+        // so schema violations will be retried as failed requests
+        let response_validator = self.response_validator.clone();
+        let completion_response: CompletionResult = post_with_retries(
+            client,
+            url,
+            serde_json::to_string(&body)?,
+            Box::new(move |body| {
+                let mut response = CohereRuntime::get_completion_response(body)?;
+                if let Some(validator) = &response_validator {
+                    response.message = validate_json_response(validator, &response.message)?;
+                }
+                Ok(response)
+            }),
+            retries.unwrap_or(5),
+        )
+        .await?;
+
+        Ok(completion_response)
+    }
+
+    pub async fn batch_completion(
+        self: Arc<&Self>,
+        model_name: &str,
+        queries: &Vec<&str>,
+    ) -> Result<BatchCompletionResult, anyhow::Error> {
+        let model_map = COMPLETION_MODEL_INFO_MAP.read().await;
+        check_and_get_model!(model_map, model_name);
+        drop(model_map);
+
+        let mut processed_tokens = 0;
+
+        let completion_futures = queries.into_iter().map(|query| {
+            let self_clone = Arc::clone(&self);
+            let model_name_clone = model_name.to_owned();
+            async move {
+                self_clone
+                    .completion(&model_name_clone, &query, Some(5))
+                    .await
+            }
+        });
+
+        let results = futures::future::join_all(completion_futures).await;
+
+        let mut responses = Vec::with_capacity(results.len());
+        let mut failed_inputs = Vec::new();
+        for (idx, result) in results.into_iter().enumerate() {
+            match result {
+                Ok(msg) => {
+                    processed_tokens += msg.processed_tokens;
+                    responses.push(msg.message);
+                }
+                Err(e) => {
+                    responses.push(format!("Error: {e}"));
+                    failed_inputs.push((idx, e.to_string()));
+                }
+            }
+        }
+
+        Ok(BatchCompletionResult {
+            messages: responses,
+            processed_tokens,
+            failed_inputs,
+        })
+    }
+
     // Static functions
     pub fn get_response(body: Vec<u8>) -> Result<EmbeddingResult, anyhow::Error> {
         let result: Result<CohereResponse, serde_json::Error> = serde_json::from_slice(&body);
@@ -197,6 +412,26 @@ impl<'a> CohereRuntime<'a> {
         Ok(EmbeddingResult {
             embeddings: result.embeddings,
             processed_tokens: result.meta.billed_units.input_tokens,
+            failed_inputs: Vec::new(),
+            truncated_inputs: Vec::new(),
+        })
+    }
+
+    pub fn get_completion_response(body: Vec<u8>) -> Result<CompletionResult, anyhow::Error> {
+        let result: Result<CohereChatResponse, serde_json::Error> = serde_json::from_slice(&body);
+        if let Err(e) = result {
+            anyhow::bail!(
+                "Error: {e}. Cohere response: {:?}",
+                serde_json::from_slice::<serde_json::Value>(&body)?
+            );
+        }
+
+        let result = result.unwrap();
+        let billed_units = result.meta.billed_units;
+
+        Ok(CompletionResult {
+            message: result.text,
+            processed_tokens: billed_units.input_tokens + billed_units.output_tokens,
         })
     }
 }
@@ -212,17 +447,29 @@ impl<'a> EmbeddingRuntimeT for CohereRuntime<'a> {
 
     async fn get_available_models(
         &self,
-        _job_type: EmbeddingJobType,
-    ) -> (String, Vec<(String, bool)>) {
-        let map = MODEL_INFO_MAP.read().await;
+        job_type: EmbeddingJobType,
+    ) -> (String, Vec<AvailableModel>) {
+        let map = match job_type {
+            EmbeddingJobType::EmbeddingGeneration => MODEL_INFO_MAP.read().await,
+            EmbeddingJobType::Completion => COMPLETION_MODEL_INFO_MAP.read().await,
+        };
+
         let mut res = String::new();
         let mut models = Vec::with_capacity(map.len());
-        for (key, value) in &*map {
+        for (key, value) in map.iter().sorted_by_key(|(key, _)| **key) {
             res.push_str(&format!(
                 "{} - sequence_len: {}, dimensions: {}\n",
                 key, value.sequence_len, value.dimensions
             ));
-            models.push((key.to_string(), false));
+            models.push(AvailableModel {
+                name: key.to_string(),
+                // Completion models do not have dimensions
+                dimensions: Some(value.dimensions).filter(|d| *d > 0),
+                max_sequence_length: Some(value.sequence_len),
+                visual: false,
+                downloaded: None,
+                default_batch_size: get_default_batch_size(key),
+            });
         }
 
         return (res, models);
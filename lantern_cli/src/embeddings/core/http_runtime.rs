@@ -27,7 +27,7 @@ macro_rules! HTTPRuntime {
                     .redirect(reqwest::redirect::Policy::limited(2));
 
                 let mut headers = reqwest::header::HeaderMap::new();
-                for header in &self.headers {
+                for header in &self.get_headers() {
                     headers.insert(
                         reqwest::header::HeaderName::from_bytes(header.0.as_bytes())?,
                         reqwest::header::HeaderValue::from_str(&header.1)?,
@@ -65,6 +65,8 @@ macro_rules! HTTPRuntime {
                 Ok(super::runtime::EmbeddingResult {
                     processed_tokens,
                     embeddings: responses,
+                    failed_inputs: Vec::new(),
+                    truncated_inputs: Vec::new(),
                 })
             }
         }
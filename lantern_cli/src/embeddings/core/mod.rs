@@ -1,19 +1,30 @@
+pub mod azure_openai;
 pub mod cohere_runtime;
 pub mod http_runtime;
+pub mod openai_batch;
 pub mod openai_runtime;
 pub mod ort_runtime;
+pub mod post_process;
 pub mod runtime;
+pub mod truncation;
 pub mod utils;
 
-use std::{str::FromStr, sync::Arc};
+use std::{collections::HashSet, str::FromStr, sync::Arc};
 use strum::{EnumIter, IntoEnumIterator};
+use tokio_util::sync::CancellationToken;
 
 use cohere_runtime::CohereRuntime;
+use openai_batch::BatchState;
 use openai_runtime::OpenAiRuntime;
 use ort_runtime::OrtRuntime;
+use post_process::PostProcessParams;
 use runtime::EmbeddingRuntimeT;
+use truncation::{
+    get_truncation_message, merge_chunked_result, merge_skipped_result, TokenCounts,
+    TruncationPolicy,
+};
 
-use self::runtime::{BatchCompletionResult, CompletionResult, EmbeddingResult};
+use self::runtime::{AvailableModel, BatchCompletionResult, CompletionResult, EmbeddingResult};
 
 use super::cli::EmbeddingJobType;
 
@@ -21,7 +32,7 @@ fn default_logger(text: &str) {
     println!("{}", text);
 }
 
-#[derive(Debug, PartialEq, Clone, EnumIter, Copy)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, EnumIter, Copy)]
 pub enum Runtime {
     Ort,
     OpenAi,
@@ -86,11 +97,84 @@ impl<'a> EmbeddingRuntime<'a> {
         })
     }
 
-    pub async fn process(
+    pub fn post_process_params(&self) -> &PostProcessParams {
+        match self {
+            EmbeddingRuntime::Cohere(runtime) => &runtime.post_process,
+            EmbeddingRuntime::OpenAi(runtime) => &runtime.post_process,
+            EmbeddingRuntime::Ort(runtime) => &runtime.post_process,
+        }
+    }
+
+    // Batch API requests are completed asynchronously within 24h, so the inputs
+    // of the whole job should be submitted together instead of per worker batch
+    pub fn uses_batch_api(&self) -> bool {
+        match self {
+            EmbeddingRuntime::OpenAi(runtime) => runtime.batch_api,
+            _ => false,
+        }
+    }
+
+    // While waiting for the Batch API results the job can be cancelled via the token
+    // The ids of submitted batches are stored in state, so they are resumed after restart
+    pub fn set_batch_context(
+        &mut self,
+        cancel_token: CancellationToken,
+        state: Option<BatchState>,
+    ) {
+        if let EmbeddingRuntime::OpenAi(runtime) = self {
+            runtime.cancel_token = cancel_token;
+            runtime.batch_state = state;
+        }
+    }
+
+    pub fn truncation_policy(&self) -> TruncationPolicy {
+        match self {
+            EmbeddingRuntime::Cohere(runtime) => runtime.truncation_policy,
+            EmbeddingRuntime::OpenAi(runtime) => runtime.truncation_policy,
+            EmbeddingRuntime::Ort(runtime) => runtime.truncation_policy,
+        }
+    }
+
+    // Returns the token counts of the inputs or None if the runtime
+    // can not tokenize the inputs locally for this model
+    pub async fn count_tokens(
+        &self,
+        model_name: &str,
+        inputs: &Vec<&str>,
+    ) -> Result<Option<TokenCounts>, anyhow::Error> {
+        match self {
+            EmbeddingRuntime::Cohere(_) => Ok(None),
+            EmbeddingRuntime::OpenAi(runtime) => runtime.count_tokens(model_name, inputs).await,
+            EmbeddingRuntime::Ort(runtime) => runtime.count_tokens(model_name, inputs).await,
+        }
+    }
+
+    async fn split_by_tokens(
+        &self,
+        model_name: &str,
+        input: &str,
+    ) -> Result<Vec<String>, anyhow::Error> {
+        match self {
+            EmbeddingRuntime::Cohere(_) => Ok(vec![input.to_owned()]),
+            EmbeddingRuntime::OpenAi(runtime) => runtime.split_by_tokens(model_name, input).await,
+            EmbeddingRuntime::Ort(runtime) => runtime.split_by_tokens(model_name, input).await,
+        }
+    }
+
+    async fn process_inputs(
         &self,
         model_name: &str,
         inputs: &Vec<&str>,
     ) -> Result<EmbeddingResult, anyhow::Error> {
+        if inputs.is_empty() {
+            return Ok(EmbeddingResult {
+                embeddings: Vec::new(),
+                processed_tokens: 0,
+                failed_inputs: Vec::new(),
+                truncated_inputs: Vec::new(),
+            });
+        }
+
         match self {
             EmbeddingRuntime::Cohere(runtime) => runtime.process(model_name, inputs).await,
             EmbeddingRuntime::OpenAi(runtime) => runtime.process(model_name, inputs).await,
@@ -98,6 +182,105 @@ impl<'a> EmbeddingRuntime<'a> {
         }
     }
 
+    // Handles the inputs exceeding the max sequence length of the model
+    // based on the truncation_policy from runtime params
+    async fn process_with_truncation_policy(
+        &self,
+        model_name: &str,
+        inputs: &Vec<&str>,
+    ) -> Result<EmbeddingResult, anyhow::Error> {
+        let token_counts = match self.count_tokens(model_name, inputs).await? {
+            Some(token_counts) => token_counts,
+            None => return self.process_inputs(model_name, inputs).await,
+        };
+
+        let oversized = token_counts.get_oversized();
+        if oversized.is_empty() {
+            return self.process_inputs(model_name, inputs).await;
+        }
+
+        let max_tokens = token_counts.max_tokens;
+        match self.truncation_policy() {
+            TruncationPolicy::Truncate => {
+                let mut result = self.process_inputs(model_name, inputs).await?;
+                result.truncated_inputs.extend(
+                    oversized
+                        .into_iter()
+                        .map(|(idx, count)| (idx, get_truncation_message(count, max_tokens))),
+                );
+                Ok(result)
+            }
+            TruncationPolicy::Fail => {
+                let (idx, count) = oversized[0];
+                anyhow::bail!(
+                    "Input at index {idx} has {count} tokens which exceeds the limit of {max_tokens} tokens for model {model_name}"
+                );
+            }
+            TruncationPolicy::Skip => {
+                let skipped: HashSet<usize> = oversized.iter().map(|(idx, _)| *idx).collect();
+                let processed: Vec<usize> = (0..inputs.len())
+                    .filter(|idx| !skipped.contains(idx))
+                    .collect();
+                let result = self
+                    .process_inputs(
+                        model_name,
+                        &processed.iter().map(|idx| inputs[*idx]).collect(),
+                    )
+                    .await?;
+
+                Ok(merge_skipped_result(
+                    inputs.len(),
+                    &processed,
+                    oversized
+                        .into_iter()
+                        .map(|(idx, count)| {
+                            (
+                                idx,
+                                format!("Input was skipped as it has {count} tokens which exceeds the limit of {max_tokens} tokens"),
+                            )
+                        })
+                        .collect(),
+                    result,
+                ))
+            }
+            TruncationPolicy::ChunkAverage => {
+                let oversized: HashSet<usize> = oversized.iter().map(|(idx, _)| *idx).collect();
+                let mut chunks = Vec::with_capacity(inputs.len());
+                let mut owners = Vec::with_capacity(inputs.len());
+                for (idx, input) in inputs.iter().enumerate() {
+                    if oversized.contains(&idx) {
+                        for chunk in self.split_by_tokens(model_name, input).await? {
+                            chunks.push(chunk);
+                            owners.push(idx);
+                        }
+                    } else {
+                        chunks.push(input.to_string());
+                        owners.push(idx);
+                    }
+                }
+
+                let result = self
+                    .process_inputs(model_name, &chunks.iter().map(|s| s.as_str()).collect())
+                    .await?;
+                Ok(merge_chunked_result(inputs.len(), &owners, result))
+            }
+        }
+    }
+
+    // Generates embeddings with the underlying runtime and applies
+    // truncation, normalization and quantization if configured in runtime params
+    pub async fn process(
+        &self,
+        model_name: &str,
+        inputs: &Vec<&str>,
+    ) -> Result<EmbeddingResult, anyhow::Error> {
+        let result = self
+            .process_with_truncation_policy(model_name, inputs)
+            .await?;
+
+        Ok(self.post_process_params().apply(result))
+    }
+
     pub async fn completion(
         &self,
         model_name: &str,
@@ -107,6 +290,9 @@ impl<'a> EmbeddingRuntime<'a> {
             EmbeddingRuntime::OpenAi(runtime) => {
                 runtime.completion(model_name, query, Some(1)).await
             }
+            EmbeddingRuntime::Cohere(runtime) => {
+                runtime.completion(model_name, query, Some(1)).await
+            }
             _ => anyhow::bail!("completion is not available for this runtime"),
         }
     }
@@ -120,6 +306,9 @@ impl<'a> EmbeddingRuntime<'a> {
             EmbeddingRuntime::OpenAi(runtime) => {
                 OpenAiRuntime::batch_completion(Arc::new(runtime), model_name, queries).await
             }
+            EmbeddingRuntime::Cohere(runtime) => {
+                CohereRuntime::batch_completion(Arc::new(runtime), model_name, queries).await
+            }
             _ => anyhow::bail!("completion is not available for this runtime"),
         }
     }
@@ -127,7 +316,7 @@ impl<'a> EmbeddingRuntime<'a> {
     pub async fn get_available_models(
         &self,
         job_type: EmbeddingJobType,
-    ) -> (String, Vec<(String, bool)>) {
+    ) -> (String, Vec<AvailableModel>) {
         match self {
             EmbeddingRuntime::Cohere(runtime) => runtime.get_available_models(job_type).await,
             EmbeddingRuntime::OpenAi(runtime) => runtime.get_available_models(job_type).await,
@@ -136,6 +325,25 @@ impl<'a> EmbeddingRuntime<'a> {
     }
 }
 
+// Counts the tokens of the input with the tokenizer of the model
+// OpenAI models are counted with tiktoken, other models are looked up in ORT runtime
+pub async fn count_tokens(
+    model_name: &str,
+    input: &str,
+    ort_params: &str,
+) -> Result<usize, anyhow::Error> {
+    if OpenAiRuntime::has_model(model_name).await {
+        let token_counts = OpenAiRuntime::count_model_tokens(model_name, &vec![input]).await?;
+        return Ok(token_counts.counts[0]);
+    }
+
+    let runtime = OrtRuntime::new(&(default_logger as LoggerFn), ort_params)?;
+    match runtime.count_tokens(model_name, &vec![input]).await? {
+        Some(token_counts) => Ok(token_counts.counts[0]),
+        None => anyhow::bail!("Model {model_name} does not use tokenizer"),
+    }
+}
+
 pub fn get_available_runtimes() -> Vec<String> {
     Runtime::iter().map(|e| e.to_string()).collect()
 }
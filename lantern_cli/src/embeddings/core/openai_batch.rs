@@ -0,0 +1,402 @@
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use tokio_postgres::NoTls;
+use tokio_util::sync::CancellationToken;
+use url::Url;
+
+use super::LoggerFn;
+use crate::types::{AnyhowVoidResult, JOB_CANCELLED_MESSAGE};
+
+// Helpers for OpenAI Batch API (https://platform.openai.com/docs/guides/batch)
+// The requests are uploaded as JSONL file, processed asynchronously by OpenAI
+// and the results are downloaded as JSONL file once the batch is completed
+
+#[derive(Deserialize, Debug)]
+struct OpenAiFile {
+    id: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct OpenAiBatch {
+    id: String,
+    status: String,
+    created_at: Option<u64>,
+    output_file_id: Option<String>,
+    error_file_id: Option<String>,
+    errors: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize, Debug)]
+struct OpenAiBatchResponse {
+    status_code: u16,
+    body: serde_json::Value,
+}
+
+#[derive(Deserialize, Debug)]
+struct OpenAiBatchOutput {
+    custom_id: String,
+    response: Option<OpenAiBatchResponse>,
+    error: Option<serde_json::Value>,
+}
+
+// Job row on which the ids of submitted batches are stored by the hash of the batch input
+// so if the daemon is restarted the batch will be resumed instead of being submitted
+// (and billed) again
+#[derive(Debug, Clone)]
+pub struct BatchState {
+    pub uri: String,
+    pub table: String,
+    pub job_id: i32,
+}
+
+impl BatchState {
+    async fn connect(&self) -> Result<tokio_postgres::Client, anyhow::Error> {
+        let (client, connection) = tokio_postgres::connect(&self.uri, NoTls).await?;
+        tokio::spawn(async move { connection.await });
+        Ok(client)
+    }
+
+    async fn get_batch_id(&self, key: &str) -> Result<Option<String>, anyhow::Error> {
+        let row = self
+            .connect()
+            .await?
+            .query_opt(
+                &format!(
+                    "SELECT batch_ids->>$1 FROM {table} WHERE id=$2",
+                    table = self.table
+                ),
+                &[&key, &self.job_id],
+            )
+            .await?;
+
+        Ok(row.and_then(|row| row.get::<usize, Option<String>>(0)))
+    }
+
+    async fn set_batch_id(&self, key: &str, batch_id: &str) -> AnyhowVoidResult {
+        self.connect()
+            .await?
+            .execute(
+                &format!(
+                    "UPDATE {table} SET batch_ids=COALESCE(batch_ids, '{{}}'::jsonb) || jsonb_build_object($1::text, $2::text) WHERE id=$3",
+                    table = self.table
+                ),
+                &[&key, &batch_id, &self.job_id],
+            )
+            .await?;
+        Ok(())
+    }
+
+    // Job is cancelled when canceled_at is set or the job is removed
+    async fn is_job_canceled(&self) -> Result<bool, anyhow::Error> {
+        let row = self
+            .connect()
+            .await?
+            .query_opt(
+                &format!(
+                    "SELECT canceled_at IS NOT NULL FROM {table} WHERE id=$1",
+                    table = self.table
+                ),
+                &[&self.job_id],
+            )
+            .await?;
+
+        Ok(row.map(|row| row.get::<usize, bool>(0)).unwrap_or(true))
+    }
+
+    async fn remove_batch_id(&self, key: &str) -> AnyhowVoidResult {
+        self.connect()
+            .await?
+            .execute(
+                &format!(
+                    "UPDATE {table} SET batch_ids=batch_ids - $1::text WHERE id=$2",
+                    table = self.table
+                ),
+                &[&key, &self.job_id],
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+// Options of waiting for the batch to finish
+pub struct BatchWaitOptions<'b> {
+    pub poll_interval: Duration,
+    // The batch is cancelled if it is not finished in this time after creation
+    pub max_wait: Duration,
+    pub state: Option<&'b BatchState>,
+    pub cancel_token: &'b CancellationToken,
+}
+
+fn get_error_message(error: &serde_json::Value) -> String {
+    match error["message"].as_str() {
+        Some(message) => message.to_owned(),
+        None => error.to_string(),
+    }
+}
+
+async fn get_json<T: for<'de> Deserialize<'de>>(
+    response: reqwest::Response,
+) -> Result<T, anyhow::Error> {
+    let status = response.status();
+    let body = response.bytes().await?;
+
+    if !status.is_success() {
+        anyhow::bail!(
+            "OpenAI Batch API request failed with status {status}: {}",
+            String::from_utf8_lossy(&body)
+        );
+    }
+
+    Ok(serde_json::from_slice(&body)?)
+}
+
+async fn get_file_content(
+    client: &reqwest::Client,
+    base_url: &Url,
+    file_id: &str,
+) -> Result<String, anyhow::Error> {
+    let response = client
+        .get(base_url.join(&format!("/v1/files/{file_id}/content"))?)
+        .send()
+        .await?;
+    let status = response.status();
+    let body = response.text().await?;
+
+    if !status.is_success() {
+        anyhow::bail!("Could not download batch file {file_id}: {body}");
+    }
+
+    Ok(body)
+}
+
+async fn create_batch(
+    client: &reqwest::Client,
+    base_url: &Url,
+    endpoint: &str,
+    input_file: String,
+) -> Result<OpenAiBatch, anyhow::Error> {
+    let form = reqwest::multipart::Form::new()
+        .text("purpose", "batch")
+        .part(
+            "file",
+            reqwest::multipart::Part::bytes(input_file.into_bytes())
+                .file_name("batch.jsonl")
+                .mime_str("application/jsonl")?,
+        );
+
+    let file: OpenAiFile = get_json(
+        client
+            .post(base_url.join("/v1/files")?)
+            .multipart(form)
+            .send()
+            .await?,
+    )
+    .await?;
+
+    get_json(
+        client
+            .post(base_url.join("/v1/batches")?)
+            .header("Content-Type", "application/json")
+            .body(
+                json!({
+                    "input_file_id": file.id,
+                    "endpoint": endpoint,
+                    "completion_window": "24h"
+                })
+                .to_string(),
+            )
+            .send()
+            .await?,
+    )
+    .await
+}
+
+async fn get_batch(
+    client: &reqwest::Client,
+    base_url: &Url,
+    batch_id: &str,
+) -> Result<OpenAiBatch, anyhow::Error> {
+    get_json(
+        client
+            .get(base_url.join(&format!("/v1/batches/{batch_id}"))?)
+            .send()
+            .await?,
+    )
+    .await
+}
+
+async fn cancel_batch(
+    client: &reqwest::Client,
+    base_url: &Url,
+    batch_id: &str,
+) -> Result<OpenAiBatch, anyhow::Error> {
+    get_json(
+        client
+            .post(base_url.join(&format!("/v1/batches/{batch_id}/cancel"))?)
+            .send()
+            .await?,
+    )
+    .await
+}
+
+// Runs the request bodies against the endpoint (e.g /v1/chat/completions) via Batch API
+// and returns the response body or the error message for each request in the same order
+pub async fn run_batch(
+    client: &reqwest::Client,
+    base_url: &str,
+    endpoint: &str,
+    bodies: Vec<serde_json::Value>,
+    options: BatchWaitOptions<'_>,
+    logger: &LoggerFn,
+) -> Result<Vec<Result<Vec<u8>, String>>, anyhow::Error> {
+    let base_url = Url::parse(base_url)?;
+    let request_cnt = bodies.len();
+
+    let input_file = bodies
+        .into_iter()
+        .enumerate()
+        .map(|(idx, body)| {
+            json!({
+                "custom_id": idx.to_string(),
+                "method": "POST",
+                "url": endpoint,
+                "body": body
+            })
+            .to_string()
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+    let state_key = format!("{:x}", Sha256::digest(input_file.as_bytes()));
+
+    let mut batch = None;
+    if let Some(state) = options.state {
+        if let Some(batch_id) = state.get_batch_id(&state_key).await? {
+            match get_batch(client, &base_url, &batch_id).await {
+                Ok(existing_batch) => {
+                    logger(&format!("Resuming batch {batch_id}"));
+                    batch = Some(existing_batch);
+                }
+                Err(e) => logger(&format!("Could not resume batch {batch_id}: {e}")),
+            }
+        }
+    }
+
+    let mut batch = match batch {
+        Some(batch) => batch,
+        None => {
+            if options.cancel_token.is_cancelled() {
+                anyhow::bail!(JOB_CANCELLED_MESSAGE);
+            }
+
+            let batch = create_batch(client, &base_url, endpoint, input_file).await?;
+            logger(&format!(
+                "Created batch {} with {request_cnt} requests",
+                batch.id
+            ));
+
+            if let Some(state) = options.state {
+                state.set_batch_id(&state_key, &batch.id).await?;
+            }
+            batch
+        }
+    };
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let waited = now.saturating_sub(batch.created_at.unwrap_or(now));
+    let deadline =
+        tokio::time::Instant::now() + options.max_wait.saturating_sub(Duration::from_secs(waited));
+
+    loop {
+        match batch.status.as_str() {
+            "completed" | "expired" | "cancelled" => break,
+            "failed" => {
+                if let Some(state) = options.state {
+                    state.remove_batch_id(&state_key).await?;
+                }
+                anyhow::bail!(
+                    "Batch {} failed: {}",
+                    batch.id,
+                    batch.errors.unwrap_or_default()
+                )
+            }
+            _ => {}
+        }
+
+        let mut error = tokio::select! {
+            biased;
+            _ = options.cancel_token.cancelled() => Some(JOB_CANCELLED_MESSAGE.to_owned()),
+            _ = tokio::time::sleep_until(deadline) => Some(format!(
+                "Batch {} did not finish in {}s",
+                batch.id,
+                options.max_wait.as_secs()
+            )),
+            _ = tokio::time::sleep(options.poll_interval) => None,
+        };
+
+        if let (None, Some(state)) = (&error, options.state) {
+            if state.is_job_canceled().await? {
+                error = Some(JOB_CANCELLED_MESSAGE.to_owned());
+            }
+        }
+
+        if let Some(error) = error {
+            // Cancel the batch, so the requests which are not processed yet will not be billed
+            if let Err(e) = cancel_batch(client, &base_url, &batch.id).await {
+                logger(&format!("Could not cancel batch {}: {e}", batch.id));
+            }
+            if let Some(state) = options.state {
+                state.remove_batch_id(&state_key).await?;
+            }
+            anyhow::bail!(error);
+        }
+
+        batch = get_batch(client, &base_url, &batch.id).await?;
+    }
+
+    logger(&format!(
+        "Batch {} finished with status {}",
+        batch.id, batch.status
+    ));
+
+    let mut results: HashMap<usize, Result<Vec<u8>, String>> = HashMap::new();
+    for file_id in [&batch.output_file_id, &batch.error_file_id]
+        .into_iter()
+        .flatten()
+    {
+        let content = get_file_content(client, &base_url, file_id).await?;
+        for line in content.lines().filter(|line| line.trim() != "") {
+            let output: OpenAiBatchOutput = serde_json::from_str(line)?;
+            let idx: usize = output.custom_id.parse()?;
+            let result = match (output.response, output.error) {
+                (Some(response), _) if response.status_code == 200 => {
+                    Ok(serde_json::to_vec(&response.body)?)
+                }
+                (Some(response), _) => Err(format!(
+                    "Request failed with status {}: {}",
+                    response.status_code,
+                    get_error_message(&response.body["error"])
+                )),
+                (None, Some(error)) => Err(get_error_message(&error)),
+                (None, None) => Err("Empty batch response".to_owned()),
+            };
+            results.insert(idx, result);
+        }
+    }
+
+    if let Some(state) = options.state {
+        state.remove_batch_id(&state_key).await?;
+    }
+
+    Ok((0..request_cnt)
+        .map(|idx| {
+            results.remove(&idx).unwrap_or(Err(format!(
+                "No result returned for request in batch {} (status: {})",
+                batch.id, batch.status
+            )))
+        })
+        .collect())
+}
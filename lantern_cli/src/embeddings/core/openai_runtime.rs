@@ -1,14 +1,25 @@
 use itertools::Itertools;
-use regex::Regex;
 use serde_json::json;
 use std::collections::HashMap;
 use tokio::sync::RwLock;
+use tokio_util::sync::CancellationToken;
 
+use super::azure_openai::{AzureDeployment, AzureParams, EntraCredentials};
+use super::openai_batch;
+use super::post_process::PostProcessParams;
+use super::truncation::{TokenCounts, TruncationPolicy};
+use super::utils::{get_response_validator, validate_json_response};
 use super::{
-    runtime::{BatchCompletionResult, CompletionResult, EmbeddingResult, EmbeddingRuntimeT},
+    runtime::{
+        AvailableModel, BatchCompletionResult, CompletionResult, EmbeddingResult, EmbeddingRuntimeT,
+    },
     LoggerFn,
 };
-use crate::{check_and_get_model, embeddings::cli::EmbeddingJobType, HTTPRuntime};
+use crate::{
+    check_and_get_model,
+    embeddings::{cli::EmbeddingJobType, get_default_batch_size},
+    HTTPRuntime,
+};
 use serde::{Deserialize, Serialize};
 use tiktoken_rs::{cl100k_base, CoreBPE};
 
@@ -60,17 +71,37 @@ struct OpenAiChatChoice {
 #[derive(Deserialize, Debug)]
 struct OpenAiChatResponse {
     choices: Vec<OpenAiChatChoice>,
-    usage: OpenAiUsage,
+    // Some OpenAI compatible servers (e.g. local LLM servers)
+    // do not return usage information
+    usage: Option<OpenAiUsage>,
 }
 
 #[derive(PartialEq, Debug)]
 enum OpenAiDeployment {
-    Azure,
+    Azure(AzureDeployment),
     OpenAi,
     Custom,
 }
 
-static AZURE_OPENAI_REGEX: &'static str = r"^https:\/\/[a-zA-Z0-9_\-]+\.openai\.azure\.com\/openai\/deployments\/[a-zA-Z0-9_\-]+\/embeddings\?api-version=2023-05-15$";
+static OPENAI_API_HOST: &'static str = "api.openai.com";
+// Batches are completed within 24h, so wait a bit longer before cancelling
+const DEFAULT_BATCH_MAX_WAIT: u64 = 25 * 60 * 60;
+
+// OpenAI compatible servers running locally or in the private network
+// (e.g. Ollama, vLLM or llama.cpp servers) may not require authorization
+pub fn is_local_base_url(base_url: &str) -> bool {
+    let url = match url::Url::parse(base_url) {
+        Ok(url) => url,
+        Err(_) => return false,
+    };
+
+    match url.host() {
+        Some(url::Host::Domain(domain)) => domain == "localhost" || domain.ends_with(".localhost"),
+        Some(url::Host::Ipv4(ip)) => ip.is_loopback() || ip.is_private(),
+        Some(url::Host::Ipv6(ip)) => ip.is_loopback(),
+        None => false,
+    }
+}
 
 impl ModelInfo {
     pub fn new(model_name: &str, job_type: EmbeddingJobType) -> Result<Self, anyhow::Error> {
@@ -129,6 +160,20 @@ impl ModelInfo {
                     dimensions: 0,
                     var_dimension: false,
                 }),
+                "gpt-4.1" | "gpt-4.1-mini" | "gpt-4.1-nano" => Ok(Self {
+                    name,
+                    tokenizer: cl100k_base()?,
+                    sequence_len: 1047576,
+                    dimensions: 0,
+                    var_dimension: false,
+                }),
+                "gpt-3.5-turbo" => Ok(Self {
+                    name,
+                    tokenizer: cl100k_base()?,
+                    sequence_len: 16385,
+                    dimensions: 0,
+                    var_dimension: false,
+                }),
                 _ => anyhow::bail!("Unsupported model {model_name}"),
             },
         }
@@ -181,6 +226,22 @@ lazy_static! {
                 "gpt-4o-mini",
                 ModelInfo::new("gpt-4o-mini", EmbeddingJobType::Completion).unwrap()
             ),
+            (
+                "gpt-4.1",
+                ModelInfo::new("gpt-4.1", EmbeddingJobType::Completion).unwrap()
+            ),
+            (
+                "gpt-4.1-mini",
+                ModelInfo::new("gpt-4.1-mini", EmbeddingJobType::Completion).unwrap()
+            ),
+            (
+                "gpt-4.1-nano",
+                ModelInfo::new("gpt-4.1-nano", EmbeddingJobType::Completion).unwrap()
+            ),
+            (
+                "gpt-3.5-turbo",
+                ModelInfo::new("gpt-3.5-turbo", EmbeddingJobType::Completion).unwrap()
+            ),
         ]));
 }
 
@@ -189,8 +250,19 @@ pub struct OpenAiRuntime<'a> {
     base_url: String,
     headers: Vec<(String, String)>,
     system_prompt: serde_json::Value,
+    response_schema: Option<serde_json::Value>,
+    response_validator: Option<Arc<jsonschema::Validator>>,
+    native_response_format: bool,
     dimensions: Option<usize>,
     deployment_type: OpenAiDeployment,
+    azure_entra_credentials: Option<EntraCredentials>,
+    pub(super) batch_api: bool,
+    batch_poll_interval: u64,
+    batch_max_wait: u64,
+    pub(super) batch_state: Option<openai_batch::BatchState>,
+    pub(super) cancel_token: CancellationToken,
+    pub(super) post_process: PostProcessParams,
+    pub(super) truncation_policy: TruncationPolicy,
     #[allow(dead_code)]
     logger: &'a LoggerFn,
 }
@@ -202,67 +274,136 @@ pub struct OpenAiRuntimeParams {
     pub azure_entra_token: Option<String>,
     pub system_prompt: Option<String>,
     pub dimensions: Option<usize>,
+    pub response_schema: Option<serde_json::Value>,
+    // Pass response_schema to the server as `response_format`, by default it is passed only to
+    // OpenAI and Azure, as OpenAI compatible servers may reject unknown fields.
+    // Otherwise the schema is added to the system prompt and the response is validated locally
+    pub native_response_format: Option<bool>,
+    // Send the requests via Batch API instead of synchronous requests
+    pub batch_api: Option<bool>,
+    // Interval in seconds to check the batch status
+    pub batch_poll_interval: Option<u64>,
+    // Maximum time in seconds to wait for the batch to finish, after that the batch is cancelled
+    pub batch_max_wait: Option<u64>,
+    // Azure OpenAI specific options, if set the requests are sent
+    // in Azure format even if base_url is a custom domain (e.g. API Management gateway)
+    pub azure: Option<AzureParams>,
 }
 
 impl<'a> OpenAiRuntime<'a> {
     pub fn new(logger: &'a LoggerFn, params: &'a str) -> Result<Self, anyhow::Error> {
         let runtime_params: OpenAiRuntimeParams = serde_json::from_str(&params)?;
 
-        let (deployment, base_url) = Self::get_base_url(&runtime_params.base_url)?;
+        let (deployment, base_url) =
+            Self::get_base_url(&runtime_params.base_url, &runtime_params.azure)?;
+        let batch_api = runtime_params.batch_api.unwrap_or(false);
+
+        if batch_api && matches!(deployment, OpenAiDeployment::Azure(_)) {
+            anyhow::bail!("'batch_api' is not supported for Azure OpenAi runtime");
+        }
+
+        let azure_entra_credentials = match &runtime_params.azure {
+            Some(azure_params) => EntraCredentials::from_params(azure_params)?,
+            None => None,
+        };
 
         let auth_header = match deployment {
-            OpenAiDeployment::OpenAi | OpenAiDeployment::Custom => {
+            OpenAiDeployment::OpenAi => {
                 if runtime_params.api_token.is_none() {
                     anyhow::bail!("'api_token' is required for OpenAi runtime");
                 }
-                (
+                Some((
                     "Authorization".to_owned(),
                     format!("Bearer {}", runtime_params.api_token.unwrap()),
-                )
+                ))
             }
-            OpenAiDeployment::Azure => {
+            OpenAiDeployment::Custom => {
+                if runtime_params.api_token.is_none() && !is_local_base_url(&base_url) {
+                    anyhow::bail!(
+                        "'api_token' is required for OpenAi runtime unless 'base_url' is a local server"
+                    );
+                }
+                runtime_params
+                    .api_token
+                    .map(|token| ("Authorization".to_owned(), format!("Bearer {token}")))
+            }
+            OpenAiDeployment::Azure(_) => {
                 // https://learn.microsoft.com/en-us/azure/ai-services/openai/reference
-                if runtime_params.api_token.is_none() && runtime_params.azure_entra_token.is_none()
+                if runtime_params.api_token.is_none()
+                    && runtime_params.azure_entra_token.is_none()
+                    && azure_entra_credentials.is_none()
                 {
                     anyhow::bail!(
-                        "'api_token' or 'azure_entra_id' is required for Azure OpenAi runtime"
+                        "'api_token', 'azure_entra_token' or Entra client credentials are required for Azure OpenAi runtime"
                     );
                 }
 
                 if let Some(key) = runtime_params.api_token {
-                    ("api-key".to_owned(), format!("{}", key))
-                } else {
-                    (
+                    Some(("api-key".to_owned(), format!("{}", key)))
+                } else if let Some(entra_token) = runtime_params.azure_entra_token {
+                    Some((
                         "Authorization".to_owned(),
-                        format!("Bearer {}", runtime_params.azure_entra_token.unwrap()),
-                    )
+                        format!("Bearer {}", entra_token),
+                    ))
+                } else {
+                    // Authorization header is added on each request with the refreshed token
+                    None
                 }
             }
         };
 
+        let mut headers = vec![("Content-Type".to_owned(), "application/json".to_owned())];
+        if let Some(auth_header) = auth_header {
+            headers.push(auth_header);
+        }
+
         let system_prompt = match &runtime_params.system_prompt {
             Some(system_prompt) => json!({ "role": "system", "content": system_prompt.clone()}),
             None => json!({ "role": "system", "content": "" }),
         };
 
+        let response_validator = get_response_validator(&runtime_params.response_schema)?;
+        let native_response_format = runtime_params
+            .native_response_format
+            .unwrap_or(deployment != OpenAiDeployment::Custom);
+        let truncation_policy = match deployment {
+            OpenAiDeployment::Custom => TruncationPolicy::from_params(params)?
+                .require_tokenizer("custom OpenAI deployments")?,
+            _ => TruncationPolicy::from_params(params)?,
+        };
+
         Ok(Self {
             base_url,
             logger,
             request_timeout: 120,
             deployment_type: deployment,
-            headers: vec![
-                ("Content-Type".to_owned(), "application/json".to_owned()),
-                auth_header,
-            ],
+            azure_entra_credentials,
+            batch_api,
+            batch_poll_interval: runtime_params.batch_poll_interval.unwrap_or(60),
+            batch_max_wait: runtime_params
+                .batch_max_wait
+                .unwrap_or(DEFAULT_BATCH_MAX_WAIT),
+            batch_state: None,
+            cancel_token: CancellationToken::new(),
+            headers,
             dimensions: runtime_params.dimensions,
             system_prompt,
+            response_schema: runtime_params.response_schema,
+            response_validator,
+            native_response_format,
+            post_process: PostProcessParams::from_params(params)?,
+            truncation_policy,
         })
     }
 
     fn get_base_url(
         base_url: &Option<String>,
+        azure_params: &Option<AzureParams>,
     ) -> Result<(OpenAiDeployment, String), anyhow::Error> {
         if base_url.is_none() {
+            if azure_params.is_some() {
+                anyhow::bail!("'base_url' is required for Azure OpenAi runtime");
+            }
             return Ok((
                 OpenAiDeployment::OpenAi,
                 "https://api.openai.com".to_owned(),
@@ -270,15 +411,62 @@ impl<'a> OpenAiRuntime<'a> {
         }
 
         let base_url = base_url.as_ref().unwrap();
-        let azure_openai_re = Regex::new(AZURE_OPENAI_REGEX).unwrap();
 
-        if azure_openai_re.is_match(base_url) {
-            return Ok((OpenAiDeployment::Azure, base_url.clone()));
+        if azure_params.is_some() || AzureDeployment::is_azure_url(base_url) {
+            let deployment =
+                AzureDeployment::new(base_url, &azure_params.clone().unwrap_or_default())?;
+            return Ok((OpenAiDeployment::Azure(deployment), base_url.clone()));
+        }
+
+        let is_openai_url =
+            url::Url::parse(base_url).is_ok_and(|url| url.host_str() == Some(OPENAI_API_HOST));
+        if is_openai_url {
+            return Ok((OpenAiDeployment::OpenAi, base_url.clone()));
         }
 
         return Ok((OpenAiDeployment::Custom, base_url.clone()));
     }
 
+    // Returns the endpoint for the request, Azure deployments have separate urls for each model
+    // As Url::join keeps absolute urls as is, the result can be joined to base_url
+    fn get_endpoint(&self, model_name: &str, job_type: EmbeddingJobType) -> String {
+        match &self.deployment_type {
+            OpenAiDeployment::Azure(deployment) => deployment.get_url(model_name, &job_type),
+            _ => match job_type {
+                EmbeddingJobType::EmbeddingGeneration => "/v1/embeddings".to_owned(),
+                EmbeddingJobType::Completion => "/v1/chat/completions".to_owned(),
+            },
+        }
+    }
+
+    // Returns the OpenAI model name to get the token limits from,
+    // for Azure the model name can be a deployment name mapped to the model
+    fn get_model_name<'b>(&'b self, model_name: &'b str) -> &'b str {
+        match &self.deployment_type {
+            OpenAiDeployment::Azure(deployment) => deployment.get_model_name(model_name),
+            _ => model_name,
+        }
+    }
+
+    fn get_headers(&self) -> Vec<(String, String)> {
+        let mut headers = self.headers.clone();
+        if let Some(credentials) = &self.azure_entra_credentials {
+            if let Some(token) = credentials.get_cached_token(Duration::ZERO) {
+                headers.push(("Authorization".to_owned(), format!("Bearer {token}")));
+            }
+        }
+        headers
+    }
+
+    // Entra tokens are valid for about an hour, so they are refreshed
+    // before the requests to keep long running jobs working
+    async fn refresh_entra_token(&self) -> Result<(), anyhow::Error> {
+        if let Some(credentials) = &self.azure_entra_credentials {
+            credentials.refresh_token().await?;
+        }
+        Ok(())
+    }
+
     fn group_vectors_by_token_count(
         &self,
         input: Vec<Vec<u32>>,
@@ -330,7 +518,7 @@ impl<'a> OpenAiRuntime<'a> {
         };
 
         let model_map = MODEL_INFO_MAP.read().await;
-        let model_info = check_and_get_model!(model_map, model_name);
+        let model_info = check_and_get_model!(model_map, self.get_model_name(model_name));
         let token_groups: Vec<Vec<u32>> = inputs
             .iter()
             .map(|input| {
@@ -370,27 +558,139 @@ impl<'a> OpenAiRuntime<'a> {
         Ok(batch_tokens)
     }
 
+    // Returns the token counts of the inputs or None for custom deployments
+    // as the tokenizer of the served model is not known
+    pub async fn count_tokens(
+        &self,
+        model_name: &str,
+        inputs: &Vec<&str>,
+    ) -> Result<Option<TokenCounts>, anyhow::Error> {
+        if self.deployment_type == OpenAiDeployment::Custom {
+            return Ok(None);
+        }
+
+        Ok(Some(
+            Self::count_model_tokens(self.get_model_name(model_name), inputs).await?,
+        ))
+    }
+
+    pub async fn count_model_tokens(
+        model_name: &str,
+        inputs: &Vec<&str>,
+    ) -> Result<TokenCounts, anyhow::Error> {
+        let model_map = MODEL_INFO_MAP.read().await;
+        let model_info = check_and_get_model!(model_map, model_name);
+
+        Ok(TokenCounts {
+            counts: inputs
+                .iter()
+                .map(|input| model_info.tokenizer.encode_with_special_tokens(input).len())
+                .collect(),
+            max_tokens: model_info.sequence_len,
+        })
+    }
+
+    pub async fn has_model(model_name: &str) -> bool {
+        MODEL_INFO_MAP.read().await.contains_key(model_name)
+    }
+
+    // Splits the input into chunks which fit into the max sequence length of the model
+    pub async fn split_by_tokens(
+        &self,
+        model_name: &str,
+        input: &str,
+    ) -> Result<Vec<String>, anyhow::Error> {
+        if self.deployment_type == OpenAiDeployment::Custom {
+            return Ok(vec![input.to_owned()]);
+        }
+
+        let model_map = MODEL_INFO_MAP.read().await;
+        let model_info = check_and_get_model!(model_map, self.get_model_name(model_name));
+        let tokens = model_info.tokenizer.encode_with_special_tokens(input);
+
+        let mut chunks = Vec::new();
+        let mut start = 0;
+        while start < tokens.len() {
+            let mut end = std::cmp::min(start + model_info.sequence_len, tokens.len());
+            // Chunk boundary may split a multi-byte character between tokens
+            // so we move it back until the chunk can be decoded
+            loop {
+                match model_info.tokenizer.decode(tokens[start..end].to_vec()) {
+                    Ok(chunk) => {
+                        chunks.push(chunk);
+                        break;
+                    }
+                    Err(_) if end - start > 1 => end -= 1,
+                    Err(e) => anyhow::bail!("Could not split input into chunks: {e}"),
+                }
+            }
+            start = end;
+        }
+
+        Ok(chunks)
+    }
+
+    fn get_completion_body(&self, model_name: &str, query: &str) -> serde_json::Value {
+        let mut system_prompt = self.system_prompt.clone();
+
+        let response_schema = match &self.response_schema {
+            Some(response_schema) if !self.native_response_format => {
+                let content = system_prompt["content"].as_str().unwrap_or("");
+                system_prompt["content"] = json!(format!(
+                    "{content}\nRespond only with JSON matching this JSON schema: {response_schema}"
+                )
+                .trim_start());
+                None
+            }
+            response_schema => response_schema.as_ref(),
+        };
+
+        let mut body = json!({
+        "model": model_name,
+        "messages": [
+          system_prompt,
+          { "role": "user", "content": query }
+        ]
+        });
+
+        if let Some(response_schema) = response_schema {
+            // https://platform.openai.com/docs/guides/structured-outputs
+            body["response_format"] = json!({
+                "type": "json_schema",
+                "json_schema": { "name": "response", "schema": response_schema }
+            });
+        }
+
+        body
+    }
+
     pub async fn completion(
         &self,
         model_name: &str,
         query: &str,
         retries: Option<usize>,
     ) -> Result<CompletionResult, anyhow::Error> {
+        self.refresh_entra_token().await?;
         let client = Arc::new(self.get_client()?);
         let url = Url::parse(&self.base_url)?
-            .join("/v1/chat/completions")?
+            .join(&self.get_endpoint(model_name, EmbeddingJobType::Completion))?
             .to_string();
+        let body = self.get_completion_body(model_name, query);
+
+        // The response is validated against the schema locally as well,
+        // so schema violations will be retried as failed requests
+        let response_validator = self.response_validator.clone();
         let completion_response: CompletionResult = post_with_retries(
             client,
             url,
-            serde_json::to_string(&json!({
-            "model": model_name,
-            "messages": [
-              self.system_prompt,
-              { "role": "user", "content": query }
-            ]
-            }))?,
-            Box::new(Self::get_completion_response),
+            serde_json::to_string(&body)?,
+            Box::new(move |body| {
+                let mut response = OpenAiRuntime::get_completion_response(body)?;
+                if let Some(validator) = &response_validator {
+                    response.message = validate_json_response(validator, &response.message)?;
+                }
+                Ok(response)
+            }),
             retries.unwrap_or(5),
         )
         .await?;
@@ -405,9 +705,16 @@ impl<'a> OpenAiRuntime<'a> {
     ) -> Result<BatchCompletionResult, anyhow::Error> {
         if self.deployment_type != OpenAiDeployment::Custom {
             let model_map = COMPLETION_MODEL_INFO_MAP.read().await;
-            check_and_get_model!(model_map, model_name);
+            check_and_get_model!(model_map, self.get_model_name(model_name));
         }
 
+        if self.batch_api {
+            return self.batch_api_completion(model_name, queries).await;
+        }
+
+        // Refresh the token once, so concurrent requests will use the cached one
+        self.refresh_entra_token().await?;
+
         let mut processed_tokens = 0;
 
         let completion_futures = queries.into_iter().map(|query| {
@@ -423,19 +730,142 @@ impl<'a> OpenAiRuntime<'a> {
         let results = futures::future::join_all(completion_futures).await;
 
         let mut responses = Vec::with_capacity(results.len());
-        for result in results {
+        let mut failed_inputs = Vec::new();
+        for (idx, result) in results.into_iter().enumerate() {
             match result {
                 Ok(msg) => {
                     processed_tokens += msg.processed_tokens;
                     responses.push(msg.message);
                 }
-                Err(e) => responses.push(format!("Error: {e}")),
+                Err(e) => {
+                    responses.push(format!("Error: {e}"));
+                    failed_inputs.push((idx, e.to_string()));
+                }
             }
         }
 
         Ok(BatchCompletionResult {
             messages: responses,
             processed_tokens,
+            failed_inputs,
+        })
+    }
+
+    async fn run_batch(
+        &self,
+        endpoint: &str,
+        bodies: Vec<serde_json::Value>,
+    ) -> Result<Vec<Result<Vec<u8>, String>>, anyhow::Error> {
+        openai_batch::run_batch(
+            &self.get_client()?,
+            &self.base_url,
+            endpoint,
+            bodies,
+            openai_batch::BatchWaitOptions {
+                poll_interval: Duration::from_secs(self.batch_poll_interval),
+                max_wait: Duration::from_secs(self.batch_max_wait),
+                state: self.batch_state.as_ref(),
+                cancel_token: &self.cancel_token,
+            },
+            self.logger,
+        )
+        .await
+    }
+
+    // Completions via Batch API, failed requests are returned
+    // as error messages the same way as for synchronous requests
+    async fn batch_api_completion(
+        &self,
+        model_name: &str,
+        queries: &Vec<&str>,
+    ) -> Result<BatchCompletionResult, anyhow::Error> {
+        let bodies = queries
+            .iter()
+            .map(|query| self.get_completion_body(model_name, query))
+            .collect();
+
+        let mut processed_tokens = 0;
+        let mut messages = Vec::with_capacity(queries.len());
+        let mut failed_inputs = Vec::new();
+        for (idx, result) in self
+            .run_batch("/v1/chat/completions", bodies)
+            .await?
+            .into_iter()
+            .enumerate()
+        {
+            let response = result.map_err(|e| anyhow::anyhow!(e)).and_then(|body| {
+                let mut response = OpenAiRuntime::get_completion_response(body)?;
+                if let Some(validator) = &self.response_validator {
+                    response.message = validate_json_response(validator, &response.message)?;
+                }
+                Ok(response)
+            });
+
+            match response {
+                Ok(response) => {
+                    processed_tokens += response.processed_tokens;
+                    messages.push(response.message);
+                }
+                Err(e) => {
+                    messages.push(format!("Error: {e}"));
+                    failed_inputs.push((idx, e.to_string()));
+                }
+            }
+        }
+
+        Ok(BatchCompletionResult {
+            messages,
+            processed_tokens,
+            failed_inputs,
+        })
+    }
+
+    async fn batch_api_embeddings(
+        &self,
+        model_name: &str,
+        inputs: &Vec<&str>,
+    ) -> Result<EmbeddingResult, anyhow::Error> {
+        let bodies = self
+            .chunk_inputs(model_name, inputs)
+            .await?
+            .iter()
+            .map(|body| serde_json::from_str(body))
+            .collect::<Result<Vec<serde_json::Value>, serde_json::Error>>()?;
+        // Each request embeds a group of inputs, if the request fails
+        // all inputs of the group are reported as failed
+        let input_counts: Vec<usize> = bodies
+            .iter()
+            .map(|body| body["input"].as_array().map(|a| a.len()).unwrap_or(0))
+            .collect();
+
+        let mut processed_tokens = 0;
+        let mut embeddings = Vec::with_capacity(inputs.len());
+        let mut failed_inputs = Vec::new();
+        let results = self.run_batch("/v1/embeddings", bodies).await?;
+        for (result, input_cnt) in results.into_iter().zip(input_counts) {
+            let response = result
+                .map_err(|e| anyhow::anyhow!("Batch request failed: {e}"))
+                .and_then(OpenAiRuntime::get_response);
+
+            match response {
+                Ok(response) => {
+                    processed_tokens += response.processed_tokens;
+                    embeddings.extend(response.embeddings);
+                }
+                Err(e) => {
+                    for _ in 0..input_cnt {
+                        failed_inputs.push((embeddings.len(), e.to_string()));
+                        embeddings.push(Vec::new());
+                    }
+                }
+            }
+        }
+
+        Ok(EmbeddingResult {
+            embeddings,
+            processed_tokens,
+            failed_inputs,
+            truncated_inputs: Vec::new(),
         })
     }
 
@@ -458,6 +888,8 @@ impl<'a> OpenAiRuntime<'a> {
                 .iter()
                 .map(|emb| emb.embedding.clone())
                 .collect(),
+            failed_inputs: Vec::new(),
+            truncated_inputs: Vec::new(),
         })
     }
 
@@ -473,7 +905,7 @@ impl<'a> OpenAiRuntime<'a> {
         let result = result.unwrap();
 
         Ok(CompletionResult {
-            processed_tokens: result.usage.total_tokens,
+            processed_tokens: result.usage.map(|u| u.total_tokens).unwrap_or(0),
             message: result
                 .choices
                 .first()
@@ -493,14 +925,23 @@ impl<'a> EmbeddingRuntimeT for OpenAiRuntime<'a> {
         model_name: &str,
         inputs: &Vec<&str>,
     ) -> Result<EmbeddingResult, anyhow::Error> {
-        self.post_request("/v1/embeddings", model_name, inputs)
-            .await
+        if self.batch_api {
+            return self.batch_api_embeddings(model_name, inputs).await;
+        }
+
+        self.refresh_entra_token().await?;
+        self.post_request(
+            &self.get_endpoint(model_name, EmbeddingJobType::EmbeddingGeneration),
+            model_name,
+            inputs,
+        )
+        .await
     }
 
     async fn get_available_models(
         &self,
         job_type: EmbeddingJobType,
-    ) -> (String, Vec<(String, bool)>) {
+    ) -> (String, Vec<AvailableModel>) {
         let map = match job_type {
             EmbeddingJobType::EmbeddingGeneration => MODEL_INFO_MAP.read().await,
             EmbeddingJobType::Completion => COMPLETION_MODEL_INFO_MAP.read().await,
@@ -508,12 +949,20 @@ impl<'a> EmbeddingRuntimeT for OpenAiRuntime<'a> {
 
         let mut res = String::new();
         let mut models = Vec::with_capacity(map.len());
-        for (key, value) in &*map {
+        for (key, value) in map.iter().sorted_by_key(|(key, _)| **key) {
             res.push_str(&format!(
                 "{} - sequence_len: {}, dimensions: {}\n",
                 key, value.sequence_len, value.dimensions
             ));
-            models.push((key.to_string(), false));
+            models.push(AvailableModel {
+                name: key.to_string(),
+                // Completion models do not have dimensions
+                dimensions: Some(value.dimensions).filter(|d| *d > 0),
+                max_sequence_length: Some(value.sequence_len),
+                visual: false,
+                downloaded: None,
+                default_batch_size: get_default_batch_size(key),
+            });
         }
 
         return (res, models);
@@ -2,6 +2,12 @@ use futures::StreamExt;
 use image::{imageops::FilterType, GenericImageView, ImageReader};
 use itertools::Itertools;
 use ndarray::{s, Array2, Array4, ArrayBase, Axis, CowArray, CowRepr, Dim, IxDynImpl};
+#[cfg(feature = "image-sources")]
+use object_store::{
+    aws::{AmazonS3, AmazonS3Builder},
+    path::Path as ObjectPath,
+    ObjectStore,
+};
 use ort::session::Session;
 use ort::tensor::ort_owned_tensor::ViewHolder;
 use ort::{Environment, ExecutionProvider, GraphOptimizationLevel, SessionBuilder, Value};
@@ -16,13 +22,16 @@ use std::{
     time::Duration,
 };
 use sysinfo::System;
-use tokenizers::{PaddingParams, Tokenizer, TruncationParams};
+use tokenizers::{Encoding, PaddingDirection, PaddingParams, Tokenizer, TruncationParams};
 use tokio::{fs, sync::Mutex};
 use url::Url;
 
-use crate::embeddings::cli::EmbeddingJobType;
+use crate::check_and_get_model;
+use crate::embeddings::{cli::EmbeddingJobType, get_default_batch_size};
 
-use super::runtime::{EmbeddingResult, EmbeddingRuntimeT};
+use super::post_process::PostProcessParams;
+use super::runtime::{AvailableModel, EmbeddingResult, EmbeddingRuntimeT, InputType};
+use super::truncation::{TokenCounts, TruncationPolicy};
 use super::utils::{download_file, get_available_memory, percent_gpu_memory_used};
 use super::LoggerFn;
 
@@ -157,20 +166,193 @@ pub struct EncoderOptions {
     pub input_image_size: Option<usize>,
 }
 
+// Controls how text inputs are grouped into model runs
+struct BatchingOptions {
+    // Sort inputs by token count and pad each run only to its longest input
+    length_bucketing: bool,
+    // Max number of tokens (including padding) processed in one model run
+    max_batch_tokens: usize,
+}
+
+// Inputs processed in one model run, padded to seq_len
+struct InputBucket {
+    indices: Vec<usize>,
+    seq_len: usize,
+    max_batch_size: usize,
+}
+
+enum EncodingField {
+    Ids,
+    TypeIds,
+    AttentionMask,
+}
+
+impl EncodingField {
+    fn get<'b>(&self, encoding: &'b Encoding) -> &'b [u32] {
+        match self {
+            EncodingField::Ids => encoding.get_ids(),
+            EncodingField::TypeIds => encoding.get_type_ids(),
+            EncodingField::AttentionMask => encoding.get_attention_mask(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+enum ExecutionProviderKind {
+    Cuda,
+    OpenVino,
+    Cpu,
+}
+
+impl ExecutionProviderKind {
+    fn get_provider(&self) -> ExecutionProvider {
+        match self {
+            ExecutionProviderKind::Cuda => ExecutionProvider::CUDA(Default::default()),
+            ExecutionProviderKind::OpenVino => ExecutionProvider::OpenVINO(Default::default()),
+            ExecutionProviderKind::Cpu => ExecutionProvider::CPU(Default::default()),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "lowercase")]
+enum OptimizationLevel {
+    Disable,
+    Level1,
+    Level2,
+    #[default]
+    Level3,
+}
+
+impl OptimizationLevel {
+    fn get_level(&self) -> GraphOptimizationLevel {
+        match self {
+            OptimizationLevel::Disable => GraphOptimizationLevel::Disable,
+            OptimizationLevel::Level1 => GraphOptimizationLevel::Level1,
+            OptimizationLevel::Level2 => GraphOptimizationLevel::Level2,
+            OptimizationLevel::Level3 => GraphOptimizationLevel::Level3,
+        }
+    }
+}
+
+// Precision of the model weights, each variant is stored in a separate onnx file
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "lowercase")]
+enum ModelVariant {
+    #[default]
+    Fp32,
+    Fp16,
+    Int8,
+}
+
+impl ModelVariant {
+    fn get_file_name(&self) -> &'static str {
+        match self {
+            ModelVariant::Fp32 => "model.onnx",
+            ModelVariant::Fp16 => "model_fp16.onnx",
+            ModelVariant::Int8 => "model_int8.onnx",
+        }
+    }
+
+    fn get_name(&self) -> &'static str {
+        match self {
+            ModelVariant::Fp32 => "fp32",
+            ModelVariant::Fp16 => "fp16",
+            ModelVariant::Int8 => "int8",
+        }
+    }
+}
+
+// Variants exported for the text models, other models have only fp32 weights
+const QUANTIZED_VARIANTS: &[ModelVariant] =
+    &[ModelVariant::Fp32, ModelVariant::Fp16, ModelVariant::Int8];
+
+// ORT session settings passed via runtime params
+// Encoders are cached per model and session options, so jobs with different settings
+// do not evict each other's sessions
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct SessionOptions {
+    // Providers in priority order, defaults to cuda, openvino, cpu
+    execution_providers: Option<Vec<ExecutionProviderKind>>,
+    // Defaults to the number of CPUs
+    intra_threads: Option<i16>,
+    inter_threads: Option<i16>,
+    optimization_level: Option<OptimizationLevel>,
+    model_variant: Option<ModelVariant>,
+}
+
+impl SessionOptions {
+    fn get_model_file_name(&self) -> &'static str {
+        self.model_variant.unwrap_or_default().get_file_name()
+    }
+
+    fn get_execution_providers(&self) -> Vec<ExecutionProvider> {
+        match &self.execution_providers {
+            Some(providers) => providers.iter().map(|p| p.get_provider()).collect(),
+            None => vec![
+                ExecutionProvider::CUDA(Default::default()),
+                ExecutionProvider::OpenVINO(Default::default()),
+                ExecutionProvider::CPU(Default::default()),
+            ],
+        }
+    }
+}
+
 pub const DATA_PATH: &'static str = ".ldb_extras_data/";
+const DEFAULT_MAX_BATCH_TOKENS: usize = 65536;
 const MAX_IMAGE_SIZE: u64 = 1024 * 1024 * 20; // 20 MB
 
+// Tokenizer without truncation and padding
+// used to measure the real token count of the inputs
+struct InputTokenizer {
+    tokenizer: Tokenizer,
+    max_tokens: usize,
+}
+
 struct ModelInfo {
-    url: String,
+    base_url: &'static str,
+    // Used only for listing the models
+    dimensions: Option<usize>,
+    sequence_len: Option<usize>,
     params: ModelParams,
     tokenizer_url: Option<String>,
     onnx_data_url: Option<String>,
+    // Quantized variants of the model weights available for download
+    variants: Vec<ModelVariant>,
     encoder_args: EncoderOptions,
-    encoder: Option<EncoderService>,
+    encoders: HashMap<SessionOptions, EncoderService>,
+    input_tokenizer: Option<InputTokenizer>,
+    query_template: Option<&'static str>,
+    document_template: Option<&'static str>,
+}
+
+impl ModelInfo {
+    // Applies the instruction template of the model for the given input type
+    // Templates use {text} placeholder for the input text
+    fn apply_template<'b>(
+        &self,
+        input_type: Option<InputType>,
+        inputs: &Vec<&'b str>,
+    ) -> Option<Vec<String>> {
+        let template = match input_type? {
+            InputType::Query => self.query_template?,
+            InputType::Document => self.document_template?,
+        };
+
+        Some(
+            inputs
+                .iter()
+                .map(|input| template.replace("{text}", input))
+                .collect(),
+        )
+    }
 }
 
 struct ModelInfoBuilder {
     base_url: &'static str,
+    dimensions: Option<usize>,
+    sequence_len: Option<usize>,
     pooling_strategy: Option<PoolingStrategy>,
     use_tokenizer: Option<bool>,
     visual: Option<bool>,
@@ -181,12 +363,17 @@ struct ModelInfoBuilder {
     head_cnt: Option<usize>,
     head_dim: Option<usize>,
     onnx_data: bool,
+    variants: &'static [ModelVariant],
+    query_template: Option<&'static str>,
+    document_template: Option<&'static str>,
 }
 
 impl ModelInfoBuilder {
     fn new(base_url: &'static str) -> Self {
         ModelInfoBuilder {
             base_url,
+            dimensions: None,
+            sequence_len: None,
             pooling_strategy: None,
             use_tokenizer: None,
             visual: None,
@@ -197,6 +384,9 @@ impl ModelInfoBuilder {
             head_cnt: None,
             head_dim: None,
             onnx_data: false,
+            variants: &[ModelVariant::Fp32],
+            query_template: None,
+            document_template: None,
         }
     }
 
@@ -235,13 +425,37 @@ impl ModelInfoBuilder {
         self
     }
 
+    fn with_dimensions(&mut self, dimensions: usize) -> &mut Self {
+        self.dimensions = Some(dimensions);
+        self
+    }
+
+    fn with_sequence_len(&mut self, sequence_len: usize) -> &mut Self {
+        self.sequence_len = Some(sequence_len);
+        self
+    }
+
     fn with_onnx_data(&mut self, status: bool) -> &mut Self {
         self.onnx_data = status;
         self
     }
 
+    fn with_variants(&mut self, variants: &'static [ModelVariant]) -> &mut Self {
+        self.variants = variants;
+        self
+    }
+
+    fn with_query_template(&mut self, template: &'static str) -> &mut Self {
+        self.query_template = Some(template);
+        self
+    }
+
+    fn with_document_template(&mut self, template: &'static str) -> &mut Self {
+        self.document_template = Some(template);
+        self
+    }
+
     fn build(&self) -> ModelInfo {
-        let model_url = format!("{}/model.onnx", self.base_url);
         let mut tokenizer_url = None;
         let mut onnx_data_url = None;
 
@@ -261,8 +475,17 @@ impl ModelInfoBuilder {
             truncation_params: self.truncation_params.clone(),
         };
 
+        // Text models have 512 tokens limit if not specified
+        let sequence_len = if self.use_tokenizer.unwrap_or(false) {
+            Some(self.sequence_len.unwrap_or(512))
+        } else {
+            None
+        };
+
         ModelInfo {
-            url: model_url,
+            base_url: self.base_url,
+            dimensions: self.dimensions,
+            sequence_len,
             tokenizer_url,
             params: ModelParams {
                 layer_cnt: self.layer_cnt.clone(),
@@ -273,43 +496,44 @@ impl ModelInfoBuilder {
                     .clone()
                     .unwrap_or(PoolingStrategy::CLS),
             },
-            encoder: None,
+            encoders: HashMap::new(),
+            input_tokenizer: None,
+            variants: self.variants.to_vec(),
             encoder_args,
             onnx_data_url,
+            query_template: self.query_template,
+            document_template: self.document_template,
         }
     }
 }
 
 lazy_static! {
     static ref MODEL_INFO_MAP: Mutex<HashMap<&'static str, ModelInfo>> = Mutex::new(HashMap::from([
-        ("clip/ViT-B-32-textual", ModelInfoBuilder::new("https://huggingface.co/varik77/onnx-models/resolve/main/openai/ViT-B-32/textual").with_tokenizer(true).build()),
-        ("clip/ViT-B-32-visual", ModelInfoBuilder::new("https://huggingface.co/varik77/onnx-models/resolve/main/openai/ViT-B-32/visual").with_visual(true).with_input_image_size(224).build()),
-        ("BAAI/bge-small-en", ModelInfoBuilder::new("https://huggingface.co/varik77/onnx-models/resolve/main/BAAI/bge-small-en-v1.5").with_tokenizer(true).build()),
-        ("BAAI/bge-base-en", ModelInfoBuilder::new("https://huggingface.co/varik77/onnx-models/resolve/main/BAAI/bge-base-en-v1.5").with_tokenizer(true).build()),
-        ("BAAI/bge-large-en", ModelInfoBuilder::new("https://huggingface.co/varik77/onnx-models/resolve/main/BAAI/bge-large-en-v1.5").with_tokenizer(true).build()),
-        ("BAAI/bge-m3", ModelInfoBuilder::new("https://huggingface.co/varik77/onnx-models/resolve/main/BAAI/bge-m3").with_tokenizer(true).with_onnx_data(true).with_layer_cnt(8).with_head_cnt(4).with_head_dim(64).build()),
-        ("intfloat/e5-base-v2", ModelInfoBuilder::new("https://huggingface.co/varik77/onnx-models/resolve/main/intfloat/e5-base-v2").with_tokenizer(true).build()),
-        ("intfloat/e5-large-v2", ModelInfoBuilder::new("https://huggingface.co/varik77/onnx-models/resolve/main/intfloat/e5-large-v2").with_tokenizer(true).build()),
-        ("llmrails/ember-v1", ModelInfoBuilder::new("https://huggingface.co/varik77/onnx-models/resolve/main/llmrails/ember-v1").with_tokenizer(true).build()),
-        ("thenlper/gte-base", ModelInfoBuilder::new("https://huggingface.co/varik77/onnx-models/resolve/main/thenlper/gte-base").with_tokenizer(true).build()),
-        ("thenlper/gte-large", ModelInfoBuilder::new("https://huggingface.co/varik77/onnx-models/resolve/main/thenlper/gte-large").with_tokenizer(true).build()),
-        ("microsoft/all-MiniLM-L12-v2", ModelInfoBuilder::new("https://huggingface.co/varik77/onnx-models/resolve/main/microsoft/all-MiniLM-L12-v2").with_tokenizer(true).build()),
-        ("microsoft/all-mpnet-base-v2", ModelInfoBuilder::new("https://huggingface.co/varik77/onnx-models/resolve/main/microsoft/all-mpnet-base-v2").with_tokenizer(true).build()),
-        ("transformers/multi-qa-mpnet-base-dot-v1", ModelInfoBuilder::new("https://huggingface.co/varik77/onnx-models/resolve/main/transformers/multi-qa-mpnet-base-dot-v1").with_tokenizer(true).build()),
-        ("jinaai/jina-embeddings-v2-small-en", ModelInfoBuilder::new("https://huggingface.co/varik77/onnx-models/resolve/main/jinaai/jina-embeddings-v2-small-en").with_tokenizer(true).with_layer_cnt(4).with_head_cnt(4).with_head_dim(64).with_pooling_strategy(PoolingStrategy::Mean).build()),
-        ("jinaai/jina-embeddings-v2-base-en", ModelInfoBuilder::new("https://huggingface.co/varik77/onnx-models/resolve/main/jinaai/jina-embeddings-v2-base-en").with_tokenizer(true).with_layer_cnt(12).with_head_cnt(12).with_head_dim(64).with_pooling_strategy(PoolingStrategy::Mean).build()),
-        ("naver/splade-v3", ModelInfoBuilder::new("https://huggingface.co/varik77/onnx-models/resolve/main/naver/splade-v3").with_tokenizer(true).with_layer_cnt(12).with_head_cnt(12).with_head_dim(64).with_pooling_strategy(PoolingStrategy::ReluLogMaxPooling).build())
+        ("clip/ViT-B-32-textual", ModelInfoBuilder::new("https://huggingface.co/varik77/onnx-models/resolve/main/openai/ViT-B-32/textual").with_tokenizer(true).with_dimensions(512).with_sequence_len(77).build()),
+        ("clip/ViT-B-32-visual", ModelInfoBuilder::new("https://huggingface.co/varik77/onnx-models/resolve/main/openai/ViT-B-32/visual").with_visual(true).with_input_image_size(224).with_dimensions(512).build()),
+        ("BAAI/bge-small-en", ModelInfoBuilder::new("https://huggingface.co/varik77/onnx-models/resolve/main/BAAI/bge-small-en-v1.5").with_tokenizer(true).with_variants(QUANTIZED_VARIANTS).with_query_template("Represent this sentence for searching relevant passages: {text}").with_dimensions(384).build()),
+        ("BAAI/bge-base-en", ModelInfoBuilder::new("https://huggingface.co/varik77/onnx-models/resolve/main/BAAI/bge-base-en-v1.5").with_tokenizer(true).with_variants(QUANTIZED_VARIANTS).with_query_template("Represent this sentence for searching relevant passages: {text}").with_dimensions(768).build()),
+        ("BAAI/bge-large-en", ModelInfoBuilder::new("https://huggingface.co/varik77/onnx-models/resolve/main/BAAI/bge-large-en-v1.5").with_tokenizer(true).with_variants(QUANTIZED_VARIANTS).with_query_template("Represent this sentence for searching relevant passages: {text}").with_dimensions(1024).build()),
+        ("BAAI/bge-m3", ModelInfoBuilder::new("https://huggingface.co/varik77/onnx-models/resolve/main/BAAI/bge-m3").with_tokenizer(true).with_onnx_data(true).with_layer_cnt(8).with_head_cnt(4).with_head_dim(64).with_dimensions(1024).with_sequence_len(8192).build()),
+        ("intfloat/e5-base-v2", ModelInfoBuilder::new("https://huggingface.co/varik77/onnx-models/resolve/main/intfloat/e5-base-v2").with_tokenizer(true).with_variants(QUANTIZED_VARIANTS).with_query_template("query: {text}").with_document_template("passage: {text}").with_dimensions(768).build()),
+        ("intfloat/e5-large-v2", ModelInfoBuilder::new("https://huggingface.co/varik77/onnx-models/resolve/main/intfloat/e5-large-v2").with_tokenizer(true).with_variants(QUANTIZED_VARIANTS).with_query_template("query: {text}").with_document_template("passage: {text}").with_dimensions(1024).build()),
+        ("llmrails/ember-v1", ModelInfoBuilder::new("https://huggingface.co/varik77/onnx-models/resolve/main/llmrails/ember-v1").with_tokenizer(true).with_variants(QUANTIZED_VARIANTS).with_dimensions(1024).build()),
+        ("thenlper/gte-base", ModelInfoBuilder::new("https://huggingface.co/varik77/onnx-models/resolve/main/thenlper/gte-base").with_tokenizer(true).with_variants(QUANTIZED_VARIANTS).with_dimensions(768).build()),
+        ("thenlper/gte-large", ModelInfoBuilder::new("https://huggingface.co/varik77/onnx-models/resolve/main/thenlper/gte-large").with_tokenizer(true).with_variants(QUANTIZED_VARIANTS).with_dimensions(1024).build()),
+        ("microsoft/all-MiniLM-L12-v2", ModelInfoBuilder::new("https://huggingface.co/varik77/onnx-models/resolve/main/microsoft/all-MiniLM-L12-v2").with_tokenizer(true).with_variants(QUANTIZED_VARIANTS).with_dimensions(384).build()),
+        ("microsoft/all-mpnet-base-v2", ModelInfoBuilder::new("https://huggingface.co/varik77/onnx-models/resolve/main/microsoft/all-mpnet-base-v2").with_tokenizer(true).with_variants(QUANTIZED_VARIANTS).with_dimensions(768).build()),
+        ("transformers/multi-qa-mpnet-base-dot-v1", ModelInfoBuilder::new("https://huggingface.co/varik77/onnx-models/resolve/main/transformers/multi-qa-mpnet-base-dot-v1").with_tokenizer(true).with_variants(QUANTIZED_VARIANTS).with_dimensions(768).build()),
+        ("jinaai/jina-embeddings-v2-small-en", ModelInfoBuilder::new("https://huggingface.co/varik77/onnx-models/resolve/main/jinaai/jina-embeddings-v2-small-en").with_tokenizer(true).with_variants(QUANTIZED_VARIANTS).with_layer_cnt(4).with_head_cnt(4).with_head_dim(64).with_pooling_strategy(PoolingStrategy::Mean).with_dimensions(512).with_sequence_len(8192).build()),
+        ("jinaai/jina-embeddings-v2-base-en", ModelInfoBuilder::new("https://huggingface.co/varik77/onnx-models/resolve/main/jinaai/jina-embeddings-v2-base-en").with_tokenizer(true).with_variants(QUANTIZED_VARIANTS).with_layer_cnt(12).with_head_cnt(12).with_head_dim(64).with_pooling_strategy(PoolingStrategy::Mean).with_dimensions(768).with_sequence_len(8192).build()),
+        ("naver/splade-v3", ModelInfoBuilder::new("https://huggingface.co/varik77/onnx-models/resolve/main/naver/splade-v3").with_tokenizer(true).with_variants(QUANTIZED_VARIANTS).with_layer_cnt(12).with_head_cnt(12).with_head_dim(64).with_pooling_strategy(PoolingStrategy::ReluLogMaxPooling).with_dimensions(30522).build())
     ]));
 }
 
 lazy_static! {
+    // Execution providers are set per session, as the providers of the environment
+    // would be registered for every session regardless of the job's runtime params
     static ref ONNX_ENV: Arc<Environment> = Environment::builder()
         .with_name("ldb_extras")
-        .with_execution_providers([
-            ExecutionProvider::CUDA(Default::default()),
-            ExecutionProvider::OpenVINO(Default::default()),
-            ExecutionProvider::CPU(Default::default()),
-        ])
         .build()
         .unwrap()
         .into_arc();
@@ -324,6 +548,7 @@ impl EncoderService {
         model_params: ModelParams,
         model_folder: &PathBuf,
         args: &EncoderOptions,
+        session_options: &SessionOptions,
     ) -> Result<EncoderService, Box<dyn std::error::Error + Send + Sync>> {
         let mut tokenizer = None;
 
@@ -346,11 +571,26 @@ impl EncoderService {
 
         let num_cpus = num_cpus::get();
 
-        let encoder = SessionBuilder::new(environment)?
+        let mut builder = SessionBuilder::new(environment)?
             .with_parallel_execution(true)?
-            .with_intra_threads(num_cpus as i16)?
-            .with_optimization_level(GraphOptimizationLevel::Level3)?
-            .with_model_from_file(Path::join(model_folder, "model.onnx"))?;
+            .with_intra_threads(session_options.intra_threads.unwrap_or(num_cpus as i16))?
+            .with_optimization_level(
+                session_options
+                    .optimization_level
+                    .unwrap_or_default()
+                    .get_level(),
+            )?;
+
+        if let Some(inter_threads) = session_options.inter_threads {
+            builder = builder.with_inter_threads(inter_threads)?;
+        }
+
+        builder = builder.with_execution_providers(session_options.get_execution_providers())?;
+
+        let encoder = builder.with_model_from_file(Path::join(
+            model_folder,
+            session_options.get_model_file_name(),
+        ))?;
 
         Ok(EncoderService {
             name: model_name.to_string(),
@@ -393,103 +633,84 @@ impl EncoderService {
         return (total_memory as f64 * 1.2) as usize;
     }
 
-    fn chunk_session_input<'a>(
+    // Groups the inputs into model runs
+    // With length bucketing the inputs are sorted by token count and each run is padded
+    // only to the longest input in it and limited by the token budget (including padding)
+    // Without it all inputs are padded to the longest input of the batch
+    // In both cases the runs are limited by the available memory
+    fn get_input_buckets(
         &self,
-        vecs: Vec<Vec<i64>>,
-        batch_size: usize,
-    ) -> Result<Vec<Vec<SessionInput>>, anyhow::Error> {
-        // Currently this function will only work for bert models
-        // get token count for each text
-        let token_cnt = vecs[0].len() / batch_size;
-        let input_cnt = vecs.len();
+        lengths: &Vec<usize>,
+        padded_len: usize,
+        batching: &BatchingOptions,
+    ) -> Result<Vec<InputBucket>, anyhow::Error> {
         // Get available memory for GPU or RAM
         let available_memory = get_available_memory()? as usize;
-        // Calculate memory consumption for one item
-        // Then devide GPU memory / memory needed
-        // Then devide array into chunks of that count
-        let memory_needed_for_one_input = self.get_required_memory(token_cnt as usize);
-        // Get max batch size
-        let max_batch_size = cmp::max(1, (available_memory / memory_needed_for_one_input) as usize);
         // For models which does not need chunking the get_required_memory will return 1
-        // And max_batch_size will be higher than provided batch_size, so we will take
-        // The minimum of batch_size and max_batch_size
-        let max_batch_size = cmp::min(batch_size, max_batch_size);
-        let max_token_cnt = max_batch_size * token_cnt;
-
-        let mut inputs = Vec::with_capacity(batch_size / max_batch_size);
-        // Make vector of shape
-        // [
-        //   [tokenIds, tokenTypeIds, Mask],
-        //   [tokenIds, tokenTypeIds, Mask],
-        //   [tokenIds, tokenTypeIds, Mask]
-        // ]
-        for input in vecs {
-            for (index, chunk) in input.chunks(max_token_cnt).enumerate() {
-                if inputs.len() == index {
-                    inputs.push(Vec::with_capacity(input_cnt));
+        let get_max_batch_size =
+            |seq_len: usize| cmp::max(1, available_memory / self.get_required_memory(seq_len));
+
+        if !batching.length_bucketing {
+            let max_batch_size = get_max_batch_size(padded_len);
+            return Ok((0..lengths.len())
+                .chunks(max_batch_size)
+                .into_iter()
+                .map(|chunk| InputBucket {
+                    indices: chunk.collect(),
+                    seq_len: padded_len,
+                    max_batch_size,
+                })
+                .collect());
+        }
+
+        // Longest inputs go first, so the first input of each bucket defines its sequence length
+        let mut indices: Vec<usize> = (0..lengths.len()).collect();
+        indices.sort_by_key(|idx| cmp::Reverse(lengths[*idx]));
+
+        let mut buckets: Vec<InputBucket> = Vec::new();
+        for idx in indices {
+            if let Some(bucket) = buckets.last_mut() {
+                if bucket.indices.len() < bucket.max_batch_size {
+                    bucket.indices.push(idx);
+                    continue;
                 }
-                let group: &mut Vec<SessionInput> = inputs.get_mut(index).unwrap();
-                let group_batch_size = chunk.len() / token_cnt;
-                group.push(
-                    CowArray::from(Array2::from_shape_vec(
-                        (group_batch_size, token_cnt),
-                        chunk.to_vec(),
-                    )?)
-                    .into_dyn(),
-                );
             }
+
+            let seq_len = cmp::max(lengths[idx], 1);
+            buckets.push(InputBucket {
+                indices: vec![idx],
+                seq_len,
+                max_batch_size: cmp::min(
+                    cmp::max(1, batching.max_batch_tokens / seq_len),
+                    get_max_batch_size(seq_len),
+                ),
+            });
         }
 
-        Ok(inputs)
+        Ok(buckets)
     }
 
     fn process_text_bert(
         &self,
         texts: &Vec<&str>,
+        batching: &BatchingOptions,
     ) -> Result<EmbeddingResult, Box<dyn std::error::Error + Send + Sync>> {
         let session = &self.encoder;
-        let text_len = texts.len();
-        let preprocessed = self
-            .tokenizer
-            .as_ref()
-            .unwrap()
-            .encode_batch(texts.clone(), true)?;
-
-        let mut vecs = Vec::with_capacity(session.inputs.len());
-        let mut processed_tokens = 0;
+        let tokenizer = self.tokenizer.as_ref().unwrap();
+        let preprocessed = tokenizer.encode_batch(texts.clone(), true)?;
 
+        let mut input_fields = Vec::with_capacity(session.inputs.len());
         let mut attention_mask_idx = None;
         for input in &session.inputs {
-            let mut val = None;
             match input.name.as_str() {
-                "input_ids" => {
-                    let v: Vec<i64> = preprocessed
-                        .iter()
-                        .map(|i| i.get_ids().iter().map(|b| *b as i64).collect())
-                        .concat();
-                    processed_tokens = v.len();
-                    val = Some(v);
-                }
+                "input_ids" => input_fields.push(EncodingField::Ids),
                 "attention_mask" => {
-                    let v: Vec<i64> = preprocessed
-                        .iter()
-                        .map(|i| i.get_attention_mask().iter().map(|b| *b as i64).collect())
-                        .concat();
-                    val = Some(v);
-                    attention_mask_idx = Some(vecs.len())
-                }
-                "token_type_ids" => {
-                    let v: Vec<i64> = preprocessed
-                        .iter()
-                        .map(|i| i.get_type_ids().iter().map(|b| *b as i64).collect())
-                        .concat();
-                    val = Some(v);
+                    attention_mask_idx = Some(input_fields.len());
+                    input_fields.push(EncodingField::AttentionMask);
                 }
+                "token_type_ids" => input_fields.push(EncodingField::TypeIds),
                 _ => {}
             }
-            if let Some(v) = val {
-                vecs.push(v);
-            }
         }
 
         if attention_mask_idx.is_none() {
@@ -502,41 +723,90 @@ impl EncoderService {
 
         let attention_mask_idx = attention_mask_idx.unwrap();
 
-        let input_chunks = self.chunk_session_input(vecs, text_len)?;
-        let embeddings = input_chunks
+        // Token count of each input without padding
+        let lengths: Vec<usize> = preprocessed
             .iter()
-            .map(|chunk| {
-                // Iterate over each chunk and create embedding for that chunk
-                let inputs: Vec<Value<'_>> = chunk
+            .map(|encoding| {
+                encoding
+                    .get_attention_mask()
                     .iter()
-                    .map(|v| Value::from_array(session.allocator(), &v).unwrap())
-                    .collect();
+                    .filter(|m| **m != 0)
+                    .count()
+            })
+            .collect();
+        let padded_len = preprocessed.iter().map(|e| e.len()).max().unwrap_or(0);
+        // Processed tokens are reported for the batch padded to its longest input
+        // regardless of bucketing, so the usage stays comparable with previous versions
+        let processed_tokens = padded_len * texts.len();
+        let left_padding = matches!(
+            tokenizer.get_padding(),
+            Some(PaddingParams {
+                direction: PaddingDirection::Left,
+                ..
+            })
+        );
 
-                let result = session.run(inputs);
+        let output_dims = session.outputs[0].dimensions.last().unwrap().unwrap() as usize;
+        let mut embeddings = vec![Vec::new(); texts.len()];
 
-                if let Err(e) = result {
-                    anyhow::bail!(e);
-                }
+        for bucket in self.get_input_buckets(&lengths, padded_len, batching)? {
+            // Make vector of shape
+            // [tokenIds, tokenTypeIds, Mask]
+            // where each of them is [bucket_size, seq_len] array
+            let inputs = input_fields
+                .iter()
+                .map(|field| {
+                    let values: Vec<i64> = bucket
+                        .indices
+                        .iter()
+                        .map(|idx| {
+                            let values = field.get(&preprocessed[*idx]);
+                            // Encodings are padded to the longest input of the whole batch
+                            // so we only take the part needed for this bucket
+                            let start = if left_padding {
+                                values.len() - bucket.seq_len
+                            } else {
+                                0
+                            };
+                            values[start..start + bucket.seq_len]
+                                .iter()
+                                .map(|v| *v as i64)
+                                .collect::<Vec<i64>>()
+                        })
+                        .concat();
 
-                let outputs = result.unwrap();
+                    Ok(CowArray::from(Array2::from_shape_vec(
+                        (bucket.indices.len(), bucket.seq_len),
+                        values,
+                    )?)
+                    .into_dyn())
+                })
+                .collect::<Result<Vec<SessionInput>, anyhow::Error>>()?;
 
-                let binding = outputs[0].try_extract()?;
-                let embeddings = binding.view();
-                let attention_mask = &chunk[attention_mask_idx];
-                let output_dims = session.outputs[0].dimensions.last().unwrap().unwrap() as usize;
-                let embeddings: Vec<Vec<f32>> = self.model_params.pooling_strategy.pool(
-                    embeddings,
-                    attention_mask,
-                    output_dims,
-                );
+            let session_inputs: Vec<Value<'_>> = inputs
+                .iter()
+                .map(|v| Value::from_array(session.allocator(), &v))
+                .collect::<Result<Vec<Value<'_>>, _>>()?;
+
+            let outputs = session.run(session_inputs)?;
+            let binding = outputs[0].try_extract()?;
+            let bucket_embeddings = self.model_params.pooling_strategy.pool(
+                binding.view(),
+                &inputs[attention_mask_idx],
+                output_dims,
+            );
 
-                Ok(embeddings)
-            })
-            .collect::<Result<Vec<Vec<Vec<f32>>>, anyhow::Error>>();
+            // Restore the original order of the inputs
+            for (idx, embedding) in bucket.indices.iter().zip(bucket_embeddings) {
+                embeddings[*idx] = embedding;
+            }
+        }
 
         Ok(EmbeddingResult {
             processed_tokens,
-            embeddings: embeddings.map(|vec_vec| vec_vec.into_iter().flatten().collect())?,
+            embeddings,
+            failed_inputs: Vec::new(),
+            truncated_inputs: Vec::new(),
         })
     }
 
@@ -594,16 +864,19 @@ impl EncoderService {
                 .into_iter()
                 .map(|b| b.collect())
                 .collect(),
+            failed_inputs: Vec::new(),
+            truncated_inputs: Vec::new(),
         })
     }
 
     fn process_text(
         &self,
         texts: &Vec<&str>,
+        batching: &BatchingOptions,
     ) -> Result<EmbeddingResult, Box<dyn std::error::Error + Send + Sync>> {
         match self.name.as_str() {
             "clip/ViT-B-32-textual" => self.process_text_clip(texts),
-            _ => self.process_text_bert(texts),
+            _ => self.process_text_bert(texts, batching),
         }
     }
 
@@ -662,6 +935,8 @@ impl EncoderService {
                 .into_iter()
                 .map(|b| b.collect())
                 .collect(),
+            failed_inputs: Vec::new(),
+            truncated_inputs: Vec::new(),
         })
     }
 
@@ -679,13 +954,60 @@ impl EncoderService {
 pub struct OrtRuntime<'a> {
     cache: bool,
     data_path: String,
+    #[cfg(feature = "image-sources")]
+    s3: Option<S3Params>,
+    // S3 clients are built once per bucket and reused for all objects
+    #[cfg(feature = "image-sources")]
+    s3_stores: std::sync::Mutex<HashMap<String, Arc<AmazonS3>>>,
+    // Set when the inputs are values of a bytea column in Postgres hex format (\x...)
+    bytea_input: bool,
+    input_type: Option<InputType>,
+    batching: BatchingOptions,
+    session_options: SessionOptions,
+    pub(super) post_process: PostProcessParams,
+    pub(super) truncation_policy: TruncationPolicy,
     logger: &'a LoggerFn,
 }
 
+// Credentials and endpoint for reading images from s3:// URIs
+// If not provided the standard AWS_* environment variables will be used
+#[cfg(feature = "image-sources")]
+#[derive(Deserialize, Clone)]
+pub struct S3Params {
+    endpoint: Option<String>,
+    region: Option<String>,
+    access_key_id: Option<String>,
+    secret_access_key: Option<String>,
+    session_token: Option<String>,
+}
+
 #[derive(Deserialize)]
 pub struct OrtRuntimeParams {
     data_path: Option<String>,
     cache: Option<bool>,
+    #[cfg(feature = "image-sources")]
+    s3: Option<S3Params>,
+    bytea_input: Option<bool>,
+    input_type: Option<InputType>,
+    length_bucketing: Option<bool>,
+    max_batch_tokens: Option<usize>,
+    #[serde(flatten)]
+    session_options: SessionOptions,
+}
+
+// Images from bytea columns are passed in Postgres hex format (\x...)
+fn decode_bytea_hex(hex: &str) -> Result<Vec<u8>, anyhow::Error> {
+    if hex.len() % 2 != 0 {
+        anyhow::bail!("[X] Invalid bytea hex value, odd number of digits");
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&hex[i..i + 2], 16)
+                .map_err(|e| anyhow::anyhow!("[X] Invalid bytea hex value - {e}"))
+        })
+        .collect()
 }
 
 impl<'a> OrtRuntime<'a> {
@@ -696,6 +1018,21 @@ impl<'a> OrtRuntime<'a> {
             logger,
             cache: runtime_params.cache.unwrap_or(false),
             data_path: runtime_params.data_path.unwrap_or(DATA_PATH.to_owned()),
+            #[cfg(feature = "image-sources")]
+            s3: runtime_params.s3,
+            #[cfg(feature = "image-sources")]
+            s3_stores: std::sync::Mutex::new(HashMap::new()),
+            bytea_input: runtime_params.bytea_input.unwrap_or(false),
+            input_type: runtime_params.input_type,
+            batching: BatchingOptions {
+                length_bucketing: runtime_params.length_bucketing.unwrap_or(true),
+                max_batch_tokens: runtime_params
+                    .max_batch_tokens
+                    .unwrap_or(DEFAULT_MAX_BATCH_TOKENS),
+            },
+            session_options: runtime_params.session_options,
+            post_process: PostProcessParams::from_params(params)?,
+            truncation_policy: TruncationPolicy::from_params(params)?,
         })
     }
 
@@ -704,7 +1041,7 @@ impl<'a> OrtRuntime<'a> {
         model_map: &mut HashMap<&'static str, ModelInfo>,
     ) -> Result<(), anyhow::Error> {
         for (_, model_info) in model_map.iter_mut() {
-            model_info.encoder = None;
+            model_info.encoders.clear();
         }
 
         Ok(())
@@ -785,30 +1122,80 @@ impl<'a> OrtRuntime<'a> {
             }
 
             let model_info = model_info.unwrap();
+            self.check_model_variant(model_name, model_info)?;
 
-            if model_info.encoder.is_some() {
-                // if encoder exists return
+            if model_info.encoders.contains_key(&self.session_options) {
                 return Ok(());
             }
         }
 
+        let model_info = models_map.get(model_name).unwrap();
+        let model_folder = self.download_model_files(model_name, model_info).await?;
+        let model_path = Path::join(&model_folder, self.session_options.get_model_file_name());
+
+        // Check available memory
+        self.check_available_memory(&model_path, &mut models_map)?;
+
         let model_info = models_map.get_mut(model_name).unwrap();
+        let encoder = EncoderService::new(
+            &ONNX_ENV,
+            model_name,
+            model_info.params.clone(),
+            &model_folder,
+            &model_info.encoder_args,
+            &self.session_options,
+        );
 
-        let model_folder = Path::join(&Path::new(&self.data_path), model_name);
-        let model_path = Path::join(&model_folder, "model.onnx");
-        let tokenizer_path = Path::join(&model_folder, "tokenizer.json");
+        match encoder {
+            Ok(enc) => {
+                model_info
+                    .encoders
+                    .insert(self.session_options.clone(), enc);
+            }
+            Err(err) => {
+                anyhow::bail!(err)
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_model_variant(
+        &self,
+        model_name: &str,
+        model_info: &ModelInfo,
+    ) -> Result<ModelVariant, anyhow::Error> {
+        let model_variant = self.session_options.model_variant.unwrap_or_default();
+        if !model_info.variants.contains(&model_variant) {
+            anyhow::bail!(
+                "Model variant \"{}\" is not available for model {model_name}.\nAvailable variants: {}",
+                model_variant.get_name(),
+                model_info.variants.iter().map(|v| v.get_name()).join(", ")
+            );
+        }
+
+        Ok(model_variant)
+    }
+
+    async fn download_model_files(
+        &self,
+        model_name: &str,
+        model_info: &ModelInfo,
+    ) -> Result<PathBuf, anyhow::Error> {
+        let model_variant = self.check_model_variant(model_name, model_info)?;
+        let model_folder = self.download_tokenizer_file(model_name, model_info).await?;
+        let model_file_name = model_variant.get_file_name();
+        let model_path = Path::join(&model_folder, model_file_name);
 
         // TODO parallel download with tokio
         if !model_path.exists() {
             // model is not downloaded, we should download it
             (self.logger)("Downloading model [this is one time operation]");
-            download_file(&model_info.url, &model_path).await?;
-        }
-
-        if !tokenizer_path.exists() && model_info.tokenizer_url.is_some() {
-            (self.logger)("Downloading tokenizer [this is one time operation]");
-            // tokenizer is not downloaded, we should download it
-            download_file(&model_info.tokenizer_url.as_ref().unwrap(), &tokenizer_path).await?;
+            download_file(
+                &format!("{}/{model_file_name}", model_info.base_url),
+                &model_path,
+            )
+            .await?;
         }
 
         if let Some(onnx_data_url) = &model_info.onnx_data_url {
@@ -820,30 +1207,238 @@ impl<'a> OrtRuntime<'a> {
             }
         }
 
-        // Check available memory
-        self.check_available_memory(&model_path, &mut models_map)?;
+        Ok(model_folder)
+    }
 
-        let model_info = models_map.get_mut(model_name).unwrap();
-        let encoder = EncoderService::new(
-            &ONNX_ENV,
-            model_name,
-            model_info.params.clone(),
-            &model_folder,
-            &model_info.encoder_args,
+    // Downloads only the tokenizer of the model, so the inputs can be tokenized
+    // without pulling the model weights
+    async fn download_tokenizer_file(
+        &self,
+        model_name: &str,
+        model_info: &ModelInfo,
+    ) -> Result<PathBuf, anyhow::Error> {
+        let model_folder = Path::join(&Path::new(&self.data_path), model_name);
+        let tokenizer_path = Path::join(&model_folder, "tokenizer.json");
+
+        if !tokenizer_path.exists() && model_info.tokenizer_url.is_some() {
+            (self.logger)("Downloading tokenizer [this is one time operation]");
+            // tokenizer is not downloaded, we should download it
+            download_file(&model_info.tokenizer_url.as_ref().unwrap(), &tokenizer_path).await?;
+        }
+
+        Ok(model_folder)
+    }
+
+    // Loads the tokenizer used to count the input tokens
+    // It is kept in memory regardless of the cache option as it is cheap
+    async fn load_input_tokenizer(
+        &self,
+        model_name: &str,
+        models_map: &mut HashMap<&'static str, ModelInfo>,
+    ) -> Result<(), anyhow::Error> {
+        let model_info = check_and_get_model!(models_map, model_name);
+
+        if model_info.input_tokenizer.is_some() || !model_info.encoder_args.use_tokenizer {
+            return Ok(());
+        }
+
+        let model_folder = self.download_tokenizer_file(model_name, model_info).await?;
+        let mut tokenizer = Tokenizer::from_file(Path::join(&model_folder, "tokenizer.json"))
+            .map_err(|e| anyhow::anyhow!("{e}"))?;
+
+        let max_tokens = match model_info
+            .encoder_args
+            .truncation_params
+            .as_ref()
+            .or(tokenizer.get_truncation())
+        {
+            Some(params) => params.max_length,
+            // Model does not have sequence length limit
+            None => usize::MAX,
+        };
+
+        tokenizer
+            .with_truncation(None)
+            .map_err(|e| anyhow::anyhow!("{e}"))?;
+        tokenizer.with_padding(None);
+
+        models_map.get_mut(model_name).unwrap().input_tokenizer = Some(InputTokenizer {
+            tokenizer,
+            max_tokens,
+        });
+
+        Ok(())
+    }
+
+    // Returns the token counts of the inputs with model specific instructions applied
+    // or None if the model does not use tokenizer (e.g visual models)
+    pub async fn count_tokens(
+        &self,
+        model_name: &str,
+        inputs: &Vec<&str>,
+    ) -> Result<Option<TokenCounts>, anyhow::Error> {
+        let mut map = MODEL_INFO_MAP.lock().await;
+        self.load_input_tokenizer(model_name, &mut map).await?;
+        let model_info = map.get(model_name).unwrap();
+
+        let input_tokenizer = match &model_info.input_tokenizer {
+            Some(input_tokenizer) => input_tokenizer,
+            None => return Ok(None),
+        };
+
+        let templated_inputs = model_info.apply_template(self.input_type, inputs);
+        let inputs: Vec<&str> = match &templated_inputs {
+            Some(templated_inputs) => templated_inputs.iter().map(|s| s.as_str()).collect(),
+            None => inputs.clone(),
+        };
+
+        let counts = input_tokenizer
+            .tokenizer
+            .encode_batch(inputs, true)
+            .map_err(|e| anyhow::anyhow!("{e}"))?
+            .iter()
+            .map(|encoding| encoding.len())
+            .collect();
+
+        Ok(Some(TokenCounts {
+            counts,
+            max_tokens: input_tokenizer.max_tokens,
+        }))
+    }
+
+    // Splits the input into chunks which fit into the max sequence length of the model
+    // taking into account special tokens and instruction template
+    pub async fn split_by_tokens(
+        &self,
+        model_name: &str,
+        input: &str,
+    ) -> Result<Vec<String>, anyhow::Error> {
+        let mut map = MODEL_INFO_MAP.lock().await;
+        self.load_input_tokenizer(model_name, &mut map).await?;
+        let model_info = map.get(model_name).unwrap();
+
+        let input_tokenizer = match &model_info.input_tokenizer {
+            Some(input_tokenizer) => input_tokenizer,
+            None => return Ok(vec![input.to_owned()]),
+        };
+
+        let empty_input = model_info
+            .apply_template(self.input_type, &vec![""])
+            .map(|inputs| inputs[0].clone())
+            .unwrap_or_default();
+        let reserved_tokens = input_tokenizer
+            .tokenizer
+            .encode(empty_input.as_str(), true)
+            .map_err(|e| anyhow::anyhow!("{e}"))?
+            .len();
+        let chunk_size = cmp::max(
+            1,
+            input_tokenizer.max_tokens.saturating_sub(reserved_tokens),
         );
 
-        match encoder {
-            Ok(enc) => model_info.encoder = Some(enc),
-            Err(err) => {
-                anyhow::bail!(err)
+        let encoding = input_tokenizer
+            .tokenizer
+            .encode(input, false)
+            .map_err(|e| anyhow::anyhow!("{e}"))?;
+
+        // Offsets are byte ranges of the tokens in the original input
+        let chunks: Vec<String> = encoding
+            .get_offsets()
+            .chunks(chunk_size)
+            .filter_map(|offsets| {
+                input
+                    .get(offsets.first()?.0..offsets.last()?.1)
+                    .map(|chunk| chunk.to_owned())
+            })
+            .collect();
+
+        if chunks.is_empty() {
+            return Ok(vec![input.to_owned()]);
+        }
+
+        Ok(chunks)
+    }
+
+    #[cfg(feature = "image-sources")]
+    fn get_s3_store(&self, bucket: &str) -> Result<Arc<AmazonS3>, anyhow::Error> {
+        let mut stores = self.s3_stores.lock().unwrap();
+        if let Some(store) = stores.get(bucket) {
+            return Ok(store.clone());
+        }
+
+        let mut builder = AmazonS3Builder::from_env().with_bucket_name(bucket);
+
+        if let Some(s3) = &self.s3 {
+            if let Some(endpoint) = &s3.endpoint {
+                // S3 compatible storages (e.g. MinIO) may be served over plain http
+                builder = builder.with_endpoint(endpoint).with_allow_http(true);
+            }
+            if let Some(region) = &s3.region {
+                builder = builder.with_region(region);
+            }
+            if let Some(access_key_id) = &s3.access_key_id {
+                builder = builder.with_access_key_id(access_key_id);
+            }
+            if let Some(secret_access_key) = &s3.secret_access_key {
+                builder = builder.with_secret_access_key(secret_access_key);
+            }
+            if let Some(session_token) = &s3.session_token {
+                builder = builder.with_token(session_token);
             }
         }
 
-        Ok(())
+        let store = Arc::new(builder.build()?);
+        stores.insert(bucket.to_owned(), store.clone());
+        Ok(store)
+    }
+
+    #[cfg(feature = "image-sources")]
+    async fn get_s3_object(&self, url: &Url) -> Result<Vec<u8>, anyhow::Error> {
+        let bucket = match url.host_str() {
+            Some(bucket) => bucket,
+            None => anyhow::bail!("[X] Bucket name is missing in \"{url}\""),
+        };
+
+        let store = self.get_s3_store(bucket)?;
+        let response = store.get(&ObjectPath::from_url_path(url.path())?).await?;
+
+        if response.meta.size as u64 > MAX_IMAGE_SIZE {
+            anyhow::bail!(
+                "[X] Maximum supported image size is {}MB, object size is {}MB",
+                MAX_IMAGE_SIZE / 1024 / 1024,
+                response.meta.size as u64 / 1024 / 1024
+            );
+        }
+
+        Ok(response.bytes().await?.to_vec())
     }
 
     async fn get_image_buffer(&self, path_or_url: &str) -> Result<Vec<u8>, anyhow::Error> {
+        if self.bytea_input {
+            let hex = match path_or_url.strip_prefix("\\x") {
+                Some(hex) => hex,
+                None => anyhow::bail!("[X] Expected bytea value in hex format (\\x...)"),
+            };
+
+            if (hex.len() / 2) as u64 > MAX_IMAGE_SIZE {
+                anyhow::bail!(
+                    "[X] Maximum supported image size is {}MB, bytea value size is {}MB",
+                    MAX_IMAGE_SIZE / 1024 / 1024,
+                    (hex.len() / 2) as u64 / 1024 / 1024
+                );
+            }
+
+            return decode_bytea_hex(hex);
+        }
+
         if let Ok(url) = Url::parse(path_or_url) {
+            if url.scheme() == "s3" {
+                #[cfg(feature = "image-sources")]
+                return self.get_s3_object(&url).await;
+                #[cfg(not(feature = "image-sources"))]
+                anyhow::bail!("[X] Reading images from s3:// URIs is not enabled");
+            }
+
             let client = reqwest::Client::builder()
                 .timeout(Duration::from_secs(15))
                 .redirect(Policy::limited(2))
@@ -870,16 +1465,16 @@ impl<'a> OrtRuntime<'a> {
             }
             return Ok(response.unwrap());
         } else {
-            anyhow::bail!("[X] Expected URL or absolute path got: {path_or_url}");
+            anyhow::bail!("[X] Expected URL, s3:// URI or absolute path got: {path_or_url}");
         }
     }
 
+    // Returns the image buffers in the same order as inputs
+    // If an image could not be loaded the error will be returned in place of its buffer
     async fn get_images_parallel(
         &self,
         paths_or_urls: &Vec<&str>,
-    ) -> Result<Vec<Vec<u8>>, anyhow::Error> {
-        let buffers: Arc<Mutex<Vec<Vec<u8>>>> =
-            Arc::new(Mutex::new(Vec::with_capacity(paths_or_urls.len())));
+    ) -> Vec<Result<Vec<u8>, anyhow::Error>> {
         let tasks: Vec<_> = paths_or_urls
             .iter()
             .map(|&path_or_url| self.get_image_buffer(path_or_url))
@@ -891,20 +1486,17 @@ impl<'a> OrtRuntime<'a> {
             paths_or_urls.len()
         ));
 
-        let mut tasks = futures::stream::iter(tasks).buffered(chunk_size);
-        while let Some(result) = tasks.next().await {
-            let mut buffers = buffers.lock().await;
-            if let Err(e) = result {
-                eprintln!("{}", e);
-                buffers.push(Vec::new());
-            } else {
-                buffers.push(result.unwrap());
-            }
-        }
+        let buffers = futures::stream::iter(tasks)
+            .buffered(chunk_size)
+            .map(|result| match result {
+                Ok(buffer) if buffer.len() == 0 => Err(anyhow::anyhow!("[X] Image is empty")),
+                result => result,
+            })
+            .collect::<Vec<_>>()
+            .await;
 
         (self.logger)("[*] All images read into buffer");
-        let buffers = buffers.lock().await;
-        Ok(buffers.clone())
+        buffers
     }
 }
 
@@ -925,26 +1517,32 @@ impl<'a> EmbeddingRuntimeT for OrtRuntime<'a> {
 
         let result;
         if model_info.encoder_args.visual {
-            let buffers = self.get_images_parallel(inputs).await?;
+            let buffers = self.get_images_parallel(inputs).await;
 
-            // If buffer will return error while downloading
-            // We will put an empty array instead, to omit that image
+            // If image failed to be read or downloaded
+            // We will report it in failed_inputs and omit that image
             // So here we are filtering the images which succeed to be downloaded
+            let mut failed_inputs = Vec::new();
             let filtered_buffers = buffers
                 .iter()
-                .filter(|b| b.len() > 0)
+                .enumerate()
+                .filter_map(|(idx, buffer)| match buffer {
+                    Ok(buffer) => Some(buffer),
+                    Err(e) => {
+                        failed_inputs.push((idx, e.to_string()));
+                        None
+                    }
+                })
                 .collect::<Vec<&Vec<u8>>>();
 
             let model_result = if filtered_buffers.len() > 0 {
-                model_info
-                    .encoder
-                    .as_ref()
-                    .unwrap()
-                    .process_image(&filtered_buffers)
+                model_info.encoders[&self.session_options].process_image(&filtered_buffers)
             } else {
                 Ok(EmbeddingResult {
                     embeddings: Vec::new(),
                     processed_tokens: 0,
+                    failed_inputs: Vec::new(),
+                    truncated_inputs: Vec::new(),
                 })
             };
 
@@ -966,10 +1564,7 @@ impl<'a> EmbeddingRuntimeT for OrtRuntime<'a> {
             // And output shuold have the dimensions
             // This should be checked when adding new model
 
-            let output_dims = model_info
-                .encoder
-                .as_ref()
-                .unwrap()
+            let output_dims = model_info.encoders[&self.session_options]
                 .encoder
                 .outputs
                 .last()
@@ -983,7 +1578,7 @@ impl<'a> EmbeddingRuntimeT for OrtRuntime<'a> {
             let return_res = buffers
                 .iter()
                 .map(|el| {
-                    if el.len() == 0 {
+                    if el.is_err() {
                         invalid_vec.clone()
                     } else {
                         let vec = model_result.embeddings[idx].clone();
@@ -996,14 +1591,23 @@ impl<'a> EmbeddingRuntimeT for OrtRuntime<'a> {
             result = Ok(EmbeddingResult {
                 embeddings: return_res,
                 processed_tokens: model_result.processed_tokens,
+                failed_inputs,
+                truncated_inputs: Vec::new(),
             })
         } else {
-            result = model_info.encoder.as_ref().unwrap().process_text(inputs);
+            let encoder = &model_info.encoders[&self.session_options];
+            result = match model_info.apply_template(self.input_type, inputs) {
+                Some(templated_inputs) => encoder.process_text(
+                    &templated_inputs.iter().map(|s| s.as_str()).collect(),
+                    &self.batching,
+                ),
+                None => encoder.process_text(inputs, &self.batching),
+            };
         }
 
         if !self.cache {
             let model_info = map.get_mut(model_name).unwrap();
-            model_info.encoder = None;
+            model_info.encoders.remove(&self.session_options);
         }
 
         match result {
@@ -1017,18 +1621,14 @@ impl<'a> EmbeddingRuntimeT for OrtRuntime<'a> {
     async fn get_available_models(
         &self,
         _job_type: EmbeddingJobType,
-    ) -> (String, Vec<(String, bool)>) {
+    ) -> (String, Vec<AvailableModel>) {
         let map = MODEL_INFO_MAP.lock().await;
         let mut res = String::new();
         let data_path = &self.data_path;
         let mut models = Vec::with_capacity(map.len());
-        for (key, value) in &*map {
+        for (key, value) in map.iter().sorted_by_key(|(key, _)| **key) {
             let model_exists =
-                if Path::join(&Path::new(data_path), format!("{}/model.onnx", key)).exists() {
-                    "true"
-                } else {
-                    "false"
-                };
+                Path::join(&Path::new(data_path), format!("{}/model.onnx", key)).exists();
             let model_type = if !value.encoder_args.visual {
                 "textual"
             } else {
@@ -1039,7 +1639,14 @@ impl<'a> EmbeddingRuntimeT for OrtRuntime<'a> {
                 "{} - type: {}, downloaded: {}\n",
                 key, model_type, model_exists
             ));
-            models.push((key.to_string(), value.encoder_args.visual));
+            models.push(AvailableModel {
+                name: key.to_string(),
+                dimensions: value.dimensions,
+                max_sequence_length: value.sequence_len,
+                visual: value.encoder_args.visual,
+                downloaded: Some(model_exists),
+                default_batch_size: get_default_batch_size(key),
+            });
         }
 
         return (res, models);
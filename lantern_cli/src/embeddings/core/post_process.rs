@@ -0,0 +1,159 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use super::runtime::EmbeddingResult;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Quantization {
+    Int8,
+    Binary,
+}
+
+// Runtime independent options applied to generated embeddings
+// These are read from the same runtime_params JSON passed to the runtime
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct PostProcessParams {
+    // Keep only first N dimensions (Matryoshka representation)
+    pub truncate_dimensions: Option<usize>,
+    pub normalize: Option<bool>,
+    pub quantization: Option<Quantization>,
+}
+
+impl PostProcessParams {
+    pub fn from_params(params: &str) -> Result<Self, anyhow::Error> {
+        let params: Self = serde_json::from_str(params)?;
+
+        if params.truncate_dimensions == Some(0) {
+            anyhow::bail!("'truncate_dimensions' should be greater than 0");
+        }
+
+        Ok(params)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.truncate_dimensions.is_none()
+            && !self.normalize.unwrap_or(false)
+            && self.quantization.is_none()
+    }
+
+    // Column type matching the output of quantization, so quantized embeddings
+    // take less space than REAL[]: int8 values are stored as SMALLINT[]
+    // and binary embeddings are stored as bit-packed BYTEA (one byte per 8 dimensions)
+    pub fn output_column_type(&self) -> &'static str {
+        match self.quantization {
+            Some(Quantization::Int8) => "SMALLINT[]",
+            Some(Quantization::Binary) => "BYTEA",
+            None => "REAL[]",
+        }
+    }
+
+    // Suffix which identifies the post processing options
+    // used to not mix up differently processed embeddings in cache
+    pub fn cache_key_suffix(&self) -> String {
+        if self.is_empty() {
+            return String::new();
+        }
+
+        let mut suffix = String::new();
+        if let Some(dims) = self.truncate_dimensions {
+            suffix.push_str(&format!(":dims={dims}"));
+        }
+        if self.normalize.unwrap_or(false) {
+            suffix.push_str(":normalize");
+        }
+        match self.quantization {
+            Some(Quantization::Int8) => suffix.push_str(":int8"),
+            Some(Quantization::Binary) => suffix.push_str(":binary"),
+            None => {}
+        }
+        suffix
+    }
+
+    fn truncate(&self, embedding: &mut Vec<f32>) {
+        if let Some(dims) = self.truncate_dimensions {
+            embedding.truncate(dims);
+        }
+    }
+
+    fn l2_normalize(embedding: &mut Vec<f32>) {
+        let norm = embedding.iter().map(|v| v * v).sum::<f32>().sqrt();
+        if norm > 0.0 {
+            embedding.iter_mut().for_each(|v| *v /= norm);
+        }
+    }
+
+    // Symmetric scalar quantization: values are scaled by the max absolute value
+    // of the vector and rounded to integers in [-127, 127]
+    fn quantize_int8(embedding: &Vec<f32>) -> Vec<f32> {
+        let max_abs = embedding.iter().fold(0.0_f32, |acc, v| acc.max(v.abs()));
+        if max_abs == 0.0 {
+            return vec![0.0; embedding.len()];
+        }
+
+        embedding
+            .iter()
+            .map(|v| (v / max_abs * 127.0).round().clamp(-127.0, 127.0))
+            .collect()
+    }
+
+    // Each dimension is converted to a single bit (1 if value > 0)
+    // and bits are packed by 8 into integers in [0, 255]
+    // so the output will have ceil(dims / 8) elements
+    fn quantize_binary(embedding: &Vec<f32>) -> Vec<f32> {
+        embedding
+            .chunks(8)
+            .map(|chunk| {
+                chunk.iter().enumerate().fold(0_u8, |byte, (idx, v)| {
+                    if *v > 0.0 {
+                        byte | (1 << (7 - idx))
+                    } else {
+                        byte
+                    }
+                }) as f32
+            })
+            .collect()
+    }
+
+    pub fn process_embedding(&self, mut embedding: Vec<f32>) -> Vec<f32> {
+        self.truncate(&mut embedding);
+
+        if self.normalize.unwrap_or(false) {
+            Self::l2_normalize(&mut embedding);
+        }
+
+        match self.quantization {
+            Some(Quantization::Int8) => Self::quantize_int8(&embedding),
+            Some(Quantization::Binary) => Self::quantize_binary(&embedding),
+            None => embedding,
+        }
+    }
+
+    pub fn apply(&self, result: EmbeddingResult) -> EmbeddingResult {
+        if self.is_empty() {
+            return result;
+        }
+
+        // Placeholder vectors of failed inputs are kept as is
+        let failed: HashSet<usize> = result.failed_inputs.iter().map(|(idx, _)| *idx).collect();
+
+        EmbeddingResult {
+            embeddings: result
+                .embeddings
+                .into_iter()
+                .enumerate()
+                .map(|(idx, embedding)| {
+                    if failed.contains(&idx) {
+                        embedding
+                    } else {
+                        self.process_embedding(embedding)
+                    }
+                })
+                .collect(),
+            processed_tokens: result.processed_tokens,
+            failed_inputs: result.failed_inputs,
+            truncated_inputs: result.truncated_inputs,
+        }
+    }
+}
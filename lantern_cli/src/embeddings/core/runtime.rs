@@ -1,18 +1,86 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
 use crate::embeddings::cli::EmbeddingJobType;
 
+// Whether the embedded text is a search query or a document stored for retrieval
+// Models which were trained with instructions expect different prefixes for them
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum InputType {
+    #[serde(alias = "search_query")]
+    Query,
+    #[serde(alias = "search_document", alias = "passage")]
+    Document,
+}
+
+impl FromStr for InputType {
+    type Err = anyhow::Error;
+    fn from_str(input: &str) -> Result<InputType, anyhow::Error> {
+        match input {
+            "query" | "search_query" => Ok(InputType::Query),
+            "document" | "passage" | "search_document" => Ok(InputType::Document),
+            _ => anyhow::bail!("Invalid input type {input}"),
+        }
+    }
+}
+
+impl ToString for InputType {
+    fn to_string(&self) -> String {
+        match self {
+            InputType::Query => "query".to_owned(),
+            InputType::Document => "document".to_owned(),
+        }
+    }
+}
+
 pub struct EmbeddingResult {
     pub embeddings: Vec<Vec<f32>>,
     pub processed_tokens: usize,
+    // Index of the input and the error message for inputs which could not be processed
+    // The embeddings for these inputs will be filled with placeholder values
+    pub failed_inputs: Vec<(usize, String)>,
+    // Index of the input and the info message for inputs which were truncated
+    // to fit the max sequence length of the model
+    pub truncated_inputs: Vec<(usize, String)>,
+}
+
+// Error of the request which was rejected because of its input (e.g. invalid or too large input)
+// The other inputs of the batch can still be processed without it
+#[derive(Debug)]
+pub struct InvalidInputError(pub String);
+
+impl std::fmt::Display for InvalidInputError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
 }
 
+impl std::error::Error for InvalidInputError {}
+
 pub struct CompletionResult {
     pub message: String,
     pub processed_tokens: usize,
 }
 
 pub struct BatchCompletionResult {
+    // Failed completions are filled with "Error: <message>" values
     pub messages: Vec<String>,
     pub processed_tokens: usize,
+    // Index of the input and the error message for completions which failed
+    pub failed_inputs: Vec<(usize, String)>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct AvailableModel {
+    pub name: String,
+    pub dimensions: Option<usize>,
+    pub max_sequence_length: Option<usize>,
+    pub visual: bool,
+    // Only known for the models which are downloaded locally
+    pub downloaded: Option<bool>,
+    pub default_batch_size: usize,
 }
 
 pub trait EmbeddingRuntimeT {
@@ -24,5 +92,5 @@ pub trait EmbeddingRuntimeT {
     fn get_available_models(
         &self,
         job_type: EmbeddingJobType,
-    ) -> impl std::future::Future<Output = (String, Vec<(String, bool)>)> + Send;
+    ) -> impl std::future::Future<Output = (String, Vec<AvailableModel>)> + Send;
 }
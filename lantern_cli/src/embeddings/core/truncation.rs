@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use super::runtime::EmbeddingResult;
+
+// What to do with inputs which are longer than the model's max sequence length
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TruncationPolicy {
+    // Cut the input to the max sequence length and flag the row as truncated
+    #[default]
+    Truncate,
+    // Do not generate embedding for the input and report it as failed row
+    Skip,
+    // Fail the whole batch
+    Fail,
+    // Split the input into chunks fitting the model and average their embeddings
+    ChunkAverage,
+}
+
+impl FromStr for TruncationPolicy {
+    type Err = anyhow::Error;
+    fn from_str(input: &str) -> Result<TruncationPolicy, anyhow::Error> {
+        match input {
+            "truncate" => Ok(TruncationPolicy::Truncate),
+            "skip" => Ok(TruncationPolicy::Skip),
+            "fail" => Ok(TruncationPolicy::Fail),
+            "chunk_average" => Ok(TruncationPolicy::ChunkAverage),
+            _ => anyhow::bail!("Invalid truncation policy {input}"),
+        }
+    }
+}
+
+impl ToString for TruncationPolicy {
+    fn to_string(&self) -> String {
+        match self {
+            TruncationPolicy::Truncate => "truncate".to_owned(),
+            TruncationPolicy::Skip => "skip".to_owned(),
+            TruncationPolicy::Fail => "fail".to_owned(),
+            TruncationPolicy::ChunkAverage => "chunk_average".to_owned(),
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+struct TruncationParams {
+    truncation_policy: Option<TruncationPolicy>,
+}
+
+impl TruncationPolicy {
+    // The policy is read from the same runtime_params JSON passed to the runtime
+    pub fn from_params(params: &str) -> Result<Self, anyhow::Error> {
+        let params: TruncationParams = serde_json::from_str(params)?;
+        Ok(params.truncation_policy.unwrap_or_default())
+    }
+
+    // Oversized inputs can only be detected when the runtime counts the tokens locally,
+    // otherwise the input is sent as is and truncated by the provider
+    pub fn require_tokenizer(self, runtime_name: &str) -> Result<Self, anyhow::Error> {
+        if self != TruncationPolicy::Truncate {
+            anyhow::bail!(
+                "Truncation policy \"{}\" is not supported for {runtime_name} as it can not count input tokens, use \"truncate\" instead",
+                self.to_string()
+            );
+        }
+
+        Ok(self)
+    }
+}
+
+// Token counts of the inputs (including special tokens and instruction templates)
+// and the maximum number of tokens the model accepts
+pub struct TokenCounts {
+    pub counts: Vec<usize>,
+    pub max_tokens: usize,
+}
+
+impl TokenCounts {
+    // Returns (input index, token count) for inputs exceeding the limit of the model
+    pub fn get_oversized(&self) -> Vec<(usize, usize)> {
+        self.counts
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > self.max_tokens)
+            .map(|(idx, count)| (idx, *count))
+            .collect()
+    }
+}
+
+pub fn get_truncation_message(token_count: usize, max_tokens: usize) -> String {
+    format!("Input was truncated from {token_count} to {max_tokens} tokens")
+}
+
+// Maps the result of the processed subset of inputs back to the original positions
+// Skipped inputs get an empty embedding and are reported as failed
+pub fn merge_skipped_result(
+    input_cnt: usize,
+    processed: &Vec<usize>,
+    skipped: Vec<(usize, String)>,
+    result: EmbeddingResult,
+) -> EmbeddingResult {
+    let mut embeddings = vec![Vec::new(); input_cnt];
+    for (idx, embedding) in processed.iter().zip(result.embeddings) {
+        embeddings[*idx] = embedding;
+    }
+
+    let mut failed_inputs: Vec<(usize, String)> = result
+        .failed_inputs
+        .into_iter()
+        .map(|(idx, failure)| (processed[idx], failure))
+        .collect();
+    failed_inputs.extend(skipped);
+
+    EmbeddingResult {
+        embeddings,
+        processed_tokens: result.processed_tokens,
+        failed_inputs,
+        truncated_inputs: result
+            .truncated_inputs
+            .into_iter()
+            .map(|(idx, message)| (processed[idx], message))
+            .collect(),
+    }
+}
+
+// Averages the embeddings of the chunks which belong to the same input
+// owners[i] is the index of the original input for the i-th chunk
+pub fn merge_chunked_result(
+    input_cnt: usize,
+    owners: &Vec<usize>,
+    result: EmbeddingResult,
+) -> EmbeddingResult {
+    let mut sums: Vec<Vec<f32>> = vec![Vec::new(); input_cnt];
+    let mut chunk_counts = vec![0; input_cnt];
+
+    for (idx, embedding) in owners.iter().zip(result.embeddings) {
+        let sum = &mut sums[*idx];
+        if sum.is_empty() {
+            *sum = embedding;
+        } else {
+            sum.iter_mut().zip(embedding).for_each(|(a, b)| *a += b);
+        }
+        chunk_counts[*idx] += 1;
+    }
+
+    let embeddings = sums
+        .into_iter()
+        .zip(chunk_counts)
+        .map(|(sum, cnt)| {
+            if cnt <= 1 {
+                sum
+            } else {
+                sum.into_iter().map(|v| v / cnt as f32).collect()
+            }
+        })
+        .collect();
+
+    // If any chunk of the input failed the whole input is reported as failed
+    let failed_inputs: HashMap<usize, String> = result
+        .failed_inputs
+        .into_iter()
+        .map(|(idx, failure)| (owners[idx], failure))
+        .collect();
+    let truncated_inputs: HashMap<usize, String> = result
+        .truncated_inputs
+        .into_iter()
+        .map(|(idx, message)| (owners[idx], message))
+        .collect();
+
+    EmbeddingResult {
+        embeddings,
+        processed_tokens: result.processed_tokens,
+        failed_inputs: failed_inputs.into_iter().collect(),
+        truncated_inputs: truncated_inputs.into_iter().collect(),
+    }
+}
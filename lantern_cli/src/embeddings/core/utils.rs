@@ -1,3 +1,4 @@
+use super::runtime::InvalidInputError;
 use super::Runtime;
 use anyhow::anyhow;
 use nvml_wrapper::Nvml;
@@ -15,7 +16,8 @@ pub async fn download_file(url: &str, path: &PathBuf) -> Result<(), anyhow::Erro
         .redirect(Policy::limited(2))
         .build()?;
 
-    let mut response = Cursor::new(client.get(url).send().await?.bytes().await?);
+    let response = client.get(url).send().await?.error_for_status()?;
+    let mut response = Cursor::new(response.bytes().await?);
     // Copy the response body to the local file
     create_dir_all(path.parent().unwrap())?;
     let mut file = std::fs::File::create(path)?;
@@ -98,6 +100,29 @@ pub async fn post_with_retries<T>(
                     .await;
             }
             Ok(response) => {
+                let status = response.status();
+                // Requests rejected because of the input (e.g. content policy or too large input)
+                // will fail the same way on retry, so the error is returned immediately
+                if status.is_client_error()
+                    && status != reqwest::StatusCode::TOO_MANY_REQUESTS
+                    && status != reqwest::StatusCode::REQUEST_TIMEOUT
+                {
+                    let message = format!(
+                        "Request failed with status {status}: {}",
+                        String::from_utf8_lossy(&response.bytes().await?)
+                    );
+
+                    // Authorization or not found errors are not caused by the input
+                    if status == reqwest::StatusCode::BAD_REQUEST
+                        || status == reqwest::StatusCode::PAYLOAD_TOO_LARGE
+                        || status == reqwest::StatusCode::UNPROCESSABLE_ENTITY
+                    {
+                        return Err(InvalidInputError(message).into());
+                    }
+
+                    anyhow::bail!(message);
+                }
+
                 let embedding_response = get_response_fn(response.bytes().await?.to_vec().clone());
 
                 match embedding_response {
@@ -123,6 +148,45 @@ pub async fn post_with_retries<T>(
     ))
 }
 
+pub fn get_response_validator(
+    response_schema: &Option<serde_json::Value>,
+) -> Result<Option<Arc<jsonschema::Validator>>, anyhow::Error> {
+    match response_schema {
+        Some(schema) => match jsonschema::validator_for(schema) {
+            Ok(validator) => Ok(Some(Arc::new(validator))),
+            Err(e) => anyhow::bail!("Invalid response_schema: {e}"),
+        },
+        None => Ok(None),
+    }
+}
+
+// Parses the LLM response as JSON and validates it against the response schema
+// The returned message is normalized JSON string, so it can be safely casted to jsonb
+pub fn validate_json_response(
+    validator: &jsonschema::Validator,
+    message: &str,
+) -> Result<String, anyhow::Error> {
+    // Models without structured output support may wrap the JSON in markdown code block
+    let message = message.trim();
+    let message = message
+        .strip_prefix("```json")
+        .or(message.strip_prefix("```"))
+        .and_then(|m| m.strip_suffix("```"))
+        .unwrap_or(message)
+        .trim();
+
+    let value: serde_json::Value = match serde_json::from_str(message) {
+        Ok(value) => value,
+        Err(e) => anyhow::bail!("Response is not a valid JSON: {e}. Response: {message}"),
+    };
+
+    if let Err(e) = validator.validate(&value) {
+        anyhow::bail!("Response does not match the response_schema: {e}. Response: {message}");
+    }
+
+    Ok(value.to_string())
+}
+
 pub fn get_clean_model_name(name: &str, runtime: Runtime) -> String {
     // This is for backward compatabilty
     // Previously openai and cohere models were prefixed
@@ -0,0 +1,422 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::sync::Arc;
+
+use arrow_array::{
+    builder::{Float32Builder, ListBuilder, StringBuilder},
+    cast::AsArray,
+    Array, ArrayRef, BooleanArray, RecordBatch, StringArray,
+};
+use arrow_schema::{DataType, Field, Schema};
+use parquet::arrow::{arrow_reader::ParquetRecordBatchReaderBuilder, ArrowWriter};
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::task::JoinHandle;
+
+use super::cli::{EmbeddingArgs, EmbeddingJobType, FileFormat};
+use super::{calculate_progress, EmbeddingRecord, EmbeddingValue, InputRecord};
+use crate::logger::Logger;
+use crate::types::*;
+
+// Besides pk and output column each output record has the error message
+// of the failed row and the flag if the input was truncated to fit the model
+const ERROR_FIELD: &'static str = "error";
+const TRUNCATED_FIELD: &'static str = "truncated";
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn json_value_to_string(value: Option<&serde_json::Value>) -> Option<String> {
+    match value {
+        None | Some(serde_json::Value::Null) => None,
+        Some(serde_json::Value::String(value)) => Some(value.clone()),
+        Some(value) => Some(value.to_string()),
+    }
+}
+
+// Converts arrow array values to strings
+// Binary values are converted to Postgres hex format (\x...) the same way as bytea columns
+fn array_to_strings(array: &ArrayRef) -> Result<Vec<Option<String>>, anyhow::Error> {
+    let values = match array.data_type() {
+        DataType::Binary | DataType::LargeBinary => {
+            let array = arrow_cast::cast(array, &DataType::LargeBinary)?;
+            let array = array.as_binary::<i64>();
+            (0..array.len())
+                .map(|idx| {
+                    if array.is_null(idx) {
+                        None
+                    } else {
+                        Some(format!("\\x{}", encode_hex(array.value(idx))))
+                    }
+                })
+                .collect()
+        }
+        _ => {
+            let array = arrow_cast::cast(array, &DataType::Utf8)?;
+            let array = array.as_string::<i32>();
+            (0..array.len())
+                .map(|idx| {
+                    if array.is_null(idx) {
+                        None
+                    } else {
+                        Some(array.value(idx).to_owned())
+                    }
+                })
+                .collect()
+        }
+    };
+
+    Ok(values)
+}
+
+// Returns the number of records in file, used only for progress tracking
+pub async fn count_file_records(path: &str, format: FileFormat) -> Result<i64, anyhow::Error> {
+    let path = path.to_owned();
+    tokio::task::spawn_blocking(move || count_file_records_blocking(&path, format)).await?
+}
+
+fn count_file_records_blocking(path: &str, format: FileFormat) -> Result<i64, anyhow::Error> {
+    let count = match format {
+        FileFormat::Csv => csv::Reader::from_path(path)?.records().count(),
+        FileFormat::Jsonl => BufReader::new(File::open(path)?)
+            .lines()
+            .filter(|line| line.as_ref().map(|l| l.trim() != "").unwrap_or(true))
+            .count(),
+        FileFormat::Parquet => {
+            let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)?;
+            builder.metadata().file_metadata().num_rows() as usize
+        }
+    };
+
+    Ok(count as i64)
+}
+
+// Returns true if the column of the parquet file holds binary values,
+// which are passed to the runtime the same way as bytea columns
+pub async fn is_binary_column(
+    path: &str,
+    format: FileFormat,
+    column: &str,
+) -> Result<bool, anyhow::Error> {
+    if format != FileFormat::Parquet {
+        return Ok(false);
+    }
+
+    let path = path.to_owned();
+    let column = column.to_owned();
+    tokio::task::spawn_blocking(move || {
+        let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(&path)?)?;
+        let is_binary = match builder.schema().field_with_name(&column) {
+            Ok(field) => matches!(field.data_type(), DataType::Binary | DataType::LargeBinary),
+            Err(_) => false,
+        };
+        Ok(is_binary)
+    })
+    .await?
+}
+
+// Reads the pk and column fields from the file in batches
+// and sends them to embedding worker the same way as producer_worker does for tables
+// The file is read on blocking thread pool, so it will not block the async workers
+pub async fn file_producer_worker(
+    path: &str,
+    format: FileFormat,
+    pk: &str,
+    column: &str,
+    limit: Option<u32>,
+    batch_size: usize,
+    tx: Sender<Vec<InputRecord>>,
+) -> AnyhowVoidResult {
+    let path = path.to_owned();
+    let pk = pk.to_owned();
+    let column = column.to_owned();
+    tokio::task::spawn_blocking(move || {
+        read_file_records(&path, format, &pk, &column, limit, batch_size, tx)
+    })
+    .await?
+}
+
+fn read_file_records(
+    path: &str,
+    format: FileFormat,
+    pk: &str,
+    column: &str,
+    limit: Option<u32>,
+    batch_size: usize,
+    tx: Sender<Vec<InputRecord>>,
+) -> AnyhowVoidResult {
+    let mut remaining = limit.map(|limit| limit as usize).unwrap_or(usize::MAX);
+    let mut batch: Vec<InputRecord> = Vec::with_capacity(batch_size);
+
+    macro_rules! push_record {
+        ($record:expr) => {
+            if remaining == 0 {
+                break;
+            }
+            batch.push($record);
+            remaining -= 1;
+            if batch.len() == batch_size {
+                if tx.blocking_send(std::mem::take(&mut batch)).is_err() {
+                    return Ok(());
+                }
+            }
+        };
+    }
+
+    match format {
+        FileFormat::Csv => {
+            let mut reader = csv::Reader::from_path(path)?;
+            let headers = reader.headers()?.clone();
+            let pk_idx = headers
+                .iter()
+                .position(|header| header == pk)
+                .ok_or(anyhow::anyhow!("Field \"{pk}\" not found in {path}"))?;
+            let column_idx = headers
+                .iter()
+                .position(|header| header == column)
+                .ok_or(anyhow::anyhow!("Field \"{column}\" not found in {path}"))?;
+
+            for record in reader.records() {
+                let record = record?;
+                let value = record
+                    .get(column_idx)
+                    .filter(|value| *value != "")
+                    .map(|value| value.to_owned());
+                push_record!((record.get(pk_idx).unwrap_or("").to_owned(), value));
+            }
+        }
+        FileFormat::Jsonl => {
+            let reader = BufReader::new(File::open(path)?);
+            for (line_no, line) in reader.lines().enumerate() {
+                let line = line?;
+                if line.trim() == "" {
+                    continue;
+                }
+                let object: serde_json::Value = serde_json::from_str(&line)
+                    .map_err(|e| anyhow::anyhow!("Invalid JSON on line {}: {e}", line_no + 1))?;
+                let pk_value = json_value_to_string(object.get(pk)).ok_or(anyhow::anyhow!(
+                    "Field \"{pk}\" not found on line {}",
+                    line_no + 1
+                ))?;
+                push_record!((pk_value, json_value_to_string(object.get(column))));
+            }
+        }
+        FileFormat::Parquet => {
+            let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)?
+                .with_batch_size(batch_size)
+                .build()?;
+
+            'outer: for record_batch in reader {
+                let record_batch = record_batch?;
+                let pks = record_batch
+                    .column_by_name(pk)
+                    .ok_or(anyhow::anyhow!("Field \"{pk}\" not found in {path}"))?;
+                let values = record_batch
+                    .column_by_name(column)
+                    .ok_or(anyhow::anyhow!("Field \"{column}\" not found in {path}"))?;
+
+                for (pk_value, value) in array_to_strings(pks)?
+                    .into_iter()
+                    .zip(array_to_strings(values)?)
+                {
+                    if remaining == 0 {
+                        break 'outer;
+                    }
+                    push_record!((pk_value.unwrap_or_default(), value));
+                }
+            }
+        }
+    }
+
+    if !batch.is_empty() {
+        let _ = tx.blocking_send(batch);
+    }
+
+    Ok(())
+}
+
+enum FileWriter {
+    Csv(csv::Writer<File>),
+    Jsonl(BufWriter<File>),
+    Parquet(ArrowWriter<File>, Arc<Schema>),
+}
+
+impl FileWriter {
+    fn new(
+        path: &str,
+        format: FileFormat,
+        pk: &str,
+        column: &str,
+        job_type: &EmbeddingJobType,
+    ) -> Result<Self, anyhow::Error> {
+        let writer = match format {
+            FileFormat::Csv => {
+                let mut writer = csv::Writer::from_path(path)?;
+                writer.write_record([pk, column, ERROR_FIELD, TRUNCATED_FIELD])?;
+                FileWriter::Csv(writer)
+            }
+            FileFormat::Jsonl => FileWriter::Jsonl(BufWriter::new(File::create(path)?)),
+            FileFormat::Parquet => {
+                let value_type = match job_type {
+                    EmbeddingJobType::EmbeddingGeneration => {
+                        DataType::List(Arc::new(Field::new("item", DataType::Float32, true)))
+                    }
+                    EmbeddingJobType::Completion => DataType::Utf8,
+                };
+                let schema = Arc::new(Schema::new(vec![
+                    Field::new(pk, DataType::Utf8, false),
+                    Field::new(column, value_type, true),
+                    Field::new(ERROR_FIELD, DataType::Utf8, true),
+                    Field::new(TRUNCATED_FIELD, DataType::Boolean, false),
+                ]));
+                let writer = ArrowWriter::try_new(File::create(path)?, schema.clone(), None)?;
+                FileWriter::Parquet(writer, schema)
+            }
+        };
+
+        Ok(writer)
+    }
+
+    fn write(&mut self, pk: &str, column: &str, rows: &Vec<EmbeddingRecord>) -> AnyhowVoidResult {
+        match self {
+            FileWriter::Csv(writer) => {
+                for row in rows {
+                    let value = match &row.value {
+                        _ if row.is_empty() => String::new(),
+                        EmbeddingValue::Vector(value) => serde_json::to_string(value)?,
+                        EmbeddingValue::Text(value) => value.clone(),
+                    };
+                    writer.write_record([
+                        row.pk.as_str(),
+                        value.as_str(),
+                        row.failure.as_deref().unwrap_or(""),
+                        if row.truncation.is_some() {
+                            "true"
+                        } else {
+                            "false"
+                        },
+                    ])?;
+                }
+            }
+            FileWriter::Jsonl(writer) => {
+                for row in rows {
+                    let value = match &row.value {
+                        _ if row.is_empty() => serde_json::Value::Null,
+                        EmbeddingValue::Vector(value) => serde_json::json!(value),
+                        EmbeddingValue::Text(value) => serde_json::json!(value),
+                    };
+                    let mut object = serde_json::Map::new();
+                    object.insert(pk.to_owned(), serde_json::json!(row.pk));
+                    object.insert(column.to_owned(), value);
+                    object.insert(ERROR_FIELD.to_owned(), serde_json::json!(row.failure));
+                    object.insert(
+                        TRUNCATED_FIELD.to_owned(),
+                        serde_json::json!(row.truncation.is_some()),
+                    );
+                    writeln!(writer, "{}", serde_json::Value::Object(object))?;
+                }
+            }
+            FileWriter::Parquet(writer, schema) => {
+                let pks = StringArray::from_iter_values(rows.iter().map(|row| row.pk.as_str()));
+                let values: ArrayRef = match schema.field(1).data_type() {
+                    DataType::Utf8 => {
+                        let mut builder = StringBuilder::new();
+                        for row in rows {
+                            match &row.value {
+                                EmbeddingValue::Text(value) if !row.is_empty() => {
+                                    builder.append_value(value)
+                                }
+                                _ => builder.append_null(),
+                            }
+                        }
+                        Arc::new(builder.finish())
+                    }
+                    _ => {
+                        let mut builder = ListBuilder::new(Float32Builder::new());
+                        for row in rows {
+                            match &row.value {
+                                EmbeddingValue::Vector(value) if !row.is_empty() => {
+                                    builder.values().append_slice(value);
+                                    builder.append(true);
+                                }
+                                _ => builder.append_null(),
+                            }
+                        }
+                        Arc::new(builder.finish())
+                    }
+                };
+                let errors = StringArray::from_iter(rows.iter().map(|row| row.failure.as_deref()));
+                let truncated =
+                    BooleanArray::from_iter(rows.iter().map(|row| Some(row.truncation.is_some())));
+                let batch = RecordBatch::try_new(
+                    schema.clone(),
+                    vec![Arc::new(pks), values, Arc::new(errors), Arc::new(truncated)],
+                )?;
+                writer.write(&batch)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finish(self) -> AnyhowVoidResult {
+        match self {
+            FileWriter::Csv(mut writer) => writer.flush()?,
+            FileWriter::Jsonl(mut writer) => writer.flush()?,
+            FileWriter::Parquet(writer, _) => {
+                writer.close()?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// File exporter worker will receive the output embeddings mapped with row ids
+// over the channel and append them to the output file
+// The worker runs on blocking thread pool as the writers use synchronous file IO
+pub fn file_exporter_worker(
+    args: Arc<EmbeddingArgs>,
+    path: String,
+    format: FileFormat,
+    job_type: EmbeddingJobType,
+    mut rx: Receiver<Vec<EmbeddingRecord>>,
+    item_count: i64,
+    progress_cb: Option<ProgressCbFn>,
+    logger: Arc<Logger>,
+) -> Result<JoinHandle<AnyhowUsizeResult>, anyhow::Error> {
+    let mut writer = FileWriter::new(&path, format, &args.pk, &args.out_column, &job_type)?;
+
+    let handle = tokio::task::spawn_blocking(move || {
+        let mut processed_row_cnt = 0;
+        let mut old_progress = 0;
+
+        while let Some(rows) = rx.blocking_recv() {
+            writer.write(&args.pk, &args.out_column, &rows)?;
+            processed_row_cnt += rows.len();
+            let progress = calculate_progress(item_count, processed_row_cnt);
+
+            if progress > old_progress {
+                old_progress = progress;
+                logger.debug(&format!("Progress {progress}%",));
+                if let Some(cb) = &progress_cb {
+                    cb(progress);
+                }
+            }
+        }
+
+        if old_progress != 100 {
+            logger.debug("Progress 100%");
+            if let Some(cb) = &progress_cb {
+                cb(100);
+            }
+        }
+
+        writer.finish()?;
+        logger.info(&format!("Embeddings exported to file {path}"));
+
+        Ok(processed_row_cnt)
+    });
+
+    Ok(handle)
+}
@@ -1,10 +1,12 @@
 use std::{cmp, time::Instant};
 
 use super::{
-    cli::EmbeddingJobType,
-    core::{EmbeddingRuntime, Runtime},
+    cli::{EmbeddingJobType, OutputFormat},
+    core::{runtime::AvailableModel, EmbeddingRuntime, Runtime},
 };
 use crate::logger::{LogLevel, Logger};
+use serde::Serialize;
+use serde_json::json;
 use tokio_postgres::NoTls;
 use tokio_util::sync::CancellationToken;
 
@@ -18,6 +20,26 @@ static OUT_COLUMN_NAME: &'static str = "title_emb";
 static PK_NAME: &'static str = "id";
 static LOREM_TEXT: &'static str = "Lorem ipsum dolor sit amet, consectetur adipiscing elit. Integer efficitur sem dui, at ultricies velit congue nec. Aenean in neque nunc. Fusce a auctor elit. Proin convallis fringilla mauris ut congue. Donec pretium, justo lobortis pharetra finibus, nulla elit pretium magna, et elementum nisl turpis vitae arcu. Nam vitae enim non magna porttitor tristique. Suspendisse ac dapibus massa. Proin pulvinar felis sed lobortis sagittis. Etiam efficitur leo ut eros mollis, vel tempus justo faucibus. Integer iaculis sed elit vel blandit. Sed maximus libero tortor. Nam vitae dui euismod urna egestas tincidunt. Suspendisse ante felis, feugiat in metus ut, mollis consequat mi. Mauris quis augue vitae mi auctor rutrum. Nulla commodo pharetra erat, ac lacinia leo euismod a. Ut consequat mollis enim, id tristique metus vehicula vitae. Phasellus venenatis faucibus dolor. Morbi a metus odio. Aenean gravida eleifend ante. Proin at mi tristique, varius risus a, porttitor ligula. Vestibulum hendrerit pellentesque risus eu semper. Proin eu condimentum enim.";
 
+// Measured speed in emb/s for each text size
+#[derive(Serialize, Debug)]
+struct ModelSpeed {
+    #[serde(flatten)]
+    model: AvailableModel,
+    small_text_speed: usize,
+    large_text_speed: usize,
+    avg_speed: usize,
+    // Only measured for ORT runtime
+    mixed_text_speed_with_bucketing: Option<usize>,
+    mixed_text_speed_without_bucketing: Option<usize>,
+}
+
+// Returns runtime params with ORT length bucketing enabled or disabled
+fn with_length_bucketing(runtime_params: &str, enabled: bool) -> Result<String, anyhow::Error> {
+    let mut params: serde_json::Value = serde_json::from_str(runtime_params)?;
+    params["length_bucketing"] = json!(enabled);
+    Ok(params.to_string())
+}
+
 async fn measure_model_speed(
     runtime: &Runtime,
     runtime_params: &String,
@@ -51,15 +73,26 @@ async fn measure_model_speed(
             limit: Some(limit.clone()),
             filter: None,
             column_type: None,
+            out_json_columns: None,
             job_type: None,
             check_column_type: false,
             create_cast_fn: false,
             failed_rows_table: None,
+            batch_state_table: None,
             internal_schema: "".to_owned(),
             job_id: 0,
+            cache: false,
+            cache_table: "embedding_cache".to_owned(),
+            cache_max_age: None,
+            cache_max_entries: None,
+            input_type: None,
+            input_file: None,
+            input_format: None,
+            output_file: None,
+            output_format: None,
         };
         let start = Instant::now();
-        let (processed, _) = super::create_embeddings_from_db(
+        let (processed, _, _) = super::create_embeddings_from_db(
             args,
             false,
             None,
@@ -92,6 +125,7 @@ pub async fn start_speed_test(
     // connect to database
     let table_name_small = format!("{TABLE_NAME}_min");
     let table_name_large = format!("{TABLE_NAME}_max");
+    let table_name_mixed = format!("{TABLE_NAME}_mixed");
 
     let (client, connection) = tokio_postgres::connect(&args.uri, NoTls).await?;
 
@@ -102,6 +136,7 @@ pub async fn start_speed_test(
        SET search_path TO {SCHEMA_NAME};
        CREATE TABLE {table_name_small} ({PK_NAME} SERIAL PRIMARY KEY, {COLUMN_NAME} TEXT, {OUT_COLUMN_NAME} REAL[]);
        CREATE TABLE {table_name_large} ({PK_NAME} SERIAL PRIMARY KEY, {COLUMN_NAME} TEXT, {OUT_COLUMN_NAME} REAL[]);
+       CREATE TABLE {table_name_mixed} ({PK_NAME} SERIAL PRIMARY KEY, {COLUMN_NAME} TEXT, {OUT_COLUMN_NAME} REAL[]);
        INSERT INTO {table_name_small} SELECT generate_series(0, 5000), 'My small title text!';
        INSERT INTO {table_name_large} SELECT generate_series(0, 5000), 'title';
     ")).await?;
@@ -121,32 +156,40 @@ pub async fn start_speed_test(
         )
         .await?;
 
+    // Mixed length inputs to measure the effect of length bucketing
+    client
+        .execute(
+            &format!("INSERT INTO {table_name_mixed} SELECT i, left($1, (i * 97) % length($1) + 1) FROM generate_series(0, 5000) i;"),
+            &[&text],
+        )
+        .await?;
+
     let runtime = EmbeddingRuntime::new(&args.runtime, None, &args.runtime_params)?;
 
     let models: Vec<_> = runtime
         .get_available_models(EmbeddingJobType::EmbeddingGeneration)
         .await
         .1
-        .iter()
-        .filter_map(|el| {
+        .into_iter()
+        .filter(|el| {
             if let Some(model) = &args.model {
-                if el.0 == *model {
-                    return Some(model.clone());
-                }
-
-                return None;
+                return el.name == *model;
             }
 
-            if !el.1 {
-                return Some(el.0.clone());
-            }
-
-            None
+            !el.visual
         })
         .collect();
 
     let logger = logger.unwrap_or(Logger::new("Lantern Embeddings", LogLevel::Info));
-    for model_name in models {
+    // In JSON mode only the final result is printed
+    let log_info = |msg: &str| {
+        if args.format == OutputFormat::Text {
+            logger.info(msg);
+        }
+    };
+    let mut results = Vec::with_capacity(models.len());
+    for model in models {
+        let model_name = model.name.clone();
         let speed_max = measure_model_speed(
             &args.runtime,
             &args.runtime_params,
@@ -169,12 +212,55 @@ pub async fn start_speed_test(
         .await?;
         let speed_avg = (speed_min + speed_max) / 2;
 
-        logger.info(&format!("{model_name} max speed - {speed_max} emb/s"));
-        logger.info(&format!("{model_name} min speed - {speed_min} emb/s"));
-        logger.info(&format!("{model_name} avg speed - {speed_avg} emb/s"));
+        log_info(&format!("{model_name} max speed - {speed_max} emb/s"));
+        log_info(&format!("{model_name} min speed - {speed_min} emb/s"));
+        log_info(&format!("{model_name} avg speed - {speed_avg} emb/s"));
+
+        let mut model_speed = ModelSpeed {
+            model,
+            small_text_speed: speed_max,
+            large_text_speed: speed_min,
+            avg_speed: speed_avg,
+            mixed_text_speed_with_bucketing: None,
+            mixed_text_speed_without_bucketing: None,
+        };
+
+        if args.runtime == Runtime::Ort {
+            let mut mixed_speeds = Vec::with_capacity(2);
+            for length_bucketing in [true, false] {
+                let speed = measure_model_speed(
+                    &args.runtime,
+                    &with_length_bucketing(&args.runtime_params, length_bucketing)?,
+                    &model_name,
+                    &args.uri,
+                    &table_name_mixed,
+                    args.initial_limit,
+                    args.batch_size,
+                )
+                .await?;
+                mixed_speeds.push(speed);
+            }
+
+            log_info(&format!(
+                "{model_name} mixed length speed with bucketing - {} emb/s",
+                mixed_speeds[0]
+            ));
+            log_info(&format!(
+                "{model_name} mixed length speed without bucketing - {} emb/s",
+                mixed_speeds[1]
+            ));
+            model_speed.mixed_text_speed_with_bucketing = Some(mixed_speeds[0]);
+            model_speed.mixed_text_speed_without_bucketing = Some(mixed_speeds[1]);
+        }
+
+        results.push(model_speed);
     }
     client
         .execute(&format!("DROP SCHEMA {SCHEMA_NAME} CASCADE"), &[])
         .await?;
+
+    if args.format == OutputFormat::Json {
+        logger.print_raw(&serde_json::to_string(&results)?);
+    }
     Ok(())
 }
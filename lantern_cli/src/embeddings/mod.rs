@@ -1,66 +1,130 @@
 use crate::logger::{LogLevel, Logger};
 use crate::types::*;
-use crate::utils::{append_params_to_uri, get_full_table_name, quote_ident};
+use crate::utils::{append_params_to_uri, get_full_table_name, quote_ident, quote_literal};
 use bytes::BytesMut;
 use core::get_available_runtimes;
 use futures::SinkExt;
 use rand::Rng;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
 use std::sync::Arc;
 use std::time::Instant;
 use tokio::sync::mpsc::{self, Receiver, Sender};
 use tokio::task::JoinHandle;
 use tokio_util::sync::CancellationToken;
 
-use tokio_postgres::{Client, NoTls, Row};
+use tokio_postgres::{Client, NoTls, Transaction};
 
-use self::cli::EmbeddingJobType;
-use self::core::EmbeddingRuntime;
+use self::cache::{get_input_hash, EmbeddingCache};
+use self::cli::{EmbeddingJobType, FileFormat};
+use self::core::{
+    openai_batch::BatchState,
+    post_process::PostProcessParams,
+    runtime::{EmbeddingResult, InvalidInputError},
+    truncation::merge_skipped_result,
+    EmbeddingRuntime,
+};
 
+pub mod cache;
 pub mod cli;
+pub mod client;
 pub mod core;
+#[cfg(feature = "embeddings-export")]
+pub mod file_io;
 pub mod measure_speed;
 
+// Row of the source which is passed from producer to embedding worker
+// (primary key, input text)
+type InputRecord = (String, Option<String>);
+
+enum EmbeddingValue {
+    Vector(Vec<f32>),
+    Text(String),
+}
+
 struct EmbeddingRecord {
     pk: String,
-    record: BytesMut,
+    value: EmbeddingValue,
+    failure: Option<String>,
+    // Set if the input was truncated to fit the model
+    truncation: Option<String>,
 }
 
 impl EmbeddingRecord {
     fn from_vec(pk: String, value: Vec<f32>) -> EmbeddingRecord {
-        let mut buf = BytesMut::with_capacity(4);
-
-        if value.len() > 0 {
-            let chunk_size = 1024 * 1024; // 1 MB
-            buf = BytesMut::with_capacity(chunk_size);
-            buf.extend_from_slice("{".as_bytes());
-            let row_str: String = value.iter().map(|&x| x.to_string() + ",").collect();
-            buf.extend_from_slice(row_str[0..row_str.len() - 1].as_bytes());
-            drop(row_str);
-            buf.extend_from_slice("}".as_bytes());
-        } else {
-            buf.extend_from_slice("NULL".as_bytes());
+        EmbeddingRecord {
+            pk,
+            value: EmbeddingValue::Vector(value),
+            failure: None,
+            truncation: None,
         }
-
-        EmbeddingRecord { pk, record: buf }
     }
 
-    #[allow(dead_code)]
     fn from_string(pk: String, value: String) -> EmbeddingRecord {
-        let buf: BytesMut;
-        if value.len() > 0 {
-            buf = BytesMut::from(
+        EmbeddingRecord {
+            pk,
+            value: EmbeddingValue::Text(value),
+            failure: None,
+            truncation: None,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        match &self.value {
+            EmbeddingValue::Vector(value) => value.is_empty(),
+            EmbeddingValue::Text(value) => value.is_empty(),
+        }
+    }
+
+    // Writes the value in Postgres COPY text format
+    // If `as_bytes` is set, the vector of binary quantized embedding
+    // is written as bytea in hex format (\x...)
+    // Failed records are written as NULL, so placeholder values
+    // returned by the runtime will not end up in the target table
+    fn write_copy_value(&self, buf: &mut BytesMut, as_bytes: bool) {
+        if self.failure.is_some() || self.is_empty() {
+            buf.extend_from_slice("NULL".as_bytes());
+            return;
+        }
+
+        match &self.value {
+            EmbeddingValue::Vector(value) if as_bytes => {
+                buf.extend_from_slice("\\\\x".as_bytes());
+                let hex: String = value.iter().map(|&x| format!("{:02x}", x as u8)).collect();
+                buf.extend_from_slice(hex.as_bytes());
+            }
+            EmbeddingValue::Vector(value) => {
+                buf.extend_from_slice("{".as_bytes());
+                let row_str: String = value.iter().map(|&x| x.to_string() + ",").collect();
+                buf.extend_from_slice(row_str[0..row_str.len() - 1].as_bytes());
+                buf.extend_from_slice("}".as_bytes());
+            }
+            EmbeddingValue::Text(value) => buf.extend_from_slice(
                 value
                     .replace('\\', "\\\\")
                     .replace('\n', "\\n")
                     .replace('\r', "\\r")
                     .replace('\t', "\\t")
                     .as_bytes(),
-            )
-        } else {
-            buf = BytesMut::from("NULL".as_bytes());
+            ),
+        }
+    }
+
+    // Marks the record as failed, so it will be inserted into failed rows table
+    fn with_failure(self, failure: String) -> EmbeddingRecord {
+        EmbeddingRecord {
+            failure: Some(failure),
+            ..self
         }
+    }
 
-        EmbeddingRecord { pk, record: buf }
+    // Marks the record as truncated, so it will be inserted into failed rows table
+    // with truncated flag
+    fn with_truncation(self, truncation: String) -> EmbeddingRecord {
+        EmbeddingRecord {
+            truncation: Some(truncation),
+            ..self
+        }
     }
 }
 
@@ -132,23 +196,24 @@ async fn estimate_count(
 async fn producer_worker(
     client: &mut Client,
     pk: &str,
-    column: &str,
+    column_sql: &str,
     full_table_name: &str,
     filter_sql: &str,
     limit_sql: &str,
     batch_size: usize,
-    tx: Sender<Vec<Row>>,
+    tx: Sender<Vec<InputRecord>>,
 ) -> AnyhowVoidResult {
     let transaction = client.transaction().await?;
     // With portal we can execute a query and poll values from it in chunks
-    let portal = transaction.bind(
+    let portal = transaction
+        .bind(
             &format!(
-                "SELECT {pk}::text, {column}::text FROM {full_table_name} {filter_sql} {limit_sql};",
-                column = quote_ident(&column),
+                "SELECT {pk}::text, {column_sql} FROM {full_table_name} {filter_sql} {limit_sql};",
                 pk = quote_ident(&pk)
             ),
             &[],
-        ).await?;
+        )
+        .await?;
 
     loop {
         // poll batch_size rows from portal and send it to embedding thread via channel
@@ -158,31 +223,349 @@ async fn producer_worker(
             break;
         }
 
-        if tx.send(rows).await.is_err() {
+        let records = rows
+            .iter()
+            .map(|row| {
+                (
+                    row.get::<usize, String>(0),
+                    row.try_get::<usize, Option<String>>(1).ok().flatten(),
+                )
+            })
+            .collect();
+
+        if tx.send(records).await.is_err() {
             break;
         }
     }
     Ok(())
 }
 
+// If all parts of the batch fail at a bisection level with this many parts,
+// the error is considered not input related (e.g. invalid api token) and the bisection is stopped
+const MAX_FAILED_BISECTION_PARTS: usize = 8;
+
+// Appends the result of the next inputs, shifting their indices by the current input count
+fn append_result(result: &mut EmbeddingResult, next: EmbeddingResult) {
+    let offset = result.embeddings.len();
+    result.embeddings.extend(next.embeddings);
+    result.processed_tokens += next.processed_tokens;
+    result.failed_inputs.extend(
+        next.failed_inputs
+            .into_iter()
+            .map(|(idx, failure)| (idx + offset, failure)),
+    );
+    result.truncated_inputs.extend(
+        next.truncated_inputs
+            .into_iter()
+            .map(|(idx, message)| (idx + offset, message)),
+    );
+}
+
+fn split_range(range: Range<usize>) -> [Range<usize>; 2] {
+    let mid = range.start + range.len() / 2;
+    [range.start..mid, mid..range.end]
+}
+
+// If the batch fails (e.g. image is too large or the input was rejected by the provider)
+// it is bisected to find the failed inputs, so the rest of the batch can still be exported
+// Failed inputs get an empty embedding and are reported in failed_inputs
+pub async fn process_isolating_failures(
+    runtime: &EmbeddingRuntime<'_>,
+    model: &str,
+    inputs: &Vec<&str>,
+    logger: &Logger,
+) -> Result<EmbeddingResult, anyhow::Error> {
+    // Batch API reports failed requests per input, bisecting the batch would
+    // only submit the same requests again and wait for them for up to 24h
+    if runtime.uses_batch_api() {
+        return runtime.process(model, inputs).await;
+    }
+
+    let error = match runtime.process(model, inputs).await {
+        Ok(result) => return Ok(result),
+        Err(e) => e,
+    };
+
+    // A single input is reported as failed only if it was rejected because of its content,
+    // otherwise the error (e.g. invalid api token or network error) fails the batch
+    if inputs.len() == 1 {
+        if !error.is::<InvalidInputError>() {
+            return Err(error);
+        }
+
+        return Ok(EmbeddingResult {
+            embeddings: vec![Vec::new()],
+            processed_tokens: 0,
+            failed_inputs: vec![(0, error.to_string())],
+            truncated_inputs: Vec::new(),
+        });
+    }
+
+    logger.warn(&format!(
+        "Failed to process batch of {} inputs: {error}. Retrying in smaller batches to find the failed inputs",
+        inputs.len()
+    ));
+
+    let mut result = EmbeddingResult {
+        embeddings: Vec::new(),
+        processed_tokens: 0,
+        failed_inputs: Vec::new(),
+        truncated_inputs: Vec::new(),
+    };
+    let mut processed = Vec::with_capacity(inputs.len());
+    let mut failed = Vec::new();
+    let mut all_invalid_input_errors = true;
+    let mut parts = split_range(0..inputs.len()).to_vec();
+
+    while !parts.is_empty() {
+        let part_cnt = parts.len();
+        let mut failed_parts = Vec::new();
+        let mut failed_part_cnt = 0;
+
+        for part in parts {
+            match runtime.process(model, &inputs[part.clone()].to_vec()).await {
+                Ok(part_result) => {
+                    append_result(&mut result, part_result);
+                    processed.extend(part.clone());
+                }
+                Err(e) => {
+                    failed_part_cnt += 1;
+                    if part.len() == 1 {
+                        all_invalid_input_errors &= e.is::<InvalidInputError>();
+                        failed.push((part.start, e.to_string()));
+                    } else {
+                        failed_parts.extend(split_range(part));
+                    }
+                }
+            }
+        }
+
+        if failed_part_cnt == part_cnt && part_cnt >= MAX_FAILED_BISECTION_PARTS {
+            return Err(error);
+        }
+
+        parts = failed_parts;
+    }
+
+    // If every input fails on its own without being rejected for its content,
+    // the error is not input related either
+    if failed.len() == inputs.len() && !all_invalid_input_errors {
+        return Err(error);
+    }
+
+    Ok(merge_skipped_result(
+        inputs.len(),
+        &processed,
+        failed,
+        result,
+    ))
+}
+
+// Generates embeddings only for the inputs which are not found in cache
+// and stores the newly generated embeddings in cache
+// Returns the result for all inputs in the original order and the number of cache hits
+async fn process_with_cache(
+    runtime: &EmbeddingRuntime<'_>,
+    cache: &EmbeddingCache,
+    model: &str,
+    inputs: &Vec<&str>,
+    logger: &Logger,
+) -> Result<(EmbeddingResult, usize), anyhow::Error> {
+    let input_hashes: Vec<String> = inputs.iter().map(|input| get_input_hash(input)).collect();
+    let cached = cache.get(&input_hashes).await?;
+    let missing: Vec<usize> = (0..inputs.len())
+        .filter(|idx| !cached.contains_key(&input_hashes[*idx]))
+        .collect();
+    let cache_hits = inputs.len() - missing.len();
+
+    let result = if missing.is_empty() {
+        EmbeddingResult {
+            embeddings: Vec::new(),
+            processed_tokens: 0,
+            failed_inputs: Vec::new(),
+            truncated_inputs: Vec::new(),
+        }
+    } else {
+        let missing_inputs: Vec<&str> = missing.iter().map(|idx| inputs[*idx]).collect();
+        process_isolating_failures(runtime, model, &missing_inputs, logger).await?
+    };
+
+    // Do not cache the placeholder embeddings of failed inputs
+    let failed: HashSet<usize> = result.failed_inputs.iter().map(|(idx, _)| *idx).collect();
+    let truncated: HashMap<usize, &String> = result
+        .truncated_inputs
+        .iter()
+        .map(|(idx, message)| (*idx, message))
+        .collect();
+    cache
+        .insert(
+            missing
+                .iter()
+                .enumerate()
+                .filter(|(idx, _)| !failed.contains(idx))
+                .map(|(idx, input_idx)| {
+                    (
+                        input_hashes[*input_idx].clone(),
+                        &result.embeddings[idx],
+                        truncated.get(&idx).copied(),
+                    )
+                })
+                .collect(),
+        )
+        .await?;
+
+    // Cached inputs keep the truncation flag they were stored with
+    let mut truncated_inputs: Vec<(usize, String)> = result
+        .truncated_inputs
+        .into_iter()
+        .map(|(idx, truncation)| (missing[idx], truncation))
+        .collect();
+    let mut generated = result.embeddings.into_iter();
+    let embeddings = input_hashes
+        .iter()
+        .enumerate()
+        .map(|(idx, input_hash)| match cached.get(input_hash) {
+            Some((embedding, truncation)) => {
+                if let Some(truncation) = truncation {
+                    truncated_inputs.push((idx, truncation.clone()));
+                }
+                embedding.clone()
+            }
+            None => generated.next().unwrap(),
+        })
+        .collect();
+
+    Ok((
+        EmbeddingResult {
+            embeddings,
+            processed_tokens: result.processed_tokens,
+            failed_inputs: result
+                .failed_inputs
+                .into_iter()
+                .map(|(idx, failure)| (missing[idx], failure))
+                .collect(),
+            truncated_inputs,
+        },
+        cache_hits,
+    ))
+}
+
+// Max inputs submitted in a single Batch API job, OpenAI accepts up to 50000 requests per batch
+// and embedding requests contain multiple inputs, so the limit is applied to inputs
+const BATCH_API_MAX_INPUTS: usize = 50000;
+
+// Generates embeddings or completions for the inputs and maps the results to row ids
+// Returns the records, the number of processed tokens and cache hits
+async fn get_embedding_records(
+    runtime: &EmbeddingRuntime<'_>,
+    cache: Option<&EmbeddingCache>,
+    model: &str,
+    job_type: &EmbeddingJobType,
+    mut input_ids: Vec<String>,
+    inputs: &Vec<&str>,
+    logger: &Logger,
+) -> Result<(Vec<EmbeddingRecord>, usize, usize), anyhow::Error> {
+    let mut response_data = Vec::with_capacity(inputs.len());
+
+    match job_type {
+        EmbeddingJobType::EmbeddingGeneration => {
+            let (embedding_response, cache_hits) = match cache {
+                Some(cache) => process_with_cache(runtime, cache, model, inputs, logger).await?,
+                None => (
+                    process_isolating_failures(runtime, model, inputs, logger).await?,
+                    0,
+                ),
+            };
+            let embeddings = embedding_response.embeddings;
+            let mut failed_inputs: HashMap<usize, String> =
+                embedding_response.failed_inputs.into_iter().collect();
+            let mut truncated_inputs: HashMap<usize, String> =
+                embedding_response.truncated_inputs.into_iter().collect();
+
+            for (idx, (pk, embedding)) in input_ids.into_iter().zip(embeddings).enumerate() {
+                let mut record = EmbeddingRecord::from_vec(pk, embedding);
+                if let Some(truncation) = truncated_inputs.remove(&idx) {
+                    logger.debug(&format!("Row {}: {truncation}", record.pk));
+                    record = record.with_truncation(truncation);
+                }
+                match failed_inputs.remove(&idx) {
+                    Some(failure) => {
+                        logger.warn(&format!(
+                            "Failed to generate embedding for row {}: {failure}",
+                            record.pk
+                        ));
+                        response_data.push(record.with_failure(failure));
+                    }
+                    None => response_data.push(record),
+                }
+            }
+
+            Ok((
+                response_data,
+                embedding_response.processed_tokens,
+                cache_hits,
+            ))
+        }
+        EmbeddingJobType::Completion => {
+            let embedding_response = runtime.batch_completion(model, inputs).await?;
+            let mut messages = embedding_response.messages;
+            for _ in 0..messages.len() {
+                response_data.push(EmbeddingRecord::from_string(
+                    input_ids.pop().unwrap(),
+                    messages.pop().unwrap(),
+                ))
+            }
+
+            Ok((response_data, embedding_response.processed_tokens, 0))
+        }
+    }
+}
+
 // Embedding worker will listen to the producer channel
 // and execute embeddings_core's corresponding function to generate embeddings
 // we will here map each vector to it's row id before sending the results over channel
 // So we will get Vec<Row<String, String> and output Vec<(String, Vec<f32>)> the output will
 // contain generated embeddings for the text. If text will be null we will skip that row
+// If the runtime uses Batch API, the rows of the whole job are collected first and submitted
+// in a few large batches which are polled concurrently, instead of one remote batch per worker batch
 async fn embedding_worker(
     args: Arc<cli::EmbeddingArgs>,
-    mut rx: Receiver<Vec<Row>>,
+    mut rx: Receiver<Vec<InputRecord>>,
     tx: Sender<Vec<EmbeddingRecord>>,
     job_type: EmbeddingJobType,
     cancel_token: CancellationToken,
     logger: Arc<Logger>,
-) -> AnyhowUsizeResult {
+) -> Result<(usize, usize), anyhow::Error> {
     let mut count: usize = 0;
     let mut processed_tokens: usize = 0;
+    let mut cache_hits: usize = 0;
     let model = &args.model;
     let mut start = Instant::now();
-    let runtime = EmbeddingRuntime::new(&args.runtime, None, &args.runtime_params)?;
+    let mut runtime = EmbeddingRuntime::new(&args.runtime, None, &args.runtime_params)?;
+    runtime.set_batch_context(
+        cancel_token.clone(),
+        args.batch_state_table.as_ref().map(|table| BatchState {
+            uri: args.uri.clone(),
+            table: get_full_table_name(&args.internal_schema, table),
+            job_id: args.job_id,
+        }),
+    );
+    let cache = match job_type {
+        EmbeddingJobType::EmbeddingGeneration if args.cache => Some(
+            EmbeddingCache::new(
+                &args.uri,
+                &args.internal_schema,
+                &args.cache_table,
+                &args.runtime,
+                model,
+                &args.runtime_params,
+            )
+            .await?,
+        ),
+        _ => None,
+    };
+    let batch_api = runtime.uses_batch_api();
+    let mut batch_api_rows: Vec<(String, String)> = Vec::new();
 
     loop {
         tokio::select! {
@@ -199,11 +582,11 @@ async fn embedding_worker(
                 let mut inputs: Vec<&str> = Vec::with_capacity(rows.len());
                 let mut input_ids: Vec<String> = Vec::with_capacity(rows.len());
 
-                for row in &rows {
-                    if let Ok(Some(src_data)) = row.try_get::<usize, Option<&str>>(1) {
+                for (pk, src_data) in &rows {
+                    if let Some(src_data) = src_data {
                         if src_data.trim() != "" {
                             inputs.push(src_data);
-                            input_ids.push(row.get::<usize, String>(0));
+                            input_ids.push(pk.clone());
                         }
                     }
                 }
@@ -212,30 +595,16 @@ async fn embedding_worker(
                     continue;
                 }
 
-                let mut response_data = Vec::with_capacity(rows.len());
-
-                match job_type {
-                    EmbeddingJobType::EmbeddingGeneration => {
-                        let embedding_response = runtime.process(&model, &inputs).await?;
-                        processed_tokens += embedding_response.processed_tokens;
-                        let mut embeddings = embedding_response.embeddings;
-
-                        count += embeddings.len();
-                        for _ in 0..embeddings.len() {
-                            response_data.push(EmbeddingRecord::from_vec(input_ids.pop().unwrap(), embeddings.pop().unwrap()))
-                        }
-                    },
-                    EmbeddingJobType::Completion => {
-                        let embedding_response = runtime.batch_completion(&model, &inputs).await?;
-                        processed_tokens += embedding_response.processed_tokens;
-                        let mut messages = embedding_response.messages;
-                        count += messages.len();
-                        for _ in 0..messages.len() {
-                            response_data.push(EmbeddingRecord::from_string(input_ids.pop().unwrap(), messages.pop().unwrap()))
-                        }
-                    }
+                if batch_api {
+                    batch_api_rows.extend(input_ids.into_iter().zip(inputs.into_iter().map(|input| input.to_owned())));
+                    continue;
                 }
 
+                let (response_data, tokens, hits) = get_embedding_records(&runtime, cache.as_ref(), &model, &job_type, input_ids, &inputs, &logger).await?;
+                processed_tokens += tokens;
+                cache_hits += hits;
+                count += response_data.len();
+
                 let duration = start.elapsed().as_secs();
                 // avoid division by zero error
                 let duration = if duration > 0 { duration } else { 1 };
@@ -256,12 +625,118 @@ async fn embedding_worker(
         }
     }
 
+    if !batch_api_rows.is_empty() {
+        logger.info(&format!(
+            "Submitting {} rows via Batch API",
+            batch_api_rows.len()
+        ));
+
+        // Rows are sorted, so the batches will have the same inputs after restart
+        // and the stored batches will be resumed instead of submitting them again
+        batch_api_rows.sort();
+
+        let batch_futures = batch_api_rows.chunks(BATCH_API_MAX_INPUTS).map(|rows| {
+            let input_ids = rows.iter().map(|(pk, _)| pk.clone()).collect();
+            let inputs = rows.iter().map(|(_, input)| input.as_str()).collect();
+            let runtime = &runtime;
+            let cache = cache.as_ref();
+            let job_type = &job_type;
+            let logger = &logger;
+            async move {
+                get_embedding_records(runtime, cache, model, job_type, input_ids, &inputs, logger)
+                    .await
+            }
+        });
+
+        // The runtime will cancel the submitted batches if the job is cancelled
+        let results = futures::future::try_join_all(batch_futures).await?;
+
+        let batch_size = args
+            .batch_size
+            .unwrap_or(get_default_batch_size(&args.model))
+            .max(1);
+        'export: for (mut response_data, tokens, hits) in results {
+            processed_tokens += tokens;
+            cache_hits += hits;
+            count += response_data.len();
+
+            // Results are sent to exporter in worker batch sized chunks
+            while !response_data.is_empty() {
+                let rest = response_data.split_off(batch_size.min(response_data.len()));
+                if tx.send(response_data).await.is_err() {
+                    // Error occured in exporter worker and channel has been closed
+                    break 'export;
+                }
+                response_data = rest;
+            }
+        }
+    }
+
     if count > 0 {
         logger.info("Embedding generation finished, waiting to export results...");
     } else {
         logger.warn("No data to generate embeddings");
     }
-    Ok(processed_tokens)
+
+    if let Some(cache) = &cache {
+        if cache_hits > 0 {
+            logger.info(&format!("{cache_hits} embeddings were taken from cache"));
+        }
+        cache
+            .evict(args.cache_max_age, args.cache_max_entries)
+            .await?;
+    }
+
+    Ok((processed_tokens, cache_hits))
+}
+
+// Parses out_json_columns argument into (property, column_type) pairs
+fn parse_json_columns(
+    json_columns: &Option<String>,
+) -> Result<Vec<(String, String)>, anyhow::Error> {
+    let json_columns = match json_columns {
+        Some(json_columns) if json_columns.trim() != "" => json_columns,
+        _ => return Ok(Vec::new()),
+    };
+
+    let columns: serde_json::Map<String, serde_json::Value> = serde_json::from_str(json_columns)
+        .map_err(|e| anyhow::anyhow!("Invalid out_json_columns: {e}"))?;
+
+    columns
+        .into_iter()
+        .map(|(name, column_type)| match column_type.as_str() {
+            Some(column_type) => Ok((name, column_type.to_owned())),
+            None => anyhow::bail!(
+                "Invalid out_json_columns: type of column \"{name}\" should be a string"
+            ),
+        })
+        .collect()
+}
+
+// Resolves the types of out_json_columns with to_regtype, so only existing types
+// in their canonical form are put into the generated queries
+async fn resolve_json_column_types(
+    transaction: &Transaction<'_>,
+    json_columns: Vec<(String, String)>,
+) -> Result<Vec<(String, String)>, anyhow::Error> {
+    let mut resolved_columns = Vec::with_capacity(json_columns.len());
+    for (name, column_type) in json_columns {
+        let row = transaction
+            .query_one("SELECT to_regtype($1)::text", &[&column_type])
+            .await
+            .map_err(|e| {
+                anyhow::anyhow!("Invalid out_json_columns: type of column \"{name}\": {e}")
+            })?;
+
+        match row.get::<usize, Option<String>>(0) {
+            Some(resolved_type) => resolved_columns.push((name, resolved_type)),
+            None => anyhow::bail!(
+                "Invalid out_json_columns: type \"{column_type}\" of column \"{name}\" does not exist"
+            ),
+        }
+    }
+
+    Ok(resolved_columns)
 }
 
 fn get_update_query_and_col_type(
@@ -269,6 +744,7 @@ fn get_update_query_and_col_type(
     full_table_name: &str,
     temp_table_name: &str,
     column_type: &str,
+    json_columns: &[(String, String)],
 ) -> (String, String) {
     let column = args.out_column.clone();
     let cast_fn_name = get_full_table_name(&args.internal_schema, "ldb_try_cast");
@@ -282,7 +758,7 @@ fn get_update_query_and_col_type(
         failed_rows_sql = format!(
             "
             INSERT INTO {failed_rows_table} (job_id, row_id, value)
-            SELECT {job_id} as job_id, row_id, value FROM failed_rows;
+            SELECT {job_id} as job_id, row_id::text, value FROM failed_rows;
         ",
             job_id = args.job_id,
         );
@@ -301,13 +777,45 @@ fn get_update_query_and_col_type(
         )
     }
 
+    // Each JSON column will be set from the corresponding property of the response
+    // e.g "score" = (src."out"::jsonb->>'score')::INT
+    let json_columns_sql: String = json_columns
+        .iter()
+        .map(|(name, json_column_type)| {
+            format!(
+                ", {name} = (src.{column}::jsonb->>{property})::{json_column_type}",
+                name = quote_ident(name),
+                column = quote_ident(&column),
+                property = quote_literal(name),
+            )
+        })
+        .collect();
+
     if args.check_column_type {
         tmp_col_type = "TEXT".to_owned();
+        // If JSON columns are specified the response should be valid JSON
+        // and each of the properties should be castable to the column type
+        let json_check_sql: String = if json_columns.is_empty() {
+            "".to_owned()
+        } else {
+            let json_value = format!(
+                "{cast_fn_name}(src.{column}, NULL::JSONB)",
+                column = quote_ident(&column)
+            );
+            let mut json_check_sql = format!(" OR {json_value} IS NULL");
+            for (name, json_column_type) in json_columns {
+                json_check_sql.push_str(&format!(
+                    " OR ({json_value}->>{property} IS NOT NULL AND {cast_fn_name}({json_value}->>{property}, NULL::{json_column_type}) IS NULL)",
+                    property = quote_literal(name),
+                ));
+            }
+            json_check_sql
+        };
         type_check_sql = format!(
             "
                  failed_rows AS (
                     DELETE FROM {temp_table_name} src
-                    WHERE {cast_fn_name}(src.{column}, NULL::{column_type}) IS NULL
+                    WHERE {cast_fn_name}(src.{column}, NULL::{column_type}) IS NULL{json_check_sql}
                     RETURNING src.{pk} AS row_id, src.{column} AS value
                 ),
         ",
@@ -366,7 +874,7 @@ fn get_update_query_and_col_type(
                 WITH {type_check_sql} 
                 updated_rows AS (
                     UPDATE {full_table_name} dst
-                    SET {column} = src.{column}::{column_type}
+                    SET {column} = src.{column}::{column_type}{json_columns_sql}
                     FROM {temp_table_subquery} src
                     WHERE src.{pk} = dst.{pk}
                 ) 
@@ -381,6 +889,41 @@ fn get_update_query_and_col_type(
     (update_sql, tmp_col_type)
 }
 
+// Inserts the rows which failed to be processed by runtime (e.g image could not be loaded)
+// into the failed rows table
+async fn insert_failed_rows(
+    transaction: &Transaction<'_>,
+    args: &cli::EmbeddingArgs,
+    failed_rows: &mut Vec<(String, String, bool)>,
+) -> AnyhowVoidResult {
+    if failed_rows.is_empty() {
+        return Ok(());
+    }
+
+    if let Some(failed_rows_table) = &args.failed_rows_table {
+        let mut row_ids = Vec::with_capacity(failed_rows.len());
+        let mut values = Vec::with_capacity(failed_rows.len());
+        let mut truncated = Vec::with_capacity(failed_rows.len());
+        for (row_id, value, is_truncated) in failed_rows.drain(..) {
+            row_ids.push(row_id);
+            values.push(value);
+            truncated.push(is_truncated);
+        }
+        transaction
+            .execute(
+                &format!(
+                    "INSERT INTO {failed_rows_table} (job_id, row_id, value, truncated) SELECT $1, row_id, value, truncated FROM unnest($2::text[], $3::text[], $4::bool[]) AS f(row_id, value, truncated)",
+                    failed_rows_table = get_full_table_name(&args.internal_schema, failed_rows_table),
+                ),
+                &[&args.job_id, &row_ids, &values, &truncated],
+            )
+            .await?;
+    }
+
+    failed_rows.clear();
+    Ok(())
+}
+
 // DB exporter worker will create temp table with name _lantern_tmp_${rand(0,1000)}
 // Then it will create writer stream which will COPY bytes from stdin to that table
 // After that it will receiver the output embeddings mapped with row ids over the channel
@@ -407,6 +950,9 @@ async fn db_exporter_worker(
     let pk = args.pk.clone();
     let stream = args.stream;
     let full_table_name = get_full_table_name(&schema, &table);
+    let json_columns =
+        resolve_json_column_types(&transaction, parse_json_columns(&args.out_json_columns)?)
+            .await?;
 
     if args.create_column {
         transaction
@@ -418,6 +964,18 @@ async fn db_exporter_worker(
                 &[],
             )
             .await?;
+
+        for (name, json_column_type) in &json_columns {
+            transaction
+                .execute(
+                    &format!(
+                        "ALTER TABLE {full_table_name} ADD COLUMN IF NOT EXISTS {name} {json_column_type}",
+                        name = quote_ident(name)
+                    ),
+                    &[],
+                )
+                .await?;
+        }
     }
 
     // Try to check if user has write permissions to table
@@ -438,6 +996,7 @@ async fn db_exporter_worker(
         &full_table_name,
         &temp_table_name,
         &column_type,
+        &json_columns,
     );
 
     transaction.commit().await?;
@@ -473,12 +1032,19 @@ async fn db_exporter_worker(
         let mut collected_row_cnt = 0;
         let mut processed_row_cnt = 0;
         let mut old_progress = 0;
+        let mut failed_rows = Vec::new();
+        let write_bytes = tmp_col_type.eq_ignore_ascii_case("bytea");
 
         while let Some(rows) = rx.recv().await {
             for row in &rows {
+                if let Some(failure) = &row.failure {
+                    failed_rows.push((row.pk.clone(), failure.clone(), false));
+                } else if let Some(truncation) = &row.truncation {
+                    failed_rows.push((row.pk.clone(), truncation.clone(), true));
+                }
                 buf.extend_from_slice(row.pk.as_bytes());
                 buf.extend_from_slice("\t".as_bytes());
-                buf.extend_from_slice(&row.record);
+                row.write_copy_value(&mut buf, write_bytes);
                 buf.extend_from_slice("\n".as_bytes());
                 collected_row_cnt += 1;
             }
@@ -518,6 +1084,7 @@ async fn db_exporter_worker(
                 }
                 writer_sink.as_mut().finish().await?;
                 transaction.batch_execute(&update_sql).await?;
+                insert_failed_rows(&transaction, &args, &mut failed_rows).await?;
                 transaction.commit().await?;
                 transaction = client.transaction().await?;
 
@@ -526,7 +1093,9 @@ async fn db_exporter_worker(
 
                 writer_sink = Box::pin(
                     transaction
-                        .copy_in(&format!("COPY {temp_table_name} FROM stdin"))
+                        .copy_in(&format!(
+                            "COPY {temp_table_name} FROM stdin WITH NULL AS 'NULL' "
+                        ))
                         .await?,
                 );
                 collected_row_cnt = 0;
@@ -557,6 +1126,7 @@ async fn db_exporter_worker(
         }
         writer_sink.as_mut().finish().await?;
         transaction.batch_execute(&update_sql).await?;
+        insert_failed_rows(&transaction, &args, &mut failed_rows).await?;
         transaction.commit().await?;
         let duration = copy_start.elapsed().as_millis();
         logger.debug(&format!("Copied {collected_row_cnt} rows in {duration}ms"));
@@ -600,16 +1170,25 @@ pub fn get_default_batch_size(model: &str) -> usize {
         | "embed-english-light-v2.0"
         | "embed-multilingual-v2.0" => 5000,
         // Completion models
-        "gpt-4" | "gpt-4o" | "gpt-4-turbo" => 2,
-        "gpt-4o-mini" => 10,
+        "gpt-4" | "gpt-4o" | "gpt-4-turbo" | "gpt-4.1" => 2,
+        "gpt-4o-mini" | "gpt-4.1-mini" | "gpt-4.1-nano" | "gpt-3.5-turbo" => 10,
+        "command-r-plus" | "command-r-plus-08-2024" | "command" => 2,
+        "command-r" | "command-r-08-2024" | "command-r7b-12-2024" | "command-light" => 10,
         _ => 100,
     }
 }
 
-fn get_default_column_type(job_type: &EmbeddingJobType) -> String {
+fn get_default_column_type(
+    job_type: &EmbeddingJobType,
+    runtime_params: &str,
+) -> Result<String, anyhow::Error> {
     match job_type {
-        &EmbeddingJobType::Completion => "TEXT".to_owned(),
-        &EmbeddingJobType::EmbeddingGeneration => "REAL[]".to_owned(),
+        &EmbeddingJobType::Completion => Ok("TEXT".to_owned()),
+        &EmbeddingJobType::EmbeddingGeneration => {
+            Ok(PostProcessParams::from_params(runtime_params)?
+                .output_column_type()
+                .to_owned())
+        }
     }
 }
 
@@ -619,10 +1198,10 @@ pub async fn create_embeddings_from_db(
     progress_cb: Option<ProgressCbFn>,
     cancel_token: CancellationToken,
     logger: Option<Logger>,
-) -> Result<(usize, usize), anyhow::Error> {
+) -> Result<(usize, usize, usize), anyhow::Error> {
     let logger = Arc::new(logger.unwrap_or(Logger::new("Lantern Embeddings", LogLevel::Debug)));
     logger.info("Lantern CLI - Create Embeddings");
-    let args = Arc::new(args);
+    let mut args = args.with_input_type()?;
     let batch_size = args
         .batch_size
         .unwrap_or(get_default_batch_size(&args.model));
@@ -639,12 +1218,23 @@ pub async fn create_embeddings_from_db(
         .job_type
         .clone()
         .unwrap_or(EmbeddingJobType::EmbeddingGeneration);
-    let column_type = args
-        .column_type
-        .clone()
-        .unwrap_or(get_default_column_type(&job_type));
+    let column_type = match &args.column_type {
+        Some(column_type) => column_type.clone(),
+        None => get_default_column_type(&job_type, &args.runtime_params)?,
+    };
     let full_table_name = get_full_table_name(&schema, &table);
 
+    if (args.input_file.is_none() || args.output_file.is_none() || args.cache)
+        && (args.uri.is_empty() || args.table.is_empty())
+    {
+        anyhow::bail!("'uri' and 'table' are required if input or output is not a file");
+    }
+
+    #[cfg(not(feature = "embeddings-export"))]
+    if args.input_file.is_some() || args.output_file.is_some() {
+        anyhow::bail!("Reading from and writing to files is not enabled");
+    }
+
     let filter_sql = if args.filter.is_some() {
         format!("WHERE {}", args.filter.as_ref().unwrap())
     } else {
@@ -658,39 +1248,149 @@ pub async fn create_embeddings_from_db(
     };
 
     let uri = append_params_to_uri(&args.uri, CONNECTION_PARAMS);
-    let (mut client, connection) = tokio_postgres::connect(&uri, NoTls).await?;
-
-    tokio::spawn(async move { connection.await });
+    let input_file = match &args.input_file {
+        Some(path) => Some((
+            path.clone(),
+            FileFormat::from_path(path, args.input_format)?,
+        )),
+        None => None,
+    };
 
     let mut item_cnt = 0;
-    if track_progress {
-        item_cnt = estimate_count(
-            &mut client,
-            &full_table_name,
-            &filter_sql,
-            &limit_sql,
-            logger.clone(),
-        )
-        .await?;
+    // When reading from table we will connect to the database
+    // and get the SQL to select the source column
+    let mut db_source = None;
+    match &input_file {
+        #[cfg(not(feature = "embeddings-export"))]
+        Some(_) => unreachable!(),
+        #[cfg(feature = "embeddings-export")]
+        Some((path, format)) => {
+            if args.filter.is_some() {
+                anyhow::bail!("'filter' is not supported when reading from file");
+            }
+
+            if file_io::is_binary_column(path, *format, &args.column).await? {
+                args = args.with_bytea_input()?;
+            }
+
+            if track_progress {
+                item_cnt = file_io::count_file_records(path, *format).await?;
+                if let Some(limit) = args.limit {
+                    item_cnt = item_cnt.min(limit as i64);
+                }
+            }
+        }
+        None => {
+            let (mut client, connection) = tokio_postgres::connect(&uri, NoTls).await?;
+
+            tokio::spawn(async move { connection.await });
+
+            // Images can be stored in bytea columns, in this case the values
+            // will be passed to the runtime in Postgres hex format (\x...)
+            let src_column_type = client
+                .query_opt(
+                    "SELECT atttypid::regtype::text FROM pg_attribute WHERE attrelid = $1::text::regclass AND attname = $2",
+                    &[&full_table_name, &column],
+                )
+                .await?
+                .map(|row| row.get::<usize, String>(0));
+
+            let column_sql = if src_column_type.as_deref() == Some("bytea") {
+                args = args.with_bytea_input()?;
+                format!(
+                    "'\\x' || encode({column}, 'hex')",
+                    column = quote_ident(&column)
+                )
+            } else {
+                format!("{column}::text", column = quote_ident(&column))
+            };
+
+            if track_progress {
+                item_cnt = estimate_count(
+                    &mut client,
+                    &full_table_name,
+                    &filter_sql,
+                    &limit_sql,
+                    logger.clone(),
+                )
+                .await?;
+            }
+
+            db_source = Some((client, column_sql));
+        }
     }
-    // Create channel that will send the database rows to embedding worker
-    let (producer_tx, producer_rx): (Sender<Vec<Row>>, Receiver<Vec<Row>>) = mpsc::channel(1);
+    let args = Arc::new(args);
+
+    // Create channel that will send the source rows to embedding worker
+    let (producer_tx, producer_rx): (Sender<Vec<InputRecord>>, Receiver<Vec<InputRecord>>) =
+        mpsc::channel(1);
     let (embedding_tx, embedding_rx): (
         Sender<Vec<EmbeddingRecord>>,
         Receiver<Vec<EmbeddingRecord>>,
     ) = mpsc::channel(1);
 
     // Create exporter based on provided args
-    let exporter_handle = db_exporter_worker(
-        &uri,
-        args.clone(),
-        embedding_rx,
-        item_cnt,
-        column_type,
-        progress_cb,
-        logger.clone(),
-    )
-    .await?;
+    let exporter_handle = match &args.output_file {
+        #[cfg(not(feature = "embeddings-export"))]
+        Some(_) => unreachable!(),
+        #[cfg(feature = "embeddings-export")]
+        Some(path) => file_io::file_exporter_worker(
+            args.clone(),
+            path.clone(),
+            FileFormat::from_path(path, args.output_format)?,
+            job_type.clone(),
+            embedding_rx,
+            item_cnt,
+            progress_cb,
+            logger.clone(),
+        )?,
+        None => {
+            db_exporter_worker(
+                &uri,
+                args.clone(),
+                embedding_rx,
+                item_cnt,
+                column_type,
+                progress_cb,
+                logger.clone(),
+            )
+            .await?
+        }
+    };
+
+    let producer = async {
+        match (&input_file, &mut db_source) {
+            #[cfg(not(feature = "embeddings-export"))]
+            (Some(_), _) => unreachable!(),
+            #[cfg(feature = "embeddings-export")]
+            (Some((path, format)), _) => {
+                file_io::file_producer_worker(
+                    path,
+                    *format,
+                    &args.pk,
+                    &column,
+                    args.limit,
+                    batch_size,
+                    producer_tx,
+                )
+                .await
+            }
+            (None, Some((client, column_sql))) => {
+                producer_worker(
+                    client,
+                    &args.pk,
+                    column_sql,
+                    &full_table_name,
+                    &filter_sql,
+                    &limit_sql,
+                    batch_size,
+                    producer_tx,
+                )
+                .await
+            }
+            (None, None) => unreachable!(),
+        }
+    };
 
     let (exporter_result, embedding_result, producer_result) = tokio::join!(
         exporter_handle,
@@ -702,22 +1402,13 @@ pub async fn create_embeddings_from_db(
             cancel_token,
             logger.clone(),
         ),
-        producer_worker(
-            &mut client,
-            &args.pk,
-            &args.column,
-            &full_table_name,
-            &filter_sql,
-            &limit_sql,
-            batch_size,
-            producer_tx,
-        ),
+        producer,
     );
 
     producer_result?;
-    let processed_tokens = embedding_result?;
+    let (processed_tokens, cache_hits) = embedding_result?;
     let processed_rows = exporter_result??;
-    Ok((processed_rows, processed_tokens))
+    Ok((processed_rows, processed_tokens, cache_hits))
 }
 
 pub async fn show_available_models(
@@ -725,26 +1416,44 @@ pub async fn show_available_models(
     logger: Option<Logger>,
 ) -> AnyhowVoidResult {
     let logger = logger.unwrap_or(Logger::new("Lantern Embeddings", LogLevel::Info));
-    logger.info("Available Models\n");
     let runtime = EmbeddingRuntime::new(&args.runtime, None, &args.runtime_params)?;
-    logger.print_raw(
-        &runtime
-            .get_available_models(
-                args.job_type
-                    .clone()
-                    .unwrap_or(EmbeddingJobType::EmbeddingGeneration),
-            )
-            .await
-            .0,
-    );
+    let (models_str, models) = runtime
+        .get_available_models(
+            args.job_type
+                .clone()
+                .unwrap_or(EmbeddingJobType::EmbeddingGeneration),
+        )
+        .await;
+
+    match args.format {
+        cli::OutputFormat::Text => {
+            logger.info("Available Models\n");
+            logger.print_raw(&models_str);
+        }
+        cli::OutputFormat::Json => {
+            logger.print_raw(&serde_json::to_string(&models)?);
+        }
+    }
     Ok(())
 }
 
-pub fn show_available_runtimes(logger: Option<Logger>) -> AnyhowVoidResult {
+pub fn show_available_runtimes(
+    args: &cli::ShowRuntimesArgs,
+    logger: Option<Logger>,
+) -> AnyhowVoidResult {
     let logger = logger.unwrap_or(Logger::new("Lantern Embeddings", LogLevel::Info));
-    let mut runtimes_str = get_available_runtimes().join("\n");
-    runtimes_str.push_str("\n");
-    logger.info("Available Runtimes\n");
-    logger.print_raw(&runtimes_str);
+    let runtimes = get_available_runtimes();
+
+    match args.format {
+        cli::OutputFormat::Text => {
+            let mut runtimes_str = runtimes.join("\n");
+            runtimes_str.push_str("\n");
+            logger.info("Available Runtimes\n");
+            logger.print_raw(&runtimes_str);
+        }
+        cli::OutputFormat::Json => {
+            logger.print_raw(&serde_json::to_string(&runtimes)?);
+        }
+    }
     Ok(())
 }
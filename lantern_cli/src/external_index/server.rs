@@ -3,11 +3,10 @@ use super::cli::{IndexServerArgs, UMetricKind};
 use actix_web::{web, App, HttpResponse, HttpServer, Responder};
 use glob::glob;
 use rand::Rng;
-use rustls::pki_types::{CertificateDer, PrivateKeyDer};
 use rustls::{ServerConfig, ServerConnection, StreamOwned};
 use std::cmp;
-use std::fs::{self, File};
-use std::io::{BufReader, Read, Write};
+use std::fs;
+use std::io::{Read, Write};
 use std::net::{TcpListener, TcpStream};
 use std::os::unix::fs::MetadataExt;
 use std::path::Path;
@@ -19,6 +18,7 @@ use usearch::Index;
 
 use crate::logger::{LogLevel, Logger};
 use crate::types::*;
+use crate::utils::tls::{load_certs, load_private_key};
 
 const CHAR_BITS: usize = 8;
 const LABEL_SIZE: usize = 8;
@@ -434,19 +434,6 @@ pub fn create_streaming_usearch_index(
     Ok(())
 }
 
-fn load_certs(path: String) -> Result<Vec<CertificateDer<'static>>, std::io::Error> {
-    let certfile = File::open(path).expect("Cannot open certificate file");
-    let mut reader = BufReader::new(certfile);
-    rustls_pemfile::certs(&mut reader).collect::<Result<Vec<CertificateDer>, _>>()
-}
-
-fn load_private_key(path: String) -> Result<PrivateKeyDer<'static>, anyhow::Error> {
-    Ok(
-        rustls_pemfile::private_key(&mut BufReader::new(&mut File::open(path)?))?
-            .expect("Can not load key file"),
-    )
-}
-
 fn initialize_listener(
     args: &IndexServerArgs,
 ) -> Result<(TcpListener, Option<Arc<ServerConfig>>), anyhow::Error> {
@@ -455,8 +442,8 @@ fn initialize_listener(
         // initialize tls socket
         let cert_path = args.cert.clone().unwrap();
         let key_path = args.key.clone().unwrap();
-        let certs = load_certs(cert_path)?;
-        let key = load_private_key(key_path)?;
+        let certs = load_certs(&cert_path)?;
+        let key = load_private_key(&key_path)?;
         // Configure rustls
         config = Some(Arc::new(
             ServerConfig::builder()
@@ -0,0 +1,194 @@
+use actix_web::{
+    delete,
+    error::{ErrorBadRequest, ErrorInternalServerError, ErrorNotFound},
+    get,
+    http::StatusCode,
+    post, web, HttpResponse, Responder, Result,
+};
+use serde::{Deserialize, Serialize};
+use tokio_postgres::Row;
+
+use super::{
+    auth::{generate_api_key, hash_api_key, Principal, Scope},
+    AppState, API_KEYS_TABLE_NAME,
+};
+
+static API_KEY_COLUMNS: &'static str = "id, name, scope, collections, rate_limit, created_at::text";
+
+#[derive(Serialize, Debug, utoipa::ToSchema)]
+pub struct ApiKeyInfo {
+    id: i64,
+    name: Option<String>,
+    scope: Scope,
+    collections: Option<Vec<String>>,
+    rate_limit: Option<i32>,
+    created_at: String,
+}
+
+impl ApiKeyInfo {
+    fn from_row(row: &Row) -> Result<Self> {
+        Ok(Self {
+            id: row.get::<usize, i64>(0),
+            name: row.get::<usize, Option<String>>(1),
+            scope: row
+                .get::<usize, &str>(2)
+                .parse()
+                .map_err(ErrorInternalServerError)?,
+            collections: row.get::<usize, Option<Vec<String>>>(3),
+            rate_limit: row.get::<usize, Option<i32>>(4),
+            created_at: row.get::<usize, String>(5),
+        })
+    }
+}
+
+#[derive(Serialize, Debug, utoipa::ToSchema)]
+pub struct CreatedApiKey {
+    key: String,
+    #[serde(flatten)]
+    info: ApiKeyInfo,
+}
+
+#[derive(Deserialize, Debug, utoipa::ToSchema)]
+pub struct CreateApiKeyInput {
+    name: Option<String>,
+    scope: Scope,
+    collections: Option<Vec<String>>,
+    rate_limit: Option<i32>,
+}
+
+/// Create new API key
+///
+/// The key should be passed in `Authorization: Bearer {key}` header.
+///
+/// Scope can be one of:
+/// - `read` - get collections and search
+/// - `write` - `read` and insert data into collections
+/// - `admin` - `write` and create/delete collections and indexes, quantize collections
+///
+/// If `collections` are specified the key can only access the listed collections,
+/// managing API keys requires `admin` scope without collection restrictions.
+///
+/// The `rate_limit` param limits the number of requests per minute for the key
+///
+/// The key is returned only once, only its hash is stored in the database
+#[utoipa::path(
+    post,
+    path = "/api-keys",
+    request_body (
+        content = CreateApiKeyInput,
+        example = json!(r#"{ "name": "search-service", "scope": "read", "collections": ["my_test_collection"], "rate_limit": 600 }"#)
+    ),
+    responses(
+        (status = 200, description = "Returns the created key", body = CreatedApiKey),
+        (status = 400, description = "Bad request"),
+        (status = 403, description = "Forbidden"),
+        (status = 500, description = "Internal Server Error")
+    )
+)]
+#[post("/api-keys")]
+pub async fn create_key(
+    data: web::Data<AppState>,
+    principal: web::ReqData<Principal>,
+    body: web::Json<CreateApiKeyInput>,
+) -> Result<impl Responder> {
+    principal.authorize(Scope::Admin, None)?;
+
+    if body.rate_limit.is_some_and(|limit| limit <= 0) {
+        return Err(ErrorBadRequest("rate_limit should be positive"));
+    }
+
+    let client = data.pool.get().await?;
+    let key = generate_api_key();
+
+    let row = client
+        .query_one(
+            &format!("INSERT INTO {API_KEYS_TABLE_NAME} (name, key_hash, scope, collections, rate_limit) VALUES ($1, $2, $3, $4, $5) RETURNING {API_KEY_COLUMNS}"),
+            &[
+                &body.name,
+                &hash_api_key(&key),
+                &body.scope.as_str(),
+                &body.collections,
+                &body.rate_limit,
+            ],
+        )
+        .await
+        .map_err(ErrorInternalServerError)?;
+    data.key_cache.invalidate();
+
+    Ok(web::Json(CreatedApiKey {
+        key,
+        info: ApiKeyInfo::from_row(&row)?,
+    }))
+}
+
+/// Get all active API keys
+#[utoipa::path(
+    get,
+    path = "/api-keys",
+    responses(
+        (status = 200, description = "Returns active API keys", body = Vec<ApiKeyInfo>),
+        (status = 403, description = "Forbidden"),
+        (status = 500, description = "Internal Server Error")
+    )
+)]
+#[get("/api-keys")]
+pub async fn list_keys(
+    data: web::Data<AppState>,
+    principal: web::ReqData<Principal>,
+) -> Result<impl Responder> {
+    principal.authorize(Scope::Admin, None)?;
+
+    let client = data.pool.get().await?;
+    let rows = client
+        .query(
+            &format!("SELECT {API_KEY_COLUMNS} FROM {API_KEYS_TABLE_NAME} WHERE revoked_at IS NULL ORDER BY id"),
+            &[],
+        )
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let keys = rows
+        .iter()
+        .map(ApiKeyInfo::from_row)
+        .collect::<Result<Vec<ApiKeyInfo>>>()?;
+
+    Ok(web::Json(keys))
+}
+
+/// Revoke the API key by id
+#[utoipa::path(
+    delete,
+    path = "/api-keys/{id}",
+    responses(
+        (status = 200, description = "API key successfully revoked"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "API key not found"),
+    ),
+    params(
+       ("id", description = "API key id")
+    ),
+)]
+#[delete("/api-keys/{id}")]
+pub async fn revoke_key(
+    data: web::Data<AppState>,
+    principal: web::ReqData<Principal>,
+    id: web::Path<i64>,
+) -> Result<impl Responder> {
+    principal.authorize(Scope::Admin, None)?;
+
+    let client = data.pool.get().await?;
+    let updated = client
+        .execute(
+            &format!("UPDATE {API_KEYS_TABLE_NAME} SET revoked_at=now() WHERE id=$1 AND revoked_at IS NULL"),
+            &[&id.into_inner()],
+        )
+        .await
+        .map_err(ErrorInternalServerError)?;
+    data.key_cache.invalidate();
+
+    if updated == 0 {
+        return Err(ErrorNotFound("API key not found"));
+    }
+
+    Ok(HttpResponse::new(StatusCode::from_u16(200).unwrap()))
+}
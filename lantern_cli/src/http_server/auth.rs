@@ -0,0 +1,303 @@
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    error::{ErrorForbidden, ErrorInternalServerError, ErrorTooManyRequests, InternalError},
+    http::header::{Header, WWW_AUTHENTICATE},
+    middleware::Next,
+    Error, HttpMessage, HttpResponse,
+};
+use actix_web_httpauth::headers::authorization::{Authorization, Basic, Bearer};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::{AppData, API_KEYS_TABLE_NAME};
+
+static API_KEY_PREFIX: &'static str = "lk_";
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+const KEY_CACHE_TTL: Duration = Duration::from_secs(10);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, PartialOrd, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    Read,
+    Write,
+    Admin,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::Write => "write",
+            Scope::Admin => "admin",
+        }
+    }
+}
+
+impl FromStr for Scope {
+    type Err = anyhow::Error;
+
+    fn from_str(scope: &str) -> Result<Self, Self::Err> {
+        match scope {
+            "read" => Ok(Scope::Read),
+            "write" => Ok(Scope::Write),
+            "admin" => Ok(Scope::Admin),
+            _ => anyhow::bail!("Invalid scope '{scope}'"),
+        }
+    }
+}
+
+// Authenticated caller of the request, inserted into request extensions by `authenticate` middleware
+// Basic auth credentials from the server args have admin scope over all the collections
+#[derive(Debug, Clone)]
+pub struct Principal {
+    pub key_id: Option<i64>,
+    pub scope: Scope,
+    // If set the key can only access the listed collections
+    pub collections: Option<Vec<String>>,
+    pub rate_limit: Option<i32>,
+}
+
+impl Principal {
+    fn admin() -> Self {
+        Self {
+            key_id: None,
+            scope: Scope::Admin,
+            collections: None,
+            rate_limit: None,
+        }
+    }
+
+    pub fn can_access(&self, collection: &str) -> bool {
+        match &self.collections {
+            None => true,
+            Some(collections) => collections.iter().any(|c| c == collection),
+        }
+    }
+
+    // Checks that the principal has at least the `scope` on the collection
+    // If collection is not specified the principal should not be restricted to specific collections
+    pub fn authorize(&self, scope: Scope, collection: Option<&str>) -> Result<(), Error> {
+        if self.scope < scope {
+            return Err(ErrorForbidden(format!(
+                "'{}' scope is required for this operation",
+                scope.as_str()
+            )));
+        }
+
+        let allowed = match collection {
+            Some(collection) => self.can_access(collection),
+            None => self.collections.is_none(),
+        };
+
+        if !allowed {
+            return Err(ErrorForbidden(match collection {
+                Some(collection) => format!("Access to collection '{collection}' is not allowed"),
+                None => "Access to all collections is required for this operation".to_owned(),
+            }));
+        }
+
+        Ok(())
+    }
+}
+
+pub fn generate_api_key() -> String {
+    let bytes: [u8; 32] = rand::thread_rng().gen();
+    let key: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{API_KEY_PREFIX}{key}")
+}
+
+// Only the hashes of the keys are stored in the database
+pub fn hash_api_key(key: &str) -> String {
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
+
+// Fixed window rate limiter for API keys with `rate_limit` (requests per minute)
+#[derive(Default)]
+pub struct RateLimiter {
+    windows: Mutex<HashMap<i64, (Instant, i32)>>,
+}
+
+impl RateLimiter {
+    fn check(&self, key_id: i64, limit: i32) -> bool {
+        let mut windows = self.windows.lock().unwrap();
+        let now = Instant::now();
+        let window = windows.entry(key_id).or_insert((now, 0));
+
+        if now.duration_since(window.0) >= RATE_LIMIT_WINDOW {
+            *window = (now, 0);
+        }
+
+        if window.1 >= limit {
+            return false;
+        }
+
+        window.1 += 1;
+        true
+    }
+}
+
+// Caches API key lookups, so the keys table is not queried on each request
+// The cache is cleared when keys are created or revoked via this server,
+// changes made by other servers are picked up after `KEY_CACHE_TTL`
+// Only valid keys are cached, so requests with random keys can not grow the cache
+#[derive(Default)]
+pub struct KeyCache {
+    principals: Mutex<HashMap<String, (Instant, Principal)>>,
+    has_keys: Mutex<Option<(Instant, bool)>>,
+}
+
+impl KeyCache {
+    fn get_principal(&self, key_hash: &str) -> Option<Principal> {
+        let principals = self.principals.lock().unwrap();
+        match principals.get(key_hash) {
+            Some((cached_at, principal)) if cached_at.elapsed() < KEY_CACHE_TTL => {
+                Some(principal.clone())
+            }
+            _ => None,
+        }
+    }
+
+    fn set_principal(&self, key_hash: String, principal: Principal) {
+        let mut principals = self.principals.lock().unwrap();
+        principals.retain(|_, (cached_at, _)| cached_at.elapsed() < KEY_CACHE_TTL);
+        principals.insert(key_hash, (Instant::now(), principal));
+    }
+
+    fn get_has_keys(&self) -> Option<bool> {
+        match *self.has_keys.lock().unwrap() {
+            Some((cached_at, has_keys)) if cached_at.elapsed() < KEY_CACHE_TTL => Some(has_keys),
+            _ => None,
+        }
+    }
+
+    fn set_has_keys(&self, has_keys: bool) {
+        *self.has_keys.lock().unwrap() = Some((Instant::now(), has_keys));
+    }
+
+    pub fn invalidate(&self) {
+        self.principals.lock().unwrap().clear();
+        *self.has_keys.lock().unwrap() = None;
+    }
+}
+
+// Includes the Basic auth challenge, so the browser will ask credentials for Swagger UI
+fn unauthorized(message: &'static str) -> Error {
+    InternalError::from_response(
+        message,
+        HttpResponse::Unauthorized()
+            .insert_header((WWW_AUTHENTICATE, "Basic realm=\"Lantern HTTP API\""))
+            .body(message),
+    )
+    .into()
+}
+
+async fn get_api_key_principal(data: &AppData, key: &str) -> Result<Option<Principal>, Error> {
+    let key_hash = hash_api_key(key);
+    if let Some(principal) = data.key_cache.get_principal(&key_hash) {
+        return Ok(Some(principal));
+    }
+
+    let client = data.pool.get().await?;
+    let row = client
+        .query_opt(
+            &format!("SELECT id, scope, collections, rate_limit FROM {API_KEYS_TABLE_NAME} WHERE key_hash=$1 AND revoked_at IS NULL"),
+            &[&key_hash],
+        )
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let row = match row {
+        None => return Ok(None),
+        Some(row) => row,
+    };
+
+    let principal = Principal {
+        key_id: Some(row.get::<usize, i64>(0)),
+        scope: row
+            .get::<usize, &str>(1)
+            .parse()
+            .map_err(ErrorInternalServerError)?,
+        collections: row.get::<usize, Option<Vec<String>>>(2),
+        rate_limit: row.get::<usize, Option<i32>>(3),
+    };
+    data.key_cache.set_principal(key_hash, principal.clone());
+
+    Ok(Some(principal))
+}
+
+async fn has_api_keys(data: &AppData) -> Result<bool, Error> {
+    if let Some(has_keys) = data.key_cache.get_has_keys() {
+        return Ok(has_keys);
+    }
+
+    let client = data.pool.get().await?;
+    let row = client
+        .query_one(
+            &format!("SELECT EXISTS(SELECT 1 FROM {API_KEYS_TABLE_NAME} WHERE revoked_at IS NULL)"),
+            &[],
+        )
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let has_keys = row.get::<usize, bool>(0);
+    data.key_cache.set_has_keys(has_keys);
+
+    Ok(has_keys)
+}
+
+async fn get_principal(req: &ServiceRequest, data: &AppData) -> Result<Principal, Error> {
+    if let Ok(auth) = Authorization::<Bearer>::parse(req) {
+        return match get_api_key_principal(data, auth.as_ref().token()).await? {
+            Some(principal) => Ok(principal),
+            None => Err(unauthorized("Invalid API key")),
+        };
+    }
+
+    if let Ok(auth) = Authorization::<Basic>::parse(req) {
+        let credentials = auth.as_ref();
+        match &data.auth_credentials {
+            Some(creds) => {
+                if creds.username == credentials.user_id()
+                    && creds.password == credentials.password().unwrap_or("")
+                {
+                    return Ok(Principal::admin());
+                }
+            }
+            // Without configured credentials any Basic auth is accepted
+            // until the first API key is created
+            None => {
+                if !has_api_keys(data).await? {
+                    return Ok(Principal::admin());
+                }
+            }
+        }
+    }
+
+    Err(unauthorized("Unauthorized"))
+}
+
+pub async fn authenticate(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let data = req.app_data::<AppData>().unwrap().clone();
+    let principal = get_principal(&req, &data).await?;
+
+    if let (Some(key_id), Some(limit)) = (principal.key_id, principal.rate_limit) {
+        if !data.rate_limiter.check(key_id, limit) {
+            return Err(ErrorTooManyRequests("Rate limit exceeded"));
+        }
+    }
+
+    req.extensions_mut().insert(principal);
+    next.call(req).await
+}
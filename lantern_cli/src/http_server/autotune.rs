@@ -0,0 +1,167 @@
+use actix_web::{
+    error::{ErrorBadRequest, ErrorInternalServerError},
+    post, web, HttpResponse, Responder, Result,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    external_index::cli::UMetricKind, index_autotune::cli::IndexAutotuneArgs,
+    utils::get_full_table_name,
+};
+
+use super::{
+    auth::{Principal, Scope},
+    operations::{OperationInfo, OperationKind},
+    validation::validate_column,
+    AppState, COLLECTION_SCHEMA_NAME,
+};
+
+// Autotune exports the results into this table, they are removed once returned in the operation result
+pub static AUTOTUNE_RESULTS_TABLE_NAME: &'static str = "http_autotune_results";
+// Experiment ids are taken from the sequence, so they are unique across server restarts and instances
+pub static AUTOTUNE_EXPERIMENT_SEQUENCE_NAME: &'static str = "http_autotune_experiment_id_seq";
+
+#[derive(Deserialize, Debug, utoipa::ToSchema)]
+pub struct AutotuneInput {
+    column: String,
+    metric: Option<String>,
+    recall: Option<f64>,
+    k: Option<u16>,
+    test_data_size: Option<usize>,
+    create_index: Option<bool>,
+}
+
+#[derive(Serialize, Debug, utoipa::ToSchema)]
+pub struct AutotuneResult {
+    ef: i32,
+    ef_construction: i32,
+    m: i32,
+    recall: f64,
+    latency: f64,
+    build_time: f64,
+}
+
+/// Find the index params for the collection
+///
+/// Indexes with different `m`, `ef_construction` and `ef` params are built over a sample of
+/// `test_data_size` rows (default: 10000) and recall and latency are measured for `k` (default: 10)
+/// nearest neighbours.
+///
+/// If `create_index` is true, the index with the best params for target `recall` (default: 99.9)
+/// will be created on the collection.
+///
+/// The autotune runs in background, poll `GET /operations/{id}` with the returned operation id,
+/// the results will be available in the `result` field of the succeeded operation.
+/// The indexes are built via external indexing server, which should be reachable on `127.0.0.1:8998` from database.
+///
+/// Metric can be one of `cosine`, `l2sq`, `hamming`
+#[utoipa::path(
+    post,
+    path = "/collections/{name}/autotune",
+    request_body  (
+        content = AutotuneInput,
+        example = json!(r#"{ "column": "vector", "metric": "cosine", "recall": 98, "k": 10, "test_data_size": 10000, "create_index": true }"#),
+    ),
+    responses(
+        (status = 202, description = "Autotune started", body = OperationInfo),
+        (status = 400, description = "Bad request"),
+        (status = 500, description = "Internal Server Error")
+    ),
+    params(
+       ("name", description = "Collection name")
+    ),
+)]
+#[post("/collections/{name}/autotune")]
+async fn autotune(
+    data: web::Data<AppState>,
+    principal: web::ReqData<Principal>,
+    body: web::Json<AutotuneInput>,
+    name: web::Path<String>,
+) -> Result<impl Responder> {
+    principal.authorize(Scope::Admin, Some(name.as_str()))?;
+    let metric = body.metric.clone().unwrap_or("l2sq".to_owned());
+    let metric_kind = UMetricKind::from(&metric).map_err(ErrorBadRequest)?;
+
+    let client = data.pool.get().await?;
+    validate_column(&client, &name, &body.column, data.allow_raw_sql).await?;
+    // Collections are resolved in the current schema of the connection like in the other endpoints
+    let schema: String = client
+        .query_one("SELECT current_schema()::text", &[])
+        .await
+        .map_err(ErrorInternalServerError)?
+        .get(0);
+    drop(client);
+
+    let mut args = IndexAutotuneArgs {
+        uri: data.db_uri.clone(),
+        schema,
+        table: name.to_string(),
+        column: body.column.clone(),
+        recall: body.recall.unwrap_or(99.9),
+        k: body.k.unwrap_or(10),
+        test_data_size: body.test_data_size.unwrap_or(10000),
+        metric_kind,
+        create_index: body.create_index.unwrap_or(false),
+        job_id: None,
+        export_db_uri: None,
+        export_schema_name: COLLECTION_SCHEMA_NAME.to_owned(),
+        export_table_name: Some(AUTOTUNE_RESULTS_TABLE_NAME.to_owned()),
+        job_schema_name: "public".to_owned(),
+        job_table_name: None,
+        model_name: None,
+    };
+
+    let state = data.clone();
+    let operation = data
+        .operations
+        .spawn(OperationKind::Autotune, &name, move |ctx| async move {
+            // Results are exported with the experiment id and taken back from the results table
+            let sequence =
+                get_full_table_name(COLLECTION_SCHEMA_NAME, AUTOTUNE_EXPERIMENT_SEQUENCE_NAME);
+            let client = state.pool.inner.get().await?;
+            let experiment_id: i32 = client
+                .query_one("SELECT nextval($1::text::regclass)::int", &[&sequence])
+                .await?
+                .get(0);
+            drop(client);
+            args.job_id = Some(experiment_id);
+
+            tokio::task::spawn_blocking(move || {
+                crate::index_autotune::autotune_index(
+                    &args,
+                    Some(ctx.progress_cb()),
+                    Some(ctx.is_canceled.clone()),
+                    None,
+                )
+            })
+            .await??;
+
+            let client = state.pool.inner.get().await?;
+            let rows = client
+                .query(
+                    &format!(
+                        "DELETE FROM {table} WHERE experiment_id=$1 RETURNING ef, efc, m, recall, latency, build_time",
+                        table = get_full_table_name(COLLECTION_SCHEMA_NAME, AUTOTUNE_RESULTS_TABLE_NAME)
+                    ),
+                    &[&experiment_id],
+                )
+                .await?;
+
+            let mut results: Vec<AutotuneResult> = rows
+                .iter()
+                .map(|row| AutotuneResult {
+                    ef: row.get(0),
+                    ef_construction: row.get(1),
+                    m: row.get(2),
+                    recall: row.get(3),
+                    latency: row.get(4),
+                    build_time: row.get(5),
+                })
+                .collect();
+            results.sort_by(|a, b| b.recall.total_cmp(&a.recall).then(a.latency.total_cmp(&b.latency)));
+
+            Ok(Some(serde_json::to_value(results)?))
+        });
+
+    Ok(HttpResponse::Accepted().json(operation))
+}
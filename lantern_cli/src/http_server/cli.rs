@@ -15,11 +15,26 @@ pub struct HttpServerArgs {
     #[arg(long, default_value_t = 8080)]
     pub port: u16,
 
-    /// Username for HTTP Auth
+    /// Username for HTTP Auth, requests with these credentials have admin access.
+    /// API keys can be created via `/api-keys` endpoints
     #[arg(short, long)]
     pub username: Option<String>,
 
     /// Password for HTTP Auth
     #[arg(short, long)]
     pub password: Option<String>,
+
+    /// Allow raw SQL in `select` fields and column data types.
+    /// By default column lists are validated against the collection columns
+    /// and only known data types can be used when creating collections
+    #[arg(long, default_value_t = false)]
+    pub allow_raw_sql: bool,
+
+    /// SSL Certificate path, the certificate and key are reloaded on SIGHUP
+    #[arg(long)]
+    pub cert: Option<String>,
+
+    /// SSL Certificate key path
+    #[arg(long)]
+    pub key: Option<String>,
 }
@@ -14,7 +14,12 @@ use actix_web::{
 
 use crate::{external_index::cli::UMetricKind, utils::quote_ident};
 
-use super::{AppState, COLLECTION_TABLE_NAME};
+use super::{
+    auth::{Principal, Scope},
+    embedding::{cancel_embedding_jobs, prepare_schema, setup_embedding, EmbeddingConfig},
+    validation::{validate_columns, validate_data_type},
+    AppState, COLLECTION_TABLE_NAME,
+};
 use serde::{Deserialize, Serialize};
 
 fn parse_index_def(definition: &str) -> HashMap<String, String> {
@@ -70,7 +75,11 @@ fn parse_indexes(index_definitions: Vec<HashMap<String, String>>) -> Vec<HashMap
 }
 
 fn get_collection_query(filter: &str) -> String {
-    format!("SELECT b.name, b.schema, COALESCE(json_agg(json_build_object('name', i.indexname , 'definition', i.indexdef)) FILTER (WHERE i.indexname IS NOT NULL), '[]')::text as indexes FROM (SELECT c.name, json_object_agg(t.column_name, t.data_type)::text as schema FROM {COLLECTION_TABLE_NAME} c INNER JOIN information_schema.columns t ON t.table_name=c.name  {filter} GROUP BY c.name) b LEFT JOIN pg_indexes i ON i.tablename=b.name AND i.indexdef ILIKE '%USING lantern_hnsw%' GROUP BY b.name, b.schema")
+    format!("SELECT b.name, b.schema, COALESCE(json_agg(json_build_object('name', i.indexname , 'definition', i.indexdef)) FILTER (WHERE i.indexname IS NOT NULL), '[]')::text as indexes, b.embedding FROM (SELECT c.name, c.embedding::text as embedding, json_object_agg(t.column_name, t.data_type)::text as schema FROM {COLLECTION_TABLE_NAME} c INNER JOIN information_schema.columns t ON t.table_name=c.name  {filter} GROUP BY c.name, c.embedding::text) b LEFT JOIN pg_indexes i ON i.tablename=b.name AND i.indexdef ILIKE '%USING lantern_hnsw%' GROUP BY b.name, b.schema, b.embedding")
+}
+
+fn parse_embedding(embedding: Option<&str>) -> Option<EmbeddingConfig> {
+    embedding.and_then(|e| serde_json::from_str(e).ok())
 }
 
 #[derive(Serialize, Debug, utoipa::ToSchema)]
@@ -78,6 +87,8 @@ pub struct CollectionInfo {
     name: String,
     schema: HashMap<String, String>,
     indexes: Vec<HashMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    embedding: Option<EmbeddingConfig>,
 }
 /// Get all collections
 #[utoipa::path(
@@ -89,19 +100,25 @@ pub struct CollectionInfo {
     )
 )]
 #[get("/collections")]
-pub async fn list(data: web::Data<AppState>) -> Result<impl Responder> {
+pub async fn list(
+    data: web::Data<AppState>,
+    principal: web::ReqData<Principal>,
+) -> Result<impl Responder> {
     let client = data.pool.get().await?;
     let rows = client
         .query(&get_collection_query(""), &[])
         .await
         .map_err(ErrorInternalServerError)?;
 
+    // Keys restricted to specific collections will only see those
     let tables: Vec<CollectionInfo> = rows
         .iter()
+        .filter(|r| principal.can_access(r.get::<usize, &str>(0)))
         .map(|r| CollectionInfo {
             name: r.get::<usize, String>(0),
             schema: serde_json::from_str(r.get::<usize, &str>(1)).unwrap(),
             indexes: parse_indexes(serde_json::from_str(r.get::<usize, &str>(2)).unwrap()),
+            embedding: parse_embedding(r.get::<usize, Option<&str>>(3)),
         })
         .collect();
 
@@ -121,7 +138,12 @@ pub async fn list(data: web::Data<AppState>) -> Result<impl Responder> {
     ),
 )]
 #[get("/collections/{name}")]
-pub async fn get(data: web::Data<AppState>, name: web::Path<String>) -> Result<impl Responder> {
+pub async fn get(
+    data: web::Data<AppState>,
+    principal: web::ReqData<Principal>,
+    name: web::Path<String>,
+) -> Result<impl Responder> {
+    principal.authorize(Scope::Read, Some(name.as_str()))?;
     let client = data.pool.get().await?;
     let rows = client
         .query(
@@ -141,6 +163,7 @@ pub async fn get(data: web::Data<AppState>, name: web::Path<String>) -> Result<i
         name: first_row.get::<usize, String>(0),
         schema: serde_json::from_str(first_row.get::<usize, &str>(1)).unwrap(),
         indexes: parse_indexes(serde_json::from_str(first_row.get::<usize, &str>(2)).unwrap()),
+        embedding: parse_embedding(first_row.get::<usize, Option<&str>>(3)),
     };
 
     Ok(web::Json(table))
@@ -150,6 +173,7 @@ pub async fn get(data: web::Data<AppState>, name: web::Path<String>) -> Result<i
 pub struct CreateTableInput {
     name: String,
     schema: Option<HashMap<String, String>>,
+    embedding: Option<EmbeddingConfig>,
 }
 
 /// Create new table with specified schema
@@ -163,12 +187,30 @@ pub struct CreateTableInput {
 /// ```no_run
 /// { "id": "serial primary key", "v": "REAL[]", "t": "TEXT" }
 /// ```
+///
+/// Unless the server is started with `--allow-raw-sql`, data types should be one of the built-in
+/// scalar types (e.g. `bigint`, `REAL[]`, `varchar(255)`, `JSONB`) optionally followed by
+/// `PRIMARY KEY`, `NOT NULL`, `NULL`, `UNIQUE` or `GENERATED ALWAYS|BY DEFAULT AS IDENTITY`
+///
+/// If `embedding` is specified, embeddings of `src_column` will be generated into `dst_column` using `model`,
+/// so rows can be inserted with text only. `dst_column` will be added as `REAL[]` if it is not in the schema,
+/// in `sync` mode an existing `dst_column` should also be `REAL[]`. `job_id` is set by the server and can not be passed.
+/// - `sync` mode (default) - embedding is generated by a trigger when the row is inserted, or `src_column` is updated,
+///   unless `dst_column` value is passed explicitly. Only `ort` runtime models are supported
+/// - `job` mode - Lantern daemon embedding job is created for the collection, embeddings are generated asynchronously.
+///   `runtime` can be `ort` (default), `openai` or `cohere`, `api_token` is required for the remote runtimes.
+///   The collection should have a primary key
+///
+/// Searching `dst_column` with `query_text` will use the collection embedding model if `query_model` is not passed
 #[utoipa::path(
     post,
     path = "/collections",
     request_body (
         content = CreateTableInput,
-        example = json!(r#"{ "name": "my_test_collection", "schema": {"id": "bigint GENERATED ALWAYS AS IDENTITY PRIMARY KEY", "vector": "REAL[]", "data": "TEXT", "metadata": "JSONB" } }"#)
+        examples (
+            ("Create collection" = (value = json!(r#"{ "name": "my_test_collection", "schema": {"id": "bigint GENERATED ALWAYS AS IDENTITY PRIMARY KEY", "vector": "REAL[]", "data": "TEXT", "metadata": "JSONB" } }"#) )),
+            ("Create collection with embedding" = (value = json!(r#"{ "name": "my_test_collection", "embedding": { "src_column": "data", "dst_column": "vector", "model": "BAAI/bge-small-en", "mode": "sync" } }"#) ))
+        )
     ),
     responses(
         (status = 200, description = "Returns the created table", body = CollectionInfo),
@@ -179,8 +221,10 @@ pub struct CreateTableInput {
 #[post("/collections")]
 pub async fn create(
     data: web::Data<AppState>,
+    principal: web::ReqData<Principal>,
     body: web::Json<CreateTableInput>,
 ) -> Result<impl Responder> {
+    principal.authorize(Scope::Admin, Some(body.name.as_str()))?;
     let mut client = data.pool.get().await.unwrap();
     let transaction = client
         .transaction()
@@ -203,6 +247,17 @@ pub async fn create(
         schema = default_schema;
     }
 
+    if let Some(embedding) = &body.embedding {
+        prepare_schema(&mut schema, embedding)?;
+    }
+
+    if !data.allow_raw_sql {
+        for (column, data_type) in &schema {
+            validate_data_type(data_type)
+                .map_err(|e| ErrorBadRequest(format!("Column '{column}': {e}")))?;
+        }
+    }
+
     let mut statement = format!("CREATE TABLE {name} (", name = quote_ident(&body.name));
 
     for (idx, column_info) in schema.iter().enumerate() {
@@ -231,6 +286,11 @@ pub async fn create(
         .await
         .map_err(ErrorInternalServerError)?;
 
+    let embedding = match body.embedding.clone() {
+        Some(config) => Some(setup_embedding(&transaction, &body.name, &schema, config).await?),
+        None => None,
+    };
+
     transaction
         .commit()
         .await
@@ -240,6 +300,7 @@ pub async fn create(
         name: body.name.clone(),
         schema,
         indexes: Vec::new(),
+        embedding,
     }))
 }
 
@@ -256,21 +317,41 @@ pub async fn create(
     ),
 )]
 #[delete("/collections/{name}")]
-pub async fn delete(data: web::Data<AppState>, name: web::Path<String>) -> Result<impl Responder> {
-    let client = data.pool.get().await.unwrap();
-
-    let statement = format!(
-        r#"
-        DROP TABLE "{name}" CASCADE;
-        DELETE FROM {COLLECTION_TABLE_NAME} WHERE name='{name}';
-    "#
-    );
+pub async fn delete(
+    data: web::Data<AppState>,
+    principal: web::ReqData<Principal>,
+    name: web::Path<String>,
+) -> Result<impl Responder> {
+    principal.authorize(Scope::Admin, Some(name.as_str()))?;
+    let mut client = data.pool.get().await.unwrap();
+    let transaction = client
+        .transaction()
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    cancel_embedding_jobs(&transaction, &name).await?;
 
-    client
-        .batch_execute(&statement)
+    transaction
+        .execute(
+            &format!("DROP TABLE {name} CASCADE", name = quote_ident(&name)),
+            &[],
+        )
         .await
         .map_err(ErrorBadRequest)?;
 
+    transaction
+        .execute(
+            &format!("DELETE FROM {COLLECTION_TABLE_NAME} WHERE name=$1"),
+            &[&name.to_string()],
+        )
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    transaction
+        .commit()
+        .await
+        .map_err(ErrorInternalServerError)?;
+
     Ok(HttpResponse::new(StatusCode::from_u16(200).unwrap()))
 }
 
@@ -304,9 +385,11 @@ pub struct InserDataInput {
 #[put("/collections/{name}")]
 async fn insert_data(
     data: web::Data<AppState>,
+    principal: web::ReqData<Principal>,
     body: web::Json<InserDataInput>,
     name: web::Path<String>,
 ) -> Result<impl Responder> {
+    principal.authorize(Scope::Write, Some(name.as_str()))?;
     let mut client = data.pool.get().await.unwrap();
 
     if body.rows.len() == 0 {
@@ -330,6 +413,8 @@ async fn insert_data(
         None => return Err(ErrorBadRequest("all rows are empty")),
     };
 
+    validate_columns(&client, &name, &columns, data.allow_raw_sql).await?;
+
     let column_names = columns.iter().map(|k| quote_ident(k)).join(",");
     let copy_statement = format!(
         "COPY {name} ({column_names}) FROM stdin NULL 'null'",
@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+
+use actix_web::error::{ErrorBadRequest, ErrorInternalServerError};
+use deadpool_postgres::Transaction;
+use serde::{Deserialize, Serialize};
+
+use crate::utils::{quote_ident, quote_literal};
+
+use super::{PoolClient, COLLECTION_TABLE_NAME, EMBEDDING_JOBS_TABLE_NAME};
+
+pub const EMBED_ROW_FUNCTION_NAME: &str = "_lantern_extras_internal.http_embed_row";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum EmbeddingMode {
+    // Embedding is computed by a trigger in the same statement which inserts or updates the row
+    #[default]
+    Sync,
+    // Embedding is computed asynchronously by Lantern daemon
+    Job,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, utoipa::ToSchema)]
+pub struct EmbeddingConfig {
+    pub src_column: String,
+    pub dst_column: String,
+    pub model: String,
+    #[serde(default)]
+    pub mode: EmbeddingMode,
+    // Runtime of the daemon job, only `ort` runtime is supported in `sync` mode
+    pub runtime: Option<String>,
+    // Passed to the daemon job, not stored in the collection config
+    #[serde(skip_serializing)]
+    pub api_token: Option<String>,
+    // Set by the server when the daemon job is created
+    #[serde(default)]
+    #[schema(read_only)]
+    pub job_id: Option<i32>,
+}
+
+// Returns SQL for the generic trigger function used by collections with `sync` embedding mode
+// Trigger arguments are source column, destination column and model name
+pub fn get_embed_row_function() -> String {
+    format!(
+        "CREATE OR REPLACE FUNCTION {EMBED_ROW_FUNCTION_NAME}() RETURNS TRIGGER AS $$
+         DECLARE
+           src_text TEXT;
+         BEGIN
+           src_text := to_jsonb(NEW) ->> TG_ARGV[0];
+           IF src_text IS NULL THEN
+             RETURN NEW;
+           END IF;
+           -- Values passed explicitly on insert are kept as is
+           IF TG_OP = 'INSERT' AND (to_jsonb(NEW) ->> TG_ARGV[1]) IS NOT NULL THEN
+             RETURN NEW;
+           END IF;
+           IF TG_OP = 'UPDATE' AND (to_jsonb(OLD) ->> TG_ARGV[0]) IS NOT DISTINCT FROM src_text THEN
+             RETURN NEW;
+           END IF;
+           NEW := jsonb_populate_record(NEW, jsonb_build_object(TG_ARGV[1], text_embedding(TG_ARGV[2], src_text)));
+           RETURN NEW;
+         END
+         $$ LANGUAGE plpgsql;"
+    )
+}
+
+fn get_trigger_name(table: &str) -> String {
+    quote_ident(&format!("_lantern_http_embed_{table}"))
+}
+
+// Embedding trigger populates the destination column with `text_embedding` result,
+// so only real arrays are supported, e.g. `REAL[]`, `float4[]` or `REAL[] NOT NULL`
+fn is_real_array_type(data_type: &str) -> bool {
+    let data_type = data_type.to_lowercase().replace(char::is_whitespace, "");
+    data_type.starts_with("real[") || data_type.starts_with("float4[")
+}
+
+fn get_primary_key_column(schema: &HashMap<String, String>) -> Option<&String> {
+    schema
+        .iter()
+        .find(|(_, data_type)| data_type.to_lowercase().contains("primary key"))
+        .map(|(column, _)| column)
+}
+
+// Adds the destination column to the schema if it is missing
+pub fn prepare_schema(
+    schema: &mut HashMap<String, String>,
+    config: &EmbeddingConfig,
+) -> Result<(), actix_web::Error> {
+    if !schema.contains_key(&config.src_column) {
+        return Err(ErrorBadRequest(format!(
+            "Embedding source column '{}' does not exist in schema",
+            config.src_column
+        )));
+    }
+
+    if config.job_id.is_some() {
+        return Err(ErrorBadRequest(
+            "Embedding 'job_id' is assigned by the server and can not be passed",
+        ));
+    }
+
+    if config.mode == EmbeddingMode::Sync && config.runtime.as_deref().is_some_and(|r| r != "ort") {
+        return Err(ErrorBadRequest(
+            "Only 'ort' runtime is supported in 'sync' embedding mode",
+        ));
+    }
+
+    if config.mode == EmbeddingMode::Sync {
+        if let Some(data_type) = schema.get(&config.dst_column) {
+            if !is_real_array_type(data_type) {
+                return Err(ErrorBadRequest(format!(
+                    "Embedding destination column '{}' should be REAL[] in 'sync' embedding mode, got '{data_type}'",
+                    config.dst_column
+                )));
+            }
+        }
+    }
+
+    schema
+        .entry(config.dst_column.clone())
+        .or_insert_with(|| "REAL[]".to_owned());
+
+    Ok(())
+}
+
+// Creates the embedding trigger or registers the daemon job for the newly created collection table
+// and stores the config in the collections table
+pub async fn setup_embedding(
+    transaction: &Transaction<'_>,
+    table: &str,
+    schema: &HashMap<String, String>,
+    mut config: EmbeddingConfig,
+) -> Result<EmbeddingConfig, actix_web::Error> {
+    match config.mode {
+        EmbeddingMode::Sync => {
+            transaction
+                .execute(
+                    &format!(
+                        "CREATE TRIGGER {trigger} BEFORE INSERT OR UPDATE ON {table} FOR EACH ROW EXECUTE FUNCTION {EMBED_ROW_FUNCTION_NAME}({src}, {dst}, {model})",
+                        trigger = get_trigger_name(table),
+                        table = quote_ident(table),
+                        src = quote_literal(&config.src_column),
+                        dst = quote_literal(&config.dst_column),
+                        model = quote_literal(&config.model),
+                    ),
+                    &[],
+                )
+                .await
+                .map_err(ErrorBadRequest)?;
+        }
+        EmbeddingMode::Job => {
+            let pk = match get_primary_key_column(schema) {
+                Some(pk) => pk,
+                None => {
+                    return Err(ErrorBadRequest(
+                        "Collection should have a primary key to use embedding job",
+                    ))
+                }
+            };
+            let runtime = config.runtime.clone().unwrap_or("ort".to_owned());
+            let row = transaction
+                .query_one(
+                    "SELECT add_embedding_job(table_name => $1, src_column => $2, dst_column => $3, model => $4, pk => $5, schema => current_schema()::text, runtime => $6, api_token => $7)",
+                    &[
+                        &table,
+                        &config.src_column,
+                        &config.dst_column,
+                        &config.model,
+                        pk,
+                        &runtime,
+                        &config.api_token.as_deref().unwrap_or(""),
+                    ],
+                )
+                .await
+                .map_err(ErrorBadRequest)?;
+            config.runtime = Some(runtime);
+            config.job_id = Some(row.get::<usize, i32>(0));
+        }
+    }
+
+    transaction
+        .execute(
+            &format!("UPDATE {COLLECTION_TABLE_NAME} SET embedding=$1::text::jsonb WHERE name=$2"),
+            &[
+                &serde_json::to_string(&config).map_err(ErrorInternalServerError)?,
+                &table,
+            ],
+        )
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(config)
+}
+
+pub async fn get_embedding_config(
+    client: &PoolClient,
+    table: &str,
+) -> Result<Option<EmbeddingConfig>, actix_web::Error> {
+    let row = client
+        .query_opt(
+            &format!("SELECT embedding::text FROM {COLLECTION_TABLE_NAME} WHERE name=$1"),
+            &[&table],
+        )
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    match row.and_then(|r| r.get::<usize, Option<String>>(0)) {
+        Some(config) => Ok(Some(
+            serde_json::from_str(&config).map_err(ErrorInternalServerError)?,
+        )),
+        None => Ok(None),
+    }
+}
+
+// Returns the model passed in request or the collection embedding model
+// if the searched column is the embedding destination column
+pub async fn get_query_model(
+    client: &PoolClient,
+    table: &str,
+    column: &str,
+    query_model: Option<&String>,
+) -> Result<Option<String>, actix_web::Error> {
+    if let Some(model) = query_model {
+        return Ok(Some(model.clone()));
+    }
+
+    Ok(get_embedding_config(client, table)
+        .await?
+        .filter(|config| config.dst_column == column)
+        .map(|config| config.model))
+}
+
+// Stops the daemon jobs of the collection before the table is dropped,
+// including the jobs created via embedding jobs endpoints
+pub async fn cancel_embedding_jobs(
+    transaction: &Transaction<'_>,
+    table: &str,
+) -> Result<(), actix_web::Error> {
+    // The jobs table is created with lantern_extras extension
+    let jobs_table_exists = transaction
+        .query_one(
+            "SELECT to_regclass($1) IS NOT NULL",
+            &[&EMBEDDING_JOBS_TABLE_NAME],
+        )
+        .await
+        .map_err(ErrorInternalServerError)?
+        .get::<usize, bool>(0);
+
+    if jobs_table_exists {
+        transaction
+            .execute(
+                &format!("UPDATE {EMBEDDING_JOBS_TABLE_NAME} SET canceled_at=NOW() WHERE \"table\"=$1 AND \"schema\"=current_schema() AND canceled_at IS NULL"),
+                &[&table],
+            )
+            .await
+            .map_err(ErrorInternalServerError)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,228 @@
+use actix_web::{
+    delete,
+    error::{ErrorBadRequest, ErrorInternalServerError, ErrorNotFound},
+    get, post, web, Responder, Result,
+};
+use serde::{Deserialize, Serialize};
+use tokio_postgres::Row;
+
+use super::{
+    auth::{Principal, Scope},
+    validation::{get_primary_key, validate_column},
+    AppState, PoolClient, EMBEDDING_JOBS_TABLE_NAME,
+};
+
+fn get_jobs_query(filter: &str) -> String {
+    format!("SELECT jobs.id, jobs.src_column::text, jobs.dst_column::text, jobs.embedding_model::text, jobs.runtime::text, s.status, s.progress, s.error FROM {EMBEDDING_JOBS_TABLE_NAME} jobs, get_embedding_job_status(jobs.id) s WHERE jobs.\"table\"=$1 AND jobs.\"schema\"=current_schema() AND jobs.job_type='embedding_generation' {filter} ORDER BY jobs.id")
+}
+
+#[derive(Serialize, Debug, utoipa::ToSchema)]
+pub struct EmbeddingJobInfo {
+    id: i32,
+    src_column: String,
+    dst_column: String,
+    model: String,
+    runtime: String,
+    status: String,
+    progress: Option<i16>,
+    error: Option<String>,
+}
+
+impl EmbeddingJobInfo {
+    fn from_row(row: &Row) -> Self {
+        Self {
+            id: row.get(0),
+            src_column: row.get(1),
+            dst_column: row.get(2),
+            model: row.get(3),
+            runtime: row.get(4),
+            status: row.get(5),
+            progress: row.get(6),
+            error: row.get(7),
+        }
+    }
+}
+
+async fn get_job(client: &PoolClient, table: &str, id: i32) -> Result<EmbeddingJobInfo> {
+    let row = client
+        .query_opt(&get_jobs_query("AND jobs.id=$2"), &[&table, &id])
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    match row {
+        Some(row) => Ok(EmbeddingJobInfo::from_row(&row)),
+        None => Err(ErrorNotFound("Embedding job not found")),
+    }
+}
+
+#[derive(Deserialize, Debug, utoipa::ToSchema)]
+pub struct CreateEmbeddingJobInput {
+    src_column: String,
+    dst_column: String,
+    model: String,
+    runtime: Option<String>,
+    api_token: Option<String>,
+    batch_size: Option<i32>,
+    dimensions: Option<i32>,
+}
+
+/// Create daemon embedding job for the collection
+///
+/// Lantern daemon will generate embeddings of `src_column` into `dst_column` for all existing rows
+/// and will keep them up to date on inserts and updates. `dst_column` will be added as `REAL[]` if it does not exist.
+///
+/// `runtime` can be `ort` (default), `openai` or `cohere`, `api_token` is required for the remote runtimes.
+/// The collection should have a primary key
+#[utoipa::path(
+    post,
+    path = "/collections/{name}/embedding-jobs",
+    request_body (
+        content = CreateEmbeddingJobInput,
+        example = json!(r#"{ "src_column": "data", "dst_column": "vector", "model": "BAAI/bge-small-en", "runtime": "ort" }"#)
+    ),
+    responses(
+        (status = 200, description = "Returns the created job", body = EmbeddingJobInfo),
+        (status = 400, description = "Bad request"),
+        (status = 403, description = "Forbidden"),
+        (status = 500, description = "Internal Server Error")
+    ),
+    params(
+       ("name", description = "Collection name")
+    ),
+)]
+#[post("/collections/{name}/embedding-jobs")]
+pub async fn create_job(
+    data: web::Data<AppState>,
+    principal: web::ReqData<Principal>,
+    body: web::Json<CreateEmbeddingJobInput>,
+    name: web::Path<String>,
+) -> Result<impl Responder> {
+    principal.authorize(Scope::Admin, Some(name.as_str()))?;
+    let client = data.pool.get().await?;
+    validate_column(&client, &name, &body.src_column, data.allow_raw_sql).await?;
+    let pk = get_primary_key(&client, &name).await?;
+
+    let row = client
+        .query_one(
+            "SELECT add_embedding_job(table_name => $1, src_column => $2, dst_column => $3, model => $4, pk => $5, schema => current_schema()::text, runtime => $6, api_token => $7, batch_size => $8, dimensions => $9)",
+            &[
+                &name.as_str(),
+                &body.src_column,
+                &body.dst_column,
+                &body.model,
+                &pk.name,
+                &body.runtime.as_deref().unwrap_or("ort"),
+                &body.api_token.as_deref().unwrap_or(""),
+                &body.batch_size.unwrap_or(-1),
+                &body.dimensions.unwrap_or(1536),
+            ],
+        )
+        .await
+        .map_err(ErrorBadRequest)?;
+
+    Ok(web::Json(
+        get_job(&client, &name, row.get::<usize, i32>(0)).await?,
+    ))
+}
+
+/// Get embedding jobs of the collection
+///
+/// Status can be one of `queued`, `in_progress`, `enabled`, `failed`, `canceled`
+#[utoipa::path(
+    get,
+    path = "/collections/{name}/embedding-jobs",
+    responses(
+        (status = 200, description = "Returns the collection jobs", body = Vec<EmbeddingJobInfo>),
+        (status = 403, description = "Forbidden"),
+        (status = 500, description = "Internal Server Error")
+    ),
+    params(
+       ("name", description = "Collection name")
+    ),
+)]
+#[get("/collections/{name}/embedding-jobs")]
+pub async fn list_jobs(
+    data: web::Data<AppState>,
+    principal: web::ReqData<Principal>,
+    name: web::Path<String>,
+) -> Result<impl Responder> {
+    principal.authorize(Scope::Read, Some(name.as_str()))?;
+    let client = data.pool.get().await?;
+    let rows = client
+        .query(&get_jobs_query(""), &[&name.as_str()])
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let jobs: Vec<EmbeddingJobInfo> = rows.iter().map(EmbeddingJobInfo::from_row).collect();
+    Ok(web::Json(jobs))
+}
+
+/// Cancel the embedding job
+///
+/// The daemon will stop generating embeddings for the collection until the job is resumed
+#[utoipa::path(
+    delete,
+    path = "/collections/{name}/embedding-jobs/{id}",
+    responses(
+        (status = 200, description = "Returns the canceled job", body = EmbeddingJobInfo),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Embedding job not found"),
+    ),
+    params(
+       ("name", description = "Collection name"),
+       ("id", description = "Embedding job id")
+    ),
+)]
+#[delete("/collections/{name}/embedding-jobs/{id}")]
+pub async fn cancel_job(
+    data: web::Data<AppState>,
+    principal: web::ReqData<Principal>,
+    path: web::Path<(String, i32)>,
+) -> Result<impl Responder> {
+    let (name, id) = path.into_inner();
+    principal.authorize(Scope::Admin, Some(name.as_str()))?;
+    let client = data.pool.get().await?;
+    get_job(&client, &name, id).await?;
+
+    client
+        .execute("SELECT cancel_embedding_job($1)", &[&id])
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(web::Json(get_job(&client, &name, id).await?))
+}
+
+/// Resume the canceled embedding job
+///
+/// The daemon will continue generating embeddings for the collection
+#[utoipa::path(
+    post,
+    path = "/collections/{name}/embedding-jobs/{id}/resume",
+    responses(
+        (status = 200, description = "Returns the resumed job", body = EmbeddingJobInfo),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Embedding job not found"),
+    ),
+    params(
+       ("name", description = "Collection name"),
+       ("id", description = "Embedding job id")
+    ),
+)]
+#[post("/collections/{name}/embedding-jobs/{id}/resume")]
+pub async fn resume_job(
+    data: web::Data<AppState>,
+    principal: web::ReqData<Principal>,
+    path: web::Path<(String, i32)>,
+) -> Result<impl Responder> {
+    let (name, id) = path.into_inner();
+    principal.authorize(Scope::Admin, Some(name.as_str()))?;
+    let client = data.pool.get().await?;
+    get_job(&client, &name, id).await?;
+
+    client
+        .execute("SELECT resume_embedding_job($1)", &[&id])
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(web::Json(get_job(&client, &name, id).await?))
+}
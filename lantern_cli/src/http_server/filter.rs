@@ -0,0 +1,325 @@
+use std::collections::HashMap;
+
+use serde_json::{Map, Value};
+use tokio_postgres::types::ToSql;
+
+use crate::utils::quote_ident;
+
+// Compiles the json filter from request body into parameterized SQL condition
+//
+// Filter is an object where keys are column names or json paths inside jsonb columns
+// (e.g. `metadata.lang` will be compiled to `"metadata" #> '{lang}'`)
+// and values are objects with operators: {"metadata.lang": {"eq": "en"}, "price": {"lt": 10}}
+// Plain values are treated as `eq` operator: {"metadata.lang": "en"}
+//
+// Conditions on multiple keys are combined with AND, `and` / `or` keys can be used
+// with an array of filters to build nested conditions: {"or": [{"price": {"lt": 10}}, {"sale": true}]}
+//
+// Columns with a known scalar type are compared directly with the values cast to the column type,
+// so the indexes on these columns can be used. Json paths, json and array columns are compared as jsonb,
+// so numbers are compared numerically and strings lexicographically
+// Column names are quoted and all values and path segments are passed as query parameters
+
+pub type FilterParam = Box<dyn ToSql + Sync + Send>;
+
+pub struct CompiledFilter {
+    pub condition: String,
+    pub params: Vec<FilterParam>,
+}
+
+impl CompiledFilter {
+    pub fn where_clause(&self) -> String {
+        if self.condition.is_empty() {
+            return String::new();
+        }
+
+        format!("WHERE {}", self.condition)
+    }
+}
+
+// Left hand side of the condition
+enum FilterField {
+    // Column compared with the values cast to its data type
+    Typed { column: String, data_type: String },
+    // Jsonb expression of the column or the json path inside it
+    Jsonb(String),
+}
+
+struct FilterCompiler<'a> {
+    params: Vec<FilterParam>,
+    // Number of params already used in the query
+    param_offset: usize,
+    // Column names and their data types from the catalog
+    column_types: &'a HashMap<String, String>,
+}
+
+impl<'a> FilterCompiler<'a> {
+    fn push_param(&mut self, param: FilterParam) -> String {
+        self.params.push(param);
+        format!("${}", self.param_offset + self.params.len())
+    }
+
+    fn push_json_param(&mut self, value: &Value) -> String {
+        let placeholder = self.push_param(Box::new(value.to_string()));
+        format!("{placeholder}::text::jsonb")
+    }
+
+    fn push_typed_param(
+        &mut self,
+        field: &str,
+        value: &Value,
+        data_type: &str,
+    ) -> Result<String, anyhow::Error> {
+        let placeholder = match value {
+            Value::Array(values) => {
+                let values = values
+                    .iter()
+                    .map(|value| match value {
+                        Value::String(value) => Ok(value.clone()),
+                        Value::Bool(_) | Value::Number(_) => Ok(value.to_string()),
+                        _ => anyhow::bail!(
+                            "Filter for field '{field}' expects an array of scalar values"
+                        ),
+                    })
+                    .collect::<Result<Vec<String>, anyhow::Error>>()?;
+                let placeholder = self.push_param(Box::new(values));
+                format!("{placeholder}::text[]::{data_type}[]")
+            }
+            Value::String(value) => {
+                let placeholder = self.push_param(Box::new(value.clone()));
+                format!("{placeholder}::text::{data_type}")
+            }
+            _ => {
+                let placeholder = self.push_param(Box::new(value.to_string()));
+                format!("{placeholder}::text::{data_type}")
+            }
+        };
+
+        Ok(placeholder)
+    }
+
+    fn compile_field(&mut self, field: &str) -> Result<FilterField, anyhow::Error> {
+        let mut parts = field.split('.');
+        let column = parts.next().unwrap_or_default();
+
+        if column.is_empty() {
+            anyhow::bail!("Invalid filter field '{field}'");
+        }
+
+        let path: Vec<String> = parts.map(|p| p.to_owned()).collect();
+
+        if path.iter().any(|p| p.is_empty()) {
+            anyhow::bail!("Invalid filter field '{field}'");
+        }
+
+        if path.is_empty() {
+            return Ok(match self.column_types.get(column) {
+                Some(data_type) if is_scalar_type(data_type) => FilterField::Typed {
+                    column: quote_ident(column),
+                    data_type: data_type.clone(),
+                },
+                _ => FilterField::Jsonb(format!("to_jsonb({})", quote_ident(column))),
+            });
+        }
+
+        let path_param = self.push_param(Box::new(path));
+        Ok(FilterField::Jsonb(format!(
+            "({} #> {path_param}::text[])",
+            quote_ident(column)
+        )))
+    }
+
+    // Returns None for operators which are not supported for typed columns
+    // so the column is compared as jsonb instead
+    fn compile_typed_operator(
+        &mut self,
+        field: &str,
+        column: &str,
+        data_type: &str,
+        operator: &str,
+        value: &Value,
+    ) -> Result<Option<String>, anyhow::Error> {
+        let condition = match (operator, value) {
+            ("eq", Value::Null) => format!("{column} IS NULL"),
+            ("ne", Value::Null) => format!("{column} IS NOT NULL"),
+            ("eq" | "ne" | "lt" | "lte" | "gt" | "gte", Value::Array(_) | Value::Object(_)) => {
+                anyhow::bail!("Operator '{operator}' for field '{field}' expects a scalar value")
+            }
+            ("eq", _) => format!(
+                "{column} = {}",
+                self.push_typed_param(field, value, data_type)?
+            ),
+            ("ne", _) => format!(
+                "{column} IS DISTINCT FROM {}",
+                self.push_typed_param(field, value, data_type)?
+            ),
+            ("lt", _) => format!(
+                "{column} < {}",
+                self.push_typed_param(field, value, data_type)?
+            ),
+            ("lte", _) => format!(
+                "{column} <= {}",
+                self.push_typed_param(field, value, data_type)?
+            ),
+            ("gt", _) => format!(
+                "{column} > {}",
+                self.push_typed_param(field, value, data_type)?
+            ),
+            ("gte", _) => format!(
+                "{column} >= {}",
+                self.push_typed_param(field, value, data_type)?
+            ),
+            ("in", Value::Array(_)) => format!(
+                "{column} = ANY({})",
+                self.push_typed_param(field, value, data_type)?
+            ),
+            ("nin", Value::Array(_)) => format!(
+                "({column} IS NULL OR NOT ({column} = ANY({})))",
+                self.push_typed_param(field, value, data_type)?
+            ),
+            _ => return Ok(None),
+        };
+
+        Ok(Some(condition))
+    }
+
+    fn compile_operator(
+        &mut self,
+        field: &str,
+        lhs: &FilterField,
+        operator: &str,
+        value: &Value,
+    ) -> Result<String, anyhow::Error> {
+        let lhs = match lhs {
+            FilterField::Typed { column, data_type } => {
+                if let Some(condition) =
+                    self.compile_typed_operator(field, column, data_type, operator, value)?
+                {
+                    return Ok(condition);
+                }
+                format!("to_jsonb({column})")
+            }
+            FilterField::Jsonb(lhs) => lhs.clone(),
+        };
+
+        let condition = match (operator, value) {
+            ("eq", Value::Null) => format!("({lhs} IS NULL OR {lhs} = 'null'::jsonb)"),
+            ("ne", Value::Null) => format!("({lhs} IS NOT NULL AND {lhs} != 'null'::jsonb)"),
+            ("eq" | "ne" | "lt" | "lte" | "gt" | "gte", Value::Array(_) | Value::Object(_)) => {
+                anyhow::bail!("Operator '{operator}' for field '{field}' expects a scalar value")
+            }
+            ("eq", _) => format!("{lhs} = {}", self.push_json_param(value)),
+            ("ne", _) => format!("{lhs} IS DISTINCT FROM {}", self.push_json_param(value)),
+            ("lt", _) => format!("{lhs} < {}", self.push_json_param(value)),
+            ("lte", _) => format!("{lhs} <= {}", self.push_json_param(value)),
+            ("gt", _) => format!("{lhs} > {}", self.push_json_param(value)),
+            ("gte", _) => format!("{lhs} >= {}", self.push_json_param(value)),
+            ("in", Value::Array(_)) => format!(
+                "{lhs} IN (SELECT jsonb_array_elements({}))",
+                self.push_json_param(value)
+            ),
+            ("nin", Value::Array(_)) => format!(
+                "({lhs} IS NULL OR {lhs} NOT IN (SELECT jsonb_array_elements({})))",
+                self.push_json_param(value)
+            ),
+            ("in" | "nin", _) => {
+                anyhow::bail!("Operator '{operator}' for field '{field}' expects an array")
+            }
+            ("contains", _) => format!("{lhs} @> {}", self.push_json_param(value)),
+            ("exists", Value::Bool(true)) => format!("{lhs} IS NOT NULL"),
+            ("exists", Value::Bool(false)) => format!("{lhs} IS NULL"),
+            ("exists", _) => {
+                anyhow::bail!("Operator 'exists' for field '{field}' expects a boolean")
+            }
+            _ => anyhow::bail!("Unknown filter operator '{operator}' for field '{field}'"),
+        };
+
+        Ok(condition)
+    }
+
+    fn compile_field_filter(
+        &mut self,
+        field: &str,
+        value: &Value,
+    ) -> Result<String, anyhow::Error> {
+        let lhs = self.compile_field(field)?;
+
+        let operators = match value {
+            Value::Object(operators) => operators,
+            _ => return self.compile_operator(field, &lhs, "eq", value),
+        };
+
+        if operators.is_empty() {
+            anyhow::bail!("No operators specified for field '{field}'");
+        }
+
+        let mut conditions = Vec::with_capacity(operators.len());
+        for (operator, operand) in operators {
+            conditions.push(self.compile_operator(field, &lhs, operator, operand)?);
+        }
+
+        Ok(conditions.join(" AND "))
+    }
+
+    fn compile_group(&mut self, key: &str, value: &Value) -> Result<String, anyhow::Error> {
+        let filters = match value {
+            Value::Array(filters) if !filters.is_empty() => filters,
+            _ => anyhow::bail!("'{key}' filter expects a non empty array of filters"),
+        };
+
+        let mut conditions = Vec::with_capacity(filters.len());
+        for filter in filters {
+            match filter {
+                Value::Object(filter) if !filter.is_empty() => {
+                    conditions.push(format!("({})", self.compile_object(filter)?))
+                }
+                _ => anyhow::bail!("'{key}' filter expects a non empty array of filters"),
+            }
+        }
+
+        let separator = if key == "and" { " AND " } else { " OR " };
+        Ok(conditions.join(separator))
+    }
+
+    fn compile_object(&mut self, filter: &Map<String, Value>) -> Result<String, anyhow::Error> {
+        let mut conditions = Vec::with_capacity(filter.len());
+
+        for (key, value) in filter {
+            let condition = match key.as_str() {
+                "and" | "or" => self.compile_group(key, value)?,
+                _ => self.compile_field_filter(key, value)?,
+            };
+            conditions.push(format!("({condition})"));
+        }
+
+        Ok(conditions.join(" AND "))
+    }
+}
+
+// Array, json and jsonb columns are compared as jsonb
+fn is_scalar_type(data_type: &str) -> bool {
+    !data_type.ends_with(']') && data_type != "json" && data_type != "jsonb"
+}
+
+pub fn compile_filter(
+    filter: Option<&Value>,
+    param_offset: usize,
+    column_types: &HashMap<String, String>,
+) -> Result<CompiledFilter, anyhow::Error> {
+    let mut compiler = FilterCompiler {
+        params: Vec::new(),
+        param_offset,
+        column_types,
+    };
+
+    let condition = match filter {
+        None | Some(Value::Null) => String::new(),
+        Some(Value::Object(filter)) => compiler.compile_object(filter)?,
+        Some(_) => anyhow::bail!("Filter should be a json object"),
+    };
+
+    Ok(CompiledFilter {
+        condition,
+        params: compiler.params,
+    })
+}
@@ -0,0 +1,366 @@
+use std::collections::HashMap;
+
+use actix_web::{
+    error::{ErrorBadRequest, ErrorInternalServerError},
+    post, web, Responder, Result,
+};
+
+use crate::{external_index::cli::UMetricKind, utils::quote_ident};
+use serde::{Deserialize, Serialize};
+use tokio_postgres::types::ToSql;
+
+use super::{
+    auth::{Principal, Scope},
+    embedding::get_query_model,
+    filter::compile_filter,
+    search::set_search_params,
+    validation::{get_column_types, get_select_fields, validate_column},
+    AppState,
+};
+
+static HYBRID_ID_COLUMN: &'static str = "_lantern_hybrid_id";
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum FusionMethod {
+    #[default]
+    Rrf,
+    Weighted,
+}
+
+#[derive(Deserialize, Debug, utoipa::ToSchema)]
+pub struct HybridSearchInput {
+    query_text: String,
+    query_model: Option<String>,
+    vector_column: String,
+    bm25_column: String,
+    id_column: Option<String>,
+    metric: Option<String>,
+    select: Option<String>,
+    k: Option<usize>,
+    ef: Option<usize>,
+    candidates: Option<usize>,
+    fusion: Option<FusionMethod>,
+    rrf_k: Option<f64>,
+    vector_weight: Option<f64>,
+    bm25_weight: Option<f64>,
+    #[schema(value_type = Option<Object>)]
+    filter: Option<serde_json::Value>,
+}
+
+#[derive(Serialize, Debug, utoipa::ToSchema)]
+pub struct HybridSearchResponse {
+    rows: Vec<serde_json::Value>,
+}
+
+// Ids are kept as text, so the collection can have any integer id column type
+#[derive(Debug, Default, Clone, PartialEq)]
+struct HybridScore {
+    id: String,
+    score: f64,
+    vector_rank: Option<usize>,
+    vector_distance: Option<f64>,
+    bm25_rank: Option<usize>,
+    bm25_score: Option<f64>,
+}
+
+struct FusionParams {
+    method: FusionMethod,
+    rrf_k: f64,
+    vector_weight: f64,
+    bm25_weight: f64,
+}
+
+// Returns min-max normalized value in [0, 1] range
+fn normalize(value: f64, min: f64, max: f64) -> f64 {
+    if max - min <= f64::EPSILON {
+        return 1.0;
+    }
+
+    (value - min) / (max - min)
+}
+
+fn min_max(values: impl Iterator<Item = f64>) -> (f64, f64) {
+    values.fold((f64::MAX, f64::MIN), |(min, max), v| {
+        (min.min(v), max.max(v))
+    })
+}
+
+// Fuses the ranked candidate lists, both of the lists should be sorted by relevance.
+// With reciprocal rank fusion the score is sum of weight / (rrf_k + rank) for each signal,
+// with weighted fusion the distances and bm25 scores are min-max normalized and summed with weights
+fn fuse_results(
+    vector_results: &[(String, f64)],
+    bm25_results: &[(String, f64)],
+    params: &FusionParams,
+) -> Vec<HybridScore> {
+    let mut scores: HashMap<&str, HybridScore> = HashMap::new();
+
+    let (min_distance, max_distance) = min_max(vector_results.iter().map(|r| r.1));
+    for (idx, (id, distance)) in vector_results.iter().enumerate() {
+        let rank = idx + 1;
+        let entry = scores.entry(id.as_str()).or_insert(HybridScore {
+            id: id.clone(),
+            ..Default::default()
+        });
+        entry.vector_rank = Some(rank);
+        entry.vector_distance = Some(*distance);
+        entry.score += match params.method {
+            FusionMethod::Rrf => params.vector_weight / (params.rrf_k + rank as f64),
+            FusionMethod::Weighted => {
+                params.vector_weight
+                    * normalize(max_distance - *distance, 0.0, max_distance - min_distance)
+            }
+        };
+    }
+
+    let (min_score, max_score) = min_max(bm25_results.iter().map(|r| r.1));
+    for (idx, (id, score)) in bm25_results.iter().enumerate() {
+        let rank = idx + 1;
+        let entry = scores.entry(id.as_str()).or_insert(HybridScore {
+            id: id.clone(),
+            ..Default::default()
+        });
+        entry.bm25_rank = Some(rank);
+        entry.bm25_score = Some(*score);
+        entry.score += match params.method {
+            FusionMethod::Rrf => params.bm25_weight / (params.rrf_k + rank as f64),
+            FusionMethod::Weighted => params.bm25_weight * normalize(*score, min_score, max_score),
+        };
+    }
+
+    let mut results: Vec<HybridScore> = scores.into_values().collect();
+    results.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.id.cmp(&b.id)));
+    results
+}
+
+/// Search rows in collection combining vector similarity and BM25 text relevance
+///
+/// The embedding for `query_text` is created using `query_model` and the nearest rows are taken from `vector_column`.
+/// If the collection has embedding config for `vector_column`, `query_model` can be omitted.
+/// The BM25 results are taken from `{name}_bm25` table, which should be created with `create_bm25_table`
+/// function over the stemmed `bm25_column` (`TEXT[]`), the `id_column` (default: `id`) should be integer.
+///
+/// The `candidates` param controls how many rows are taken from each of the signals before fusion (default: `k * 5`).
+///
+/// Fusion can be one of:
+/// - `rrf` (default) - reciprocal rank fusion, score is `vector_weight / (rrf_k + vector_rank) + bm25_weight / (rrf_k + bm25_rank)`
+/// - `weighted` - distances and BM25 scores are min-max normalized and summed with `vector_weight` and `bm25_weight`
+///
+/// Weights default to `0.5` and `rrf_k` defaults to `60`.
+///
+/// Each row contains the selected columns along with `score`, `vector_rank`, `vector_distance`, `bm25_rank` and `bm25_score`.
+/// Ranks start from 1 and are `null` if the row was not found by the signal.
+///
+/// The `filter` param has the same format as in vector search and is applied to both vector and BM25 candidates
+/// before fusion. With a filter the BM25 candidates are selected from all the documents matching the query text
+#[utoipa::path(
+    post,
+    path = "/collections/{name}/hybrid-search",
+    request_body  (
+        content = HybridSearchInput,
+        examples (
+         ("Reciprocal rank fusion" = (value = json!(r#"{ "query_text": "User query text", "query_model": "BAAI/bge-small-en", "vector_column": "vector", "bm25_column": "data_stemmed", "select": "id,data", "k": 10 }"#) )),
+         ("Weighted fusion" = (value = json!(r#"{ "query_text": "User query text", "query_model": "BAAI/bge-small-en", "vector_column": "vector", "bm25_column": "data_stemmed", "metric": "cosine", "select": "id,data", "k": 10, "fusion": "weighted", "vector_weight": 0.7, "bm25_weight": 0.3 }"#) ))
+        ),
+    ),
+    responses(
+        (status = 200, body=HybridSearchResponse, description = "Array with the columns selected and per-signal scores"),
+        (status = 400, description = "Bad request"),
+        (status = 500, description = "Internal Server Error")
+    ),
+    params(
+       ("name", description = "Collection name")
+    ),
+)]
+#[post("/collections/{name}/hybrid-search")]
+async fn hybrid_search(
+    data: web::Data<AppState>,
+    principal: web::ReqData<Principal>,
+    body: web::Json<HybridSearchInput>,
+    name: web::Path<String>,
+) -> Result<impl Responder> {
+    principal.authorize(Scope::Read, Some(name.as_str()))?;
+    let mut client = data.pool.get().await?;
+    let k = body.k.unwrap_or(10);
+    let candidates = body.candidates.unwrap_or(k * 5).max(k);
+    let ef = body.ef.unwrap_or(10);
+    let metric = body.metric.clone().unwrap_or("l2sq".to_owned());
+    let id_column = body.id_column.clone().unwrap_or("id".to_owned());
+    let fusion_params = FusionParams {
+        method: body.fusion.unwrap_or_default(),
+        rrf_k: body.rrf_k.unwrap_or(60.0),
+        vector_weight: body.vector_weight.unwrap_or(0.5),
+        bm25_weight: body.bm25_weight.unwrap_or(0.5),
+    };
+
+    for column in [&body.vector_column, &body.bm25_column, &id_column] {
+        validate_column(&client, &name, column, data.allow_raw_sql).await?;
+    }
+    let select_fields =
+        get_select_fields(&client, &name, body.select.as_deref(), data.allow_raw_sql).await?;
+
+    let metric_kind = UMetricKind::from(&metric).map_err(ErrorBadRequest)?;
+    let operator = metric_kind.sql_operator();
+    let function = metric_kind.sql_function();
+
+    let query_model = match get_query_model(
+        &client,
+        &name,
+        &body.vector_column,
+        body.query_model.as_ref(),
+    )
+    .await?
+    {
+        Some(model) => model,
+        None => return Err(ErrorBadRequest("Please provide query_model")),
+    };
+    let column_types = get_column_types(&client, &name).await?;
+    let id_type = match column_types.get(&id_column) {
+        Some(id_type) => id_type.clone(),
+        None => {
+            return Err(ErrorBadRequest(format!(
+                "Column '{id_column}' does not exist in collection '{name}'"
+            )))
+        }
+    };
+
+    let transaction = client
+        .transaction()
+        .await
+        .map_err(ErrorInternalServerError)?;
+    set_search_params(&transaction, candidates, ef).await?;
+
+    let filter = compile_filter(body.filter.as_ref(), 2, &column_types).map_err(ErrorBadRequest)?;
+    let mut params: Vec<&(dyn ToSql + Sync)> = vec![&query_model, &body.query_text];
+    params.extend(
+        filter
+            .params
+            .iter()
+            .map(|p| p.as_ref() as &(dyn ToSql + Sync)),
+    );
+
+    let vector_results: Vec<(String, f64)> = transaction
+        .query(
+            &format!(
+                "
+           WITH cte AS (SELECT text_embedding($1, $2) as emb)
+           SELECT {id_column}::text, {function}({column}, cte.emb)::float8 FROM {name}, cte {where_clause} ORDER BY {column} {operator} cte.emb LIMIT {candidates}
+        ",
+                name = quote_ident(&name),
+                id_column = quote_ident(&id_column),
+                column = quote_ident(&body.vector_column),
+                where_clause = filter.where_clause()
+            ),
+            &params,
+        )
+        .await
+        .map_err(ErrorBadRequest)?
+        .iter()
+        .map(|row| (row.get(0), row.get(1)))
+        .collect();
+
+    // BM25 function returns the top documents of the whole table, so with a filter
+    // all the documents matching the query are scored and filtered before the limit
+    let bm25_params: Vec<&(dyn ToSql + Sync)> =
+        vec![&*name, &id_column, &body.bm25_column, &body.query_text];
+    let filter = compile_filter(body.filter.as_ref(), bm25_params.len(), &column_types)
+        .map_err(ErrorBadRequest)?;
+    let (result_limit, filter_clause) = if filter.condition.is_empty() {
+        (candidates.to_string(), String::new())
+    } else {
+        (
+            format!(
+                "(SELECT term_freq FROM {bm25_table} WHERE term IS NULL)",
+                bm25_table = quote_ident(&format!("{name}_bm25"))
+            ),
+            format!(
+                "WHERE b.doc_id IN (SELECT {id_column} FROM {name} {where_clause})",
+                name = quote_ident(&name),
+                id_column = quote_ident(&id_column),
+                where_clause = filter.where_clause()
+            ),
+        )
+    };
+    let mut params = bm25_params;
+    params.extend(
+        filter
+            .params
+            .iter()
+            .map(|p| p.as_ref() as &(dyn ToSql + Sync)),
+    );
+
+    let bm25_results: Vec<(String, f64)> = transaction
+        .query(
+            &format!(
+                "
+           SELECT b.doc_id::text, b.bm25_score::float8 FROM search_bm25($1::text, $2::text, ARRAY[$3::text], $4::text, {result_limit}, false) b
+           {filter_clause} ORDER BY b.bm25_score DESC, b.doc_id LIMIT {candidates}
+        "
+            ),
+            &params,
+        )
+        .await
+        .map_err(ErrorBadRequest)?
+        .iter()
+        .map(|row| (row.get(0), row.get(1)))
+        .collect();
+
+    let scores = fuse_results(&vector_results, &bm25_results, &fusion_params);
+    let ids: Vec<&str> = scores.iter().map(|s| s.id.as_str()).collect();
+
+    let row = transaction
+        .query_one(
+            &format!(
+                "
+           SELECT COALESCE(json_agg(q.*)::text, '[]') as data FROM (
+              SELECT {id_column}::text AS {HYBRID_ID_COLUMN}, {select_fields} FROM {name} WHERE {id_column} = ANY($1::text[]::{id_type}[])
+           ) q;
+        ",
+                name = quote_ident(&name),
+                id_column = quote_ident(&id_column),
+            ),
+            &[&ids],
+        )
+        .await
+        .map_err(ErrorBadRequest)?;
+
+    transaction
+        .commit()
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let rows: Vec<serde_json::Value> =
+        serde_json::from_str(row.get(0)).map_err(ErrorInternalServerError)?;
+    let mut rows_by_id: HashMap<String, serde_json::Map<String, serde_json::Value>> = rows
+        .into_iter()
+        .filter_map(|row| match row {
+            serde_json::Value::Object(mut map) => match map.remove(HYBRID_ID_COLUMN)? {
+                serde_json::Value::String(id) => Some((id, map)),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect();
+
+    let mut response = Vec::with_capacity(k);
+    for score in scores {
+        if response.len() == k {
+            break;
+        }
+
+        let mut row = match rows_by_id.remove(&score.id) {
+            Some(row) => row,
+            None => continue,
+        };
+
+        row.insert("score".to_owned(), score.score.into());
+        row.insert("vector_rank".to_owned(), score.vector_rank.into());
+        row.insert("vector_distance".to_owned(), score.vector_distance.into());
+        row.insert("bm25_rank".to_owned(), score.bm25_rank.into());
+        row.insert("bm25_score".to_owned(), score.bm25_score.into());
+        response.push(serde_json::Value::Object(row));
+    }
+
+    Ok(web::Json(HybridSearchResponse { rows: response }))
+}
@@ -9,7 +9,12 @@ use crate::{external_index::cli::UMetricKind, utils::quote_ident};
 
 use serde::Deserialize;
 
-use super::AppState;
+use super::{
+    auth::{Principal, Scope},
+    operations::{OperationInfo, OperationKind},
+    validation::validate_column,
+    AppState,
+};
 
 #[derive(Deserialize, Debug, utoipa::ToSchema)]
 pub struct CreateIndexInput {
@@ -32,6 +37,9 @@ pub struct CreateIndexInput {
 /// pq index over it
 ///
 /// Metric can be one of `cosine`, `l2sq`, `hamming`
+///
+/// The index is built in background, poll `GET /operations/{id}` with the returned operation id
+/// to wait until it is finished or cancel the build with `DELETE /operations/{id}`
 #[utoipa::path(
     post,
     path = "/collections/{name}/index",
@@ -40,7 +48,7 @@ pub struct CreateIndexInput {
         example = json!(r#"{ "metric": "cosine", "column": "vector", "ef_construction": 128, "ef": 64, "m": 16, "pq": false, "external": true }"#),
     ),
     responses(
-        (status = 200, description = "Index created successfully"),
+        (status = 202, description = "Index creation started", body = OperationInfo),
         (status = 400, description = "Bad request"),
         (status = 500, description = "Internal Server Error")
     ),
@@ -51,9 +59,11 @@ pub struct CreateIndexInput {
 #[post("/collections/{name}/index")]
 async fn create_index(
     data: web::Data<AppState>,
+    principal: web::ReqData<Principal>,
     body: web::Json<CreateIndexInput>,
     name: web::Path<String>,
 ) -> Result<impl Responder> {
+    principal.authorize(Scope::Admin, Some(name.as_str()))?;
     let external = body.external.unwrap_or(true);
     let metric = body.metric.clone().unwrap_or("l2sq".to_owned());
     let column = body.column.clone();
@@ -66,20 +76,25 @@ async fn create_index(
     let metric_kind = UMetricKind::from(&metric).map_err(ErrorBadRequest)?;
 
     let client = data.pool.get().await?;
-    client
-            .execute(
-                &format!(
-                    "CREATE INDEX {index_name} ON {name} USING lantern_hnsw({column} {op_class}) WITH (m={m}, ef={ef}, ef_construction={ef_construction}, pq={pq}, external={external})",
-                    index_name = quote_ident(&index_name),
-                    name = quote_ident(&name),
-                    column = quote_ident(&column),
-                    op_class = metric_kind.to_ops()
-                ),
-                &[],
-            )
-            .await.map_err(ErrorInternalServerError)?;
+    validate_column(&client, &name, &column, data.allow_raw_sql).await?;
+    let statement = format!(
+        "CREATE INDEX {index_name} ON {name} USING lantern_hnsw({column} {op_class}) WITH (m={m}, ef={ef}, ef_construction={ef_construction}, pq={pq}, external={external})",
+        index_name = quote_ident(&index_name),
+        name = quote_ident(&name),
+        column = quote_ident(&column),
+        op_class = metric_kind.to_ops()
+    );
 
-    Ok(HttpResponse::new(StatusCode::from_u16(200).unwrap()))
+    let operation =
+        data.operations
+            .spawn(OperationKind::CreateIndex, &name, move |ctx| async move {
+                // The index is built by a single statement, so it is canceled on the server
+                ctx.set_cancel_token(client.cancel_token());
+                client.execute(&statement, &[]).await?;
+                Ok(None)
+            });
+
+    Ok(HttpResponse::Accepted().json(operation))
 }
 
 /// Delete the specified index by name
@@ -97,9 +112,19 @@ async fn create_index(
 #[delete("/index/{index_name}")]
 async fn delete_index(
     data: web::Data<AppState>,
+    principal: web::ReqData<Principal>,
     index_name: web::Path<String>,
 ) -> Result<impl Responder> {
     let client = data.pool.get().await?;
+    let table = client
+        .query_opt(
+            "SELECT tablename::text FROM pg_indexes WHERE indexname=$1 AND schemaname = ANY(current_schemas(false))",
+            &[&index_name.as_str()],
+        )
+        .await
+        .map_err(ErrorInternalServerError)?
+        .map(|row| row.get::<usize, String>(0));
+    principal.authorize(Scope::Admin, table.as_deref())?;
     let res = client
         .execute(
             &format!(
@@ -1,28 +1,39 @@
 use crate::{logger::LogLevel, types::AnyhowVoidResult};
 use actix_web::{
-    dev::ServiceRequest,
-    error::{ErrorInternalServerError, ErrorUnauthorized},
-    middleware::Logger,
-    web, App, Error, HttpServer, Result,
+    error::ErrorInternalServerError,
+    middleware::{from_fn, Logger},
+    web, App, HttpServer, Result,
 };
-use actix_web_httpauth::{extractors::basic::BasicAuth, middleware::HttpAuthentication};
 use cli::HttpServerArgs;
 use deadpool_postgres::{Config as PoolConfig, Manager, Pool};
 use tokio_postgres::NoTls;
 use utoipa::OpenApi;
 use utoipa_swagger_ui::SwaggerUi;
 
+mod api_keys;
+mod auth;
+mod autotune;
 pub mod cli;
 mod collection;
+mod embedding;
+mod embedding_jobs;
+mod filter;
+mod hybrid_search;
 mod index;
+mod operations;
 mod pq;
+mod rows;
 mod search;
 mod setup;
+pub mod tls;
+mod validation;
 
 type PoolClient = deadpool::managed::Object<Manager>;
 
 pub const COLLECTION_SCHEMA_NAME: &str = "_lantern_extras_internal";
 pub const COLLECTION_TABLE_NAME: &str = "_lantern_extras_internal.http_collections";
+pub const API_KEYS_TABLE_NAME: &str = "_lantern_extras_internal.http_api_keys";
+pub const EMBEDDING_JOBS_TABLE_NAME: &str = "_lantern_extras_internal.embedding_generation_jobs";
 
 struct AppPool {
     inner: Pool,
@@ -52,34 +63,29 @@ pub struct AppState {
     db_uri: String,
     auth_credentials: Option<AuthCredentials>,
     pool: AppPool,
+    allow_raw_sql: bool,
+    rate_limiter: auth::RateLimiter,
+    key_cache: auth::KeyCache,
+    operations: operations::Operations,
     #[allow(dead_code)]
     logger: crate::logger::Logger,
 }
 
 pub type AppData = web::Data<AppState>;
 
-async fn auth_validator(
-    req: ServiceRequest,
-    credentials: BasicAuth,
-) -> Result<ServiceRequest, (Error, ServiceRequest)> {
-    let data = req.app_data::<AppData>().unwrap();
-    if let Some(creds) = &data.auth_credentials {
-        if creds.username != credentials.user_id()
-            || creds.password != credentials.password().unwrap_or("")
-        {
-            return Err((ErrorUnauthorized("Unauthorized"), req));
-        }
-    }
-    Ok(req)
-}
-
 #[derive(OpenApi)]
 #[openapi(
     info(
         title = "Lantern HTTP API",
         description = "This is an HTTP wrapper over Lantern database, which also includes pq and external indexing functionalities from Lantern CLI.
 
-The API endpoints are not SQL injection safe, so it can provide maximum flexibility for data manipulation, so please sanitize user input before sending requests to this API."
+All the values are passed to the database as query parameters, identifiers are quoted and column lists and data types are validated against the collection schema.
+
+If the server is started with `--allow-raw-sql` flag, the `select` fields and column data types will be used in SQL statements as is, so it can provide maximum flexibility for data manipulation. In this mode the API is not SQL injection safe, so please sanitize user input before sending requests to this API.
+
+Requests are authorized either with Basic auth credentials passed to the server, which have admin access, or with API keys passed in `Authorization: Bearer {key}` header. API keys are created via `/api-keys` endpoints and have `read`, `write` or `admin` scope, optionally restricted to specific collections and rate limited.
+
+Index creation, quantization and autotune run in background, these endpoints return an operation which can be polled or canceled via `/operations/{id}` endpoints."
     ),
     paths(
         collection::create,
@@ -88,18 +94,59 @@ The API endpoints are not SQL injection safe, so it can provide maximum flexibil
         collection::delete,
         collection::insert_data,
         search::vector_search,
+        search::batch_search,
+        hybrid_search::hybrid_search,
         index::create_index,
         index::delete_index,
         pq::quantize_table,
+        operations::get_operation,
+        operations::cancel_operation,
+        autotune::autotune,
+        embedding_jobs::create_job,
+        embedding_jobs::list_jobs,
+        embedding_jobs::cancel_job,
+        embedding_jobs::resume_job,
+        api_keys::create_key,
+        api_keys::list_keys,
+        api_keys::revoke_key,
+        rows::list_rows,
+        rows::get_row,
+        rows::update_row,
+        rows::upsert_rows,
+        rows::delete_row,
+        rows::delete_rows,
     ),
     components(schemas(
         collection::CollectionInfo,
         collection::CreateTableInput,
         collection::InserDataInput,
+        embedding::EmbeddingConfig,
+        embedding::EmbeddingMode,
         search::SearchInput,
         search::SearchResponse,
+        search::BatchSearchInput,
+        search::BatchSearchResponse,
+        hybrid_search::HybridSearchInput,
+        hybrid_search::HybridSearchResponse,
+        hybrid_search::FusionMethod,
         index::CreateIndexInput,
-        pq::CreatePQInput
+        pq::CreatePQInput,
+        operations::OperationInfo,
+        operations::OperationStatus,
+        operations::OperationKind,
+        autotune::AutotuneInput,
+        autotune::AutotuneResult,
+        embedding_jobs::EmbeddingJobInfo,
+        embedding_jobs::CreateEmbeddingJobInput,
+        auth::Scope,
+        api_keys::ApiKeyInfo,
+        api_keys::CreatedApiKey,
+        api_keys::CreateApiKeyInput,
+        rows::ListRowsResponse,
+        rows::UpsertRowsInput,
+        rows::UpsertRowsResponse,
+        rows::DeleteRowsInput,
+        rows::DeleteRowsResponse
     ))
 )]
 pub struct ApiDoc;
@@ -110,20 +157,41 @@ pub async fn start(
     logger: Option<crate::logger::Logger>,
 ) -> AnyhowVoidResult {
     let logger = logger.unwrap_or(crate::logger::Logger::new("Lantern HTTP", LogLevel::Debug));
+    let tls_config = match (&args.cert, &args.key) {
+        (Some(cert), Some(key)) => {
+            let (config, resolver) = tls::get_server_config(cert, key)?;
+            tls::spawn_reload_on_sighup(resolver, logger.clone())?;
+            Some(config)
+        }
+        (None, None) => None,
+        _ => anyhow::bail!("Both --cert and --key should be specified to enable TLS"),
+    };
+    let scheme = if tls_config.is_some() {
+        "https"
+    } else {
+        "http"
+    };
     logger.info(&format!(
-        "Starting web server on http://{host}:{port}",
+        "Starting web server on {scheme}://{host}:{port}",
         host = args.host,
         port = args.port,
     ));
     logger.info(&format!(
-        "Documentation available at http://{host}:{port}/swagger-ui/",
+        "Documentation available at {scheme}://{host}:{port}/swagger-ui/",
         host = args.host,
         port = args.port,
     ));
+    if args.allow_raw_sql {
+        logger.warn("Raw SQL is allowed in requests, the API is not SQL injection safe");
+    }
     env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
     let mut config = PoolConfig::new();
     config.url = Some(args.db_uri.clone());
-    let pool = config.create_pool(None, NoTls)?;
+    let db_tls = tls::get_db_tls_connector(&args.db_uri)?;
+    let pool = match &db_tls {
+        Some(db_tls) => config.create_pool(None, db_tls.clone())?,
+        None => config.create_pool(None, NoTls)?,
+    };
 
     setup::setup_tables(&pool).await?;
 
@@ -140,14 +208,18 @@ pub async fn start(
         auth_credentials,
         db_uri: args.db_uri.clone(),
         pool: AppPool::new(pool),
+        allow_raw_sql: args.allow_raw_sql,
+        rate_limiter: auth::RateLimiter::default(),
+        key_cache: auth::KeyCache::default(),
+        operations: operations::Operations::new(db_tls),
         logger,
     });
 
-    HttpServer::new(move || {
+    let server = HttpServer::new(move || {
         App::new()
             .wrap(Logger::new("%r - %s %Dms"))
             .app_data(state.clone())
-            .wrap(HttpAuthentication::basic(auth_validator))
+            .wrap(from_fn(auth::authenticate))
             .app_data(
                 web::JsonConfig::default()
                     // limit request payload size to 1GB
@@ -163,12 +235,34 @@ pub async fn start(
             .service(collection::delete)
             .service(collection::insert_data)
             .service(search::vector_search)
+            .service(search::batch_search)
+            .service(hybrid_search::hybrid_search)
             .service(index::create_index)
             .service(index::delete_index)
             .service(pq::quantize_table)
-    })
-    .bind((args.host.clone(), args.port))?
-    .run()
-    .await?;
+            .service(operations::get_operation)
+            .service(operations::cancel_operation)
+            .service(autotune::autotune)
+            .service(embedding_jobs::create_job)
+            .service(embedding_jobs::list_jobs)
+            .service(embedding_jobs::cancel_job)
+            .service(embedding_jobs::resume_job)
+            .service(api_keys::create_key)
+            .service(api_keys::list_keys)
+            .service(api_keys::revoke_key)
+            .service(rows::list_rows)
+            .service(rows::get_row)
+            .service(rows::update_row)
+            .service(rows::upsert_rows)
+            .service(rows::delete_rows)
+            .service(rows::delete_row)
+    });
+
+    let server = match tls_config {
+        Some(config) => server.bind_rustls_0_23((args.host.clone(), args.port), config)?,
+        None => server.bind((args.host.clone(), args.port))?,
+    };
+
+    server.run().await?;
     Ok(())
 }
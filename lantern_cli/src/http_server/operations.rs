@@ -0,0 +1,293 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use actix_web::{
+    delete,
+    error::{ErrorConflict, ErrorNotFound},
+    get, web, Responder, Result,
+};
+use serde::Serialize;
+use tokio_postgres::{CancelToken, NoTls};
+use tokio_postgres_rustls::MakeRustlsConnect;
+
+use crate::types::ProgressCbFn;
+
+// Finished operations are kept for this number of seconds, so their status can be polled
+const FINISHED_OPERATION_TTL: u64 = 60 * 60;
+
+use super::{
+    auth::{Principal, Scope},
+    AppState,
+};
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum OperationStatus {
+    Running,
+    Succeeded,
+    Failed,
+    Canceled,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OperationKind {
+    CreateIndex,
+    Quantize,
+    Autotune,
+}
+
+#[derive(Serialize, Debug, Clone, utoipa::ToSchema)]
+pub struct OperationInfo {
+    id: u64,
+    kind: OperationKind,
+    collection: String,
+    status: OperationStatus,
+    progress: u8,
+    error: Option<String>,
+    // Output of the succeeded operation, e.g. autotune results
+    #[schema(value_type = Option<Object>)]
+    result: Option<serde_json::Value>,
+    // Unix timestamps in seconds
+    started_at: u64,
+    finished_at: Option<u64>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+struct Operation {
+    info: Arc<Mutex<OperationInfo>>,
+    is_canceled: Arc<RwLock<bool>>,
+    // Set by operations running a single long query, so it can be canceled on the server
+    cancel_token: Arc<Mutex<Option<CancelToken>>>,
+}
+
+// Handles passed to the background task to report the progress and check cancellation
+pub struct OperationContext {
+    info: Arc<Mutex<OperationInfo>>,
+    pub is_canceled: Arc<RwLock<bool>>,
+    cancel_token: Arc<Mutex<Option<CancelToken>>>,
+}
+
+impl OperationContext {
+    pub fn progress_cb(&self) -> ProgressCbFn {
+        let info = self.info.clone();
+        Box::new(move |progress| info.lock().unwrap().progress = progress)
+    }
+
+    pub fn set_cancel_token(&self, token: CancelToken) {
+        *self.cancel_token.lock().unwrap() = Some(token);
+    }
+}
+
+// Long running operations started via HTTP API
+// Operations are kept in memory, so they are lost when the server restarts
+// Finished operations are evicted after `FINISHED_OPERATION_TTL`
+pub struct Operations {
+    next_id: AtomicU64,
+    operations: RwLock<HashMap<u64, Operation>>,
+    // Queries are canceled over a new connection, so it should use the same TLS
+    // settings as the pool
+    db_tls: Option<MakeRustlsConnect>,
+}
+
+impl Operations {
+    pub fn new(db_tls: Option<MakeRustlsConnect>) -> Self {
+        Self {
+            next_id: AtomicU64::new(0),
+            operations: RwLock::new(HashMap::new()),
+            db_tls,
+        }
+    }
+
+    // Registers new operation and runs the task in background
+    pub fn spawn<F, Fut>(&self, kind: OperationKind, collection: &str, task: F) -> OperationInfo
+    where
+        F: FnOnce(OperationContext) -> Fut,
+        Fut: Future<Output = Result<Option<serde_json::Value>, anyhow::Error>> + 'static,
+    {
+        self.evict_finished();
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst) + 1;
+        let info = OperationInfo {
+            id,
+            kind,
+            collection: collection.to_owned(),
+            status: OperationStatus::Running,
+            progress: 0,
+            error: None,
+            result: None,
+            started_at: now(),
+            finished_at: None,
+        };
+
+        let operation = Operation {
+            info: Arc::new(Mutex::new(info.clone())),
+            is_canceled: Arc::new(RwLock::new(false)),
+            cancel_token: Arc::new(Mutex::new(None)),
+        };
+
+        let ctx = OperationContext {
+            info: operation.info.clone(),
+            is_canceled: operation.is_canceled.clone(),
+            cancel_token: operation.cancel_token.clone(),
+        };
+        let shared_info = operation.info.clone();
+        let is_canceled = operation.is_canceled.clone();
+        let future = task(ctx);
+
+        self.operations.write().unwrap().insert(id, operation);
+
+        actix_web::rt::spawn(async move {
+            let result = future.await;
+            let mut info = shared_info.lock().unwrap();
+            info.finished_at = Some(now());
+
+            // The status is set from the task result, as the task can complete
+            // before the cancellation reaches it, e.g. the index is already committed
+            match result {
+                Ok(result) => {
+                    info.status = OperationStatus::Succeeded;
+                    info.progress = 100;
+                    info.result = result;
+                }
+                Err(_) if *is_canceled.read().unwrap() => {
+                    info.status = OperationStatus::Canceled;
+                }
+                Err(e) => {
+                    info.status = OperationStatus::Failed;
+                    info.error = Some(e.to_string());
+                }
+            }
+        });
+
+        info
+    }
+
+    fn evict_finished(&self) {
+        let now = now();
+        self.operations.write().unwrap().retain(|_, op| {
+            match op.info.lock().unwrap().finished_at {
+                Some(finished_at) => now.saturating_sub(finished_at) < FINISHED_OPERATION_TTL,
+                None => true,
+            }
+        });
+    }
+
+    fn get(&self, id: u64) -> Option<OperationInfo> {
+        self.evict_finished();
+        self.operations
+            .read()
+            .unwrap()
+            .get(&id)
+            .map(|op| op.info.lock().unwrap().clone())
+    }
+
+    fn cancel(&self, id: u64) -> Result<()> {
+        let operations = self.operations.read().unwrap();
+        let operation = match operations.get(&id) {
+            Some(operation) => operation,
+            None => return Err(ErrorNotFound("Operation not found")),
+        };
+
+        if operation.info.lock().unwrap().status != OperationStatus::Running {
+            return Err(ErrorConflict("Operation is already finished"));
+        }
+
+        *operation.is_canceled.write().unwrap() = true;
+
+        if let Some(token) = operation.cancel_token.lock().unwrap().clone() {
+            let db_tls = self.db_tls.clone();
+            actix_web::rt::spawn(async move {
+                let _ = match db_tls {
+                    Some(db_tls) => token.cancel_query(db_tls).await,
+                    None => token.cancel_query(NoTls).await,
+                };
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Get the operation status and progress
+///
+/// Status can be one of `running`, `succeeded`, `failed`, `canceled`,
+/// `progress` is a percentage from 0 to 100.
+/// The `result` is set for succeeded operations which produce an output.
+/// Finished operations are removed after an hour
+#[utoipa::path(
+    get,
+    path = "/operations/{id}",
+    responses(
+        (status = 200, description = "Returns the operation", body = OperationInfo),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Operation not found"),
+    ),
+    params(
+       ("id", description = "Operation id")
+    ),
+)]
+#[get("/operations/{id}")]
+pub async fn get_operation(
+    data: web::Data<AppState>,
+    principal: web::ReqData<Principal>,
+    id: web::Path<u64>,
+) -> Result<impl Responder> {
+    let info = match data.operations.get(*id) {
+        Some(info) => info,
+        None => return Err(ErrorNotFound("Operation not found")),
+    };
+    principal.authorize(Scope::Read, Some(info.collection.as_str()))?;
+
+    Ok(web::Json(info))
+}
+
+/// Cancel the running operation
+///
+/// The operation is stopped asynchronously, poll `GET /operations/{id}` to wait until it is `canceled`.
+/// If the operation completes before it is stopped, it will be `succeeded`
+#[utoipa::path(
+    delete,
+    path = "/operations/{id}",
+    responses(
+        (status = 200, description = "Returns the operation", body = OperationInfo),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Operation not found"),
+        (status = 409, description = "Operation is already finished"),
+    ),
+    params(
+       ("id", description = "Operation id")
+    ),
+)]
+#[delete("/operations/{id}")]
+pub async fn cancel_operation(
+    data: web::Data<AppState>,
+    principal: web::ReqData<Principal>,
+    id: web::Path<u64>,
+) -> Result<impl Responder> {
+    let info = match data.operations.get(*id) {
+        Some(info) => info,
+        None => return Err(ErrorNotFound("Operation not found")),
+    };
+    principal.authorize(Scope::Admin, Some(info.collection.as_str()))?;
+
+    data.operations.cancel(*id)?;
+
+    // The operation can finish and be evicted meanwhile
+    match data.operations.get(*id) {
+        Some(info) => Ok(web::Json(info)),
+        None => Err(ErrorNotFound("Operation not found")),
+    }
+}
@@ -1,14 +1,18 @@
 use actix_web::{
     error::{ErrorInternalServerError, ErrorUnprocessableEntity},
-    http::StatusCode,
     post, web, HttpResponse, Responder, Result,
 };
 
-use crate::pq::cli::PQArgs;
+use crate::{pq::cli::PQArgs, utils::quote_ident};
 
 use serde::Deserialize;
 
-use super::AppState;
+use super::{
+    auth::{Principal, Scope},
+    operations::{OperationInfo, OperationKind},
+    validation::validate_column,
+    AppState,
+};
 
 #[derive(Deserialize, Debug, utoipa::ToSchema)]
 pub struct CreatePQInput {
@@ -28,6 +32,9 @@ pub struct CreatePQInput {
 /// be divisible by vector length.
 ///
 /// If the `limit` param is passed PQ will be done on subset of the data
+///
+/// The quantization runs in background, poll `GET /operations/{id}` with the returned operation id
+/// to get the progress or cancel it with `DELETE /operations/{id}`
 #[utoipa::path(
     post,
     path = "/collections/{name}/pq",
@@ -36,7 +43,7 @@ pub struct CreatePQInput {
         example = json!(r#"{ "column": "vector", "clusters": 2, "splits": 1 }"#),
     ),
     responses(
-        (status = 200, description = "Index created successfully"),
+        (status = 202, description = "Quantization started", body = OperationInfo),
         (status = 400, description = "Bad request"),
         (status = 500, description = "Internal Server Error")
     ),
@@ -47,18 +54,21 @@ pub struct CreatePQInput {
 #[post("/collections/{name}/pq")]
 async fn quantize_table(
     data: web::Data<AppState>,
+    principal: web::ReqData<Principal>,
     body: web::Json<CreatePQInput>,
     name: web::Path<String>,
 ) -> Result<impl Responder> {
+    principal.authorize(Scope::Admin, Some(name.as_str()))?;
     let column = body.column.clone();
     let clusters = body.clusters.unwrap_or(256);
     let splits = body.splits;
     let dataset_limit = body.limit.clone();
 
     let client = data.pool.get().await?;
+    validate_column(&client, &name, &column, data.allow_raw_sql).await?;
 
-    let pk_query = format!("SELECT a.attname FROM pg_index i JOIN pg_attribute a ON a.attrelid = i.indrelid AND a.attnum = ANY(i.indkey) WHERE i.indrelid = '{name}'::regclass AND i.indisprimary");
-    let pk = match client.query(&pk_query, &[]).await {
+    let pk_query = "SELECT a.attname FROM pg_index i JOIN pg_attribute a ON a.attrelid = i.indrelid AND a.attnum = ANY(i.indkey) WHERE i.indrelid = $1::text::regclass AND i.indisprimary";
+    let pk = match client.query(pk_query, &[&quote_ident(&name)]).await {
         Ok(rows) => {
             if rows.is_empty() {
                 return Err(ErrorUnprocessableEntity(
@@ -71,50 +81,56 @@ async fn quantize_table(
         Err(e) => return Err(ErrorInternalServerError(e)),
     };
 
-    tokio::task::spawn_blocking(move || {
-        crate::pq::quantize_table(
-            PQArgs {
-                column,
-                clusters,
-                splits,
-                pk,
-                uri: data.db_uri.clone(),
-                table: name.clone(),
-                schema: "public".to_owned(),
-                codebook_table_name: None,
-                dataset_limit,
-                subvector_id: None,
-                overwrite: true,
-                skip_table_setup: false,
-                skip_vector_quantization: false,
-                skip_codebook_creation: false,
-                total_task_count: None,
-                parallel_task_count: None,
-                quantization_task_id: None,
-                run_on_gcp: false,
-                gcp_cli_image_tag: None,
-                gcp_project: None,
-                gcp_region: None,
-                gcp_image: None,
-                gcp_quantization_task_count: None,
-                gcp_quantization_task_parallelism: None,
-                gcp_clustering_task_parallelism: None,
-                gcp_enable_image_streaming: false,
-                gcp_clustering_cpu: None,
-                gcp_clustering_memory_gb: None,
-                gcp_quantization_cpu: None,
-                gcp_quantization_memory_gb: None,
-                dataset_size: None,
-                start_offset_id: None,
-            },
-            None,
-            None,
-            None,
-        )
-    })
-    .await
-    .map_err(ErrorInternalServerError)?
-    .map_err(ErrorInternalServerError)?;
+    let db_uri = data.db_uri.clone();
+    let table = name.to_string();
+
+    let operation = data
+        .operations
+        .spawn(OperationKind::Quantize, &name, move |ctx| async move {
+            tokio::task::spawn_blocking(move || {
+                crate::pq::quantize_table(
+                    PQArgs {
+                        column,
+                        clusters,
+                        splits,
+                        pk,
+                        uri: db_uri,
+                        table,
+                        schema: "public".to_owned(),
+                        codebook_table_name: None,
+                        dataset_limit,
+                        subvector_id: None,
+                        overwrite: true,
+                        skip_table_setup: false,
+                        skip_vector_quantization: false,
+                        skip_codebook_creation: false,
+                        total_task_count: None,
+                        parallel_task_count: None,
+                        quantization_task_id: None,
+                        run_on_gcp: false,
+                        gcp_cli_image_tag: None,
+                        gcp_project: None,
+                        gcp_region: None,
+                        gcp_image: None,
+                        gcp_quantization_task_count: None,
+                        gcp_quantization_task_parallelism: None,
+                        gcp_clustering_task_parallelism: None,
+                        gcp_enable_image_streaming: false,
+                        gcp_clustering_cpu: None,
+                        gcp_clustering_memory_gb: None,
+                        gcp_quantization_cpu: None,
+                        gcp_quantization_memory_gb: None,
+                        dataset_size: None,
+                        start_offset_id: None,
+                    },
+                    Some(ctx.progress_cb()),
+                    Some(ctx.is_canceled.clone()),
+                    None,
+                )
+            })
+            .await??;
+            Ok(None)
+        });
 
-    Ok(HttpResponse::new(StatusCode::from_u16(200).unwrap()))
+    Ok(HttpResponse::Accepted().json(operation))
 }
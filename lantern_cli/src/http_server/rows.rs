@@ -0,0 +1,594 @@
+use std::collections::HashMap;
+
+use actix_web::{
+    delete,
+    error::{ErrorBadRequest, ErrorInternalServerError, ErrorNotFound},
+    get, patch, post, web, Responder, Result,
+};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tokio_postgres::types::ToSql;
+
+use crate::utils::quote_ident;
+
+use super::{
+    auth::{Principal, Scope},
+    filter::compile_filter,
+    validation::{
+        get_filter_column_types, get_primary_key, get_select_fields, validate_columns, PrimaryKey,
+    },
+    AppState, PoolClient,
+};
+
+static CURSOR_COLUMN: &'static str = "_lantern_cursor";
+
+// Ids are passed to the database as text and casted to the primary key type
+fn id_to_string(id: &Value) -> Result<String> {
+    match id {
+        Value::String(s) => Ok(s.clone()),
+        Value::Number(n) => Ok(n.to_string()),
+        _ => Err(ErrorBadRequest(format!(
+            "Invalid id {id}, expected string or number"
+        ))),
+    }
+}
+
+// Groups the rows by their columns, so each row only sets the columns passed in it
+fn group_by_columns<'a>(
+    rows: &[&'a Map<String, Value>],
+) -> Vec<(Vec<&'a String>, Vec<&'a Map<String, Value>>)> {
+    let mut groups: Vec<(Vec<&String>, Vec<&Map<String, Value>>)> = Vec::new();
+    let mut group_idx_by_columns: HashMap<Vec<&String>, usize> = HashMap::new();
+    for &row in rows {
+        let columns: Vec<&String> = row.keys().sorted().collect();
+        match group_idx_by_columns.get(&columns) {
+            Some(idx) => groups[*idx].1.push(row),
+            None => {
+                group_idx_by_columns.insert(columns.clone(), groups.len());
+                groups.push((columns, vec![row]));
+            }
+        }
+    }
+    groups
+}
+
+// Returns the rows of the query as json objects
+// Data modifying statements should be passed in `ctes` as they can only be used in top level WITH clause
+async fn query_rows(
+    client: &PoolClient,
+    ctes: Option<&str>,
+    query: &str,
+    params: &[&(dyn ToSql + Sync)],
+) -> Result<Vec<Value>> {
+    let with_clause = ctes.map(|ctes| format!("WITH {ctes}")).unwrap_or_default();
+    let row = client
+        .query_one(
+            &format!("{with_clause} SELECT COALESCE(json_agg(q.*)::text, '[]') FROM ({query}) q"),
+            params,
+        )
+        .await
+        .map_err(ErrorBadRequest)?;
+
+    serde_json::from_str(row.get::<usize, &str>(0)).map_err(ErrorInternalServerError)
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ListRowsQuery {
+    limit: Option<usize>,
+    cursor: Option<String>,
+    select: Option<String>,
+}
+
+#[derive(Serialize, Debug, utoipa::ToSchema)]
+pub struct ListRowsResponse {
+    rows: Vec<Value>,
+    next_cursor: Option<String>,
+}
+
+/// Page through collection rows ordered by primary key
+///
+/// Pass the `next_cursor` from the response as `cursor` to get the next page,
+/// `next_cursor` is `null` on the last page. Default `limit` is 100
+#[utoipa::path(
+    get,
+    path = "/collections/{name}/rows",
+    responses(
+        (status = 200, description = "Returns a page of rows", body = ListRowsResponse),
+        (status = 400, description = "Bad request"),
+        (status = 422, description = "Collection does not have a primary key"),
+    ),
+    params(
+       ("name", description = "Collection name"),
+       ("limit" = Option<usize>, Query, description = "Number of rows in the page"),
+       ("cursor" = Option<String>, Query, description = "Cursor from the previous page"),
+       ("select" = Option<String>, Query, description = "Comma separated column names"),
+    ),
+)]
+#[get("/collections/{name}/rows")]
+pub async fn list_rows(
+    data: web::Data<AppState>,
+    principal: web::ReqData<Principal>,
+    name: web::Path<String>,
+    query: web::Query<ListRowsQuery>,
+) -> Result<impl Responder> {
+    principal.authorize(Scope::Read, Some(name.as_str()))?;
+    let client = data.pool.get().await?;
+    let PrimaryKey {
+        name: pk,
+        data_type,
+    } = get_primary_key(&client, &name).await?;
+    let select_fields =
+        get_select_fields(&client, &name, query.select.as_deref(), data.allow_raw_sql).await?;
+    let limit = query.limit.unwrap_or(100);
+
+    let mut params: Vec<&(dyn ToSql + Sync)> = Vec::new();
+    let where_clause = match &query.cursor {
+        Some(cursor) => {
+            params.push(cursor);
+            format!("WHERE {pk} > $1::text::{data_type}", pk = quote_ident(&pk))
+        }
+        None => String::new(),
+    };
+
+    let rows = query_rows(
+        &client,
+        None,
+        &format!(
+            "SELECT {pk}::text AS {CURSOR_COLUMN}, {select_fields} FROM {name} {where_clause} ORDER BY {pk} LIMIT {limit}",
+            pk = quote_ident(&pk),
+            name = quote_ident(&name)
+        ),
+        &params,
+    )
+    .await?;
+
+    let mut next_cursor = None;
+    let rows_len = rows.len();
+    let rows = rows
+        .into_iter()
+        .map(|mut row| {
+            if let Some(cursor) = row.as_object_mut().and_then(|r| r.remove(CURSOR_COLUMN)) {
+                next_cursor = cursor.as_str().map(|c| c.to_owned());
+            }
+            row
+        })
+        .collect();
+
+    Ok(web::Json(ListRowsResponse {
+        rows,
+        next_cursor: if rows_len == limit { next_cursor } else { None },
+    }))
+}
+
+/// Get collection row by primary key
+#[utoipa::path(
+    get,
+    path = "/collections/{name}/rows/{id}",
+    responses(
+        (status = 200, description = "Returns the row"),
+        (status = 404, description = "Row not found"),
+        (status = 422, description = "Collection does not have a primary key"),
+    ),
+    params(
+       ("name", description = "Collection name"),
+       ("id", description = "Primary key value"),
+    ),
+)]
+#[get("/collections/{name}/rows/{id}")]
+pub async fn get_row(
+    data: web::Data<AppState>,
+    principal: web::ReqData<Principal>,
+    path: web::Path<(String, String)>,
+) -> Result<impl Responder> {
+    let (name, id) = path.into_inner();
+    principal.authorize(Scope::Read, Some(name.as_str()))?;
+    let client = data.pool.get().await?;
+    let PrimaryKey {
+        name: pk,
+        data_type,
+    } = get_primary_key(&client, &name).await?;
+
+    let mut rows = query_rows(
+        &client,
+        None,
+        &format!(
+            "SELECT * FROM {name} WHERE {pk} = $1::text::{data_type}",
+            pk = quote_ident(&pk),
+            name = quote_ident(&name)
+        ),
+        &[&id],
+    )
+    .await?;
+
+    match rows.pop() {
+        Some(row) => Ok(web::Json(row)),
+        None => Err(ErrorNotFound("Row not found")),
+    }
+}
+
+/// Update columns of the row by primary key
+///
+/// Only the columns passed in the body are updated, values are converted to the column types
+#[utoipa::path(
+    patch,
+    path = "/collections/{name}/rows/{id}",
+    request_body (
+        content = Object,
+        example = json!(r#"{ "data": "updated text", "metadata": {"k": "v"} }"#)
+    ),
+    responses(
+        (status = 200, description = "Returns the updated row"),
+        (status = 400, description = "Bad request"),
+        (status = 404, description = "Row not found"),
+        (status = 422, description = "Collection does not have a primary key"),
+    ),
+    params(
+       ("name", description = "Collection name"),
+       ("id", description = "Primary key value"),
+    ),
+)]
+#[patch("/collections/{name}/rows/{id}")]
+pub async fn update_row(
+    data: web::Data<AppState>,
+    principal: web::ReqData<Principal>,
+    path: web::Path<(String, String)>,
+    body: web::Json<Map<String, Value>>,
+) -> Result<impl Responder> {
+    let (name, id) = path.into_inner();
+    principal.authorize(Scope::Write, Some(name.as_str()))?;
+
+    if body.is_empty() {
+        return Err(ErrorBadRequest("No columns to update"));
+    }
+
+    let client = data.pool.get().await?;
+    validate_columns(&client, &name, body.keys(), data.allow_raw_sql).await?;
+    let PrimaryKey {
+        name: pk,
+        data_type,
+    } = get_primary_key(&client, &name).await?;
+
+    let columns = body.keys().map(|c| quote_ident(c)).join(",");
+    let values = Value::Object(body.into_inner()).to_string();
+
+    let mut rows = query_rows(
+        &client,
+        Some(&format!(
+            "updated AS (UPDATE {name} SET ({columns}) = (SELECT {columns} FROM json_populate_record(NULL::{name}, $2::text::json)) WHERE {pk} = $1::text::{data_type} RETURNING *)",
+            pk = quote_ident(&pk),
+            name = quote_ident(&name)
+        )),
+        "SELECT * FROM updated",
+        &[&id, &values],
+    )
+    .await?;
+
+    match rows.pop() {
+        Some(row) => Ok(web::Json(row)),
+        None => Err(ErrorNotFound("Row not found")),
+    }
+}
+
+#[derive(Deserialize, Debug, utoipa::ToSchema)]
+pub struct UpsertRowsInput {
+    rows: Vec<Map<String, Value>>,
+}
+
+#[derive(Serialize, Debug, utoipa::ToSchema)]
+pub struct UpsertRowsResponse {
+    ids: Vec<Value>,
+}
+
+/// Insert or update rows by primary key
+///
+/// Rows with existing primary key are updated, other rows are inserted.
+/// Rows without primary key are inserted using the column default (e.g. identity or serial).
+/// Primary keys are compared after conversion to the key type, so `1` and `"1"` are the same key.
+/// If the same primary key is passed multiple times the last row is used.
+///
+/// Only the columns passed in a row are set, existing rows keep the values of the other columns
+/// and new rows use the column defaults for them.
+/// Values are converted to the column types, so arrays can be passed for `REAL[]` columns and objects for `JSONB` columns.
+///
+/// Returns the primary keys of the upserted rows
+#[utoipa::path(
+    post,
+    path = "/collections/{name}/rows/upsert",
+    request_body (
+        content = UpsertRowsInput,
+        example = json!(r#"{ "rows": [{"id": 1, "vector": [1,1,1], "data": "t1", "metadata": {"k": "v"}}, {"id": 2, "vector": [2,2,2], "data": "t2", "metadata": {"k": "v"}}] }"#)
+    ),
+    responses(
+        (status = 200, description = "Returns the primary keys of upserted rows", body = UpsertRowsResponse),
+        (status = 400, description = "Bad request"),
+        (status = 422, description = "Collection does not have a primary key"),
+    ),
+    params(
+       ("name", description = "Collection name"),
+    ),
+)]
+#[post("/collections/{name}/rows/upsert")]
+pub async fn upsert_rows(
+    data: web::Data<AppState>,
+    principal: web::ReqData<Principal>,
+    name: web::Path<String>,
+    body: web::Json<UpsertRowsInput>,
+) -> Result<impl Responder> {
+    principal.authorize(Scope::Write, Some(name.as_str()))?;
+
+    if body.rows.is_empty() {
+        return Ok(web::Json(UpsertRowsResponse { ids: Vec::new() }));
+    }
+
+    let client = data.pool.get().await?;
+    let columns: Vec<String> = body
+        .rows
+        .iter()
+        .flat_map(|row| row.keys().cloned())
+        .unique()
+        .collect();
+    validate_columns(&client, &name, &columns, data.allow_raw_sql).await?;
+    let PrimaryKey {
+        name: pk,
+        data_type,
+    } = get_primary_key(&client, &name).await?;
+
+    // Ids are converted to the primary key type and back to text,
+    // so the same key passed as number and string is detected
+    let ids: Vec<String> = body
+        .rows
+        .iter()
+        .filter_map(|row| row.get(&pk).filter(|id| !id.is_null()))
+        .map(id_to_string)
+        .collect::<Result<_>>()?;
+    let normalized_ids: Vec<String> = client
+        .query(
+            &format!("SELECT id::text FROM unnest($1::text[]::{data_type}[]) WITH ORDINALITY AS t(id, idx) ORDER BY idx"),
+            &[&ids],
+        )
+        .await
+        .map_err(ErrorBadRequest)?
+        .iter()
+        .map(|row| row.get::<usize, String>(0))
+        .collect();
+
+    // Rows without primary key are inserted separately, so the column default will be used.
+    // Only one row per primary key can be updated in a single statement, so the last one is kept
+    let mut rows_with_pk: Vec<&Map<String, Value>> = Vec::new();
+    let mut rows_without_pk: Vec<&Map<String, Value>> = Vec::new();
+    let mut row_idx_by_id: HashMap<&str, usize> = HashMap::new();
+    let mut normalized_ids = normalized_ids.iter();
+    for row in &body.rows {
+        if row.get(&pk).map_or(true, |id| id.is_null()) {
+            rows_without_pk.push(row);
+            continue;
+        }
+
+        let id = normalized_ids.next().unwrap().as_str();
+        match row_idx_by_id.get(id) {
+            Some(idx) => rows_with_pk[*idx] = row,
+            None => {
+                row_idx_by_id.insert(id, rows_with_pk.len());
+                rows_with_pk.push(row);
+            }
+        }
+    }
+
+    let name = quote_ident(&name);
+    let pk_ident = quote_ident(&pk);
+    let mut statements = Vec::new();
+    let mut values = Vec::new();
+
+    for (columns, rows) in group_by_columns(&rows_with_pk) {
+        let updates = columns
+            .iter()
+            .filter(|c| ***c != pk)
+            .map(|c| format!("{col}=EXCLUDED.{col}", col = quote_ident(c)))
+            .join(",");
+        let conflict_action = if updates.is_empty() {
+            "NOTHING".to_owned()
+        } else {
+            format!("UPDATE SET {updates}")
+        };
+        let column_names = columns.iter().map(|c| quote_ident(c)).join(",");
+
+        values.push(serde_json::to_string(&rows).map_err(ErrorInternalServerError)?);
+        statements.push(format!(
+            "INSERT INTO {name} ({column_names}) OVERRIDING SYSTEM VALUE SELECT {column_names} FROM json_populate_recordset(NULL::{name}, ${idx}::text::json) ON CONFLICT ({pk_ident}) DO {conflict_action} RETURNING {pk_ident}",
+            idx = values.len()
+        ));
+    }
+
+    for (columns, rows) in group_by_columns(&rows_without_pk) {
+        let column_names = columns
+            .iter()
+            .filter(|c| ***c != pk)
+            .map(|c| quote_ident(c))
+            .join(",");
+
+        if column_names.is_empty() {
+            return Err(ErrorBadRequest("Rows should not be empty"));
+        }
+
+        values.push(serde_json::to_string(&rows).map_err(ErrorInternalServerError)?);
+        statements.push(format!(
+            "INSERT INTO {name} ({column_names}) SELECT {column_names} FROM json_populate_recordset(NULL::{name}, ${idx}::text::json) RETURNING {pk_ident}",
+            idx = values.len()
+        ));
+    }
+
+    let query = statements
+        .iter()
+        .enumerate()
+        .map(|(idx, _)| format!("SELECT {pk_ident} FROM upserted_{idx}"))
+        .join(" UNION ALL ");
+    let ctes = statements
+        .iter()
+        .enumerate()
+        .map(|(idx, statement)| format!("upserted_{idx} AS ({statement})"))
+        .join(",");
+    let params: Vec<&(dyn ToSql + Sync)> =
+        values.iter().map(|v| v as &(dyn ToSql + Sync)).collect();
+
+    // Both of the inserts are executed in a single statement, so the upsert is atomic
+    let ids = query_rows(&client, Some(&ctes), &query, &params)
+        .await?
+        .into_iter()
+        .filter_map(|mut row| row.as_object_mut().and_then(|r| r.remove(&pk)))
+        .collect();
+
+    Ok(web::Json(UpsertRowsResponse { ids }))
+}
+
+/// Delete collection row by primary key
+#[utoipa::path(
+    delete,
+    path = "/collections/{name}/rows/{id}",
+    responses(
+        (status = 200, description = "Row successfully deleted", body = DeleteRowsResponse),
+        (status = 404, description = "Row not found"),
+        (status = 422, description = "Collection does not have a primary key"),
+    ),
+    params(
+       ("name", description = "Collection name"),
+       ("id", description = "Primary key value"),
+    ),
+)]
+#[delete("/collections/{name}/rows/{id}")]
+pub async fn delete_row(
+    data: web::Data<AppState>,
+    principal: web::ReqData<Principal>,
+    path: web::Path<(String, String)>,
+) -> Result<impl Responder> {
+    let (name, id) = path.into_inner();
+    principal.authorize(Scope::Write, Some(name.as_str()))?;
+    let client = data.pool.get().await?;
+    let PrimaryKey {
+        name: pk,
+        data_type,
+    } = get_primary_key(&client, &name).await?;
+
+    let deleted = client
+        .execute(
+            &format!(
+                "DELETE FROM {name} WHERE {pk} = $1::text::{data_type}",
+                pk = quote_ident(&pk),
+                name = quote_ident(&name)
+            ),
+            &[&id],
+        )
+        .await
+        .map_err(ErrorBadRequest)?;
+
+    if deleted == 0 {
+        return Err(ErrorNotFound("Row not found"));
+    }
+
+    Ok(web::Json(DeleteRowsResponse { deleted }))
+}
+
+#[derive(Deserialize, Debug, utoipa::ToSchema)]
+pub struct DeleteRowsInput {
+    ids: Option<Vec<Value>>,
+    #[schema(value_type = Option<Object>)]
+    filter: Option<Value>,
+}
+
+#[derive(Serialize, Debug, utoipa::ToSchema)]
+pub struct DeleteRowsResponse {
+    deleted: u64,
+}
+
+/// Delete collection rows by primary keys or filter
+///
+/// The `filter` param has the same format as in vector search,
+/// if both `ids` and `filter` are passed the rows matching both will be deleted
+#[utoipa::path(
+    post,
+    path = "/collections/{name}/rows/delete",
+    request_body (
+        content = DeleteRowsInput,
+        examples (
+         ("Delete by ids" = (value = json!(r#"{ "ids": [1, 2, 3] }"#) )),
+         ("Delete by filter" = (value = json!(r#"{ "filter": { "metadata.source": { "eq": "crawler" } } }"#) ))
+        ),
+    ),
+    responses(
+        (status = 200, description = "Returns the number of deleted rows", body = DeleteRowsResponse),
+        (status = 400, description = "Bad request"),
+        (status = 422, description = "Collection does not have a primary key"),
+    ),
+    params(
+       ("name", description = "Collection name"),
+    ),
+)]
+#[post("/collections/{name}/rows/delete")]
+pub async fn delete_rows(
+    data: web::Data<AppState>,
+    principal: web::ReqData<Principal>,
+    name: web::Path<String>,
+    body: web::Json<DeleteRowsInput>,
+) -> Result<impl Responder> {
+    principal.authorize(Scope::Write, Some(name.as_str()))?;
+
+    if body.ids.is_none() && body.filter.is_none() {
+        return Err(ErrorBadRequest("Please provide ids or filter"));
+    }
+
+    let client = data.pool.get().await?;
+    let mut conditions = Vec::new();
+    let mut ids: Vec<String> = Vec::new();
+
+    if let Some(id_values) = &body.ids {
+        let PrimaryKey {
+            name: pk,
+            data_type,
+        } = get_primary_key(&client, &name).await?;
+        ids = id_values.iter().map(id_to_string).collect::<Result<_>>()?;
+        conditions.push(format!(
+            "{pk} = ANY($1::text[]::{data_type}[])",
+            pk = quote_ident(&pk)
+        ));
+    }
+
+    let column_types = get_filter_column_types(&client, &name, body.filter.as_ref()).await?;
+    let filter = compile_filter(
+        body.filter.as_ref(),
+        if body.ids.is_some() { 1 } else { 0 },
+        &column_types,
+    )
+    .map_err(ErrorBadRequest)?;
+    if !filter.condition.is_empty() {
+        conditions.push(filter.condition.clone());
+    }
+
+    if conditions.is_empty() {
+        return Err(ErrorBadRequest("Please provide ids or filter"));
+    }
+
+    let mut params: Vec<&(dyn ToSql + Sync)> = Vec::new();
+    if body.ids.is_some() {
+        params.push(&ids);
+    }
+    params.extend(
+        filter
+            .params
+            .iter()
+            .map(|p| p.as_ref() as &(dyn ToSql + Sync)),
+    );
+
+    let deleted = client
+        .execute(
+            &format!(
+                "DELETE FROM {name} WHERE {conditions}",
+                name = quote_ident(&name),
+                conditions = conditions.join(" AND ")
+            ),
+            &params,
+        )
+        .await
+        .map_err(ErrorBadRequest)?;
+
+    Ok(web::Json(DeleteRowsResponse { deleted }))
+}
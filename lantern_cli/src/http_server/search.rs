@@ -2,11 +2,20 @@ use actix_web::{
     error::{ErrorBadRequest, ErrorInternalServerError},
     post, web, Responder, Result,
 };
+use deadpool_postgres::Transaction;
+use futures::future::try_join_all;
 
 use crate::{external_index::cli::UMetricKind, utils::quote_ident};
 use serde::{Deserialize, Serialize};
+use tokio_postgres::{types::ToSql, Row};
 
-use super::AppState;
+use super::{
+    auth::{Principal, Scope},
+    embedding::get_query_model,
+    filter::compile_filter,
+    validation::{get_filter_column_types, get_select_fields, validate_column},
+    AppState,
+};
 
 #[derive(Deserialize, Debug, utoipa::ToSchema)]
 pub struct SearchInput {
@@ -18,6 +27,8 @@ pub struct SearchInput {
     select: Option<String>,
     k: Option<usize>,
     ef: Option<usize>,
+    #[schema(value_type = Option<Object>)]
+    filter: Option<serde_json::Value>,
 }
 
 #[derive(Serialize, Debug, utoipa::ToSchema)]
@@ -25,17 +36,82 @@ pub struct SearchResponse {
     rows: Vec<serde_json::Value>,
 }
 
+// Sets HNSW search params for the current transaction only,
+// so they are not leaked to other requests through the pooled connection
+pub async fn set_search_params(transaction: &Transaction<'_>, k: usize, ef: usize) -> Result<()> {
+    transaction
+        .batch_execute(&format!(
+            "
+        SET LOCAL lantern_hnsw.init_k={k};
+        SET LOCAL lantern_hnsw.ef={ef};
+    "
+        ))
+        .await
+        .map_err(ErrorInternalServerError)
+}
+
+// Returns the search query, which takes the query vector as $1
+// or the model and query text as $1 and $2 if `by_text` is true
+fn get_search_query(
+    name: &str,
+    column: &str,
+    select_fields: &str,
+    metric_kind: &UMetricKind,
+    k: usize,
+    where_clause: &str,
+    by_text: bool,
+) -> String {
+    let operator = metric_kind.sql_operator();
+    let function = metric_kind.sql_function();
+    let name = quote_ident(name);
+    let column = quote_ident(column);
+
+    if by_text {
+        format!(
+            "
+           WITH cte AS (SELECT text_embedding($1, $2) as emb)
+           SELECT COALESCE(json_agg(q.*)::text, '[]') as data FROM (
+              SELECT {select_fields}, {function}({column}, cte.emb) as distance FROM {name}, cte {where_clause} ORDER BY {column} {operator} cte.emb LIMIT {k}
+           ) q;
+        "
+        )
+    } else {
+        format!(
+            "
+           SELECT COALESCE(json_agg(q.*)::text, '[]') as data FROM (
+             SELECT {select_fields}, {function}({column}, $1) as distance FROM {name} {where_clause} ORDER BY {column} {operator} $1 LIMIT {k}
+            ) q;
+        "
+        )
+    }
+}
+
+fn parse_search_rows(row: &Row) -> Result<Vec<serde_json::Value>> {
+    serde_json::from_str(row.get::<usize, &str>(0)).map_err(ErrorInternalServerError)
+}
+
 /// Search rows in collection using vector search operators
 ///
 /// You can provide the `query_vector` or `query_text` with `query_model`
-/// in the later case embedding will be created automatically using the query model
+/// in the later case embedding will be created automatically using the query model.
+/// If the collection has embedding config for the searched column, `query_model` can be omitted
 ///
 /// Output type is array of dynamic json constructed from your table columns.
 ///
-/// The `select` param should be string with comma separated values or you can omit it to get all the
-/// columns back
+/// The `select` param should be string with comma separated column names or you can omit it to get all the
+/// columns back. Expressions are allowed only if the server is started with `--allow-raw-sql`
 ///
 /// Metric can be one of `cosine`, `l2sq`, `hamming`
+///
+/// The `filter` param can be used to restrict the results by column values or by json paths inside `jsonb` columns:
+/// `{"metadata.lang": {"eq": "en"}, "price": {"lt": 10}}`
+///
+/// Supported operators are `eq`, `ne`, `lt`, `lte`, `gt`, `gte`, `in`, `nin`, `contains` and `exists`,
+/// plain values are treated as `eq`. Conditions are combined with AND, use `and` / `or` keys with an array
+/// of filters to build nested conditions. Values are passed as query parameters and cast to the column type,
+/// json paths and `json`, `jsonb` or array columns are compared as `jsonb`.
+/// The filter is evaluated on the rows returned by the index scan, not inside it,
+/// so selective filters may return fewer than `k` rows
 #[utoipa::path(
     post,
     path = "/collections/{name}/search",
@@ -43,7 +119,8 @@ pub struct SearchResponse {
         content = SearchInput,
         examples (
          ("Search by vector" = (value = json!(r#"{ "column": "vector", "query_vector": [1,0,1], "metric": "cosine", "select": "id,metadata", "k": 10, "ef": 64 }"#) )),
-         ("Search with model" = (value = json!(r#"{ "column": "vector", "query_text": "User query text", "query_model": "BAAI/bge-small-en", "metric": "l2sq", "select": "id,metadata", "k": 10, "ef": 64 }"#) ))
+         ("Search with model" = (value = json!(r#"{ "column": "vector", "query_text": "User query text", "query_model": "BAAI/bge-small-en", "metric": "l2sq", "select": "id,metadata", "k": 10, "ef": 64 }"#) )),
+         ("Search with filter" = (value = json!(r#"{ "column": "vector", "query_vector": [1,0,1], "select": "id,metadata", "k": 10, "filter": { "metadata.lang": { "eq": "en" }, "price": { "lt": 10 } } }"#) ))
 
         ),
     ),
@@ -59,76 +136,229 @@ pub struct SearchResponse {
 #[post("/collections/{name}/search")]
 async fn vector_search(
     data: web::Data<AppState>,
+    principal: web::ReqData<Principal>,
     body: web::Json<SearchInput>,
     name: web::Path<String>,
 ) -> Result<impl Responder> {
-    let client = data.pool.get().await.unwrap();
+    principal.authorize(Scope::Read, Some(name.as_str()))?;
+    let mut client = data.pool.get().await?;
     let k = body.k.unwrap_or(10);
     let ef = body.ef.unwrap_or(10);
     let metric = body.metric.clone().unwrap_or("l2sq".to_owned());
-    let select_fields = body.select.clone().unwrap_or("*".to_owned());
     let column = &body.column;
 
+    validate_column(&client, &name, column, data.allow_raw_sql).await?;
+    let select_fields =
+        get_select_fields(&client, &name, body.select.as_deref(), data.allow_raw_sql).await?;
+
     let metric_kind = UMetricKind::from(&metric).map_err(ErrorBadRequest)?;
 
-    let operator = metric_kind.sql_operator();
-    let function = metric_kind.sql_function();
+    let model = match &body.query_vector {
+        Some(_) => None,
+        None => get_query_model(&client, &name, column, body.query_model.as_ref()).await?,
+    };
 
-    client
-        .batch_execute(&format!(
-            "
-        SET lantern_hnsw.init_k={k};
-        SET lantern_hnsw.ef={ef};
-    "
-        ))
+    let mut params: Vec<&(dyn ToSql + Sync)> = match (&body.query_vector, &body.query_text, &model)
+    {
+        (Some(vector), _, _) => vec![vector],
+        (None, Some(text), Some(model)) => vec![model, text],
+        _ => {
+            return Err(ErrorBadRequest(
+                "Please provide query_vector or query_text and query_model",
+            ))
+        }
+    };
+
+    let column_types = get_filter_column_types(&client, &name, body.filter.as_ref()).await?;
+    let filter = compile_filter(body.filter.as_ref(), params.len(), &column_types)
+        .map_err(ErrorBadRequest)?;
+    params.extend(
+        filter
+            .params
+            .iter()
+            .map(|p| p.as_ref() as &(dyn ToSql + Sync)),
+    );
+
+    let query = get_search_query(
+        &name,
+        column,
+        &select_fields,
+        &metric_kind,
+        k,
+        &filter.where_clause(),
+        body.query_vector.is_none(),
+    );
+
+    let transaction = client
+        .transaction()
         .await
         .map_err(ErrorInternalServerError)?;
+    set_search_params(&transaction, k, ef).await?;
 
-    let res = match &body.query_vector {
-        Some(vector) => {
-            client
-                .query_one(
-                    &format!(
-                        "
-           SELECT COALESCE(json_agg(q.*)::text, '[]') as data FROM (
-             SELECT {select_fields}, {function}({column}, $1) as distance FROM {name} ORDER BY {column} {operator} $1 LIMIT {k}
-            ) q;
-        ",
-                        name = quote_ident(&name),
-                        column = quote_ident(column)
-                    ),
-                    &[&vector],
-                )
-                .await
-        }
-        None => {
-            if body.query_model.is_none() || body.query_text.is_none() {
-                return Err(ErrorBadRequest("Please provide query_vector or query_text and query_model"));
+    let row = transaction
+        .query_one(&query, &params)
+        .await
+        .map_err(ErrorBadRequest)?;
+
+    transaction
+        .commit()
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(web::Json(SearchResponse {
+        rows: parse_search_rows(&row)?,
+    }))
+}
+
+#[derive(Deserialize, Debug, utoipa::ToSchema)]
+pub struct BatchSearchInput {
+    column: String,
+    query_vectors: Option<Vec<Vec<f32>>>,
+    query_texts: Option<Vec<String>>,
+    query_model: Option<String>,
+    metric: Option<String>,
+    select: Option<String>,
+    k: Option<usize>,
+    ef: Option<usize>,
+    #[schema(value_type = Option<Object>)]
+    filter: Option<serde_json::Value>,
+}
+
+#[derive(Serialize, Debug, utoipa::ToSchema)]
+pub struct BatchSearchResponse {
+    results: Vec<SearchResponse>,
+}
+
+/// Search rows in collection for multiple queries at once
+///
+/// Provide either `query_vectors` or `query_texts` (with `query_model`), the other params are the same
+/// as for the single search and are applied to each of the queries.
+///
+/// The queries are sent over a single database connection without waiting for the previous results,
+/// `results` contain rows for each of the queries in the same order as the queries were passed
+#[utoipa::path(
+    post,
+    path = "/collections/{name}/search/batch",
+    request_body  (
+        content = BatchSearchInput,
+        examples (
+         ("Search by vectors" = (value = json!(r#"{ "column": "vector", "query_vectors": [[1,0,1], [0,1,0]], "metric": "cosine", "select": "id,metadata", "k": 10, "ef": 64 }"#) )),
+         ("Search with model" = (value = json!(r#"{ "column": "vector", "query_texts": ["First query", "Second query"], "query_model": "BAAI/bge-small-en", "select": "id,metadata", "k": 10 }"#) ))
+        ),
+    ),
+    responses(
+        (status = 200, body=BatchSearchResponse, description = "Array with search results for each query"),
+        (status = 400, description = "Bad request"),
+        (status = 500, description = "Internal Server Error")
+    ),
+    params(
+       ("name", description = "Collection name")
+    ),
+)]
+#[post("/collections/{name}/search/batch")]
+async fn batch_search(
+    data: web::Data<AppState>,
+    principal: web::ReqData<Principal>,
+    body: web::Json<BatchSearchInput>,
+    name: web::Path<String>,
+) -> Result<impl Responder> {
+    principal.authorize(Scope::Read, Some(name.as_str()))?;
+    let mut client = data.pool.get().await?;
+    let k = body.k.unwrap_or(10);
+    let ef = body.ef.unwrap_or(10);
+    let metric = body.metric.clone().unwrap_or("l2sq".to_owned());
+    let column = &body.column;
+
+    if body.query_vectors.is_some() == body.query_texts.is_some() {
+        return Err(ErrorBadRequest(
+            "Please provide either query_vectors or query_texts",
+        ));
+    }
+
+    validate_column(&client, &name, column, data.allow_raw_sql).await?;
+    let select_fields =
+        get_select_fields(&client, &name, body.select.as_deref(), data.allow_raw_sql).await?;
+
+    let metric_kind = UMetricKind::from(&metric).map_err(ErrorBadRequest)?;
+
+    let model = match &body.query_texts {
+        Some(_) => {
+            match get_query_model(&client, &name, column, body.query_model.as_ref()).await? {
+                Some(model) => Some(model),
+                None => return Err(ErrorBadRequest("Please provide query_model")),
             }
-            let text = body.query_text.as_ref().unwrap();
-            let model = body.query_model.as_ref().unwrap();
-            client
-                .query_one(
-                    &format!(
-                        "
-           WITH cte AS (SELECT text_embedding($1, $2) as emb)
-           SELECT COALESCE(json_agg(q.*)::text, '[]') as data FROM (
-              SELECT {select_fields}, {function}({column}, cte.emb) as distance FROM {name}, cte ORDER BY {column} {operator} cte.emb LIMIT {k}
-           ) q;
-        ",
-                        name = quote_ident(&name),
-                        column = quote_ident(column)
-                    ),
-                    &[model, text],
-                )
-                .await
         }
+        None => None,
     };
 
-    let response = match res {
-        Err(e) => return Err(ErrorBadRequest(e)),
-        Ok(row) => serde_json::from_str(row.get(0)).unwrap(),
+    // Each query is passed with the model for text search
+    let queries: Vec<Vec<&(dyn ToSql + Sync)>> = match (&body.query_vectors, &body.query_texts) {
+        (Some(vectors), _) => vectors
+            .iter()
+            .map(|v| vec![v as &(dyn ToSql + Sync)])
+            .collect(),
+        (None, Some(texts)) => texts
+            .iter()
+            .map(|t| vec![model.as_ref().unwrap() as &(dyn ToSql + Sync), t])
+            .collect(),
+        (None, None) => unreachable!(),
     };
 
-    Ok(web::Json(SearchResponse { rows: response }))
+    let by_text = body.query_texts.is_some();
+    let column_types = get_filter_column_types(&client, &name, body.filter.as_ref()).await?;
+    let filter = compile_filter(
+        body.filter.as_ref(),
+        if by_text { 2 } else { 1 },
+        &column_types,
+    )
+    .map_err(ErrorBadRequest)?;
+    let filter_params: Vec<&(dyn ToSql + Sync)> = filter
+        .params
+        .iter()
+        .map(|p| p.as_ref() as &(dyn ToSql + Sync))
+        .collect();
+
+    let query = get_search_query(
+        &name,
+        column,
+        &select_fields,
+        &metric_kind,
+        k,
+        &filter.where_clause(),
+        by_text,
+    );
+
+    let transaction = client
+        .transaction()
+        .await
+        .map_err(ErrorInternalServerError)?;
+    set_search_params(&transaction, k, ef).await?;
+
+    let statement = transaction.prepare(&query).await.map_err(ErrorBadRequest)?;
+
+    // Queries are pipelined on the connection as all the futures are polled together
+    let rows = try_join_all(queries.into_iter().map(|mut params| {
+        params.extend(filter_params.iter().copied());
+        let transaction = &transaction;
+        let statement = &statement;
+        async move { transaction.query_one(statement, &params).await }
+    }))
+    .await
+    .map_err(ErrorBadRequest)?;
+
+    transaction
+        .commit()
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let results = rows
+        .iter()
+        .map(|row| {
+            Ok(SearchResponse {
+                rows: parse_search_rows(row)?,
+            })
+        })
+        .collect::<Result<Vec<SearchResponse>>>()?;
+
+    Ok(web::Json(BatchSearchResponse { results }))
 }
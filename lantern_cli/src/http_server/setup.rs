@@ -1,5 +1,9 @@
-use super::{COLLECTION_SCHEMA_NAME, COLLECTION_TABLE_NAME};
-use crate::types::AnyhowVoidResult;
+use super::{
+    autotune::{AUTOTUNE_EXPERIMENT_SEQUENCE_NAME, AUTOTUNE_RESULTS_TABLE_NAME},
+    embedding::get_embed_row_function,
+    API_KEYS_TABLE_NAME, COLLECTION_SCHEMA_NAME, COLLECTION_TABLE_NAME,
+};
+use crate::{types::AnyhowVoidResult, utils::get_full_table_name};
 use deadpool_postgres::Pool;
 
 pub async fn setup_tables(pool: &Pool) -> AnyhowVoidResult {
@@ -11,7 +15,34 @@ pub async fn setup_tables(pool: &Pool) -> AnyhowVoidResult {
              CREATE TABLE IF NOT EXISTS {COLLECTION_TABLE_NAME} (
          id bigint GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
          name NAME
-        );",
+        );
+             ALTER TABLE {COLLECTION_TABLE_NAME} ADD COLUMN IF NOT EXISTS embedding JSONB;
+             CREATE TABLE IF NOT EXISTS {API_KEYS_TABLE_NAME} (
+         id bigint GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+         name TEXT,
+         key_hash TEXT NOT NULL UNIQUE,
+         scope TEXT NOT NULL CHECK (scope IN ('read', 'write', 'admin')),
+         collections TEXT[],
+         rate_limit INT,
+         created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+         revoked_at TIMESTAMPTZ
+        );
+             CREATE TABLE IF NOT EXISTS {autotune_results_table} (
+         experiment_id INT,
+         ef INT,
+         efc INT,
+         m INT,
+         recall FLOAT8,
+         latency FLOAT8,
+         build_time FLOAT8
+        );
+             CREATE SEQUENCE IF NOT EXISTS {autotune_experiment_sequence} AS INT CYCLE;
+        {embed_row_function}",
+            autotune_results_table =
+                get_full_table_name(COLLECTION_SCHEMA_NAME, AUTOTUNE_RESULTS_TABLE_NAME),
+            autotune_experiment_sequence =
+                get_full_table_name(COLLECTION_SCHEMA_NAME, AUTOTUNE_EXPERIMENT_SEQUENCE_NAME),
+            embed_row_function = get_embed_row_function()
         ))
         .await?;
     Ok(())
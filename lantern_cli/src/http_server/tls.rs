@@ -0,0 +1,153 @@
+use std::sync::{Arc, RwLock};
+
+use std::str::FromStr;
+
+use rustls::{
+    crypto::CryptoProvider,
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+    ClientConfig, RootCertStore, ServerConfig,
+};
+use tokio_postgres::config::SslMode;
+use tokio_postgres_rustls::MakeRustlsConnect;
+
+use crate::{
+    logger::Logger,
+    utils::tls::{load_certs, load_private_key},
+};
+
+// Resolves the certificate loaded from PEM files for every connection,
+// so the certificate can be replaced without restarting the server
+#[derive(Debug)]
+pub struct ReloadableCertResolver {
+    cert_path: String,
+    key_path: String,
+    provider: Arc<CryptoProvider>,
+    certified_key: RwLock<Arc<CertifiedKey>>,
+}
+
+impl ReloadableCertResolver {
+    pub fn new(
+        cert_path: &str,
+        key_path: &str,
+        provider: Arc<CryptoProvider>,
+    ) -> Result<Self, anyhow::Error> {
+        let certified_key = Self::load(cert_path, key_path, &provider)?;
+        Ok(Self {
+            cert_path: cert_path.to_owned(),
+            key_path: key_path.to_owned(),
+            provider,
+            certified_key: RwLock::new(Arc::new(certified_key)),
+        })
+    }
+
+    fn load(
+        cert_path: &str,
+        key_path: &str,
+        provider: &CryptoProvider,
+    ) -> Result<CertifiedKey, anyhow::Error> {
+        let certs = load_certs(cert_path)?;
+        let key = provider
+            .key_provider
+            .load_private_key(load_private_key(key_path)?)?;
+        let certified_key = CertifiedKey::new(certs, key);
+        // The files may be replaced one by one, so the pair is checked
+        // before serving it to the clients
+        certified_key.keys_match().map_err(|e| {
+            anyhow::anyhow!(
+                "Certificate {cert_path} does not match the private key {key_path}: {e}"
+            )
+        })?;
+        Ok(certified_key)
+    }
+
+    // The current certificate is kept if the new one can not be loaded
+    pub fn reload(&self) -> Result<(), anyhow::Error> {
+        let certified_key = Self::load(&self.cert_path, &self.key_path, &self.provider)?;
+        *self.certified_key.write().unwrap() = Arc::new(certified_key);
+        Ok(())
+    }
+
+    pub fn current(&self) -> Arc<CertifiedKey> {
+        self.certified_key.read().unwrap().clone()
+    }
+}
+
+impl ResolvesServerCert for ReloadableCertResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.current())
+    }
+}
+
+fn get_crypto_provider() -> Arc<CryptoProvider> {
+    CryptoProvider::get_default()
+        .cloned()
+        .unwrap_or_else(|| Arc::new(rustls::crypto::aws_lc_rs::default_provider()))
+}
+
+pub fn get_server_config(
+    cert_path: &str,
+    key_path: &str,
+) -> Result<(ServerConfig, Arc<ReloadableCertResolver>), anyhow::Error> {
+    let provider = get_crypto_provider();
+    let resolver = Arc::new(ReloadableCertResolver::new(
+        cert_path,
+        key_path,
+        provider.clone(),
+    )?);
+
+    let config = ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_cert_resolver(resolver.clone());
+
+    Ok((config, resolver))
+}
+
+// Returns the TLS connector for the database connections if the connection string
+// has sslmode=require, the certificate of the database is verified with the system roots
+// Other modes keep connecting without TLS, as `prefer` would fail on self-signed certificates
+pub fn get_db_tls_connector(db_uri: &str) -> Result<Option<MakeRustlsConnect>, anyhow::Error> {
+    if tokio_postgres::Config::from_str(db_uri)?.get_ssl_mode() != SslMode::Require {
+        return Ok(None);
+    }
+
+    let mut roots = RootCertStore::empty();
+    roots.add_parsable_certificates(rustls_native_certs::load_native_certs().certs);
+    let config = ClientConfig::builder_with_provider(get_crypto_provider())
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    Ok(Some(MakeRustlsConnect::new(config)))
+}
+
+// Reloads the certificate and key files on SIGHUP
+#[cfg(unix)]
+pub fn spawn_reload_on_sighup(
+    resolver: Arc<ReloadableCertResolver>,
+    logger: Logger,
+) -> Result<(), anyhow::Error> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = signal(SignalKind::hangup())?;
+    actix_web::rt::spawn(async move {
+        while hangup.recv().await.is_some() {
+            match resolver.reload() {
+                Ok(_) => logger.info("TLS certificate reloaded"),
+                Err(e) => logger.error(&format!("Failed to reload TLS certificate: {e}")),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn spawn_reload_on_sighup(
+    _resolver: Arc<ReloadableCertResolver>,
+    logger: Logger,
+) -> Result<(), anyhow::Error> {
+    logger.warn("TLS certificate reload on SIGHUP is not supported on this platform");
+    Ok(())
+}
@@ -0,0 +1,250 @@
+use std::collections::{HashMap, HashSet};
+
+use actix_web::error::{ErrorBadRequest, ErrorInternalServerError, ErrorUnprocessableEntity};
+use regex::Regex;
+
+use crate::utils::quote_ident;
+
+use super::PoolClient;
+
+// Helpers for strict mode of the HTTP server
+// When the server is started without --allow-raw-sql all the user supplied
+// column lists and data types are validated before being used in SQL statements
+
+static ALLOWED_DATA_TYPES: &[&str] = &[
+    "smallint",
+    "integer",
+    "int",
+    "int2",
+    "int4",
+    "int8",
+    "bigint",
+    "smallserial",
+    "serial",
+    "bigserial",
+    "serial2",
+    "serial4",
+    "serial8",
+    "real",
+    "float4",
+    "float8",
+    "double precision",
+    "numeric",
+    "decimal",
+    "boolean",
+    "bool",
+    "text",
+    "varchar",
+    "character varying",
+    "char",
+    "character",
+    "uuid",
+    "json",
+    "jsonb",
+    "date",
+    "time",
+    "timestamp",
+    "timestamptz",
+    "timestamp with time zone",
+    "timestamp without time zone",
+    "bytea",
+    "vector",
+];
+
+lazy_static! {
+    static ref DATA_TYPE_REGEX: Regex = Regex::new(
+        r"^(?P<type>double precision|character varying|timestamp with(?:out)? time zone|[a-z][a-z0-9]*)(?:\s*\(\s*\d+\s*(?:,\s*\d+\s*)?\))?(?:\s*\[\s*\d*\s*\])*(?P<constraints>.*)$"
+    )
+    .unwrap();
+    static ref CONSTRAINTS_REGEX: Regex = Regex::new(
+        r"^(?:\s+(?:primary key|not null|null|unique|generated always as identity|generated by default as identity))*$"
+    )
+    .unwrap();
+}
+
+// Checks that column definition consists of a known data type
+// with optional type modifiers, array dimensions and simple constraints
+// e.g. `bigint GENERATED ALWAYS AS IDENTITY PRIMARY KEY`, `REAL[]`, `varchar(255) NOT NULL`
+pub fn validate_data_type(data_type: &str) -> Result<(), anyhow::Error> {
+    let normalized = data_type
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .to_lowercase();
+
+    let captures = match DATA_TYPE_REGEX.captures(&normalized) {
+        Some(captures) => captures,
+        None => anyhow::bail!("Invalid data type '{data_type}'"),
+    };
+
+    if !ALLOWED_DATA_TYPES.contains(&&captures["type"]) {
+        anyhow::bail!(
+            "Data type '{}' is not allowed, supported types are: {}",
+            &captures["type"],
+            ALLOWED_DATA_TYPES.join(", ")
+        );
+    }
+
+    if !CONSTRAINTS_REGEX.is_match(&captures["constraints"]) {
+        anyhow::bail!(
+            "Invalid column constraints '{}', supported constraints are: PRIMARY KEY, NOT NULL, NULL, UNIQUE, GENERATED ALWAYS AS IDENTITY, GENERATED BY DEFAULT AS IDENTITY",
+            captures["constraints"].trim()
+        );
+    }
+
+    Ok(())
+}
+
+pub async fn get_table_columns(
+    client: &PoolClient,
+    table: &str,
+) -> Result<HashSet<String>, actix_web::Error> {
+    let rows = client
+        .query(
+            "SELECT column_name::text FROM information_schema.columns WHERE table_name=$1 AND table_schema = ANY(current_schemas(false))",
+            &[&table],
+        )
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    if rows.is_empty() {
+        return Err(ErrorBadRequest(format!("Collection '{table}' not found")));
+    }
+
+    Ok(rows.iter().map(|r| r.get::<usize, String>(0)).collect())
+}
+
+// Returns the SQL for comma separated column list
+// In strict mode each column is checked to exist in the table and is quoted,
+// otherwise the list is used as is
+pub async fn get_select_fields(
+    client: &PoolClient,
+    table: &str,
+    select: Option<&str>,
+    allow_raw_sql: bool,
+) -> Result<String, actix_web::Error> {
+    let select = select.unwrap_or("*").trim();
+
+    if allow_raw_sql || select == "*" {
+        return Ok(select.to_owned());
+    }
+
+    let table_columns = get_table_columns(client, table).await?;
+    let mut fields = Vec::new();
+
+    for column in select.split(',').map(|c| c.trim()) {
+        if !table_columns.contains(column) {
+            return Err(ErrorBadRequest(format!(
+                "Column '{column}' does not exist in collection '{table}'"
+            )));
+        }
+        fields.push(quote_ident(column));
+    }
+
+    Ok(fields.join(","))
+}
+
+pub async fn validate_columns<'a>(
+    client: &PoolClient,
+    table: &str,
+    columns: impl IntoIterator<Item = &'a String>,
+    allow_raw_sql: bool,
+) -> Result<(), actix_web::Error> {
+    if allow_raw_sql {
+        return Ok(());
+    }
+
+    let table_columns = get_table_columns(client, table).await?;
+    for column in columns {
+        if !table_columns.contains(column) {
+            return Err(ErrorBadRequest(format!(
+                "Column '{column}' does not exist in collection '{table}'"
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+// Returns the data types of the table columns used to compile the request filter
+// The catalog is queried only if the filter is passed
+pub async fn get_filter_column_types(
+    client: &PoolClient,
+    table: &str,
+    filter: Option<&serde_json::Value>,
+) -> Result<HashMap<String, String>, actix_web::Error> {
+    if filter.map_or(true, |filter| filter.is_null()) {
+        return Ok(HashMap::new());
+    }
+
+    get_column_types(client, table).await
+}
+
+pub async fn get_column_types(
+    client: &PoolClient,
+    table: &str,
+) -> Result<HashMap<String, String>, actix_web::Error> {
+    let rows = client
+        .query(
+            "SELECT a.attname::text, format_type(a.atttypid, a.atttypmod) FROM pg_attribute a WHERE a.attrelid = to_regclass($1) AND a.attnum > 0 AND NOT a.attisdropped",
+            &[&quote_ident(table)],
+        )
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(rows
+        .iter()
+        .map(|r| (r.get::<usize, String>(0), r.get::<usize, String>(1)))
+        .collect())
+}
+
+pub struct PrimaryKey {
+    pub name: String,
+    // Data type from the catalog, used to cast the ids passed as text
+    pub data_type: String,
+}
+
+pub async fn get_primary_key(
+    client: &PoolClient,
+    table: &str,
+) -> Result<PrimaryKey, actix_web::Error> {
+    let rows = client
+        .query(
+            "SELECT a.attname::text, format_type(a.atttypid, a.atttypmod) FROM pg_index i JOIN pg_attribute a ON a.attrelid = i.indrelid AND a.attnum = ANY(i.indkey) WHERE i.indrelid = to_regclass($1) AND i.indisprimary",
+            &[&quote_ident(table)],
+        )
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    match rows.len() {
+        0 => Err(ErrorUnprocessableEntity(format!(
+            "Collection '{table}' should have a primary key"
+        ))),
+        1 => Ok(PrimaryKey {
+            name: rows[0].get::<usize, String>(0),
+            data_type: rows[0].get::<usize, String>(1),
+        }),
+        _ => Err(ErrorUnprocessableEntity(format!(
+            "Composite primary key of collection '{table}' is not supported"
+        ))),
+    }
+}
+
+pub async fn validate_column(
+    client: &PoolClient,
+    table: &str,
+    column: &str,
+    allow_raw_sql: bool,
+) -> Result<(), actix_web::Error> {
+    if allow_raw_sql {
+        return Ok(());
+    }
+
+    if !get_table_columns(client, table).await?.contains(column) {
+        return Err(ErrorBadRequest(format!(
+            "Column '{column}' does not exist in collection '{table}'"
+        )));
+    }
+
+    Ok(())
+}
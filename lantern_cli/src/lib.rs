@@ -1,5 +1,7 @@
 #[cfg(feature = "daemon")]
 pub mod daemon;
+#[cfg(feature = "embedding-server")]
+pub mod embedding_server;
 #[cfg(feature = "embeddings")]
 pub mod embeddings;
 #[cfg(feature = "external-index-server")]
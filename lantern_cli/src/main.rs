@@ -42,10 +42,10 @@ async fn main() {
             _main_logger = Some(logger.clone());
             embeddings::show_available_models(&args, Some(logger)).await
         }
-        cli::Commands::ShowRuntimes => {
+        cli::Commands::ShowRuntimes(args) => {
             let logger = Logger::new("Lantern Embeddings", LogLevel::Debug);
             _main_logger = Some(logger.clone());
-            embeddings::show_available_runtimes(Some(logger))
+            embeddings::show_available_runtimes(&args, Some(logger))
         }
         cli::Commands::MeasureModelSpeed(args) => {
             let logger = Logger::new("Lantern Embeddings", LogLevel::Info);
@@ -83,6 +83,12 @@ async fn main() {
             _main_logger = Some(logger.clone());
             external_index::server::start_tcp_server(args, Some(logger))
         }
+        #[cfg(feature = "embedding-server")]
+        cli::Commands::StartEmbeddingServer(args) => {
+            let logger = Logger::new("Lantern Embedding Server", LogLevel::Debug);
+            _main_logger = Some(logger.clone());
+            embedding_server::start(args, Some(logger))
+        }
     };
 
     let logger = _main_logger.unwrap();
@@ -1,5 +1,7 @@
 #[cfg(feature = "daemon")]
 pub mod test_utils;
+#[cfg(any(feature = "http-server", feature = "external-index-server"))]
+pub mod tls;
 
 pub fn quote_ident(str: &str) -> String {
     format!("\"{}\"", str.replace("\"", "\"\""))
@@ -0,0 +1,21 @@
+use std::{fs::File, io::BufReader};
+
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+
+pub fn load_certs(path: &str) -> Result<Vec<CertificateDer<'static>>, anyhow::Error> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let certs = rustls_pemfile::certs(&mut reader).collect::<Result<Vec<CertificateDer>, _>>()?;
+
+    if certs.is_empty() {
+        anyhow::bail!("No certificates found in {path}");
+    }
+
+    Ok(certs)
+}
+
+pub fn load_private_key(path: &str) -> Result<PrivateKeyDer<'static>, anyhow::Error> {
+    match rustls_pemfile::private_key(&mut BufReader::new(File::open(path)?))? {
+        Some(key) => Ok(key),
+        None => anyhow::bail!("No private key found in {path}"),
+    }
+}
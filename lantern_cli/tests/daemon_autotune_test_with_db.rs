@@ -49,6 +49,9 @@ async fn test_daemon_autotune_with_create_index() {
                 log_level: LogLevel::Debug,
                 data_path: None,
                 inside_postgres: false,
+                embedding_cache: false,
+                embedding_cache_max_age: None,
+                embedding_cache_max_entries: None,
             },
             None,
             cancel_token_clone,
@@ -51,6 +51,9 @@ async fn test_daemon_completion_init_job() {
                 log_level: LogLevel::Debug,
                 data_path: None,
                 inside_postgres: false,
+                embedding_cache: false,
+                embedding_cache_max_age: None,
+                embedding_cache_max_entries: None,
             },
             None,
             cancel_token_clone,
@@ -48,6 +48,9 @@ async fn test_daemon_embedding_init_job() {
                 log_level: LogLevel::Debug,
                 data_path: None,
                 inside_postgres: false,
+                embedding_cache: false,
+                embedding_cache_max_age: None,
+                embedding_cache_max_entries: None,
             },
             None,
             cancel_token_clone,
@@ -100,6 +103,9 @@ async fn test_daemon_embedding_job_client_insert_listener() {
                 log_level: LogLevel::Debug,
                 data_path: None,
                 inside_postgres: false,
+                embedding_cache: false,
+                embedding_cache_max_age: None,
+                embedding_cache_max_entries: None,
             },
             None,
             cancel_token_clone,
@@ -174,6 +180,9 @@ async fn test_daemon_embedding_job_client_update_listener() {
                 log_level: LogLevel::Debug,
                 data_path: None,
                 inside_postgres: false,
+                embedding_cache: false,
+                embedding_cache_max_age: None,
+                embedding_cache_max_entries: None,
             },
             None,
             cancel_token_clone,
@@ -247,6 +256,9 @@ async fn test_daemon_embedding_job_resume() {
                 log_level: LogLevel::Debug,
                 data_path: None,
                 inside_postgres: false,
+                embedding_cache: false,
+                embedding_cache_max_age: None,
+                embedding_cache_max_entries: None,
             },
             None,
             cancel_token_clone,
@@ -309,6 +321,9 @@ async fn test_daemon_embedding_finished_job_listener() {
                 log_level: LogLevel::Debug,
                 data_path: None,
                 inside_postgres: false,
+                embedding_cache: false,
+                embedding_cache_max_age: None,
+                embedding_cache_max_entries: None,
             },
             None,
             cancel_token_clone,
@@ -387,6 +402,9 @@ async fn test_daemon_embedding_multiple_jobs_listener() {
                 log_level: LogLevel::Debug,
                 data_path: None,
                 inside_postgres: false,
+                embedding_cache: false,
+                embedding_cache_max_age: None,
+                embedding_cache_max_entries: None,
             },
             None,
             cancel_token_clone,
@@ -476,6 +494,9 @@ async fn test_daemon_embedding_multiple_new_jobs_streaming() {
                 log_level: LogLevel::Debug,
                 data_path: None,
                 inside_postgres: false,
+                embedding_cache: false,
+                embedding_cache_max_age: None,
+                embedding_cache_max_entries: None,
             },
             None,
             cancel_token_clone,
@@ -573,6 +594,9 @@ async fn test_daemon_embedding_multiple_new_jobs_with_failure() {
                 log_level: LogLevel::Debug,
                 data_path: None,
                 inside_postgres: false,
+                embedding_cache: false,
+                embedding_cache_max_age: None,
+                embedding_cache_max_entries: None,
             },
             None,
             cancel_token_clone,
@@ -668,6 +692,9 @@ async fn test_daemon_embedding_jobs_streaming_with_failure() {
                 log_level: LogLevel::Debug,
                 data_path: None,
                 inside_postgres: false,
+                embedding_cache: false,
+                embedding_cache_max_age: None,
+                embedding_cache_max_entries: None,
             },
             None,
             cancel_token_clone,
@@ -760,6 +787,9 @@ async fn test_daemon_job_labels_mismatch() {
                 log_level: LogLevel::Debug,
                 data_path: None,
                 inside_postgres: false,
+                embedding_cache: false,
+                embedding_cache_max_age: None,
+                embedding_cache_max_entries: None,
             },
             None,
             cancel_token_clone,
@@ -806,6 +836,9 @@ async fn test_daemon_job_labels_match_insert() {
                 log_level: LogLevel::Debug,
                 data_path: None,
                 inside_postgres: false,
+                embedding_cache: false,
+                embedding_cache_max_age: None,
+                embedding_cache_max_entries: None,
             },
             None,
             cancel_token_clone,
@@ -872,6 +905,9 @@ async fn test_daemon_job_label_update() {
                 log_level: LogLevel::Debug,
                 data_path: None,
                 inside_postgres: false,
+                embedding_cache: false,
+                embedding_cache_max_age: None,
+                embedding_cache_max_entries: None,
             },
             None,
             cancel_token_clone,
@@ -955,6 +991,9 @@ async fn test_daemon_job_label_update_cancel() {
                 log_level: LogLevel::Debug,
                 data_path: None,
                 inside_postgres: false,
+                embedding_cache: false,
+                embedding_cache_max_age: None,
+                embedding_cache_max_entries: None,
             },
             None,
             cancel_token_clone,
@@ -1037,6 +1076,9 @@ async fn test_daemon_job_labels_match() {
                 log_level: LogLevel::Debug,
                 data_path: None,
                 inside_postgres: false,
+                embedding_cache: false,
+                embedding_cache_max_age: None,
+                embedding_cache_max_entries: None,
             },
             None,
             cancel_token_clone,
@@ -1093,6 +1135,9 @@ async fn test_daemon_embedding_init_job_streaming_large() {
                 log_level: LogLevel::Debug,
                 data_path: None,
                 inside_postgres: false,
+                embedding_cache: false,
+                embedding_cache_max_age: None,
+                embedding_cache_max_entries: None,
             },
             None,
             cancel_token_clone,
@@ -1171,6 +1216,9 @@ async fn test_daemon_embedding_job_text_pk() {
                 log_level: LogLevel::Debug,
                 data_path: None,
                 inside_postgres: false,
+                embedding_cache: false,
+                embedding_cache_max_age: None,
+                embedding_cache_max_entries: None,
             },
             None,
             cancel_token_clone,
@@ -1230,6 +1278,9 @@ async fn test_daemon_embedding_job_uuid_pk() {
                 log_level: LogLevel::Debug,
                 data_path: None,
                 inside_postgres: false,
+                embedding_cache: false,
+                embedding_cache_max_age: None,
+                embedding_cache_max_entries: None,
             },
             None,
             cancel_token_clone,
@@ -0,0 +1,145 @@
+use std::fs::{self, File};
+
+use arrow_array::{cast::AsArray, types::Float32Type, Array};
+use lantern_cli::embeddings::{self, cli, core::Runtime};
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use tokio_util::sync::CancellationToken;
+
+fn get_args(input_file: &str, output_file: &str) -> cli::EmbeddingArgs {
+    cli::EmbeddingArgs {
+        model: "BAAI/bge-small-en".to_owned(),
+        uri: "".to_owned(),
+        pk: "id".to_owned(),
+        column: "content".to_owned(),
+        table: "".to_owned(),
+        schema: "public".to_owned(),
+        out_uri: None,
+        out_column: "emb".to_owned(),
+        batch_size: Some(4),
+        visual: false,
+        out_table: None,
+        limit: None,
+        filter: None,
+        runtime: Runtime::Ort,
+        runtime_params: "{\"data_path\": \"/tmp/lantern-embeddings-core-test\"}".to_owned(),
+        create_column: false,
+        stream: false,
+        job_type: None,
+        column_type: None,
+        out_json_columns: None,
+        check_column_type: false,
+        create_cast_fn: false,
+        internal_schema: "public".to_owned(),
+        failed_rows_table: None,
+        batch_state_table: None,
+        job_id: 0,
+        cache: false,
+        cache_table: "embedding_cache".to_owned(),
+        cache_max_age: None,
+        cache_max_entries: None,
+        input_type: None,
+        input_file: Some(input_file.to_owned()),
+        input_format: None,
+        output_file: Some(output_file.to_owned()),
+        output_format: None,
+    }
+}
+
+#[tokio::test]
+async fn test_embedding_generation_from_csv_to_jsonl() {
+    let input_file = "/tmp/lantern-embeddings-file-test-input.csv";
+    let output_file = "/tmp/lantern-embeddings-file-test-output.jsonl";
+    let mut input = String::from("id,content\n");
+    for i in 1..=10 {
+        input.push_str(&format!("{i},\"Hello world, {i}!\"\n"));
+    }
+    // Empty input should be skipped
+    input.push_str("11,\n");
+    fs::write(input_file, input).unwrap();
+
+    let (processed_rows, _, _) = embeddings::create_embeddings_from_db(
+        get_args(input_file, output_file),
+        true,
+        None,
+        CancellationToken::new(),
+        None,
+    )
+    .await
+    .unwrap();
+
+    let output = fs::read_to_string(output_file).unwrap();
+    let lines: Vec<serde_json::Value> = output
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+
+    fs::remove_file(input_file).unwrap();
+    fs::remove_file(output_file).unwrap();
+
+    assert_eq!(processed_rows, 10);
+    assert_eq!(lines.len(), 10);
+    for (idx, line) in lines.iter().enumerate() {
+        assert_eq!(line["id"], serde_json::json!((idx + 1).to_string()));
+        assert_eq!(line["emb"].as_array().unwrap().len(), 384);
+        assert_eq!(line["error"], serde_json::Value::Null);
+        assert_eq!(line["truncated"], serde_json::json!(false));
+    }
+}
+
+#[tokio::test]
+async fn test_embedding_generation_from_jsonl_to_parquet() {
+    let input_file = "/tmp/lantern-embeddings-file-test-input.jsonl";
+    let output_file = "/tmp/lantern-embeddings-file-test-output.parquet";
+    let input: String = (1..=10)
+        .map(|i| {
+            format!(
+                "{}\n",
+                serde_json::json!({"id": i, "content": "Hello world!"})
+            )
+        })
+        .collect();
+    fs::write(input_file, input).unwrap();
+
+    let mut args = get_args(input_file, output_file);
+    args.limit = Some(6);
+    let (processed_rows, processed_tokens, _) =
+        embeddings::create_embeddings_from_db(args, false, None, CancellationToken::new(), None)
+            .await
+            .unwrap();
+
+    let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(output_file).unwrap())
+        .unwrap()
+        .build()
+        .unwrap();
+    let mut row_cnt = 0;
+    for batch in reader {
+        let batch = batch.unwrap();
+        let embeddings = batch.column_by_name("emb").unwrap().as_list::<i32>();
+        for idx in 0..embeddings.len() {
+            assert_eq!(
+                embeddings.value(idx).as_primitive::<Float32Type>().len(),
+                384
+            );
+        }
+        assert_eq!(
+            batch.column_by_name("error").unwrap().null_count(),
+            batch.num_rows()
+        );
+        assert_eq!(
+            batch
+                .column_by_name("truncated")
+                .unwrap()
+                .as_boolean()
+                .true_count(),
+            0
+        );
+        row_cnt += batch.num_rows();
+    }
+
+    fs::remove_file(input_file).unwrap();
+    fs::remove_file(output_file).unwrap();
+
+    assert_eq!(processed_rows, 6);
+    assert_eq!(processed_tokens, 30);
+    assert_eq!(row_cnt, 6);
+}
@@ -0,0 +1,224 @@
+#![cfg(feature = "embedding-server")]
+
+use std::time::Duration;
+
+use lantern_cli::{
+    embedding_server::{self, cli::EmbeddingServerArgs},
+    embeddings::{
+        client::EmbeddingServerClient,
+        core::{EmbeddingRuntime, Runtime},
+    },
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+static DATA_PATH: &'static str = "/tmp/lantern-embeddings-core-test";
+static SOCKET_PATH: &'static str = "/tmp/lantern-embedding-server-test.sock";
+
+fn start_server(port: u16, socket_path: Option<&str>) {
+    let args = EmbeddingServerArgs {
+        host: "127.0.0.1".to_owned(),
+        port,
+        socket_path: socket_path.map(|s| s.to_owned()),
+        models: vec!["BAAI/bge-small-en".to_owned()],
+        data_path: Some(DATA_PATH.to_owned()),
+        max_batch_size: 64,
+        batch_wait_ms: 50,
+        workers: Some(2),
+    };
+
+    std::thread::spawn(move || {
+        embedding_server::start(args, None).expect("Failed to start embedding server");
+    });
+}
+
+fn cosine_similarity(v1: &[f32], v2: &[f32]) -> f32 {
+    let dot_product = v1.iter().zip(v2.iter()).map(|(&x, &y)| x * y).sum::<f32>();
+    let magnitude_v1 = f32::sqrt(v1.iter().map(|&x| x * x).sum::<f32>());
+    let magnitude_v2 = f32::sqrt(v2.iter().map(|&x| x * x).sum::<f32>());
+    dot_product / (magnitude_v1 * magnitude_v2)
+}
+
+async fn get_expected_embeddings(inputs: &Vec<&str>) -> Vec<Vec<f32>> {
+    let params = format!(r#"{{"data_path": "{DATA_PATH}"}}"#);
+    let runtime = EmbeddingRuntime::new(&Runtime::Ort, None, &params).unwrap();
+    runtime
+        .process("BAAI/bge-small-en", inputs)
+        .await
+        .unwrap()
+        .embeddings
+}
+
+#[tokio::test]
+async fn test_embedding_server_concurrent_requests() {
+    start_server(8091, None);
+    // wait for the model to be loaded
+    tokio::time::sleep(Duration::from_secs(10)).await;
+
+    let inputs: Vec<String> = (0..20).map(|i| format!("Hello world {i}!")).collect();
+    let expected = get_expected_embeddings(&inputs.iter().map(|s| s.as_str()).collect()).await;
+
+    // Concurrent requests should be processed together and each get its own result back
+    let requests = inputs.iter().map(|input| async move {
+        let client = EmbeddingServerClient::new("http://127.0.0.1:8091").unwrap();
+        client
+            .embeddings(
+                &Runtime::Ort,
+                "BAAI/bge-small-en",
+                "{}",
+                vec![input.clone(), input.clone()],
+            )
+            .await
+    });
+    let responses = futures::future::join_all(requests).await;
+
+    for (idx, response) in responses.into_iter().enumerate() {
+        let response = response.unwrap();
+        assert_eq!(response.embeddings.len(), 2);
+        // Token count of the batch is split between the requests
+        assert!(response.processed_tokens > 0);
+        for embedding in &response.embeddings {
+            assert!(1.0 - cosine_similarity(embedding, &expected[idx]) < 0.001);
+        }
+    }
+
+    let client = EmbeddingServerClient::new("http://127.0.0.1:8091").unwrap();
+    let result = client
+        .embeddings(
+            &Runtime::Ort,
+            "unknown/model",
+            "{}",
+            vec!["test".to_owned()],
+        )
+        .await;
+    assert!(result.is_err());
+
+    let result = client
+        .completions(
+            &Runtime::Ort,
+            "BAAI/bge-small-en",
+            "{}",
+            vec!["test".to_owned()],
+        )
+        .await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn test_embedding_server_unix_socket() {
+    start_server(8092, Some(SOCKET_PATH));
+    tokio::time::sleep(Duration::from_secs(10)).await;
+
+    let inputs = vec!["Hello world!"];
+    let expected = get_expected_embeddings(&inputs).await;
+
+    let client = EmbeddingServerClient::new(&format!("unix:{SOCKET_PATH}")).unwrap();
+    let response = client
+        .embeddings(
+            &Runtime::Ort,
+            "BAAI/bge-small-en",
+            r#"{"input_type": "document"}"#,
+            vec![inputs[0].to_owned()],
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.embeddings.len(), 1);
+    assert!(1.0 - cosine_similarity(&response.embeddings[0], &expected[0]) < 0.001);
+    assert!(response.failed_inputs.is_empty());
+}
+
+// Starts a minimal OpenAI compatible embeddings server
+// which rejects the requests containing "bad input"
+async fn start_mock_embedding_server() -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut request = Vec::new();
+                let mut buf = vec![0; 8192];
+                let body = loop {
+                    let n = socket.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                    let request_str = String::from_utf8_lossy(&request).to_string();
+                    if let Some(header_end) = request_str.find("\r\n\r\n") {
+                        let body = &request_str[header_end + 4..];
+                        if n == 0 || serde_json::from_str::<serde_json::Value>(body).is_ok() {
+                            break body.to_owned();
+                        }
+                    }
+                };
+
+                let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+                let inputs = body["input"].as_array().unwrap();
+                let (status, response) = if inputs.iter().any(|i| i == "bad input") {
+                    (
+                        "400 Bad Request",
+                        serde_json::json!({ "error": { "message": "Input was rejected" } }),
+                    )
+                } else {
+                    (
+                        "200 OK",
+                        serde_json::json!({
+                            "data": inputs.iter().map(|_| serde_json::json!({ "embedding": [1.0, 2.0, 3.0] })).collect::<Vec<_>>(),
+                            "usage": { "total_tokens": inputs.len() }
+                        }),
+                    )
+                };
+                let response = response.to_string();
+                socket
+                    .write_all(
+                        format!(
+                            "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{response}",
+                            response.len()
+                        )
+                        .as_bytes(),
+                    )
+                    .await
+                    .unwrap();
+            });
+        }
+    });
+
+    format!("http://{addr}")
+}
+
+#[tokio::test]
+async fn test_embedding_server_failed_inputs_isolation() {
+    start_server(8093, None);
+    tokio::time::sleep(Duration::from_secs(10)).await;
+
+    let base_url = start_mock_embedding_server().await;
+    let runtime_params = format!(r#"{{"base_url": "{base_url}"}}"#);
+
+    // Both requests are processed in one batch, the rejected input
+    // should be reported only to its own request
+    let requests = ["Hello world!", "bad input"].map(|input| {
+        let runtime_params = runtime_params.clone();
+        async move {
+            let client = EmbeddingServerClient::new("http://127.0.0.1:8093").unwrap();
+            client
+                .embeddings(
+                    &Runtime::OpenAi,
+                    "my-embedding-model",
+                    &runtime_params,
+                    vec![input.to_owned()],
+                )
+                .await
+        }
+    });
+    let responses = futures::future::join_all(requests).await;
+
+    let good_response = responses[0].as_ref().unwrap();
+    assert_eq!(good_response.embeddings[0], vec![1.0, 2.0, 3.0]);
+    assert!(good_response.failed_inputs.is_empty());
+
+    let bad_response = responses[1].as_ref().unwrap();
+    assert_eq!(bad_response.failed_inputs.len(), 1);
+    assert_eq!(bad_response.failed_inputs[0].0, 0);
+    assert!(bad_response.failed_inputs[0]
+        .1
+        .contains("Input was rejected"));
+}
@@ -9,6 +9,7 @@ use std::{
 use lantern_cli::embeddings::{self, cli::EmbeddingJobType};
 use lantern_cli::embeddings::{core::Runtime, get_try_cast_fn_sql};
 use lantern_cli::{daemon::embedding_jobs::FAILURE_TABLE_DEFINITION, embeddings::cli};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio_postgres::IsolationLevel;
 use tokio_postgres::{Client, NoTls};
 use tokio_util::sync::CancellationToken;
@@ -68,7 +69,7 @@ async fn test_embedding_generation_from_db() {
         final_progress_r1.store(progress, Ordering::SeqCst);
     };
 
-    let (processed_rows, processed_tokens) = embeddings::create_embeddings_from_db(
+    let (processed_rows, processed_tokens, _) = embeddings::create_embeddings_from_db(
         cli::EmbeddingArgs {
             model: "BAAI/bge-small-en".to_owned(),
             uri: db_url.clone(),
@@ -89,11 +90,22 @@ async fn test_embedding_generation_from_db() {
             stream: true,
             job_type: None,
             column_type: None,
+            out_json_columns: None,
             check_column_type: false,
             create_cast_fn: false,
             internal_schema: "".to_owned(),
             failed_rows_table: None,
+            batch_state_table: None,
             job_id: 0,
+            cache: false,
+            cache_table: "embedding_cache".to_owned(),
+            cache_max_age: None,
+            cache_max_entries: None,
+            input_type: None,
+            input_file: None,
+            input_format: None,
+            output_file: None,
+            output_format: None,
         },
         true,
         Some(Box::new(callback)),
@@ -123,6 +135,388 @@ async fn test_embedding_generation_from_db() {
     assert_eq!(final_progress.load(Ordering::SeqCst), 100);
 }
 
+#[tokio::test]
+async fn test_embedding_generation_with_truncation_from_db() {
+    let db_url = env::var("DB_URL").expect("`DB_URL` not specified");
+    let table_name = String::from("_embeddings_truncation_test");
+    let failure_table_name = format!("{table_name}_failure_info");
+    let (mut db_client, connection) = tokio_postgres::connect(&db_url, NoTls)
+        .await
+        .expect("Can not connect to database");
+    tokio::spawn(async move { connection.await.unwrap() });
+    setup_db_tables(&mut db_client, &table_name).await;
+    db_client
+        .batch_execute(&format!(
+            "UPDATE {table_name} SET content=repeat('Hello world! ', 300) WHERE id=1"
+        ))
+        .await
+        .unwrap();
+
+    let (processed_rows, _, _) = embeddings::create_embeddings_from_db(
+        cli::EmbeddingArgs {
+            model: "BAAI/bge-small-en".to_owned(),
+            uri: db_url.clone(),
+            pk: "id".to_owned(),
+            column: "content".to_owned(),
+            table: table_name.clone(),
+            schema: "public".to_owned(),
+            out_uri: None,
+            out_column: "emb".to_owned(),
+            batch_size: None,
+            visual: false,
+            out_table: None,
+            limit: None,
+            filter: Some("id < 11".to_owned()),
+            runtime: Runtime::Ort,
+            runtime_params: "{\"data_path\": \"/tmp/lantern-embeddings-core-test\"}".to_owned(),
+            create_column: true,
+            stream: true,
+            job_type: None,
+            column_type: None,
+            out_json_columns: None,
+            check_column_type: false,
+            create_cast_fn: false,
+            internal_schema: "public".to_owned(),
+            failed_rows_table: Some(failure_table_name.clone()),
+            batch_state_table: None,
+            job_id: 0,
+            cache: false,
+            cache_table: "embedding_cache".to_owned(),
+            cache_max_age: None,
+            cache_max_entries: None,
+            input_type: None,
+            input_file: None,
+            input_format: None,
+            output_file: None,
+            output_format: None,
+        },
+        false,
+        None,
+        CancellationToken::new(),
+        None,
+    )
+    .await
+    .unwrap();
+
+    let rows = db_client
+        .query(
+            &format!("SELECT row_id, value, truncated FROM {failure_table_name}"),
+            &[],
+        )
+        .await
+        .unwrap();
+
+    let cnt = db_client
+        .query_one(
+            &format!(
+                "SELECT COUNT(id) FROM {table_name} WHERE id < 11 AND array_length(emb, 1) = 384"
+            ),
+            &[],
+        )
+        .await
+        .unwrap();
+    let cnt = cnt.get::<usize, i64>(0);
+
+    drop_db_tables(&mut db_client, &table_name).await;
+
+    assert_eq!(processed_rows, 10);
+    assert_eq!(cnt, 10);
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].get::<usize, String>(0), "1");
+    assert!(rows[0]
+        .get::<usize, String>(1)
+        .starts_with("Input was truncated from"));
+    assert_eq!(rows[0].get::<usize, bool>(2), true);
+}
+
+#[tokio::test]
+async fn test_embedding_generation_with_truncation_and_text_pk_from_db() {
+    let db_url = env::var("DB_URL").expect("`DB_URL` not specified");
+    let table_name = String::from("_embeddings_truncation_text_pk_test");
+    let failure_table_name = format!("{table_name}_failure_info");
+    let (mut db_client, connection) = tokio_postgres::connect(&db_url, NoTls)
+        .await
+        .expect("Can not connect to database");
+    tokio::spawn(async move { connection.await.unwrap() });
+    setup_db_tables(&mut db_client, &table_name).await;
+    db_client
+        .batch_execute(&format!(
+            "
+            ALTER TABLE {table_name} ALTER COLUMN id DROP DEFAULT;
+            ALTER TABLE {table_name} ALTER COLUMN id TYPE TEXT USING 'row-' || id;
+            UPDATE {table_name} SET content=repeat('Hello world! ', 300) WHERE id='row-1';
+            "
+        ))
+        .await
+        .unwrap();
+
+    let (processed_rows, _, _) = embeddings::create_embeddings_from_db(
+        cli::EmbeddingArgs {
+            model: "BAAI/bge-small-en".to_owned(),
+            uri: db_url.clone(),
+            pk: "id".to_owned(),
+            column: "content".to_owned(),
+            table: table_name.clone(),
+            schema: "public".to_owned(),
+            out_uri: None,
+            out_column: "emb".to_owned(),
+            batch_size: None,
+            visual: false,
+            out_table: None,
+            limit: None,
+            filter: Some("id IN ('row-1', 'row-2', 'row-3')".to_owned()),
+            runtime: Runtime::Ort,
+            runtime_params: "{\"data_path\": \"/tmp/lantern-embeddings-core-test\"}".to_owned(),
+            create_column: true,
+            stream: true,
+            job_type: None,
+            column_type: None,
+            out_json_columns: None,
+            check_column_type: false,
+            create_cast_fn: false,
+            internal_schema: "public".to_owned(),
+            failed_rows_table: Some(failure_table_name.clone()),
+            batch_state_table: None,
+            job_id: 0,
+            cache: false,
+            cache_table: "embedding_cache".to_owned(),
+            cache_max_age: None,
+            cache_max_entries: None,
+            input_type: None,
+            input_file: None,
+            input_format: None,
+            output_file: None,
+            output_format: None,
+        },
+        false,
+        None,
+        CancellationToken::new(),
+        None,
+    )
+    .await
+    .unwrap();
+
+    let rows = db_client
+        .query(
+            &format!("SELECT row_id, truncated FROM {failure_table_name}"),
+            &[],
+        )
+        .await
+        .unwrap();
+
+    let cnt = db_client
+        .query_one(
+            &format!("SELECT COUNT(id) FROM {table_name} WHERE array_length(emb, 1) = 384"),
+            &[],
+        )
+        .await
+        .unwrap()
+        .get::<usize, i64>(0);
+
+    drop_db_tables(&mut db_client, &table_name).await;
+
+    assert_eq!(processed_rows, 3);
+    assert_eq!(cnt, 3);
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].get::<usize, String>(0), "row-1");
+    assert_eq!(rows[0].get::<usize, bool>(1), true);
+}
+
+#[tokio::test]
+async fn test_embedding_generation_with_binary_quantization_from_db() {
+    let db_url = env::var("DB_URL").expect("`DB_URL` not specified");
+    let table_name = String::from("_embeddings_binary_quantization_test");
+    let (mut db_client, connection) = tokio_postgres::connect(&db_url, NoTls)
+        .await
+        .expect("Can not connect to database");
+    tokio::spawn(async move { connection.await.unwrap() });
+    setup_db_tables(&mut db_client, &table_name).await;
+
+    let (processed_rows, _, _) = embeddings::create_embeddings_from_db(
+        cli::EmbeddingArgs {
+            model: "BAAI/bge-small-en".to_owned(),
+            uri: db_url.clone(),
+            pk: "id".to_owned(),
+            column: "content".to_owned(),
+            table: table_name.clone(),
+            schema: "public".to_owned(),
+            out_uri: None,
+            out_column: "emb".to_owned(),
+            batch_size: None,
+            visual: false,
+            out_table: None,
+            limit: None,
+            filter: Some("id < 11".to_owned()),
+            runtime: Runtime::Ort,
+            runtime_params: "{\"data_path\": \"/tmp/lantern-embeddings-core-test\", \"quantization\": \"binary\"}".to_owned(),
+            create_column: true,
+            stream: true,
+            job_type: None,
+            column_type: None,
+            out_json_columns: None,
+            check_column_type: false,
+            create_cast_fn: false,
+            internal_schema: "public".to_owned(),
+            failed_rows_table: None,
+            batch_state_table: None,
+            job_id: 0,
+            cache: false,
+            cache_table: "embedding_cache".to_owned(),
+            cache_max_age: None,
+            cache_max_entries: None,
+            input_type: None,
+            input_file: None,
+            input_format: None,
+            output_file: None,
+            output_format: None,
+        },
+        false,
+        None,
+        CancellationToken::new(),
+        None,
+    )
+    .await
+    .unwrap();
+
+    // 384 dimensions are packed into 48 bytes
+    let row = db_client
+        .query_one(
+            &format!(
+                "SELECT pg_typeof(emb)::text, COUNT(id) FILTER (WHERE octet_length(emb) = 48) FROM {table_name} WHERE id < 11 GROUP BY 1"
+            ),
+            &[],
+        )
+        .await
+        .unwrap();
+
+    drop_db_tables(&mut db_client, &table_name).await;
+
+    assert_eq!(processed_rows, 10);
+    assert_eq!(row.get::<usize, String>(0), "bytea");
+    assert_eq!(row.get::<usize, i64>(1), 10);
+}
+
+#[tokio::test]
+async fn test_embedding_generation_with_cache_from_db() {
+    let db_url = env::var("DB_URL").expect("`DB_URL` not specified");
+    let table_name = String::from("_embeddings_cache_test");
+    let cache_table_name = format!("{table_name}_cache");
+    let (mut db_client, connection) = tokio_postgres::connect(&db_url, NoTls)
+        .await
+        .expect("Can not connect to database");
+    tokio::spawn(async move { connection.await.unwrap() });
+    setup_db_tables(&mut db_client, &table_name).await;
+    db_client
+        .batch_execute(&format!("DROP TABLE IF EXISTS {cache_table_name}"))
+        .await
+        .unwrap();
+
+    let get_args = |runtime_params: &str| cli::EmbeddingArgs {
+        model: "BAAI/bge-small-en".to_owned(),
+        uri: db_url.clone(),
+        pk: "id".to_owned(),
+        column: "content".to_owned(),
+        table: table_name.clone(),
+        schema: "public".to_owned(),
+        out_uri: None,
+        out_column: "emb".to_owned(),
+        batch_size: Some(100),
+        visual: false,
+        out_table: None,
+        limit: None,
+        filter: None,
+        runtime: Runtime::Ort,
+        runtime_params: runtime_params.to_owned(),
+        create_column: true,
+        stream: true,
+        job_type: None,
+        column_type: None,
+        out_json_columns: None,
+        check_column_type: false,
+        create_cast_fn: false,
+        internal_schema: "public".to_owned(),
+        failed_rows_table: None,
+        batch_state_table: None,
+        job_id: 0,
+        cache: true,
+        cache_table: cache_table_name.clone(),
+        cache_max_age: None,
+        cache_max_entries: Some(10),
+        input_type: None,
+        input_file: None,
+        input_format: None,
+        output_file: None,
+        output_format: None,
+    };
+
+    let runtime_params = "{\"data_path\": \"/tmp/lantern-embeddings-core-test\"}";
+
+    // All rows have the same content, so only the first batch will be embedded
+    let (processed_rows, processed_tokens, cache_hits) = embeddings::create_embeddings_from_db(
+        get_args(runtime_params),
+        false,
+        None,
+        CancellationToken::new(),
+        None,
+    )
+    .await
+    .unwrap();
+    assert_eq!(processed_rows, 4000);
+    assert_eq!(processed_tokens, 500);
+    assert_eq!(cache_hits, 3900);
+
+    let (processed_rows, processed_tokens, cache_hits) = embeddings::create_embeddings_from_db(
+        get_args(runtime_params),
+        false,
+        None,
+        CancellationToken::new(),
+        None,
+    )
+    .await
+    .unwrap();
+    assert_eq!(processed_rows, 4000);
+    assert_eq!(processed_tokens, 0);
+    assert_eq!(cache_hits, 4000);
+
+    let cnt = db_client
+        .query_one(
+            &format!(
+            "SELECT COUNT(id) FROM {table_name} WHERE emb IS NULL OR array_length(emb, 1) != 384"
+        ),
+            &[],
+        )
+        .await
+        .unwrap();
+    let cnt = cnt.get::<usize, i64>(0);
+
+    let cache_cnt = db_client
+        .query_one(&format!("SELECT COUNT(*) FROM {cache_table_name}"), &[])
+        .await
+        .unwrap();
+    let cache_cnt = cache_cnt.get::<usize, i64>(0);
+
+    // Embeddings generated with other truncation policy are not taken from cache
+    let (_, _, chunked_cache_hits) = embeddings::create_embeddings_from_db(
+        get_args("{\"data_path\": \"/tmp/lantern-embeddings-core-test\", \"truncation_policy\": \"chunk_average\"}"),
+        false,
+        None,
+        CancellationToken::new(),
+        None,
+    )
+    .await
+    .unwrap();
+
+    drop_db_tables(&mut db_client, &table_name).await;
+    db_client
+        .batch_execute(&format!("DROP TABLE IF EXISTS {cache_table_name}"))
+        .await
+        .unwrap();
+
+    assert_eq!(cnt, 0);
+    assert_eq!(cache_cnt, 1);
+    assert_eq!(chunked_cache_hits, 3900);
+}
+
 #[tokio::test]
 async fn test_openai_completion_from_db() {
     let db_url = env::var("DB_URL").expect("`DB_URL` not specified");
@@ -147,7 +541,7 @@ async fn test_openai_completion_from_db() {
         final_progress_r1.store(progress, Ordering::SeqCst);
     };
 
-    let (processed_rows, _) = embeddings::create_embeddings_from_db(
+    let (processed_rows, _, _) = embeddings::create_embeddings_from_db(
         cli::EmbeddingArgs {
             model: "gpt-4o".to_owned(),
             uri: db_url.clone(),
@@ -168,11 +562,22 @@ async fn test_openai_completion_from_db() {
             stream: true,
             job_type: Some(EmbeddingJobType::Completion),
             column_type: Some("TEXT[]".to_owned()),
+            out_json_columns: None,
             failed_rows_table: Some(failure_table_name.clone()),
+            batch_state_table: None,
             internal_schema: "public".to_owned(),
             create_cast_fn: false,
             check_column_type: true,
-            job_id: 0
+            job_id: 0,
+            cache: false,
+            cache_table: "embedding_cache".to_owned(),
+            cache_max_age: None,
+            cache_max_entries: None,
+            input_type: None,
+            input_file: None,
+            input_format: None,
+            output_file: None,
+            output_format: None,
         },
         true,
         Some(Box::new(callback)),
@@ -225,7 +630,7 @@ async fn test_openai_completion_special_chars_from_db() {
         final_progress_r1.store(progress, Ordering::SeqCst);
     };
 
-    let (processed_rows, _) = embeddings::create_embeddings_from_db(
+    let (processed_rows, _, _) = embeddings::create_embeddings_from_db(
         cli::EmbeddingArgs {
             model: "gpt-4o".to_owned(),
             uri: db_url.clone(),
@@ -246,11 +651,22 @@ async fn test_openai_completion_special_chars_from_db() {
             stream: true,
             job_type: Some(EmbeddingJobType::Completion),
             column_type: Some("TEXT".to_owned()),
+            out_json_columns: None,
             failed_rows_table: Some(failure_table_name.clone()),
+            batch_state_table: None,
             internal_schema: "public".to_owned(),
             create_cast_fn: false,
             check_column_type: true,
-            job_id: 0
+            job_id: 0,
+            cache: false,
+            cache_table: "embedding_cache".to_owned(),
+            cache_max_age: None,
+            cache_max_entries: None,
+            input_type: None,
+            input_file: None,
+            input_format: None,
+            output_file: None,
+            output_format: None,
         },
         true,
         Some(Box::new(callback)),
@@ -303,7 +719,7 @@ async fn test_openai_completion_failed_rows_from_db() {
         final_progress_r1.store(progress, Ordering::SeqCst);
     };
 
-    let (processed_rows, _) = embeddings::create_embeddings_from_db(
+    let (processed_rows, _, _) = embeddings::create_embeddings_from_db(
         cli::EmbeddingArgs {
             model: "gpt-4o".to_owned(),
             uri: db_url.clone(),
@@ -324,11 +740,22 @@ async fn test_openai_completion_failed_rows_from_db() {
             stream: true,
             job_type: Some(EmbeddingJobType::Completion),
             column_type: Some("TEXT[]".to_owned()),
+            out_json_columns: None,
             failed_rows_table: Some(failure_table_name.clone()),
+            batch_state_table: None,
             internal_schema: "public".to_owned(),
             create_cast_fn: false,
             check_column_type: true,
-            job_id: 0
+            job_id: 0,
+            cache: false,
+            cache_table: "embedding_cache".to_owned(),
+            cache_max_age: None,
+            cache_max_entries: None,
+            input_type: None,
+            input_file: None,
+            input_format: None,
+            output_file: None,
+            output_format: None,
         },
         true,
         Some(Box::new(callback)),
@@ -351,3 +778,361 @@ async fn test_openai_completion_failed_rows_from_db() {
     assert_eq!(cnt, 10);
     assert_eq!(final_progress.load(Ordering::SeqCst), 100);
 }
+
+// Starts a minimal OpenAI compatible embeddings server
+// which rejects the requests containing "bad input" and fails authorization for "unauthorized"
+async fn start_mock_embedding_server() -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut request = Vec::new();
+                let mut buf = vec![0; 8192];
+                let body = loop {
+                    let n = socket.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                    let request_str = String::from_utf8_lossy(&request).to_string();
+                    if let Some(header_end) = request_str.find("\r\n\r\n") {
+                        let body = &request_str[header_end + 4..];
+                        if n == 0 || serde_json::from_str::<serde_json::Value>(body).is_ok() {
+                            break body.to_owned();
+                        }
+                    }
+                };
+
+                let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+                let inputs = body["input"].as_array().unwrap();
+                let (status, response) = if inputs.iter().any(|i| i == "unauthorized") {
+                    (
+                        "401 Unauthorized",
+                        serde_json::json!({ "error": { "message": "Invalid api key" } }),
+                    )
+                } else if inputs.iter().any(|i| i == "bad input") {
+                    (
+                        "400 Bad Request",
+                        serde_json::json!({ "error": { "message": "Input was rejected" } }),
+                    )
+                } else {
+                    (
+                        "200 OK",
+                        serde_json::json!({
+                            "data": inputs.iter().map(|_| serde_json::json!({ "embedding": [1.0, 2.0, 3.0] })).collect::<Vec<_>>(),
+                            "usage": { "total_tokens": inputs.len() }
+                        }),
+                    )
+                };
+                let response = response.to_string();
+                socket
+                    .write_all(
+                        format!(
+                            "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{response}",
+                            response.len()
+                        )
+                        .as_bytes(),
+                    )
+                    .await
+                    .unwrap();
+            });
+        }
+    });
+
+    format!("http://{addr}")
+}
+
+#[tokio::test]
+async fn test_embedding_generation_failed_rows_isolation_from_db() {
+    let db_url = env::var("DB_URL").expect("`DB_URL` not specified");
+    let table_name = String::from("_embeddings_failed_rows_isolation_test");
+    let failure_table_name = format!("{table_name}_failure_info");
+    let (mut db_client, connection) = tokio_postgres::connect(&db_url, NoTls)
+        .await
+        .expect("Can not connect to database");
+    tokio::spawn(async move { connection.await.unwrap() });
+    setup_db_tables(&mut db_client, &table_name).await;
+    db_client
+        .batch_execute(&format!(
+            "
+            UPDATE {table_name} SET content='bad input' WHERE id IN (3, 7, 12, 13);
+            UPDATE {table_name} SET content='unauthorized' WHERE id = 20;
+            "
+        ))
+        .await
+        .unwrap();
+
+    let base_url = start_mock_embedding_server().await;
+    let get_args = |filter: &str| cli::EmbeddingArgs {
+        model: "my-embedding-model".to_owned(),
+        uri: db_url.clone(),
+        pk: "id".to_owned(),
+        column: "content".to_owned(),
+        table: table_name.clone(),
+        schema: "public".to_owned(),
+        out_uri: None,
+        out_column: "emb".to_owned(),
+        batch_size: Some(10),
+        visual: false,
+        out_table: None,
+        limit: None,
+        filter: Some(filter.to_owned()),
+        runtime: Runtime::OpenAi,
+        runtime_params: format!(r#"{{"base_url": "{base_url}"}}"#),
+        create_column: true,
+        stream: true,
+        job_type: None,
+        column_type: None,
+        out_json_columns: None,
+        check_column_type: false,
+        create_cast_fn: false,
+        internal_schema: "public".to_owned(),
+        failed_rows_table: Some(failure_table_name.clone()),
+        batch_state_table: None,
+        job_id: 0,
+        cache: false,
+        cache_table: "embedding_cache".to_owned(),
+        cache_max_age: None,
+        cache_max_entries: None,
+        input_type: None,
+        input_file: None,
+        input_format: None,
+        output_file: None,
+        output_format: None,
+    };
+
+    let (processed_rows, _, _) = embeddings::create_embeddings_from_db(
+        get_args("id < 11"),
+        false,
+        None,
+        CancellationToken::new(),
+        None,
+    )
+    .await
+    .unwrap();
+
+    let rows = db_client
+        .query(
+            &format!("SELECT row_id, value FROM {failure_table_name} ORDER BY row_id"),
+            &[],
+        )
+        .await
+        .unwrap();
+
+    let cnt = db_client
+        .query_one(
+            &format!(
+                "SELECT COUNT(id) FROM {table_name} WHERE id < 11 AND array_length(emb, 1) = 3"
+            ),
+            &[],
+        )
+        .await
+        .unwrap();
+    let cnt = cnt.get::<usize, i64>(0);
+
+    // Failed rows are written as NULL instead of placeholder embeddings
+    let failed_null_cnt = db_client
+        .query_one(
+            &format!("SELECT COUNT(id) FROM {table_name} WHERE id IN (3, 7) AND emb IS NULL"),
+            &[],
+        )
+        .await
+        .unwrap()
+        .get::<usize, i64>(0);
+
+    // Rows rejected because of their input are recorded even if all rows of the batch fail
+    let (all_failed_rows, _, _) = embeddings::create_embeddings_from_db(
+        get_args("id IN (12, 13)"),
+        false,
+        None,
+        CancellationToken::new(),
+        None,
+    )
+    .await
+    .unwrap();
+
+    let all_failed_cnt = db_client
+        .query_one(
+            &format!("SELECT COUNT(*) FROM {failure_table_name} WHERE row_id IN ('12', '13')"),
+            &[],
+        )
+        .await
+        .unwrap()
+        .get::<usize, i64>(0);
+
+    // Errors which are not caused by the input fail the batch even for a single row
+    let unauthorized_result = embeddings::create_embeddings_from_db(
+        get_args("id = 20"),
+        false,
+        None,
+        CancellationToken::new(),
+        None,
+    )
+    .await;
+
+    drop_db_tables(&mut db_client, &table_name).await;
+
+    assert_eq!(processed_rows, 10);
+    assert_eq!(cnt, 8);
+    assert_eq!(failed_null_cnt, 2);
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[0].get::<usize, String>(0), "3");
+    assert_eq!(rows[1].get::<usize, String>(0), "7");
+    assert!(rows[0]
+        .get::<usize, String>(1)
+        .contains("Input was rejected"));
+    assert_eq!(all_failed_rows, 2);
+    assert_eq!(all_failed_cnt, 2);
+    assert!(unauthorized_result.is_err());
+}
+
+#[tokio::test]
+async fn test_embedding_generation_failed_rows_with_uuid_pk_from_db() {
+    let db_url = env::var("DB_URL").expect("`DB_URL` not specified");
+    let table_name = String::from("_embeddings_failed_rows_uuid_pk_test");
+    let failure_table_name = format!("{table_name}_failure_info");
+    let (mut db_client, connection) = tokio_postgres::connect(&db_url, NoTls)
+        .await
+        .expect("Can not connect to database");
+    tokio::spawn(async move { connection.await.unwrap() });
+    setup_db_tables(&mut db_client, &table_name).await;
+    db_client
+        .batch_execute(&format!(
+            "
+            ALTER TABLE {table_name} ALTER COLUMN id DROP DEFAULT;
+            ALTER TABLE {table_name} ALTER COLUMN id TYPE UUID USING lpad(id::text, 32, '0')::uuid;
+            UPDATE {table_name} SET content='bad input' WHERE id='00000000-0000-0000-0000-000000000003';
+            "
+        ))
+        .await
+        .unwrap();
+
+    let base_url = start_mock_embedding_server().await;
+    let (processed_rows, _, _) = embeddings::create_embeddings_from_db(
+        cli::EmbeddingArgs {
+            model: "my-embedding-model".to_owned(),
+            uri: db_url.clone(),
+            pk: "id".to_owned(),
+            column: "content".to_owned(),
+            table: table_name.clone(),
+            schema: "public".to_owned(),
+            out_uri: None,
+            out_column: "emb".to_owned(),
+            batch_size: Some(10),
+            visual: false,
+            out_table: None,
+            limit: None,
+            filter: Some("id <= '00000000-0000-0000-0000-000000000005'".to_owned()),
+            runtime: Runtime::OpenAi,
+            runtime_params: format!(r#"{{"base_url": "{base_url}"}}"#),
+            create_column: true,
+            stream: true,
+            job_type: None,
+            column_type: None,
+            out_json_columns: None,
+            check_column_type: false,
+            create_cast_fn: false,
+            internal_schema: "public".to_owned(),
+            failed_rows_table: Some(failure_table_name.clone()),
+            batch_state_table: None,
+            job_id: 0,
+            cache: false,
+            cache_table: "embedding_cache".to_owned(),
+            cache_max_age: None,
+            cache_max_entries: None,
+            input_type: None,
+            input_file: None,
+            input_format: None,
+            output_file: None,
+            output_format: None,
+        },
+        false,
+        None,
+        CancellationToken::new(),
+        None,
+    )
+    .await
+    .unwrap();
+
+    let rows = db_client
+        .query(&format!("SELECT row_id FROM {failure_table_name}"), &[])
+        .await
+        .unwrap();
+
+    drop_db_tables(&mut db_client, &table_name).await;
+
+    assert_eq!(processed_rows, 5);
+    assert_eq!(rows.len(), 1);
+    assert_eq!(
+        rows[0].get::<usize, String>(0),
+        "00000000-0000-0000-0000-000000000003"
+    );
+}
+
+#[tokio::test]
+async fn test_invalid_json_column_type_from_db() {
+    let db_url = env::var("DB_URL").expect("`DB_URL` not specified");
+    let table_name = String::from("_embeddings_test_json_column_type");
+    let (mut db_client, connection) = tokio_postgres::connect(&db_url, NoTls)
+        .await
+        .expect("Can not connect to database");
+    tokio::spawn(async move { connection.await.unwrap() });
+    setup_db_tables(&mut db_client, &table_name).await;
+
+    let base_url = start_mock_embedding_server().await;
+    let result = embeddings::create_embeddings_from_db(
+        cli::EmbeddingArgs {
+            model: "my-embedding-model".to_owned(),
+            uri: db_url.clone(),
+            pk: "id".to_owned(),
+            column: "content".to_owned(),
+            table: table_name.clone(),
+            schema: "public".to_owned(),
+            out_uri: None,
+            out_column: "emb".to_owned(),
+            batch_size: Some(10),
+            visual: false,
+            out_table: None,
+            limit: Some(10),
+            filter: None,
+            runtime: Runtime::OpenAi,
+            runtime_params: format!(r#"{{"base_url": "{base_url}"}}"#),
+            create_column: true,
+            stream: true,
+            job_type: None,
+            column_type: None,
+            out_json_columns: Some(format!(r#"{{"score": "INT; DROP TABLE {table_name}"}}"#)),
+            check_column_type: false,
+            create_cast_fn: false,
+            internal_schema: "public".to_owned(),
+            failed_rows_table: None,
+            batch_state_table: None,
+            job_id: 0,
+            cache: false,
+            cache_table: "embedding_cache".to_owned(),
+            cache_max_age: None,
+            cache_max_entries: None,
+            input_type: None,
+            input_file: None,
+            input_format: None,
+            output_file: None,
+            output_format: None,
+        },
+        false,
+        None,
+        CancellationToken::new(),
+        None,
+    )
+    .await;
+
+    let table_exists = db_client
+        .query_one("SELECT to_regclass($1) IS NOT NULL", &[&table_name])
+        .await
+        .unwrap()
+        .get::<usize, bool>(0);
+
+    drop_db_tables(&mut db_client, &table_name).await;
+
+    assert!(result.is_err());
+    assert!(table_exists);
+}
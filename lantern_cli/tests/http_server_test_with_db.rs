@@ -1,12 +1,15 @@
 use std::{
     collections::HashMap,
     env,
+    fs::File,
+    io::BufReader,
+    process::Command,
     sync::mpsc::{self, Sender, TryRecvError},
     time::Duration,
 };
 
 use lantern_cli::{
-    http_server::{self, cli::HttpServerArgs},
+    http_server::{self, cli::HttpServerArgs, tls},
     types::AnyhowVoidResult,
 };
 use reqwest::{
@@ -16,8 +19,18 @@ use reqwest::{
 use tokio_postgres::{Client, NoTls};
 
 static TEST_COLLECTION_NAME: &'static str = "_lantern_http_test1";
+static TEST_HYBRID_COLLECTION_NAME: &'static str = "_lantern_http_hybrid_test1";
+static TEST_ROWS_COLLECTION_NAME: &'static str = "_lantern_http_rows_test1";
+static TEST_EMBEDDING_COLLECTION_NAME: &'static str = "_lantern_http_embedding_test1";
+static TEST_JOBS_COLLECTION_NAME: &'static str = "_lantern_http_jobs_test1";
 static SERVER_URL: &'static str = "http://127.0.0.1:7777";
 static AUTH_HEADER: &'static str = "Basic dGVzdDp0ZXN0";
+static TLS_SERVER_URL: &'static str = "https://127.0.0.1:7778";
+static TLS_CERT_PATH: &'static str = "/tmp/lantern-http-server-test-cert.pem";
+static TLS_KEY_PATH: &'static str = "/tmp/lantern-http-server-test-key.pem";
+// Both servers use the same database and internal tables,
+// so the tests should not run in parallel
+static SERVER_TEST_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
 
 async fn drop_db_tables(client: &mut Client) {
     client
@@ -25,7 +38,13 @@ async fn drop_db_tables(client: &mut Client) {
             "
         DROP TABLE IF EXISTS {TEST_COLLECTION_NAME};
         DROP TABLE IF EXISTS _lantern_extras_internal.http_collections;
+        DROP TABLE IF EXISTS _lantern_extras_internal.http_api_keys;
         DROP TABLE IF EXISTS _lantern_internal.pq_{TEST_COLLECTION_NAME}_v;
+        DROP TABLE IF EXISTS {TEST_HYBRID_COLLECTION_NAME};
+        DROP TABLE IF EXISTS {TEST_HYBRID_COLLECTION_NAME}_bm25;
+        DROP TABLE IF EXISTS {TEST_ROWS_COLLECTION_NAME};
+        DROP TABLE IF EXISTS {TEST_EMBEDDING_COLLECTION_NAME};
+        DROP TABLE IF EXISTS {TEST_JOBS_COLLECTION_NAME};
     "
         ))
         .await
@@ -38,20 +57,23 @@ async fn test_setup() -> Sender<()> {
 }
 
 fn start_server(db_uri: String) -> Sender<()> {
+    start_server_with_args(HttpServerArgs {
+        db_uri,
+        host: "127.0.0.1".to_owned(),
+        port: 7777,
+        username: Some("test".to_owned()),
+        password: Some("test".to_owned()),
+        allow_raw_sql: false,
+        cert: None,
+        key: None,
+    })
+}
+
+fn start_server_with_args(args: HttpServerArgs) -> Sender<()> {
     let (tx, rx) = mpsc::channel();
     std::thread::spawn(move || {
         std::thread::spawn(move || {
-            http_server::start(
-                HttpServerArgs {
-                    db_uri,
-                    host: "127.0.0.1".to_owned(),
-                    port: 7777,
-                    username: Some("test".to_owned()),
-                    password: Some("test".to_owned()),
-                },
-                None,
-            )
-            .expect("Failed to start HTTP Server");
+            http_server::start(args, None).expect("Failed to start HTTP Server");
         });
 
         loop {
@@ -140,6 +162,354 @@ async fn test_collection_insert() -> AnyhowVoidResult {
     Ok(())
 }
 
+async fn test_strict_mode() -> AnyhowVoidResult {
+    let http_client = reqwest::Client::builder();
+    let mut headers = HeaderMap::new();
+    headers.insert(AUTHORIZATION, HeaderValue::from_static(AUTH_HEADER));
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    let http_client = http_client.default_headers(headers).build()?;
+
+    // Data types are validated
+    let body = format!(
+        r#"{{
+                 "name": "{TEST_COLLECTION_NAME}_strict",
+                 "schema": {{ "id": "serial primary key", "t": "TEXT); DROP TABLE {TEST_COLLECTION_NAME}; --" }}
+             }}"#
+    );
+
+    let response = http_client
+        .post(&format!("{SERVER_URL}/collections"))
+        .body(body)
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), StatusCode::from_u16(400)?);
+
+    // Select fields are validated against collection columns
+    for select in [
+        "id, (SELECT 1)",
+        "id; DROP TABLE _lantern_extras_internal.http_collections",
+        "unknown",
+    ] {
+        let body = serde_json::json!({
+            "column": "v",
+            "query_vector": [0, 0, 0],
+            "select": select
+        });
+
+        let response = http_client
+            .post(&format!(
+                "{SERVER_URL}/collections/{TEST_COLLECTION_NAME}/search"
+            ))
+            .body(body.to_string())
+            .send()
+            .await?;
+
+        assert_eq!(response.status(), StatusCode::from_u16(400)?);
+    }
+
+    // Insert columns are validated against collection columns
+    let response = http_client
+        .put(&format!("{SERVER_URL}/collections/{TEST_COLLECTION_NAME}"))
+        .body(r#"{ "rows": [{ "unknown": 1 }] }"#)
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), StatusCode::from_u16(400)?);
+
+    Ok(())
+}
+
+async fn create_api_key(body: serde_json::Value) -> Result<serde_json::Value, anyhow::Error> {
+    let response = reqwest::Client::new()
+        .post(&format!("{SERVER_URL}/api-keys"))
+        .header(CONTENT_TYPE, "application/json")
+        .header(AUTHORIZATION, AUTH_HEADER)
+        .body(body.to_string())
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), StatusCode::from_u16(200)?);
+    Ok(serde_json::from_str(&response.text().await?)?)
+}
+
+async fn test_api_keys() -> AnyhowVoidResult {
+    let read_key = create_api_key(serde_json::json!({
+        "name": "test-read",
+        "scope": "read",
+        "collections": [TEST_COLLECTION_NAME]
+    }))
+    .await?;
+    assert_eq!(read_key["scope"], "read");
+    assert_eq!(read_key["name"], "test-read");
+    let read_auth = format!("Bearer {}", read_key["key"].as_str().unwrap());
+
+    let http_client = reqwest::Client::new();
+
+    // Read access to allowed collection
+    let response = http_client
+        .get(&format!("{SERVER_URL}/collections/{TEST_COLLECTION_NAME}"))
+        .header(AUTHORIZATION, &read_auth)
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::from_u16(200)?);
+
+    // Listing collections returns only allowed collections
+    let response = http_client
+        .get(&format!("{SERVER_URL}/collections"))
+        .header(AUTHORIZATION, &read_auth)
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::from_u16(200)?);
+    let collections: Vec<serde_json::Value> = serde_json::from_str(&response.text().await?)?;
+    assert_eq!(collections.len(), 1);
+    assert_eq!(collections[0]["name"], TEST_COLLECTION_NAME);
+
+    // Other collections are not allowed
+    let response = http_client
+        .get(&format!("{SERVER_URL}/collections/other_collection"))
+        .header(AUTHORIZATION, &read_auth)
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::from_u16(403)?);
+
+    // Write and admin operations are not allowed
+    let response = http_client
+        .put(&format!("{SERVER_URL}/collections/{TEST_COLLECTION_NAME}"))
+        .header(AUTHORIZATION, &read_auth)
+        .header(CONTENT_TYPE, "application/json")
+        .body(r#"{ "rows": [{ "v": [0,0,0] }] }"#)
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::from_u16(403)?);
+
+    let response = http_client
+        .delete(&format!("{SERVER_URL}/collections/{TEST_COLLECTION_NAME}"))
+        .header(AUTHORIZATION, &read_auth)
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::from_u16(403)?);
+
+    let response = http_client
+        .post(&format!("{SERVER_URL}/api-keys"))
+        .header(AUTHORIZATION, &read_auth)
+        .header(CONTENT_TYPE, "application/json")
+        .body(r#"{ "scope": "admin" }"#)
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::from_u16(403)?);
+
+    // Invalid keys are rejected
+    let response = http_client
+        .get(&format!("{SERVER_URL}/collections"))
+        .header(AUTHORIZATION, "Bearer lk_invalid")
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::from_u16(401)?);
+
+    // Rate limit
+    let limited_key = create_api_key(serde_json::json!({
+        "scope": "read",
+        "rate_limit": 2
+    }))
+    .await?;
+    let limited_auth = format!("Bearer {}", limited_key["key"].as_str().unwrap());
+
+    for status in [200, 200, 429] {
+        let response = http_client
+            .get(&format!("{SERVER_URL}/collections"))
+            .header(AUTHORIZATION, &limited_auth)
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::from_u16(status)?);
+    }
+
+    // Keys list
+    let response = http_client
+        .get(&format!("{SERVER_URL}/api-keys"))
+        .header(AUTHORIZATION, AUTH_HEADER)
+        .send()
+        .await?;
+    let keys: Vec<serde_json::Value> = serde_json::from_str(&response.text().await?)?;
+    assert_eq!(keys.len(), 2);
+    assert!(keys.iter().all(|k| k.get("key").is_none()));
+
+    // Revoked keys are rejected
+    for key in [&read_key, &limited_key] {
+        let response = http_client
+            .delete(&format!("{SERVER_URL}/api-keys/{}", key["id"]))
+            .header(AUTHORIZATION, AUTH_HEADER)
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::from_u16(200)?);
+    }
+
+    let response = http_client
+        .get(&format!("{SERVER_URL}/collections/{TEST_COLLECTION_NAME}"))
+        .header(AUTHORIZATION, &read_auth)
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::from_u16(401)?);
+
+    Ok(())
+}
+
+async fn test_collection_rows() -> AnyhowVoidResult {
+    let http_client = reqwest::Client::builder();
+    let mut headers = HeaderMap::new();
+    headers.insert(AUTHORIZATION, HeaderValue::from_static(AUTH_HEADER));
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    let http_client = http_client.default_headers(headers).build()?;
+    let rows_url = format!("{SERVER_URL}/collections/{TEST_ROWS_COLLECTION_NAME}/rows");
+
+    let response = http_client
+        .post(&format!("{SERVER_URL}/collections"))
+        .body(
+            serde_json::json!({
+                "name": TEST_ROWS_COLLECTION_NAME,
+                "schema": { "id": "text primary key", "v": "REAL[]", "m": "JSONB" }
+            })
+            .to_string(),
+        )
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::from_u16(200)?);
+
+    // Upsert with duplicate ids keeps the last row
+    let response = http_client
+        .post(&format!("{rows_url}/upsert"))
+        .body(
+            serde_json::json!({
+                "rows": [
+                    { "id": "a", "v": [1, 2, 3], "m": { "lang": "en" } },
+                    { "id": "b", "v": [0, 0, 1], "m": { "lang": "de" } },
+                    { "id": "a", "v": [1, 1, 1], "m": { "lang": "en" } }
+                ]
+            })
+            .to_string(),
+        )
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::from_u16(200)?);
+    let body_json: serde_json::Value = serde_json::from_str(&response.text().await?)?;
+    assert_eq!(body_json["ids"].as_array().unwrap().len(), 2);
+
+    let response = http_client.get(&format!("{rows_url}/a")).send().await?;
+    let row: serde_json::Value = serde_json::from_str(&response.text().await?)?;
+    assert_eq!(row["v"], serde_json::json!([1.0, 1.0, 1.0]));
+
+    // Existing rows are updated
+    let response = http_client
+        .post(&format!("{rows_url}/upsert"))
+        .body(
+            serde_json::json!({
+                "rows": [
+                    { "id": "b", "v": [0, 0, 2], "m": { "lang": "fr" } },
+                    { "id": "c", "v": [0, 1, 2], "m": { "lang": "en" } }
+                ]
+            })
+            .to_string(),
+        )
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::from_u16(200)?);
+
+    let response = http_client.get(&format!("{rows_url}/b")).send().await?;
+    let row: serde_json::Value = serde_json::from_str(&response.text().await?)?;
+    assert_eq!(row["m"]["lang"], "fr");
+
+    // Upsert sets only the passed columns
+    let response = http_client
+        .post(&format!("{rows_url}/upsert"))
+        .body(r#"{ "rows": [{ "id": "b", "m": { "lang": "it" } }] }"#)
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::from_u16(200)?);
+
+    let response = http_client.get(&format!("{rows_url}/b")).send().await?;
+    let row: serde_json::Value = serde_json::from_str(&response.text().await?)?;
+    assert_eq!(row["m"]["lang"], "it");
+    assert_eq!(row["v"], serde_json::json!([0.0, 0.0, 2.0]));
+
+    // Ids are compared by the primary key type
+    let response = http_client
+        .post(&format!("{rows_url}/upsert"))
+        .body(r#"{ "rows": [{ "id": 1, "m": { "lang": "en" } }, { "id": "1", "m": { "lang": "pt" } }] }"#)
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::from_u16(200)?);
+    let body_json: serde_json::Value = serde_json::from_str(&response.text().await?)?;
+    assert_eq!(body_json["ids"], serde_json::json!(["1"]));
+
+    let response = http_client.delete(&format!("{rows_url}/1")).send().await?;
+    let body_json: serde_json::Value = serde_json::from_str(&response.text().await?)?;
+    assert_eq!(body_json["deleted"], 1);
+
+    // Update only the passed columns
+    let response = http_client
+        .patch(&format!("{rows_url}/a"))
+        .body(r#"{ "m": { "lang": "es" } }"#)
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::from_u16(200)?);
+    let row: serde_json::Value = serde_json::from_str(&response.text().await?)?;
+    assert_eq!(row["m"]["lang"], "es");
+    assert_eq!(row["v"], serde_json::json!([1.0, 1.0, 1.0]));
+
+    // Page through rows
+    let response = http_client
+        .get(&format!("{rows_url}?limit=2&select=id"))
+        .send()
+        .await?;
+    let page: serde_json::Value = serde_json::from_str(&response.text().await?)?;
+    assert_eq!(
+        page["rows"],
+        serde_json::json!([{ "id": "a" }, { "id": "b" }])
+    );
+    assert_eq!(page["next_cursor"], "b");
+
+    let response = http_client
+        .get(&format!("{rows_url}?limit=2&select=id&cursor=b"))
+        .send()
+        .await?;
+    let page: serde_json::Value = serde_json::from_str(&response.text().await?)?;
+    assert_eq!(page["rows"], serde_json::json!([{ "id": "c" }]));
+    assert!(page["next_cursor"].is_null());
+
+    // Delete by filter and by id
+    let response = http_client
+        .post(&format!("{rows_url}/delete"))
+        .body(r#"{ "filter": { "m.lang": "en" } }"#)
+        .send()
+        .await?;
+    let body_json: serde_json::Value = serde_json::from_str(&response.text().await?)?;
+    assert_eq!(body_json["deleted"], 1);
+
+    let response = http_client.delete(&format!("{rows_url}/a")).send().await?;
+    assert_eq!(response.status(), StatusCode::from_u16(200)?);
+
+    let response = http_client.get(&format!("{rows_url}/a")).send().await?;
+    assert_eq!(response.status(), StatusCode::from_u16(404)?);
+
+    let response = http_client
+        .post(&format!("{rows_url}/delete"))
+        .body(r#"{ "ids": ["b", "c"] }"#)
+        .send()
+        .await?;
+    let body_json: serde_json::Value = serde_json::from_str(&response.text().await?)?;
+    assert_eq!(body_json["deleted"], 1);
+
+    let response = http_client
+        .delete(&format!(
+            "{SERVER_URL}/collections/{TEST_ROWS_COLLECTION_NAME}"
+        ))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::from_u16(200)?);
+
+    Ok(())
+}
+
 async fn test_collection_list() -> AnyhowVoidResult {
     let http_client = reqwest::Client::builder();
     let mut headers = HeaderMap::new();
@@ -219,6 +589,25 @@ async fn test_collection_delete() -> AnyhowVoidResult {
     Ok(())
 }
 
+// Polls the operation until it is finished
+async fn wait_for_operation(id: &serde_json::Value) -> Result<serde_json::Value, anyhow::Error> {
+    loop {
+        let response = reqwest::Client::new()
+            .get(&format!("{SERVER_URL}/operations/{id}"))
+            .header(AUTHORIZATION, AUTH_HEADER)
+            .send()
+            .await?;
+        assert_eq!(response.status(), reqwest::StatusCode::from_u16(200)?);
+        let operation: serde_json::Value = serde_json::from_str(&response.text().await?)?;
+
+        if operation["status"] != "running" {
+            return Ok(operation);
+        }
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
 async fn test_pq() -> AnyhowVoidResult {
     let body = format!(
         r#"{{
@@ -238,7 +627,28 @@ async fn test_pq() -> AnyhowVoidResult {
         .send()
         .await?;
 
-    assert_eq!(response.status(), reqwest::StatusCode::from_u16(200)?);
+    assert_eq!(response.status(), reqwest::StatusCode::from_u16(202)?);
+    let operation: serde_json::Value = serde_json::from_str(&response.text().await?)?;
+    assert_eq!(operation["kind"], "quantize");
+
+    let operation = wait_for_operation(&operation["id"]).await?;
+    assert_eq!(operation["status"], "succeeded");
+    assert_eq!(operation["progress"], 100);
+
+    // Finished operation can not be canceled
+    let response = reqwest::Client::new()
+        .delete(&format!("{SERVER_URL}/operations/{}", operation["id"]))
+        .header(AUTHORIZATION, AUTH_HEADER)
+        .send()
+        .await?;
+    assert_eq!(response.status(), reqwest::StatusCode::from_u16(409)?);
+
+    let response = reqwest::Client::new()
+        .get(&format!("{SERVER_URL}/operations/1000000"))
+        .header(AUTHORIZATION, AUTH_HEADER)
+        .send()
+        .await?;
+    assert_eq!(response.status(), reqwest::StatusCode::from_u16(404)?);
 
     Ok(())
 }
@@ -263,7 +673,10 @@ async fn test_index_create() -> AnyhowVoidResult {
         .send()
         .await?;
 
-    assert_eq!(response.status(), reqwest::StatusCode::from_u16(200)?);
+    assert_eq!(response.status(), reqwest::StatusCode::from_u16(202)?);
+    let operation: serde_json::Value = serde_json::from_str(&response.text().await?)?;
+    let operation = wait_for_operation(&operation["id"]).await?;
+    assert_eq!(operation["status"], "succeeded");
 
     let response = reqwest::Client::new()
         .get(&format!("{SERVER_URL}/collections/{TEST_COLLECTION_NAME}"))
@@ -287,13 +700,14 @@ async fn test_index_create() -> AnyhowVoidResult {
     Ok(())
 }
 
-async fn test_search_vector() -> AnyhowVoidResult {
+async fn test_search_filter() -> AnyhowVoidResult {
     let body = format!(
         r#"{{
                  "column": "v",
                  "query_vector": [0,0,0],
                  "k": 1,
-                 "select": "id"
+                 "select": "id",
+                 "filter": {{ "m.name": {{ "ne": "test3" }} }}
              }}"#
     );
 
@@ -313,18 +727,16 @@ async fn test_search_vector() -> AnyhowVoidResult {
 
     let rows = body_json.get("rows").unwrap();
     assert_eq!(rows.len(), 1);
-    let first = rows.first().unwrap();
-
-    assert_eq!(first["id"], 6);
-    assert_eq!(first["distance"], 0);
+    assert_eq!(rows.first().unwrap()["id"], 1);
 
-    // Test with k=5
+    // Test with nested conditions
     let body = format!(
         r#"{{
                  "column": "v",
                  "query_vector": [0,0,0],
                  "k": 5,
-                 "select": "id, v"
+                 "select": "id",
+                 "filter": {{ "or": [{{ "m.name": "test2" }}, {{ "id": {{ "in": [6, 7] }} }}], "id": {{ "lt": 7 }} }}
              }}"#
     );
 
@@ -343,30 +755,18 @@ async fn test_search_vector() -> AnyhowVoidResult {
     let body_json: HashMap<String, Vec<serde_json::Value>> = serde_json::from_str(&body_json)?;
 
     let rows = body_json.get("rows").unwrap();
-    assert_eq!(rows.len(), 5);
-    let first = rows.first().unwrap();
-
-    assert_eq!(first["id"], 6);
-    assert_eq!(first["v"], serde_json::to_value(vec![0, 0, 0])?);
-    assert_eq!(first["distance"], 0);
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[0]["id"], 6);
+    assert_eq!(rows[1]["id"], 2);
 
-    let db_uri = env::var("DB_URL").expect("`DB_URL` not specified");
-    let (db_client, connection) = tokio_postgres::connect(&db_uri, NoTls).await.unwrap();
-    tokio::spawn(async move { connection.await.unwrap() });
-    db_client.batch_execute(&format!("
-        DROP TABLE {TEST_COLLECTION_NAME} CASCADE;
-        CREATE TABLE {TEST_COLLECTION_NAME} (id serial primary key, v real[]);
-        INSERT INTO {TEST_COLLECTION_NAME} (v) VALUES (text_embedding('BAAI/bge-small-en', 'Weather is nice today')), (text_embedding('BAAI/bge-small-en', 'The car is red'));
-        CREATE INDEX test_idx ON {TEST_COLLECTION_NAME} USING lantern_hnsw (v) WITH (m=16, ef_construction=128, ef=128);
-    ")).await?;
-    // Test with model
+    // Typed columns are compared directly with the values cast to the column type
     let body = format!(
         r#"{{
                  "column": "v",
-                 "query_text": "How is the weather today?",
-                 "query_model": "BAAI/bge-small-en",
+                 "query_vector": [0,0,0],
                  "k": 2,
-                 "select": "id"
+                 "select": "id",
+                 "filter": {{ "id": {{ "nin": [1, "2"], "ne": null }} }}
              }}"#
     );
 
@@ -381,22 +781,18 @@ async fn test_search_vector() -> AnyhowVoidResult {
         .await?;
 
     let body_json = response.text().await?;
-    println!("Response: {:?}", body_json);
     let body_json: HashMap<String, Vec<serde_json::Value>> = serde_json::from_str(&body_json)?;
 
     let rows = body_json.get("rows").unwrap();
     assert_eq!(rows.len(), 2);
-    let first = rows.first().unwrap();
-
-    assert_eq!(first["id"], 1);
+    assert!(rows.iter().all(|row| row["id"] != 1 && row["id"] != 2));
 
+    // Value which can not be cast to the column type
     let body = format!(
         r#"{{
                  "column": "v",
-                 "query_text": "What color is the car?",
-                 "query_model": "BAAI/bge-small-en",
-                 "k": 2,
-                 "select": "id"
+                 "query_vector": [0,0,0],
+                 "filter": {{ "id": {{ "gte": "abc" }} }}
              }}"#
     );
 
@@ -410,46 +806,716 @@ async fn test_search_vector() -> AnyhowVoidResult {
         .send()
         .await?;
 
-    let body_json = response.text().await?;
-    println!("Response: {:?}", body_json);
-    let body_json: HashMap<String, Vec<serde_json::Value>> = serde_json::from_str(&body_json)?;
-
-    let rows = body_json.get("rows").unwrap();
-    assert_eq!(rows.len(), 2);
-    let first = rows.first().unwrap();
+    assert_eq!(response.status(), StatusCode::from_u16(400)?);
 
-    assert_eq!(first["id"], 2);
-    Ok(())
-}
+    // Test with invalid operator
+    let body = format!(
+        r#"{{
+                 "column": "v",
+                 "query_vector": [0,0,0],
+                 "filter": {{ "id": {{ "like": "1" }} }}
+             }}"#
+    );
 
-async fn test_index_delete() -> AnyhowVoidResult {
-    let body = String::new();
     let response = reqwest::Client::new()
-        .delete(&format!("{SERVER_URL}/index/test_idx"))
+        .post(&format!(
+            "{SERVER_URL}/collections/{TEST_COLLECTION_NAME}/search"
+        ))
         .header(CONTENT_TYPE, "application/json")
         .header(AUTHORIZATION, AUTH_HEADER)
         .body(body)
         .send()
         .await?;
 
-    assert_eq!(response.status(), reqwest::StatusCode::from_u16(200)?);
+    assert_eq!(response.status(), StatusCode::from_u16(400)?);
 
     Ok(())
 }
 
-#[tokio::test]
-async fn test_http_server() {
-    test_cleanup().await;
-    let tx = test_setup().await;
-    test_collection_create().await.unwrap();
-    test_collection_list().await.unwrap();
-    test_collection_get().await.unwrap();
-    test_collection_insert().await.unwrap();
-    test_pq().await.unwrap();
-    test_index_create().await.unwrap();
-    test_search_vector().await.unwrap();
-    test_index_delete().await.unwrap();
-    test_collection_delete().await.unwrap();
+async fn test_search_vector() -> AnyhowVoidResult {
+    let body = format!(
+        r#"{{
+                 "column": "v",
+                 "query_vector": [0,0,0],
+                 "k": 1,
+                 "select": "id"
+             }}"#
+    );
+
+    let response = reqwest::Client::new()
+        .post(&format!(
+            "{SERVER_URL}/collections/{TEST_COLLECTION_NAME}/search"
+        ))
+        .header(CONTENT_TYPE, "application/json")
+        .header(AUTHORIZATION, AUTH_HEADER)
+        .body(body)
+        .send()
+        .await?;
+
+    let body_json = response.text().await?;
+    println!("Response: {:?}", body_json);
+    let body_json: HashMap<String, Vec<serde_json::Value>> = serde_json::from_str(&body_json)?;
+
+    let rows = body_json.get("rows").unwrap();
+    assert_eq!(rows.len(), 1);
+    let first = rows.first().unwrap();
+
+    assert_eq!(first["id"], 6);
+    assert_eq!(first["distance"], 0);
+
+    // Test with k=5
+    let body = format!(
+        r#"{{
+                 "column": "v",
+                 "query_vector": [0,0,0],
+                 "k": 5,
+                 "select": "id, v"
+             }}"#
+    );
+
+    let response = reqwest::Client::new()
+        .post(&format!(
+            "{SERVER_URL}/collections/{TEST_COLLECTION_NAME}/search"
+        ))
+        .header(CONTENT_TYPE, "application/json")
+        .header(AUTHORIZATION, AUTH_HEADER)
+        .body(body)
+        .send()
+        .await?;
+
+    let body_json = response.text().await?;
+    println!("Response: {:?}", body_json);
+    let body_json: HashMap<String, Vec<serde_json::Value>> = serde_json::from_str(&body_json)?;
+
+    let rows = body_json.get("rows").unwrap();
+    assert_eq!(rows.len(), 5);
+    let first = rows.first().unwrap();
+
+    assert_eq!(first["id"], 6);
+    assert_eq!(first["v"], serde_json::to_value(vec![0, 0, 0])?);
+    assert_eq!(first["distance"], 0);
+
+    let db_uri = env::var("DB_URL").expect("`DB_URL` not specified");
+    let (db_client, connection) = tokio_postgres::connect(&db_uri, NoTls).await.unwrap();
+    tokio::spawn(async move { connection.await.unwrap() });
+    db_client.batch_execute(&format!("
+        DROP TABLE {TEST_COLLECTION_NAME} CASCADE;
+        CREATE TABLE {TEST_COLLECTION_NAME} (id serial primary key, v real[]);
+        INSERT INTO {TEST_COLLECTION_NAME} (v) VALUES (text_embedding('BAAI/bge-small-en', 'Weather is nice today')), (text_embedding('BAAI/bge-small-en', 'The car is red'));
+        CREATE INDEX test_idx ON {TEST_COLLECTION_NAME} USING lantern_hnsw (v) WITH (m=16, ef_construction=128, ef=128);
+    ")).await?;
+    // Test with model
+    let body = format!(
+        r#"{{
+                 "column": "v",
+                 "query_text": "How is the weather today?",
+                 "query_model": "BAAI/bge-small-en",
+                 "k": 2,
+                 "select": "id"
+             }}"#
+    );
+
+    let response = reqwest::Client::new()
+        .post(&format!(
+            "{SERVER_URL}/collections/{TEST_COLLECTION_NAME}/search"
+        ))
+        .header(CONTENT_TYPE, "application/json")
+        .header(AUTHORIZATION, AUTH_HEADER)
+        .body(body)
+        .send()
+        .await?;
+
+    let body_json = response.text().await?;
+    println!("Response: {:?}", body_json);
+    let body_json: HashMap<String, Vec<serde_json::Value>> = serde_json::from_str(&body_json)?;
+
+    let rows = body_json.get("rows").unwrap();
+    assert_eq!(rows.len(), 2);
+    let first = rows.first().unwrap();
+
+    assert_eq!(first["id"], 1);
+
+    let body = format!(
+        r#"{{
+                 "column": "v",
+                 "query_text": "What color is the car?",
+                 "query_model": "BAAI/bge-small-en",
+                 "k": 2,
+                 "select": "id"
+             }}"#
+    );
+
+    let response = reqwest::Client::new()
+        .post(&format!(
+            "{SERVER_URL}/collections/{TEST_COLLECTION_NAME}/search"
+        ))
+        .header(CONTENT_TYPE, "application/json")
+        .header(AUTHORIZATION, AUTH_HEADER)
+        .body(body)
+        .send()
+        .await?;
+
+    let body_json = response.text().await?;
+    println!("Response: {:?}", body_json);
+    let body_json: HashMap<String, Vec<serde_json::Value>> = serde_json::from_str(&body_json)?;
+
+    let rows = body_json.get("rows").unwrap();
+    assert_eq!(rows.len(), 2);
+    let first = rows.first().unwrap();
+
+    assert_eq!(first["id"], 2);
+    Ok(())
+}
+
+async fn test_search_batch() -> AnyhowVoidResult {
+    let http_client = reqwest::Client::builder();
+    let mut headers = HeaderMap::new();
+    headers.insert(AUTHORIZATION, HeaderValue::from_static(AUTH_HEADER));
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    let http_client = http_client.default_headers(headers).build()?;
+    let batch_url = format!("{SERVER_URL}/collections/{TEST_COLLECTION_NAME}/search/batch");
+
+    let response = http_client
+        .post(&batch_url)
+        .body(
+            serde_json::json!({
+                "column": "v",
+                "query_texts": ["How is the weather today?", "What color is the car?"],
+                "query_model": "BAAI/bge-small-en",
+                "k": 2,
+                "select": "id"
+            })
+            .to_string(),
+        )
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::from_u16(200)?);
+    let body_json: serde_json::Value = serde_json::from_str(&response.text().await?)?;
+    let results = body_json["results"].as_array().unwrap();
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0]["rows"].as_array().unwrap().len(), 2);
+    assert_eq!(results[0]["rows"][0]["id"], 1);
+    assert_eq!(results[1]["rows"][0]["id"], 2);
+
+    // Results are returned in the order of queries
+    let response = http_client
+        .post(&batch_url)
+        .body(
+            serde_json::json!({
+                "column": "v",
+                "query_texts": ["What color is the car?", "How is the weather today?", "What color is the car?"],
+                "query_model": "BAAI/bge-small-en",
+                "k": 1,
+                "ef": 64,
+                "select": "id"
+            })
+            .to_string(),
+        )
+        .send()
+        .await?;
+    let body_json: serde_json::Value = serde_json::from_str(&response.text().await?)?;
+    let ids: Vec<i64> = body_json["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|r| r["rows"][0]["id"].as_i64().unwrap())
+        .collect();
+    assert_eq!(ids, vec![2, 1, 2]);
+
+    let response = http_client
+        .post(&batch_url)
+        .body(r#"{ "column": "v", "query_vectors": [[0, 0, 0]], "query_texts": ["test"] }"#)
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::from_u16(400)?);
+
+    Ok(())
+}
+
+async fn test_hybrid_search() -> AnyhowVoidResult {
+    let db_uri = env::var("DB_URL").expect("`DB_URL` not specified");
+    let (db_client, connection) = tokio_postgres::connect(&db_uri, NoTls).await.unwrap();
+    tokio::spawn(async move { connection.await.unwrap() });
+    db_client.batch_execute(&format!("
+        CREATE TABLE {TEST_HYBRID_COLLECTION_NAME} (id serial primary key, content text, content_stemmed text[], v real[]);
+        INSERT INTO {TEST_HYBRID_COLLECTION_NAME} (content) VALUES ('Weather is nice today'), ('The car is red'), ('Red apples are sweet');
+        UPDATE {TEST_HYBRID_COLLECTION_NAME} SET content_stemmed=text_to_stem_array(content), v=text_embedding('BAAI/bge-small-en', content);
+        SELECT create_bm25_table('{TEST_HYBRID_COLLECTION_NAME}', 'id', ARRAY['content_stemmed']);
+    ")).await?;
+
+    let body = format!(
+        r#"{{
+                 "query_text": "red car",
+                 "query_model": "BAAI/bge-small-en",
+                 "vector_column": "v",
+                 "bm25_column": "content_stemmed",
+                 "k": 3,
+                 "select": "id,content"
+             }}"#
+    );
+
+    let response = reqwest::Client::new()
+        .post(&format!(
+            "{SERVER_URL}/collections/{TEST_HYBRID_COLLECTION_NAME}/hybrid-search"
+        ))
+        .header(CONTENT_TYPE, "application/json")
+        .header(AUTHORIZATION, AUTH_HEADER)
+        .body(body)
+        .send()
+        .await?;
+
+    let body_json = response.text().await?;
+    println!("Response: {:?}", body_json);
+    let body_json: HashMap<String, Vec<serde_json::Value>> = serde_json::from_str(&body_json)?;
+
+    let rows = body_json.get("rows").unwrap();
+    assert_eq!(rows.len(), 3);
+    let first = rows.first().unwrap();
+    assert_eq!(first["id"], 2);
+    assert_eq!(first["content"], "The car is red");
+    assert_eq!(first["vector_rank"], 1);
+    assert_eq!(first["bm25_rank"], 1);
+    assert!(first["vector_distance"].is_number());
+    assert!(first["bm25_score"].is_number());
+    assert_eq!(first["score"], 2.0 * 0.5 / 61.0);
+
+    let weather_row = rows.iter().find(|r| r["id"] == 1).unwrap();
+    assert!(weather_row["bm25_rank"].is_null());
+    assert!(weather_row["bm25_score"].is_null());
+
+    // Test with weighted fusion
+    let body = format!(
+        r#"{{
+                 "query_text": "red car",
+                 "query_model": "BAAI/bge-small-en",
+                 "vector_column": "v",
+                 "bm25_column": "content_stemmed",
+                 "k": 1,
+                 "select": "id",
+                 "fusion": "weighted",
+                 "vector_weight": 0.3,
+                 "bm25_weight": 0.7
+             }}"#
+    );
+
+    let response = reqwest::Client::new()
+        .post(&format!(
+            "{SERVER_URL}/collections/{TEST_HYBRID_COLLECTION_NAME}/hybrid-search"
+        ))
+        .header(CONTENT_TYPE, "application/json")
+        .header(AUTHORIZATION, AUTH_HEADER)
+        .body(body)
+        .send()
+        .await?;
+
+    let body_json = response.text().await?;
+    println!("Response: {:?}", body_json);
+    let body_json: HashMap<String, Vec<serde_json::Value>> = serde_json::from_str(&body_json)?;
+
+    let rows = body_json.get("rows").unwrap();
+    assert_eq!(rows.len(), 1);
+    let first = rows.first().unwrap();
+    assert_eq!(first["id"], 2);
+    assert_eq!(first["score"].as_f64().unwrap(), 1.0);
+
+    // Test with filter applied to both vector and bm25 candidates
+    let body = format!(
+        r#"{{
+                 "query_text": "red car",
+                 "query_model": "BAAI/bge-small-en",
+                 "vector_column": "v",
+                 "bm25_column": "content_stemmed",
+                 "k": 3,
+                 "select": "id,content",
+                 "filter": {{ "id": {{ "ne": 2 }} }}
+             }}"#
+    );
+
+    let response = reqwest::Client::new()
+        .post(&format!(
+            "{SERVER_URL}/collections/{TEST_HYBRID_COLLECTION_NAME}/hybrid-search"
+        ))
+        .header(CONTENT_TYPE, "application/json")
+        .header(AUTHORIZATION, AUTH_HEADER)
+        .body(body)
+        .send()
+        .await?;
+
+    let body_json = response.text().await?;
+    println!("Response: {:?}", body_json);
+    let body_json: HashMap<String, Vec<serde_json::Value>> = serde_json::from_str(&body_json)?;
+
+    let rows = body_json.get("rows").unwrap();
+    assert_eq!(rows.len(), 2);
+    assert!(rows.iter().all(|r| r["id"] != 2));
+    let first = rows.first().unwrap();
+    assert_eq!(first["id"], 3);
+    assert_eq!(first["bm25_rank"], 1);
+    assert_eq!(first["vector_rank"], 1);
+
+    Ok(())
+}
+
+async fn test_collection_embedding() -> AnyhowVoidResult {
+    let http_client = reqwest::Client::builder();
+    let mut headers = HeaderMap::new();
+    headers.insert(AUTHORIZATION, HeaderValue::from_static(AUTH_HEADER));
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    let http_client = http_client.default_headers(headers).build()?;
+    let collection_url = format!("{SERVER_URL}/collections/{TEST_EMBEDDING_COLLECTION_NAME}");
+
+    // Destination column is added to the schema
+    let response = http_client
+        .post(&format!("{SERVER_URL}/collections"))
+        .body(
+            serde_json::json!({
+                "name": TEST_EMBEDDING_COLLECTION_NAME,
+                "schema": { "id": "serial primary key", "content": "TEXT" },
+                "embedding": { "src_column": "content", "dst_column": "v", "model": "BAAI/bge-small-en" }
+            })
+            .to_string(),
+        )
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::from_u16(200)?);
+    let body_json: serde_json::Value = serde_json::from_str(&response.text().await?)?;
+    assert_eq!(body_json["schema"]["v"], "REAL[]");
+    assert_eq!(body_json["embedding"]["mode"], "sync");
+
+    let response = http_client
+        .put(&collection_url)
+        .body(r#"{ "rows": [{ "content": "Weather is nice today" }, { "content": "The car is red" }] }"#)
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::from_u16(200)?);
+
+    let response = http_client.get(&collection_url).send().await?;
+    let body_json: serde_json::Value = serde_json::from_str(&response.text().await?)?;
+    assert_eq!(body_json["embedding"]["model"], "BAAI/bge-small-en");
+
+    // Search without query_model uses the collection model
+    let response = http_client
+        .post(&format!("{collection_url}/search"))
+        .body(
+            r#"{ "column": "v", "query_text": "What color is the car?", "k": 2, "select": "id" }"#,
+        )
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::from_u16(200)?);
+    let body_json: serde_json::Value = serde_json::from_str(&response.text().await?)?;
+    assert_eq!(body_json["rows"][0]["id"], 2);
+
+    // Embedding is regenerated when the source column is updated
+    let response = http_client
+        .patch(&format!("{collection_url}/rows/1"))
+        .body(r#"{ "content": "My car is red" }"#)
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::from_u16(200)?);
+
+    let response = http_client
+        .post(&format!("{collection_url}/search"))
+        .body(r#"{ "column": "v", "query_text": "My car is red", "k": 1, "select": "id" }"#)
+        .send()
+        .await?;
+    let body_json: serde_json::Value = serde_json::from_str(&response.text().await?)?;
+    assert_eq!(body_json["rows"][0]["id"], 1);
+
+    // Source column should be in schema
+    let response = http_client
+        .post(&format!("{SERVER_URL}/collections"))
+        .body(
+            serde_json::json!({
+                "name": format!("{TEST_EMBEDDING_COLLECTION_NAME}_invalid"),
+                "schema": { "id": "serial primary key" },
+                "embedding": { "src_column": "content", "dst_column": "v", "model": "BAAI/bge-small-en" }
+            })
+            .to_string(),
+        )
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::from_u16(400)?);
+
+    // Destination column should be REAL[] in sync mode
+    let response = http_client
+        .post(&format!("{SERVER_URL}/collections"))
+        .body(
+            serde_json::json!({
+                "name": format!("{TEST_EMBEDDING_COLLECTION_NAME}_invalid"),
+                "schema": { "id": "serial primary key", "content": "TEXT", "v": "TEXT" },
+                "embedding": { "src_column": "content", "dst_column": "v", "model": "BAAI/bge-small-en" }
+            })
+            .to_string(),
+        )
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::from_u16(400)?);
+
+    // Job id can not be passed
+    let response = http_client
+        .post(&format!("{SERVER_URL}/collections"))
+        .body(
+            serde_json::json!({
+                "name": format!("{TEST_EMBEDDING_COLLECTION_NAME}_invalid"),
+                "schema": { "id": "serial primary key", "content": "TEXT" },
+                "embedding": { "src_column": "content", "dst_column": "v", "model": "BAAI/bge-small-en", "job_id": 1 }
+            })
+            .to_string(),
+        )
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::from_u16(400)?);
+
+    let response = http_client.delete(&collection_url).send().await?;
+    assert_eq!(response.status(), StatusCode::from_u16(200)?);
+
+    Ok(())
+}
+
+async fn test_autotune() -> AnyhowVoidResult {
+    let response = reqwest::Client::new()
+        .post(&format!(
+            "{SERVER_URL}/collections/{TEST_COLLECTION_NAME}/autotune"
+        ))
+        .header(CONTENT_TYPE, "application/json")
+        .header(AUTHORIZATION, AUTH_HEADER)
+        .body(r#"{ "column": "v", "metric": "cosine", "k": 1, "test_data_size": 2 }"#)
+        .send()
+        .await?;
+    assert_eq!(response.status(), reqwest::StatusCode::from_u16(202)?);
+    let operation: serde_json::Value = serde_json::from_str(&response.text().await?)?;
+    assert_eq!(operation["kind"], "autotune");
+
+    let operation = wait_for_operation(&operation["id"]).await?;
+    assert_eq!(operation["status"], "succeeded");
+
+    let results = operation["result"].as_array().unwrap();
+    assert!(!results.is_empty());
+    assert!(results[0]["recall"].as_f64().is_some());
+    assert!(results[0]["m"].as_i64().is_some());
+
+    Ok(())
+}
+
+async fn test_embedding_jobs() -> AnyhowVoidResult {
+    let http_client = reqwest::Client::builder();
+    let mut headers = HeaderMap::new();
+    headers.insert(AUTHORIZATION, HeaderValue::from_static(AUTH_HEADER));
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    let http_client = http_client.default_headers(headers).build()?;
+    let jobs_url = format!("{SERVER_URL}/collections/{TEST_JOBS_COLLECTION_NAME}/embedding-jobs");
+
+    let response = http_client
+        .post(&format!("{SERVER_URL}/collections"))
+        .body(
+            serde_json::json!({
+                "name": TEST_JOBS_COLLECTION_NAME,
+                "schema": { "id": "serial primary key", "content": "TEXT" }
+            })
+            .to_string(),
+        )
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::from_u16(200)?);
+
+    let response = http_client
+        .post(&jobs_url)
+        .body(r#"{ "src_column": "content", "dst_column": "v", "model": "BAAI/bge-small-en" }"#)
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::from_u16(200)?);
+    let job: serde_json::Value = serde_json::from_str(&response.text().await?)?;
+    assert_eq!(job["runtime"], "ort");
+    assert_eq!(job["dst_column"], "v");
+    let job_id = job["id"].as_i64().unwrap();
+
+    let response = http_client.get(&jobs_url).send().await?;
+    let jobs: Vec<serde_json::Value> = serde_json::from_str(&response.text().await?)?;
+    assert_eq!(jobs.len(), 1);
+    assert_eq!(jobs[0]["id"], job_id);
+
+    let response = http_client
+        .delete(&format!("{jobs_url}/{job_id}"))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::from_u16(200)?);
+    let job: serde_json::Value = serde_json::from_str(&response.text().await?)?;
+    assert_eq!(job["status"], "canceled");
+
+    let response = http_client
+        .post(&format!("{jobs_url}/{job_id}/resume"))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::from_u16(200)?);
+    let job: serde_json::Value = serde_json::from_str(&response.text().await?)?;
+    assert_ne!(job["status"], "canceled");
+
+    // Jobs of other collections are not accessible
+    let response = http_client
+        .delete(&format!(
+            "{SERVER_URL}/collections/{TEST_COLLECTION_NAME}/embedding-jobs/{job_id}"
+        ))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::from_u16(404)?);
+
+    // Source column should exist
+    let response = http_client
+        .post(&jobs_url)
+        .body(r#"{ "src_column": "title", "dst_column": "v", "model": "BAAI/bge-small-en" }"#)
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::from_u16(400)?);
+
+    let response = http_client
+        .delete(&format!(
+            "{SERVER_URL}/collections/{TEST_JOBS_COLLECTION_NAME}"
+        ))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::from_u16(200)?);
+
+    Ok(())
+}
+
+async fn test_index_delete() -> AnyhowVoidResult {
+    let body = String::new();
+    let response = reqwest::Client::new()
+        .delete(&format!("{SERVER_URL}/index/test_idx"))
+        .header(CONTENT_TYPE, "application/json")
+        .header(AUTHORIZATION, AUTH_HEADER)
+        .body(body)
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::from_u16(200)?);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_http_server() {
+    let _lock = SERVER_TEST_LOCK.lock().await;
+    test_cleanup().await;
+    let tx = test_setup().await;
+    test_collection_create().await.unwrap();
+    test_collection_list().await.unwrap();
+    test_collection_get().await.unwrap();
+    test_collection_insert().await.unwrap();
+    test_strict_mode().await.unwrap();
+    test_api_keys().await.unwrap();
+    test_collection_rows().await.unwrap();
+    test_pq().await.unwrap();
+    test_index_create().await.unwrap();
+    test_search_filter().await.unwrap();
+    test_search_vector().await.unwrap();
+    test_search_batch().await.unwrap();
+    test_hybrid_search().await.unwrap();
+    test_collection_embedding().await.unwrap();
+    test_autotune().await.unwrap();
+    test_embedding_jobs().await.unwrap();
+    test_index_delete().await.unwrap();
+    test_collection_delete().await.unwrap();
     tx.send(()).unwrap();
     test_cleanup().await;
 }
+
+fn generate_certificate(common_name: &str) {
+    Command::new("openssl")
+        .args([
+            "req",
+            "-x509",
+            "-nodes",
+            "-days",
+            "365",
+            "-newkey",
+            "rsa:2048",
+            "-keyout",
+            TLS_KEY_PATH,
+            "-out",
+            TLS_CERT_PATH,
+            "-subj",
+            &format!("/C=US/ST=California/L=San Francisco/O=Lantern/CN={common_name}"),
+        ])
+        .output()
+        .unwrap();
+}
+
+async fn get_peer_certificate() -> Result<Vec<u8>, anyhow::Error> {
+    let response = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .tls_info(true)
+        .build()?
+        .get(&format!("{TLS_SERVER_URL}/api-docs/openapi.json"))
+        .header(AUTHORIZATION, AUTH_HEADER)
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), StatusCode::from_u16(200)?);
+    let tls_info = response
+        .extensions()
+        .get::<reqwest::tls::TlsInfo>()
+        .unwrap();
+    Ok(tls_info.peer_certificate().unwrap().to_vec())
+}
+
+fn read_certificate() -> Vec<u8> {
+    let mut reader = BufReader::new(File::open(TLS_CERT_PATH).unwrap());
+    rustls_pemfile::certs(&mut reader)
+        .next()
+        .unwrap()
+        .unwrap()
+        .to_vec()
+}
+
+#[tokio::test]
+async fn test_http_server_tls() {
+    let _lock = SERVER_TEST_LOCK.lock().await;
+    let db_uri = env::var("DB_URL").expect("`DB_URL` not specified");
+    generate_certificate("lantern.dev");
+    let tx = start_server_with_args(HttpServerArgs {
+        db_uri,
+        host: "127.0.0.1".to_owned(),
+        port: 7778,
+        username: Some("test".to_owned()),
+        password: Some("test".to_owned()),
+        allow_raw_sql: false,
+        cert: Some(TLS_CERT_PATH.to_owned()),
+        key: Some(TLS_KEY_PATH.to_owned()),
+    });
+
+    assert_eq!(get_peer_certificate().await.unwrap(), read_certificate());
+    tx.send(()).unwrap();
+
+    // Certificate is reloaded from the same files
+    let (_, resolver) = tls::get_server_config(TLS_CERT_PATH, TLS_KEY_PATH).unwrap();
+    assert_eq!(resolver.current().cert[0].to_vec(), read_certificate());
+
+    generate_certificate("reloaded.lantern.dev");
+    let new_certificate = read_certificate();
+    resolver.reload().unwrap();
+    assert_eq!(resolver.current().cert[0].to_vec(), new_certificate);
+
+    // Certificate is not replaced if it does not match the key
+    let key = std::fs::read(TLS_KEY_PATH).unwrap();
+    generate_certificate("mismatched.lantern.dev");
+    std::fs::write(TLS_KEY_PATH, key).unwrap();
+    let err = resolver.reload().unwrap_err();
+    assert!(err.to_string().contains("does not match the private key"));
+    assert_eq!(resolver.current().cert[0].to_vec(), new_certificate);
+}
+
+#[test]
+fn test_http_server_db_tls_connector() {
+    // Database connections use TLS only when it is required by the connection string
+    assert!(
+        tls::get_db_tls_connector("postgres://postgres@127.0.0.1:5432/postgres")
+            .unwrap()
+            .is_none()
+    );
+    assert!(tls::get_db_tls_connector(
+        "postgres://postgres@127.0.0.1:5432/postgres?sslmode=require"
+    )
+    .unwrap()
+    .is_some());
+}
@@ -0,0 +1,105 @@
+use image::{ImageFormat, Rgb, RgbImage};
+use lantern_cli::embeddings::core::{EmbeddingRuntime, Runtime};
+use std::io::Cursor;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+fn get_test_image() -> Vec<u8> {
+    let image = RgbImage::from_fn(64, 64, |x, y| Rgb([(x * 4) as u8, (y * 4) as u8, 128]));
+    let mut buf = Cursor::new(Vec::new());
+    image.write_to(&mut buf, ImageFormat::Png).unwrap();
+    buf.into_inner()
+}
+
+// Starts a minimal S3 compatible server (MinIO stand-in)
+// which will answer each GET object request with the provided body
+async fn start_mock_s3_server(body: Vec<u8>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let body = body.clone();
+            tokio::spawn(async move {
+                let mut buf = vec![0; 8192];
+                let mut request = Vec::new();
+                loop {
+                    let n = socket.read(&mut buf).await.unwrap();
+                    if n == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buf[..n]);
+                    if String::from_utf8_lossy(&request).contains("\r\n\r\n") {
+                        break;
+                    }
+                }
+
+                let headers = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: image/png\r\nContent-Length: {}\r\nETag: \"test\"\r\nLast-Modified: Wed, 21 Oct 2015 07:28:00 GMT\r\nConnection: close\r\n\r\n",
+                    body.len()
+                );
+                socket.write_all(headers.as_bytes()).await.unwrap();
+                socket.write_all(&body).await.unwrap();
+            });
+        }
+    });
+
+    format!("http://{addr}")
+}
+
+#[tokio::test]
+async fn generate_clip_image_embeddings_from_s3_and_bytea() {
+    let image = get_test_image();
+    let endpoint = start_mock_s3_server(image.clone()).await;
+    let params = format!(
+        r#"{{"data_path": "/tmp/lantern-embeddings-core-test", "s3": {{"endpoint": "{endpoint}", "region": "us-east-1", "access_key_id": "minioadmin", "secret_access_key": "minioadmin"}}}}"#
+    );
+    let bytea_params = r#"{"data_path": "/tmp/lantern-embeddings-core-test", "bytea_input": true}"#;
+    let bytea_input = format!(
+        "\\x{}",
+        image.iter().map(|b| format!("{b:02x}")).collect::<String>()
+    );
+
+    let runtime = EmbeddingRuntime::new(&Runtime::Ort, None, &params).unwrap();
+    let output = runtime
+        .process(
+            "clip/ViT-B-32-visual",
+            &vec![
+                "s3://images/test.png",
+                "s3://images/test.png",
+                "/tmp/lantern-embeddings-core-test/not-existing.png",
+                // text values starting with \x are not decoded as bytea
+                &bytea_input,
+            ],
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(output.embeddings.len(), 4);
+    assert_eq!(output.embeddings[0], output.embeddings[1]);
+    assert!(output.embeddings[2].iter().all(|v| *v == -1.0));
+    assert_eq!(
+        output
+            .failed_inputs
+            .iter()
+            .map(|(idx, _)| *idx)
+            .collect::<Vec<_>>(),
+        vec![2, 3]
+    );
+
+    let bytea_runtime = EmbeddingRuntime::new(&Runtime::Ort, None, bytea_params).unwrap();
+    let bytea_output = bytea_runtime
+        .process(
+            "clip/ViT-B-32-visual",
+            &vec![&bytea_input, "s3://images/test.png"],
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(bytea_output.embeddings[0], output.embeddings[0]);
+    assert_eq!(bytea_output.failed_inputs.len(), 1);
+    assert_eq!(bytea_output.failed_inputs[0].0, 1);
+}
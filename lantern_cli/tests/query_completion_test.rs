@@ -1,5 +1,14 @@
-use lantern_cli::embeddings::core::{EmbeddingRuntime, Runtime};
+use lantern_cli::embeddings::core::{openai_batch::BatchState, EmbeddingRuntime, Runtime};
+use lantern_cli::embeddings::{self, cli};
 use std::env;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tokio_util::sync::CancellationToken;
 
 static LLM_SYSTEM_PROMPT: &'static str = "You will be provided JSON with the following schema: {x: string}, answer to the message returning the x propery from the provided JSON object";
 
@@ -88,3 +97,590 @@ query_completion_test! {
 query_completion_test_multiple! {
   query_completion_openai_batch: (Runtime::OpenAi, "gpt-4o-mini", r#"{"x": "working!"}"#, r#"{"x": "working2!"}"#, "working!", "working2!", 2, 100),
 }
+
+// Reads the whole HTTP request (headers and body) from the socket
+async fn read_http_request(socket: &mut TcpStream) -> String {
+    let mut buf = vec![0; 8192];
+    let mut request = Vec::new();
+    loop {
+        let n = socket.read(&mut buf).await.unwrap();
+        if n == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..n]);
+        let request_str = String::from_utf8_lossy(&request);
+        if let Some(header_end) = request_str.find("\r\n\r\n") {
+            let content_length = request_str[..header_end]
+                .lines()
+                .find_map(|line| {
+                    let (name, value) = line.split_once(':')?;
+                    if name.eq_ignore_ascii_case("content-length") {
+                        value.trim().parse::<usize>().ok()
+                    } else {
+                        None
+                    }
+                })
+                .unwrap_or(0);
+
+            if request.len() >= header_end + 4 + content_length {
+                break;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&request).to_string()
+}
+
+async fn write_http_response(socket: &mut TcpStream, body: &str) {
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    socket.write_all(response.as_bytes()).await.unwrap();
+}
+
+// Starts a minimal OpenAI compatible chat completion server
+// which will answer each request with the provided message
+async fn start_mock_llm_server(message: &'static str) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                read_http_request(&mut socket).await;
+
+                // Local LLM servers may not return usage information
+                let body = serde_json::json!({
+                    "choices": [{ "message": { "role": "assistant", "content": message } }]
+                })
+                .to_string();
+                write_http_response(&mut socket, &body).await;
+            });
+        }
+    });
+
+    format!("http://{addr}")
+}
+
+// Starts a minimal OpenAI Batch API server
+// The batch is completed on the first status check if `completed` is set, each request
+// is answered with "working {custom_id}" message except the last one which fails
+// Returns the server url, the number of created and the number of cancelled batches
+async fn start_mock_batch_server(
+    completed: Arc<AtomicBool>,
+) -> (String, Arc<Mutex<usize>>, Arc<Mutex<usize>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let custom_ids: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+    let batch_cnt = Arc::new(Mutex::new(0));
+    let batch_cnt_clone = batch_cnt.clone();
+    let cancel_cnt = Arc::new(Mutex::new(0));
+    let cancel_cnt_clone = cancel_cnt.clone();
+
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let custom_ids = custom_ids.clone();
+            let batch_cnt = batch_cnt_clone.clone();
+            let cancel_cnt = cancel_cnt_clone.clone();
+            let completed = completed.clone();
+            tokio::spawn(async move {
+                let request = read_http_request(&mut socket).await;
+                let request_line = request.lines().next().unwrap_or("").to_owned();
+
+                let body = if request_line.starts_with("POST /v1/files") {
+                    // Multipart body contains the JSONL file with one request per line
+                    let mut custom_ids = custom_ids.lock().unwrap();
+                    for line in request.lines().filter(|l| l.contains("\"custom_id\"")) {
+                        let line: serde_json::Value = serde_json::from_str(line).unwrap();
+                        assert_eq!(line["url"], "/v1/chat/completions");
+                        custom_ids.push(line["custom_id"].as_str().unwrap().to_owned());
+                    }
+                    serde_json::json!({ "id": "file-input" }).to_string()
+                } else if request_line.starts_with("POST /v1/batches/batch-1/cancel") {
+                    *cancel_cnt.lock().unwrap() += 1;
+                    serde_json::json!({ "id": "batch-1", "status": "cancelling" }).to_string()
+                } else if request_line.starts_with("POST /v1/batches") {
+                    *batch_cnt.lock().unwrap() += 1;
+                    serde_json::json!({ "id": "batch-1", "status": "validating" }).to_string()
+                } else if request_line.starts_with("GET /v1/batches/batch-1") {
+                    if completed.load(Ordering::SeqCst) {
+                        serde_json::json!({ "id": "batch-1", "status": "completed", "output_file_id": "file-output" }).to_string()
+                    } else {
+                        serde_json::json!({ "id": "batch-1", "status": "in_progress" }).to_string()
+                    }
+                } else if request_line.starts_with("GET /v1/files/file-output/content") {
+                    let custom_ids = custom_ids.lock().unwrap();
+                    // Results are returned in reverse order as batch output is not ordered
+                    custom_ids
+                        .iter()
+                        .rev()
+                        .enumerate()
+                        .map(|(idx, custom_id)| {
+                            if idx == 0 {
+                                serde_json::json!({
+                                    "custom_id": custom_id,
+                                    "response": { "status_code": 500, "body": { "error": { "message": "server error" } } }
+                                })
+                            } else {
+                                serde_json::json!({
+                                    "custom_id": custom_id,
+                                    "response": {
+                                        "status_code": 200,
+                                        "body": {
+                                            "choices": [{ "message": { "role": "assistant", "content": format!("working {custom_id}") } }],
+                                            "usage": { "total_tokens": 10 }
+                                        }
+                                    }
+                                })
+                            }
+                            .to_string()
+                        })
+                        .collect::<Vec<String>>()
+                        .join("\n")
+                } else {
+                    panic!("Unexpected request {request_line}");
+                };
+
+                write_http_response(&mut socket, &body).await;
+            });
+        }
+    });
+
+    (format!("http://{addr}"), batch_cnt, cancel_cnt)
+}
+
+#[tokio::test]
+async fn query_completion_openai_compatible_without_token() {
+    let base_url = start_mock_llm_server("working!").await;
+    let params = format!(r#"{{"base_url": "{base_url}", "system_prompt": "{LLM_SYSTEM_PROMPT}"}}"#);
+
+    let runtime = EmbeddingRuntime::new(&Runtime::OpenAi, None, &params).unwrap();
+    let output = runtime
+        .completion("my-local-model", r#"{"x": "working!"}"#)
+        .await
+        .unwrap();
+    assert_eq!(output.message, "working!");
+    assert_eq!(output.processed_tokens, 0);
+
+    let output = runtime
+        .batch_completion(
+            "my-local-model",
+            &vec![r#"{"x": "working!"}"#, r#"{"x": "working!"}"#],
+        )
+        .await
+        .unwrap();
+    assert_eq!(output.messages, vec!["working!", "working!"]);
+}
+
+#[tokio::test]
+async fn query_completion_openai_compatible_response_schema() {
+    let base_url = start_mock_llm_server("```json\n{ \"x\": \"working!\" }\n```").await;
+    let params = format!(
+        r#"{{"base_url": "{base_url}", "system_prompt": "{LLM_SYSTEM_PROMPT}", "response_schema": {{"type": "object", "properties": {{"x": {{"type": "string"}}}}, "required": ["x"]}}}}"#
+    );
+
+    let runtime = EmbeddingRuntime::new(&Runtime::OpenAi, None, &params).unwrap();
+    let output = runtime
+        .completion("my-local-model", r#"{"x": "working!"}"#)
+        .await
+        .unwrap();
+    assert_eq!(output.message, r#"{"x":"working!"}"#);
+
+    let params = format!(r#"{{"base_url": "{base_url}", "response_schema": {{"type": 1}}}}"#);
+    assert!(EmbeddingRuntime::new(&Runtime::OpenAi, None, &params).is_err());
+}
+
+#[tokio::test]
+async fn query_completion_openai_batch_api() {
+    let (base_url, _, _) = start_mock_batch_server(Arc::new(AtomicBool::new(true))).await;
+    let params = format!(
+        r#"{{"base_url": "{base_url}", "system_prompt": "{LLM_SYSTEM_PROMPT}", "batch_api": true, "batch_poll_interval": 0}}"#
+    );
+
+    let runtime = EmbeddingRuntime::new(&Runtime::OpenAi, None, &params).unwrap();
+    let output = runtime
+        .batch_completion(
+            "my-local-model",
+            &vec![
+                r#"{"x": "working!"}"#,
+                r#"{"x": "working!"}"#,
+                r#"{"x": "working!"}"#,
+            ],
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(output.messages.len(), 3);
+    assert_eq!(output.messages[0], "working 0");
+    assert_eq!(output.messages[1], "working 1");
+    assert!(output.messages[2].starts_with("Error: "));
+    assert_eq!(output.processed_tokens, 20);
+}
+
+#[tokio::test]
+async fn query_completion_openai_batch_api_job() {
+    let (base_url, batch_cnt, _) = start_mock_batch_server(Arc::new(AtomicBool::new(true))).await;
+    let input_file = "/tmp/lantern-completion-batch-api-test-input.csv";
+    let output_file = "/tmp/lantern-completion-batch-api-test-output.jsonl";
+    let mut input = String::from("id,content\n");
+    for i in 1..=5 {
+        input.push_str(&format!("{i},\"{{\"\"x\"\": \"\"working!\"\"}}\"\n"));
+    }
+    std::fs::write(input_file, input).unwrap();
+
+    let (processed_rows, processed_tokens, _) = embeddings::create_embeddings_from_db(
+        cli::EmbeddingArgs {
+            model: "my-local-model".to_owned(),
+            uri: "".to_owned(),
+            pk: "id".to_owned(),
+            column: "content".to_owned(),
+            table: "".to_owned(),
+            schema: "public".to_owned(),
+            out_uri: None,
+            out_column: "out".to_owned(),
+            batch_size: Some(2),
+            visual: false,
+            out_table: None,
+            limit: None,
+            filter: None,
+            runtime: Runtime::OpenAi,
+            runtime_params: format!(
+                r#"{{"base_url": "{base_url}", "batch_api": true, "batch_poll_interval": 0}}"#
+            ),
+            create_column: false,
+            stream: false,
+            job_type: Some(cli::EmbeddingJobType::Completion),
+            column_type: None,
+            out_json_columns: None,
+            check_column_type: false,
+            create_cast_fn: false,
+            internal_schema: "public".to_owned(),
+            failed_rows_table: None,
+            batch_state_table: None,
+            job_id: 0,
+            cache: false,
+            cache_table: "embedding_cache".to_owned(),
+            cache_max_age: None,
+            cache_max_entries: None,
+            input_type: None,
+            input_file: Some(input_file.to_owned()),
+            input_format: None,
+            output_file: Some(output_file.to_owned()),
+            output_format: None,
+        },
+        false,
+        None,
+        CancellationToken::new(),
+        None,
+    )
+    .await
+    .unwrap();
+
+    let output = std::fs::read_to_string(output_file).unwrap();
+    let lines: Vec<serde_json::Value> = output
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+
+    std::fs::remove_file(input_file).unwrap();
+    std::fs::remove_file(output_file).unwrap();
+
+    // All rows of the job are submitted in a single batch
+    assert_eq!(*batch_cnt.lock().unwrap(), 1);
+    assert_eq!(processed_rows, 5);
+    assert_eq!(processed_tokens, 40);
+    assert_eq!(lines.len(), 5);
+    for line in &lines {
+        let message = line["out"].as_str().unwrap();
+        if line["id"] == serde_json::json!("5") {
+            assert!(message.starts_with("Error: "));
+        } else {
+            assert!(message.starts_with("working "));
+        }
+    }
+}
+
+#[tokio::test]
+async fn query_completion_openai_batch_api_max_wait() {
+    let (base_url, _, cancel_cnt) = start_mock_batch_server(Arc::new(AtomicBool::new(false))).await;
+    let params = format!(
+        r#"{{"base_url": "{base_url}", "batch_api": true, "batch_poll_interval": 0, "batch_max_wait": 0}}"#
+    );
+
+    let runtime = EmbeddingRuntime::new(&Runtime::OpenAi, None, &params).unwrap();
+    let result = runtime
+        .batch_completion("my-local-model", &vec![r#"{"x": "working!"}"#])
+        .await;
+
+    assert!(result
+        .err()
+        .unwrap()
+        .to_string()
+        .contains("Batch batch-1 did not finish"));
+    assert_eq!(*cancel_cnt.lock().unwrap(), 1);
+}
+
+#[tokio::test]
+async fn query_completion_openai_batch_api_cancel() {
+    let (base_url, _, cancel_cnt) = start_mock_batch_server(Arc::new(AtomicBool::new(false))).await;
+    let params =
+        format!(r#"{{"base_url": "{base_url}", "batch_api": true, "batch_poll_interval": 0}}"#);
+
+    let cancel_token = CancellationToken::new();
+    let mut runtime = EmbeddingRuntime::new(&Runtime::OpenAi, None, &params).unwrap();
+    runtime.set_batch_context(cancel_token.clone(), None);
+
+    let cancel_token_clone = cancel_token.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        cancel_token_clone.cancel();
+    });
+
+    let result = runtime
+        .batch_completion("my-local-model", &vec![r#"{"x": "working!"}"#])
+        .await;
+
+    assert_eq!(result.err().unwrap().to_string(), "Job cancelled");
+    assert_eq!(*cancel_cnt.lock().unwrap(), 1);
+}
+
+#[tokio::test]
+async fn query_completion_openai_batch_api_resume() {
+    let db_url = env::var("DB_URL").unwrap_or("".to_owned());
+    if db_url == "" {
+        return;
+    }
+
+    let table_name = "_lantern_batch_api_resume_test_jobs";
+    let (db_client, connection) = tokio_postgres::connect(&db_url, tokio_postgres::NoTls)
+        .await
+        .unwrap();
+    tokio::spawn(async move { connection.await.unwrap() });
+    db_client
+        .batch_execute(&format!(
+            "
+            DROP TABLE IF EXISTS {table_name};
+            CREATE TABLE {table_name} (id INT PRIMARY KEY, canceled_at timestamp, batch_ids jsonb);
+            INSERT INTO {table_name} (id) VALUES (1);
+            "
+        ))
+        .await
+        .unwrap();
+
+    let completed = Arc::new(AtomicBool::new(false));
+    let (base_url, batch_cnt, _) = start_mock_batch_server(completed.clone()).await;
+    let params =
+        format!(r#"{{"base_url": "{base_url}", "batch_api": true, "batch_poll_interval": 0}}"#);
+    let state = BatchState {
+        uri: db_url.clone(),
+        table: table_name.to_owned(),
+        job_id: 1,
+    };
+    let queries = vec![r#"{"x": "working!"}"#, r#"{"x": "working!"}"#];
+
+    // The process is stopped while the batch is in progress (e.g. daemon restart)
+    let mut runtime = EmbeddingRuntime::new(&Runtime::OpenAi, None, &params).unwrap();
+    runtime.set_batch_context(CancellationToken::new(), Some(state.clone()));
+    let result = tokio::time::timeout(
+        Duration::from_millis(500),
+        runtime.batch_completion("my-local-model", &queries),
+    )
+    .await;
+    assert!(result.is_err());
+
+    let stored_cnt = db_client
+        .query_one(
+            &format!("SELECT COUNT(*) FROM {table_name}, jsonb_each_text(batch_ids) WHERE value = 'batch-1'"),
+            &[],
+        )
+        .await
+        .unwrap()
+        .get::<usize, i64>(0);
+
+    completed.store(true, Ordering::SeqCst);
+    let mut runtime = EmbeddingRuntime::new(&Runtime::OpenAi, None, &params).unwrap();
+    runtime.set_batch_context(CancellationToken::new(), Some(state));
+    let output = runtime
+        .batch_completion("my-local-model", &queries)
+        .await
+        .unwrap();
+
+    let remaining_cnt = db_client
+        .query_one(
+            &format!("SELECT COUNT(*) FROM {table_name}, jsonb_each_text(batch_ids)"),
+            &[],
+        )
+        .await
+        .unwrap()
+        .get::<usize, i64>(0);
+
+    db_client
+        .batch_execute(&format!("DROP TABLE {table_name}"))
+        .await
+        .unwrap();
+
+    assert_eq!(stored_cnt, 1);
+    // The stored batch is resumed instead of submitting a new one
+    assert_eq!(*batch_cnt.lock().unwrap(), 1);
+    assert_eq!(output.messages[0], "working 0");
+    assert_eq!(remaining_cnt, 0);
+}
+
+// Starts a minimal Azure OpenAI server with Entra token endpoint
+// Returns the server url and the received requests as "{request line} {authorization header}"
+async fn start_mock_azure_server() -> (String, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let requests: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+    let requests_clone = requests.clone();
+
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let requests = requests_clone.clone();
+            tokio::spawn(async move {
+                let request = read_http_request(&mut socket).await;
+                let request_line = request.lines().next().unwrap_or("").to_owned();
+                let authorization = request
+                    .lines()
+                    .find_map(|line| {
+                        let (name, value) = line.split_once(':')?;
+                        if name.eq_ignore_ascii_case("authorization") {
+                            Some(value.trim().to_owned())
+                        } else {
+                            None
+                        }
+                    })
+                    .unwrap_or_default();
+                requests
+                    .lock()
+                    .unwrap()
+                    .push(format!("{request_line} {authorization}"));
+
+                let body = if request_line.starts_with("POST /test-tenant/oauth2/v2.0/token") {
+                    assert!(request.contains("grant_type=client_credentials"));
+                    serde_json::json!({ "access_token": "entra-token", "expires_in": 3600 })
+                        .to_string()
+                } else if request_line.contains("/chat/completions") {
+                    serde_json::json!({
+                        "choices": [{ "message": { "role": "assistant", "content": "working!" } }],
+                        "usage": { "total_tokens": 10 }
+                    })
+                    .to_string()
+                } else if request_line.contains("/embeddings") {
+                    serde_json::json!({
+                        "data": [{ "embedding": [0.1, 0.2, 0.3] }],
+                        "usage": { "total_tokens": 3 }
+                    })
+                    .to_string()
+                } else {
+                    panic!("Unexpected request {request_line}");
+                };
+
+                write_http_response(&mut socket, &body).await;
+            });
+        }
+    });
+
+    (format!("http://{addr}"), requests)
+}
+
+#[tokio::test]
+async fn query_completion_azure_openai_with_entra_credentials() {
+    let (base_url, requests) = start_mock_azure_server().await;
+    let params = format!(
+        r#"{{"base_url": "{base_url}", "system_prompt": "{LLM_SYSTEM_PROMPT}", "azure": {{"api_version": "2024-10-21", "deployments": {{"my-chat": "gpt-4o", "my-embeddings": "text-embedding-3-small"}}, "tenant_id": "test-tenant", "client_id": "test-client", "client_secret": "test-secret", "authority_host": "{base_url}"}}}}"#
+    );
+
+    let runtime = EmbeddingRuntime::new(&Runtime::OpenAi, None, &params).unwrap();
+    let output = runtime
+        .batch_completion(
+            "my-chat",
+            &vec![r#"{"x": "working!"}"#, r#"{"x": "working!"}"#],
+        )
+        .await
+        .unwrap();
+    assert_eq!(output.messages, vec!["working!", "working!"]);
+    assert_eq!(output.processed_tokens, 20);
+
+    let output = runtime
+        .process("my-embeddings", &vec!["Hello world!"])
+        .await
+        .unwrap();
+    assert_eq!(output.embeddings, vec![vec![0.1, 0.2, 0.3]]);
+
+    // Token limits are taken from the mapped model
+    let token_counts = runtime
+        .count_tokens("my-embeddings", &vec!["Hello world!"])
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(token_counts.max_tokens, 8190);
+
+    // Unknown deployments should fail as the token limits are not known
+    assert!(runtime
+        .batch_completion("unknown", &vec!["test"])
+        .await
+        .is_err());
+
+    let requests = requests.lock().unwrap();
+    // Token is requested once and reused for the following requests
+    assert_eq!(
+        requests
+            .iter()
+            .filter(|r| r.contains("/oauth2/v2.0/token"))
+            .count(),
+        1
+    );
+    assert_eq!(
+        requests
+            .iter()
+            .filter(|r| r.starts_with(
+                "POST /openai/deployments/my-chat/chat/completions?api-version=2024-10-21 HTTP/1.1 Bearer entra-token"
+            ))
+            .count(),
+        2
+    );
+    assert!(requests.iter().any(|r| r.starts_with(
+        "POST /openai/deployments/my-embeddings/embeddings?api-version=2024-10-21 HTTP/1.1 Bearer entra-token"
+    )));
+}
+
+#[tokio::test]
+async fn query_completion_custom_url_without_token() {
+    // Only local servers may be used without api_token
+    for base_url in ["https://api.openai.com", "https://my-llm.example.com"] {
+        let params = format!(r#"{{"base_url": "{base_url}"}}"#);
+        assert!(EmbeddingRuntime::new(&Runtime::OpenAi, None, &params).is_err());
+    }
+
+    for base_url in [
+        "http://localhost:11434",
+        "http://127.0.0.1:8000",
+        "http://10.0.0.5:8000",
+    ] {
+        let params = format!(r#"{{"base_url": "{base_url}"}}"#);
+        assert!(EmbeddingRuntime::new(&Runtime::OpenAi, None, &params).is_ok());
+    }
+}
+
+#[tokio::test]
+async fn query_completion_azure_openai_url() {
+    // Requires api_token or Entra token
+    let params = r#"{"base_url": "https://my-resource.openai.azure.com/openai/deployments/gpt-4o/chat/completions?api-version=2024-10-21"}"#;
+    assert!(EmbeddingRuntime::new(&Runtime::OpenAi, None, params).is_err());
+
+    // Batch API is not supported for Azure
+    let params = r#"{"base_url": "https://my-resource.openai.azure.com", "api_token": "xxx", "batch_api": true}"#;
+    assert!(EmbeddingRuntime::new(&Runtime::OpenAi, None, params).is_err());
+
+    // Client credentials should be complete
+    let params = r#"{"base_url": "https://my-resource.openai.azure.com", "azure": {"tenant_id": "test-tenant"}}"#;
+    assert!(EmbeddingRuntime::new(&Runtime::OpenAi, None, params).is_err());
+
+    let params = r#"{"base_url": "https://my-resource.openai.azure.com/openai/deployments/gpt-4o/chat/completions?api-version=2024-10-21", "api_token": "xxx"}"#;
+    assert!(EmbeddingRuntime::new(&Runtime::OpenAi, None, params).is_ok());
+}
@@ -156,3 +156,300 @@ text_embedding_test_multiple! {
   generate_bge_m3_embeddings_large_text_batch_multiple: ("BAAI/bge-m3", PATIENT_EMB_TEXT, WEATHER_TEXT, PATIENT_BGE_M3_EMB.to_vec(), BGE_M3_WEATHER_EMB.to_vec(), 10, 1430, 0.01),
   generate_naver_spladev3_embeddings_large_text_batch_multiple: ("naver/splade-v3", PATIENT_EMB_TEXT, WEATHER_TEXT, create_sparse_vector(NAVER_SPLADE_PATIENT_SPARSE_EMB, 30522), create_sparse_vector(NAVER_SPLADE_WEATHER_SPARSE_EMB, 30522), 10, 1380, 0.03),
 }
+
+#[tokio::test]
+async fn generate_bge_embeddings_with_post_processing() {
+    let runtime = EmbeddingRuntime::new(
+        &Runtime::Ort,
+        None,
+        r#"{"data_path": "/tmp/lantern-embeddings-core-test", "truncate_dimensions": 128, "normalize": true}"#,
+    )
+    .unwrap();
+    let output = runtime
+        .process("BAAI/bge-small-en", &vec![HELLO_WORLD_TEXT])
+        .await
+        .unwrap();
+
+    let embedding = &output.embeddings[0];
+    let norm = embedding.iter().map(|v| v * v).sum::<f32>().sqrt();
+    assert_eq!(embedding.len(), 128);
+    assert!((norm - 1.0).abs() < 0.001);
+    let distance = 1.0 - cosine_similarity(embedding, &HELLO_WORLD_BGE_SMALL_EMB[..128]);
+    assert!(distance < 0.01);
+
+    let runtime = EmbeddingRuntime::new(
+        &Runtime::Ort,
+        None,
+        r#"{"data_path": "/tmp/lantern-embeddings-core-test", "quantization": "int8"}"#,
+    )
+    .unwrap();
+    let output = runtime
+        .process("BAAI/bge-small-en", &vec![HELLO_WORLD_TEXT])
+        .await
+        .unwrap();
+
+    let embedding = &output.embeddings[0];
+    assert_eq!(embedding.len(), 384);
+    assert!(embedding
+        .iter()
+        .all(|v| v.fract() == 0.0 && *v >= -127.0 && *v <= 127.0));
+    assert!(embedding.iter().any(|v| v.abs() == 127.0));
+
+    let runtime = EmbeddingRuntime::new(
+        &Runtime::Ort,
+        None,
+        r#"{"data_path": "/tmp/lantern-embeddings-core-test", "quantization": "binary"}"#,
+    )
+    .unwrap();
+    let output = runtime
+        .process("BAAI/bge-small-en", &vec![HELLO_WORLD_TEXT])
+        .await
+        .unwrap();
+
+    let expected: Vec<f32> = HELLO_WORLD_BGE_SMALL_EMB
+        .chunks(8)
+        .map(|chunk| {
+            chunk
+                .iter()
+                .enumerate()
+                .filter(|(_, v)| **v > 0.0)
+                .fold(0_u8, |byte, (idx, _)| byte | (1 << (7 - idx))) as f32
+        })
+        .collect();
+    let embedding = &output.embeddings[0];
+    assert_eq!(embedding.len(), 48);
+    // Values close to 0 may flip, so allow a few differences
+    let mismatches = embedding
+        .iter()
+        .zip(expected.iter())
+        .filter(|(a, b)| a != b)
+        .count();
+    assert!(mismatches <= 4);
+}
+
+#[tokio::test]
+async fn generate_e5_embeddings_with_input_type() {
+    let plain_runtime = EmbeddingRuntime::new(
+        &Runtime::Ort,
+        None,
+        r#"{"data_path": "/tmp/lantern-embeddings-core-test"}"#,
+    )
+    .unwrap();
+    let query_runtime = EmbeddingRuntime::new(
+        &Runtime::Ort,
+        None,
+        r#"{"data_path": "/tmp/lantern-embeddings-core-test", "input_type": "query"}"#,
+    )
+    .unwrap();
+    let document_runtime = EmbeddingRuntime::new(
+        &Runtime::Ort,
+        None,
+        r#"{"data_path": "/tmp/lantern-embeddings-core-test", "input_type": "document"}"#,
+    )
+    .unwrap();
+
+    let query_text = format!("query: {WEATHER_TEXT}");
+    let document_text = format!("passage: {WEATHER_TEXT}");
+    let expected = plain_runtime
+        .process(
+            "intfloat/e5-base-v2",
+            &vec![query_text.as_str(), document_text.as_str()],
+        )
+        .await
+        .unwrap();
+    let query_output = query_runtime
+        .process("intfloat/e5-base-v2", &vec![WEATHER_TEXT])
+        .await
+        .unwrap();
+    let document_output = document_runtime
+        .process("intfloat/e5-base-v2", &vec![WEATHER_TEXT])
+        .await
+        .unwrap();
+
+    assert!(1.0 - cosine_similarity(&query_output.embeddings[0], &expected.embeddings[0]) < 0.001);
+    assert!(
+        1.0 - cosine_similarity(&document_output.embeddings[0], &expected.embeddings[1]) < 0.001
+    );
+    assert!(
+        1.0 - cosine_similarity(&query_output.embeddings[0], &document_output.embeddings[0])
+            > 0.001
+    );
+}
+
+#[tokio::test]
+async fn generate_bge_embeddings_with_truncation_policy() {
+    let long_text = "Hello world! ".repeat(300);
+    let inputs = vec![HELLO_WORLD_TEXT, long_text.as_str()];
+    let get_params = |policy: &str| {
+        format!(
+            r#"{{"data_path": "/tmp/lantern-embeddings-core-test", "truncation_policy": "{policy}"}}"#
+        )
+    };
+
+    let params = get_params("truncate");
+    let runtime = EmbeddingRuntime::new(&Runtime::Ort, None, &params).unwrap();
+    let token_counts = runtime
+        .count_tokens("BAAI/bge-small-en", &inputs)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(token_counts.max_tokens, 512);
+    assert_eq!(token_counts.counts[0], 5);
+    assert!(token_counts.counts[1] > 512);
+
+    let output = runtime.process("BAAI/bge-small-en", &inputs).await.unwrap();
+    assert_eq!(output.embeddings.len(), 2);
+    assert_eq!(output.truncated_inputs.len(), 1);
+    assert_eq!(output.truncated_inputs[0].0, 1);
+    assert!(output.failed_inputs.is_empty());
+
+    let params = get_params("skip");
+    let runtime = EmbeddingRuntime::new(&Runtime::Ort, None, &params).unwrap();
+    let output = runtime.process("BAAI/bge-small-en", &inputs).await.unwrap();
+    assert_eq!(output.embeddings[0].len(), 384);
+    assert!(output.embeddings[1].is_empty());
+    assert_eq!(output.failed_inputs.len(), 1);
+    assert_eq!(output.failed_inputs[0].0, 1);
+
+    let params = get_params("fail");
+    let runtime = EmbeddingRuntime::new(&Runtime::Ort, None, &params).unwrap();
+    assert!(runtime.process("BAAI/bge-small-en", &inputs).await.is_err());
+
+    let params = get_params("chunk_average");
+    let runtime = EmbeddingRuntime::new(&Runtime::Ort, None, &params).unwrap();
+    let output = runtime.process("BAAI/bge-small-en", &inputs).await.unwrap();
+    assert_eq!(output.embeddings.len(), 2);
+    assert_eq!(output.embeddings[1].len(), 384);
+    assert!(output.truncated_inputs.is_empty());
+    assert!(output.failed_inputs.is_empty());
+    let distance = 1.0 - cosine_similarity(&output.embeddings[0], &HELLO_WORLD_BGE_SMALL_EMB);
+    assert!(distance < 0.01);
+
+    // Cohere runtime can not count the tokens, so only truncate policy is accepted
+    let cohere_params =
+        |policy: &str| format!(r#"{{"api_token": "test", "truncation_policy": "{policy}"}}"#);
+    assert!(EmbeddingRuntime::new(&Runtime::Cohere, None, &cohere_params("truncate")).is_ok());
+    for policy in ["skip", "fail", "chunk_average"] {
+        assert!(EmbeddingRuntime::new(&Runtime::Cohere, None, &cohere_params(policy)).is_err());
+    }
+}
+
+#[tokio::test]
+async fn count_tokens_without_downloading_model() {
+    let data_path = "/tmp/lantern-embeddings-tokenizer-test";
+    let _ = std::fs::remove_dir_all(data_path);
+    let params = format!(r#"{{"data_path": "{data_path}"}}"#);
+    let runtime = EmbeddingRuntime::new(&Runtime::Ort, None, &params).unwrap();
+    let token_counts = runtime
+        .count_tokens("thenlper/gte-base", &vec![HELLO_WORLD_TEXT])
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(token_counts.counts[0], 5);
+    assert!(
+        std::path::Path::new(&format!("{data_path}/thenlper/gte-base/tokenizer.json")).exists()
+    );
+    assert!(!std::path::Path::new(&format!("{data_path}/thenlper/gte-base/model.onnx")).exists());
+}
+
+#[tokio::test]
+async fn generate_jina_embeddings_with_length_bucketing() {
+    let inputs: Vec<&str> = (0..10)
+        .map(|i| match i % 3 {
+            0 => PATIENT_EMB_TEXT,
+            1 => WEATHER_TEXT,
+            _ => HELLO_WORLD_TEXT,
+        })
+        .collect();
+
+    // Small token budget to split the inputs into multiple model runs
+    let bucketed_runtime = EmbeddingRuntime::new(
+        &Runtime::Ort,
+        None,
+        r#"{"data_path": "/tmp/lantern-embeddings-core-test", "max_batch_tokens": 300}"#,
+    )
+    .unwrap();
+    let padded_runtime = EmbeddingRuntime::new(
+        &Runtime::Ort,
+        None,
+        r#"{"data_path": "/tmp/lantern-embeddings-core-test", "length_bucketing": false}"#,
+    )
+    .unwrap();
+
+    let bucketed_output = bucketed_runtime
+        .process("jinaai/jina-embeddings-v2-base-en", &inputs)
+        .await
+        .unwrap();
+    let padded_output = padded_runtime
+        .process("jinaai/jina-embeddings-v2-base-en", &inputs)
+        .await
+        .unwrap();
+
+    assert_eq!(bucketed_output.embeddings.len(), inputs.len());
+    assert_eq!(
+        bucketed_output.processed_tokens,
+        padded_output.processed_tokens
+    );
+    for (idx, input) in inputs.iter().enumerate() {
+        let distance = 1.0
+            - cosine_similarity(
+                &bucketed_output.embeddings[idx],
+                &padded_output.embeddings[idx],
+            );
+        assert!(distance < 0.001);
+
+        if *input == PATIENT_EMB_TEXT {
+            let distance =
+                1.0 - cosine_similarity(&bucketed_output.embeddings[idx], &PATIENT_JINA_BASE_EMB);
+            assert!(distance < 0.01);
+        }
+    }
+}
+
+#[tokio::test]
+async fn generate_bge_embeddings_with_session_options() {
+    let runtime = EmbeddingRuntime::new(
+        &Runtime::Ort,
+        None,
+        r#"{"data_path": "/tmp/lantern-embeddings-core-test", "cache": true, "execution_providers": ["cpu"], "intra_threads": 1, "inter_threads": 1, "optimization_level": "level1"}"#,
+    )
+    .unwrap();
+    let output = runtime
+        .process("BAAI/bge-small-en", &vec![HELLO_WORLD_TEXT])
+        .await
+        .unwrap();
+    let distance = 1.0 - cosine_similarity(&output.embeddings[0], &HELLO_WORLD_BGE_SMALL_EMB);
+    assert!(distance < 0.01);
+
+    let result = EmbeddingRuntime::new(
+        &Runtime::Ort,
+        None,
+        r#"{"data_path": "/tmp/lantern-embeddings-core-test", "execution_providers": ["tpu"]}"#,
+    );
+    assert!(result.is_err());
+
+    // Quantized variants are not available for models with external onnx data
+    let runtime = EmbeddingRuntime::new(
+        &Runtime::Ort,
+        None,
+        r#"{"data_path": "/tmp/lantern-embeddings-core-test", "model_variant": "int8"}"#,
+    )
+    .unwrap();
+    let err = runtime
+        .process("BAAI/bge-m3", &vec![HELLO_WORLD_TEXT])
+        .await
+        .unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("Model variant \"int8\" is not available for model BAAI/bge-m3"));
+
+    // Only fp32 weights are exported for CLIP models
+    let err = runtime
+        .process("clip/ViT-B-32-textual", &vec![HELLO_WORLD_TEXT])
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains(
+        "Model variant \"int8\" is not available for model clip/ViT-B-32-textual.\nAvailable variants: fp32"
+    ));
+}
@@ -1,8 +1,13 @@
-use std::time::Duration;
+use std::{str::FromStr, time::Duration};
 
 use lantern_cli::{
     daemon::{cli::DaemonArgs, start},
-    embeddings::core::ort_runtime::DATA_PATH,
+    embeddings::core::{
+        ort_runtime::{OrtRuntimeParams, DATA_PATH},
+        post_process::PostProcessParams,
+        runtime::InputType,
+        truncation::TruncationPolicy,
+    },
     logger::{LogLevel, Logger},
     types::AnyhowVoidResult,
     utils::{get_full_table_name, quote_ident},
@@ -12,7 +17,8 @@ use tokio_util::sync::CancellationToken;
 
 use crate::{
     embeddings::{get_cohere_runtime_params, get_openai_runtime_params},
-    DAEMON_DATABASES, ENABLE_DAEMON,
+    DAEMON_DATABASES, EMBEDDING_CACHE_MAX_AGE, EMBEDDING_CACHE_MAX_ENTRIES, ENABLE_DAEMON,
+    ENABLE_EMBEDDING_CACHE,
 };
 
 pub fn start_daemon(embeddings: bool, indexing: bool, autotune: bool) -> Result<(), anyhow::Error> {
@@ -90,6 +96,15 @@ pub fn start_daemon(embeddings: bool, indexing: bool, autotune: bool) -> Result<
                 target_db: Some(target_dbs.clone()),
                 data_path: Some(DATA_PATH.to_owned()),
                 inside_postgres: true,
+                embedding_cache: ENABLE_EMBEDDING_CACHE.get(),
+                embedding_cache_max_age: match EMBEDDING_CACHE_MAX_AGE.get() {
+                    0 => None,
+                    max_age => Some(max_age as u64),
+                },
+                embedding_cache_max_entries: match EMBEDDING_CACHE_MAX_ENTRIES.get() {
+                    0 => None,
+                    max_entries => Some(max_entries as u64),
+                },
             },
             Some(logger.clone()),
             cancellation_token.clone(),
@@ -118,6 +133,21 @@ pub fn start_daemon(embeddings: bool, indexing: bool, autotune: bool) -> Result<
     Ok(())
 }
 
+// Enables OpenAI Batch API for the initial backfill of the job
+fn set_batch_api(params: String, runtime: &str, batch_api: bool) -> Result<String, anyhow::Error> {
+    if !batch_api {
+        return Ok(params);
+    }
+
+    if runtime != "openai" {
+        anyhow::bail!("batch_api is supported only for openai runtime");
+    }
+
+    let mut params: serde_json::Value = serde_json::from_str(&params)?;
+    params["batch_api"] = serde_json::json!(true);
+    Ok(params.to_string())
+}
+
 #[pg_extern(immutable, parallel_unsafe, security_definer)]
 fn add_embedding_job<'a>(
     table_name: &'a str,
@@ -132,14 +162,61 @@ fn add_embedding_job<'a>(
     api_token: default!(&'a str, "''"),
     azure_entra_token: default!(&'a str, "''"),
     runtime: default!(&'a str, "'openai'"),
+    ort_params: default!(&'a str, "'{}'"),
+    input_type: default!(&'a str, "''"),
+    batch_api: default!(bool, false),
+    truncation_policy: default!(&'a str, "'truncate'"),
+    azure_params: default!(&'a str, "''"),
 ) -> Result<i32, anyhow::Error> {
+    // Input type is only used by ort (model instruction templates) and cohere runtimes
+    let input_type = if input_type != "" {
+        Some(InputType::from_str(input_type)?.to_string())
+    } else {
+        None
+    };
+    let truncation_policy = TruncationPolicy::from_str(truncation_policy)?;
+    if runtime == "cohere" {
+        truncation_policy.require_tokenizer("cohere runtime")?;
+    }
     let params = match runtime {
-        "openai" => {
-            get_openai_runtime_params(api_token, azure_entra_token, base_url, "", dimensions)?
+        "openai" => get_openai_runtime_params(
+            api_token,
+            azure_entra_token,
+            base_url,
+            "",
+            "",
+            dimensions,
+            azure_params,
+        )?,
+        "cohere" => {
+            get_cohere_runtime_params(api_token, input_type.as_deref().unwrap_or(""), "", "")?
+        }
+        _ => {
+            let mut params: serde_json::Value = serde_json::from_str(ort_params)
+                .map_err(|e| anyhow::anyhow!("Invalid ort_params: {e}"))?;
+            if !params.is_object() {
+                anyhow::bail!("Invalid ort_params: expected JSON object");
+            }
+            if let Some(input_type) = input_type {
+                params["input_type"] = serde_json::json!(input_type);
+            }
+            // Validate session options (execution providers, threads, etc.) early
+            serde_json::from_value::<OrtRuntimeParams>(params.clone())
+                .map_err(|e| anyhow::anyhow!("Invalid ort_params: {e}"))?;
+            params.to_string()
         }
-        "cohere" => get_cohere_runtime_params(api_token, "search_document")?,
-        _ => "{}".to_owned(),
     };
+    let params = set_batch_api(params, runtime, batch_api)?;
+    let params = if truncation_policy != TruncationPolicy::default() {
+        let mut params: serde_json::Value = serde_json::from_str(&params)?;
+        params["truncation_policy"] = serde_json::json!(truncation_policy);
+        params.to_string()
+    } else {
+        params
+    };
+
+    // Quantized embeddings are stored as SMALLINT[] or BYTEA instead of REAL[]
+    let column_type = PostProcessParams::from_params(&params)?.output_column_type();
 
     let batch_size = if batch_size == -1 {
         "NULL".to_string()
@@ -149,9 +226,9 @@ fn add_embedding_job<'a>(
     let id: Option<i32> = Spi::get_one_with_args(
         &format!(
             r#"
-          ALTER TABLE {table} ADD COLUMN IF NOT EXISTS {dst_column} REAL[];
-          INSERT INTO _lantern_extras_internal.embedding_generation_jobs ("table", "schema", pk, src_column, dst_column, embedding_model, runtime, runtime_params, batch_size) VALUES
-          ($1, $2, $3, $4, $5, $6, $7, $8::jsonb, {batch_size}) RETURNING id;
+          ALTER TABLE {table} ADD COLUMN IF NOT EXISTS {dst_column} {column_type};
+          INSERT INTO _lantern_extras_internal.embedding_generation_jobs ("table", "schema", pk, src_column, dst_column, embedding_model, runtime, runtime_params, batch_size, column_type) VALUES
+          ($1, $2, $3, $4, $5, $6, $7, $8::jsonb, {batch_size}, '{column_type}') RETURNING id;
         "#,
             table = get_full_table_name(schema, table_name),
             dst_column = quote_ident(dst_column)
@@ -186,13 +263,52 @@ fn add_completion_job<'a>(
     api_token: default!(&'a str, "''"),
     azure_entra_token: default!(&'a str, "''"),
     runtime: default!(&'a str, "'openai'"),
+    response_schema: default!(&'a str, "''"),
+    json_columns: default!(&'a str, "''"),
+    batch_api: default!(bool, false),
+    azure_params: default!(&'a str, "''"),
 ) -> Result<i32, anyhow::Error> {
     let params = match runtime {
-        "openai" => {
-            get_openai_runtime_params(api_token, azure_entra_token, base_url, system_prompt, 0)?
-        }
+        "openai" => get_openai_runtime_params(
+            api_token,
+            azure_entra_token,
+            base_url,
+            system_prompt,
+            response_schema,
+            0,
+            azure_params,
+        )?,
+        "cohere" => get_cohere_runtime_params(api_token, "", system_prompt, response_schema)?,
         _ => anyhow::bail!("Runtime {runtime} does not support completion jobs"),
     };
+    let params = set_batch_api(params, runtime, batch_api)?;
+
+    // json_columns maps the response properties to the destination columns
+    // e.g '{"summary": "TEXT", "score": "INT"}'
+    let mut json_columns_sql = String::new();
+    let json_columns = if json_columns.trim() == "" {
+        None
+    } else {
+        let columns: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_str(json_columns)
+                .map_err(|e| anyhow::anyhow!("Invalid json_columns: {e}"))?;
+
+        for (name, json_column_type) in &columns {
+            let json_column_type = match json_column_type.as_str() {
+                Some(json_column_type) => json_column_type,
+                None => anyhow::bail!(
+                    "Invalid json_columns: type of column \"{name}\" should be a string"
+                ),
+            };
+            json_columns_sql.push_str(&format!(
+                "ALTER TABLE {table} ADD COLUMN IF NOT EXISTS {name} {json_column_type};",
+                table = get_full_table_name(schema, table_name),
+                name = quote_ident(name)
+            ));
+        }
+
+        Some(serde_json::to_string(&columns)?)
+    };
 
     let batch_size = if batch_size == -1 {
         "NULL".to_string()
@@ -204,8 +320,9 @@ fn add_completion_job<'a>(
         &format!(
             r#"
           ALTER TABLE {table} ADD COLUMN IF NOT EXISTS {dst_column} {column_type};
-          INSERT INTO _lantern_extras_internal.embedding_generation_jobs ("table", "schema", pk, src_column, dst_column, embedding_model, runtime, runtime_params, column_type, batch_size, job_type) VALUES
-          ($1, $2, $3, $4, $5, $6, $7, $8::jsonb, $9, {batch_size}, 'completion') RETURNING id;
+          {json_columns_sql}
+          INSERT INTO _lantern_extras_internal.embedding_generation_jobs ("table", "schema", pk, src_column, dst_column, dst_json_columns, embedding_model, runtime, runtime_params, column_type, batch_size, job_type) VALUES
+          ($1, $2, $3, $4, $5, $10::jsonb, $6, $7, $8::jsonb, $9, {batch_size}, 'completion') RETURNING id;
         "#,
             table = get_full_table_name(schema, table_name),
             dst_column = quote_ident(dst_column)
@@ -220,6 +337,7 @@ fn add_completion_job<'a>(
             (PgBuiltInOids::TEXTOID.oid(), runtime.into_datum()),
             (PgBuiltInOids::TEXTOID.oid(), params.into_datum()),
             (PgBuiltInOids::TEXTOID.oid(), column_type.into_datum()),
+            (PgBuiltInOids::TEXTOID.oid(), json_columns.into_datum()),
         ],
     )?;
 
@@ -273,7 +391,7 @@ $$;
 extension_sql!(
     r#"
 CREATE OR REPLACE FUNCTION get_completion_job_failures(job_id INT)
-RETURNS TABLE (row_id INT, value TEXT)
+RETURNS TABLE (row_id TEXT, value TEXT)
 STRICT IMMUTABLE PARALLEL SAFE
 LANGUAGE plpgsql
 AS $$
@@ -281,13 +399,48 @@ BEGIN
   RETURN QUERY
   SELECT info.row_id, info.value 
   FROM _lantern_extras_internal.embedding_failure_info info
-  WHERE info.job_id=get_completion_job_failures.job_id;
+  WHERE info.job_id=get_completion_job_failures.job_id AND NOT info.truncated;
 END
 $$;
 "#,
     name = "get_completion_job_failures",
 );
 
+extension_sql!(
+    r#"
+CREATE OR REPLACE FUNCTION get_embedding_job_failures(job_id INT)
+RETURNS TABLE (row_id TEXT, value TEXT)
+STRICT IMMUTABLE PARALLEL SAFE
+LANGUAGE plpgsql
+AS $$
+BEGIN
+  RETURN QUERY
+  SELECT * FROM get_completion_job_failures(job_id);
+END
+$$;
+"#,
+    name = "get_embedding_job_failures",
+    requires = ["get_completion_job_failures"]
+);
+
+extension_sql!(
+    r#"
+CREATE OR REPLACE FUNCTION get_embedding_job_truncations(job_id INT)
+RETURNS TABLE (row_id TEXT, value TEXT)
+STRICT IMMUTABLE PARALLEL SAFE
+LANGUAGE plpgsql
+AS $$
+BEGIN
+  RETURN QUERY
+  SELECT info.row_id, info.value
+  FROM _lantern_extras_internal.embedding_failure_info info
+  WHERE info.job_id=get_embedding_job_truncations.job_id AND info.truncated;
+END
+$$;
+"#,
+    name = "get_embedding_job_truncations",
+);
+
 extension_sql!(
     r#"
 CREATE OR REPLACE FUNCTION get_embedding_jobs()
@@ -563,9 +716,9 @@ pub mod tests {
             client.update(
                 "
                 INSERT INTO _lantern_extras_internal.embedding_failure_info (job_id, row_id, value) VALUES
-                (1, 1, '1test1'),
-                (1, 2, '1test2'),
-                (2, 1, '2test1');
+                (1, '1', '1test1'),
+                (1, '2', '1test2'),
+                (2, '1', '2test1');
                 CREATE ROLE test_role1;
                 SET ROLE test_role1;
                 ",
@@ -583,12 +736,12 @@ pub mod tests {
 
             let row = rows.next().unwrap();
 
-            assert_eq!(row.get::<i32>(1)?.unwrap(), 1);
+            assert_eq!(row.get::<&str>(1)?.unwrap(), "1");
             assert_eq!(row.get::<&str>(2)?.unwrap(), "1test1");
 
             let row = rows.next().unwrap();
 
-            assert_eq!(row.get::<i32>(1)?.unwrap(), 2);
+            assert_eq!(row.get::<&str>(1)?.unwrap(), "2");
             assert_eq!(row.get::<&str>(2)?.unwrap(), "1test2");
 
             let mut rows = client.select(
@@ -601,7 +754,7 @@ pub mod tests {
 
             let row = rows.next().unwrap();
 
-            assert_eq!(row.get::<i32>(1)?.unwrap(), 1);
+            assert_eq!(row.get::<&str>(1)?.unwrap(), "1");
             assert_eq!(row.get::<&str>(2)?.unwrap(), "2test1");
 
             Ok::<(), anyhow::Error>(())
@@ -609,6 +762,47 @@ pub mod tests {
         .unwrap();
     }
 
+    #[pg_test]
+    fn test_get_embedding_job_truncations() {
+        Spi::connect(|mut client| {
+            // wait for daemon
+            std::thread::sleep(Duration::from_secs(10));
+            client.update(
+                "
+                INSERT INTO _lantern_extras_internal.embedding_failure_info (job_id, row_id, value, truncated) VALUES
+                (1, '1', 'failed', false),
+                (1, '2', 'Input was truncated from 600 to 512 tokens', true);
+                ",
+                None,
+                None,
+            )?;
+
+            let mut rows = client.select(
+                "SELECT row_id, value FROM get_embedding_job_truncations($1)",
+                None,
+                Some(vec![(PgBuiltInOids::INT4OID.oid(), 1.into_datum())])
+            )?;
+
+            assert_eq!(rows.len(), 1);
+            let row = rows.next().unwrap();
+            assert_eq!(row.get::<&str>(1)?.unwrap(), "2");
+            assert_eq!(row.get::<&str>(2)?.unwrap(), "Input was truncated from 600 to 512 tokens");
+
+            let mut rows = client.select(
+                "SELECT row_id, value FROM get_embedding_job_failures($1)",
+                None,
+                Some(vec![(PgBuiltInOids::INT4OID.oid(), 1.into_datum())])
+            )?;
+
+            assert_eq!(rows.len(), 1);
+            let row = rows.next().unwrap();
+            assert_eq!(row.get::<&str>(1)?.unwrap(), "1");
+
+            Ok::<(), anyhow::Error>(())
+        })
+        .unwrap();
+    }
+
     #[pg_test]
     fn test_add_daemon_job_default_params() {
         Spi::connect(|mut client| {
@@ -648,6 +842,52 @@ pub mod tests {
         .unwrap();
     }
 
+    #[pg_test]
+    fn test_add_daemon_job_ort_session_options() {
+        Spi::connect(|mut client| {
+            client.update("CREATE TABLE t1 (id serial primary key, title text);", None, None)?;
+            let id = client.update("SELECT add_embedding_job(table_name => 't1', src_column => 'title', dst_column => 'title_embedding', model => 'BAAI/bge-small-en', runtime => 'ort', ort_params => '{\"execution_providers\": [\"cpu\"], \"intra_threads\": 2, \"model_variant\": \"int8\"}')", None, None)?;
+            let id: Option<i32> = id.first().get(1)?;
+
+            let rows = client.select("SELECT (runtime_params->'intra_threads')::int as intra_threads, runtime_params->>'model_variant' as model_variant FROM _lantern_extras_internal.embedding_generation_jobs WHERE id=$1", None, Some(vec![(PgBuiltInOids::INT4OID.oid(), id.into_datum())]))?;
+            let row = rows.first();
+            assert_eq!(row.get::<i32>(1)?, Some(2));
+            assert_eq!(row.get::<String>(2)?, Some("int8".to_owned()));
+            Ok::<(), anyhow::Error>(())
+        })
+        .unwrap();
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "Invalid ort_params")]
+    fn test_add_daemon_job_invalid_execution_provider() {
+        Spi::run("CREATE TABLE t1 (id serial primary key, title text);").unwrap();
+        Spi::run("SELECT add_embedding_job(table_name => 't1', src_column => 'title', dst_column => 'title_embedding', model => 'BAAI/bge-small-en', runtime => 'ort', ort_params => '{\"execution_providers\": [\"tpu\"]}')").unwrap();
+    }
+
+    #[pg_test]
+    fn test_add_daemon_job_azure_params() {
+        Spi::connect(|mut client| {
+            client.update("CREATE TABLE t1 (id serial primary key, title text);", None, None)?;
+            let id = client.update("SELECT add_embedding_job(table_name => 't1', src_column => 'title', dst_column => 'title_embedding', model => 'my-embeddings', runtime => 'openai', base_url => 'https://my-resource.openai.azure.com', azure_params => '{\"api_version\": \"2024-10-21\", \"deployments\": {\"my-embeddings\": \"text-embedding-3-small\"}, \"tenant_id\": \"tenant\", \"client_id\": \"client\", \"client_secret\": \"secret\"}')", None, None)?;
+            let id: Option<i32> = id.first().get(1)?;
+
+            let rows = client.select("SELECT runtime_params->'azure'->>'api_version' as api_version, runtime_params->'azure'->'deployments'->>'my-embeddings' as model FROM _lantern_extras_internal.embedding_generation_jobs WHERE id=$1", None, Some(vec![(PgBuiltInOids::INT4OID.oid(), id.into_datum())]))?;
+            let row = rows.first();
+            assert_eq!(row.get::<String>(1)?, Some("2024-10-21".to_owned()));
+            assert_eq!(row.get::<String>(2)?, Some("text-embedding-3-small".to_owned()));
+            Ok::<(), anyhow::Error>(())
+        })
+        .unwrap();
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "Invalid azure_params")]
+    fn test_add_daemon_job_invalid_azure_params() {
+        Spi::run("CREATE TABLE t1 (id serial primary key, title text);").unwrap();
+        Spi::run("SELECT add_embedding_job(table_name => 't1', src_column => 'title', dst_column => 'title_embedding', runtime => 'openai', api_token => 'xxx', azure_params => '{\"deployments\": []}')").unwrap();
+    }
+
     #[pg_test]
     fn test_get_daemon_job() {
         Spi::connect(|mut client| {
@@ -1,13 +1,22 @@
 use lantern_cli::embeddings::{
     cli::EmbeddingJobType,
+    client::EmbeddingServerClient,
     core::{
-        cohere_runtime::CohereRuntimeParams, openai_runtime::OpenAiRuntimeParams,
-        utils::get_clean_model_name, EmbeddingRuntime, LoggerFn, Runtime,
+        azure_openai::AzureParams,
+        cohere_runtime::CohereRuntimeParams,
+        count_tokens as count_model_tokens,
+        openai_runtime::{is_local_base_url, OpenAiRuntimeParams},
+        runtime::InputType,
+        utils::get_clean_model_name,
+        EmbeddingRuntime, LoggerFn, Runtime,
     },
 };
 use pgrx::prelude::*;
+use std::str::FromStr;
 
-use crate::{LLM_DEPLOYMENT_URL, LLM_TOKEN, OPENAI_AZURE_ENTRA_TOKEN, OPENAI_TOKEN};
+use crate::{
+    EMBEDDING_SERVER_URL, LLM_DEPLOYMENT_URL, LLM_TOKEN, OPENAI_AZURE_ENTRA_TOKEN, OPENAI_TOKEN,
+};
 
 pub static ORT_RUNTIME_PARAMS: &'static str = r#"{ "cache": true }"#;
 
@@ -15,6 +24,37 @@ fn notice_fn(text: &str) {
     notice!("{}", text);
 }
 
+fn get_response_schema(response_schema: &str) -> Result<Option<serde_json::Value>, anyhow::Error> {
+    if response_schema.trim() == "" {
+        return Ok(None);
+    }
+
+    match serde_json::from_str(response_schema) {
+        Ok(schema) => Ok(Some(schema)),
+        Err(e) => anyhow::bail!("Invalid response_schema: {e}"),
+    }
+}
+
+// Input type is used to apply model specific query/document instructions
+pub fn get_ort_runtime_params(input_type: &str) -> Result<String, anyhow::Error> {
+    let mut params: serde_json::Value = serde_json::from_str(ORT_RUNTIME_PARAMS)?;
+    if input_type != "" {
+        params["input_type"] = serde_json::json!(InputType::from_str(input_type)?.to_string());
+    }
+    Ok(params.to_string())
+}
+
+// Returns the client for the embedding server if lantern_extras.embedding_server_url is set
+// in this case the models are not loaded inside the backend process
+fn get_embedding_server_client() -> Result<Option<EmbeddingServerClient>, anyhow::Error> {
+    match EMBEDDING_SERVER_URL.get() {
+        Some(url) if url.to_str()?.trim() != "" => {
+            Ok(Some(EmbeddingServerClient::new(url.to_str()?.trim())?))
+        }
+        _ => Ok(None),
+    }
+}
+
 fn get_dummy_runtime_params(runtime: &Runtime) -> String {
     match runtime {
         Runtime::Ort => ORT_RUNTIME_PARAMS.to_owned(),
@@ -27,9 +67,38 @@ pub fn get_openai_runtime_params(
     azure_entra_token: &str,
     base_url: &str,
     system_prompt: &str,
+    response_schema: &str,
     dimensions: i32,
+    azure_params: &str,
 ) -> Result<String, anyhow::Error> {
-    if api_token == ""
+    let base_url = if base_url == "" {
+        if let Some(deployment_url) = LLM_DEPLOYMENT_URL.get() {
+            Some(deployment_url.to_str().unwrap().to_owned())
+        } else {
+            None
+        }
+    } else {
+        Some(base_url.to_owned())
+    };
+
+    let azure = if azure_params.trim() == "" {
+        None
+    } else {
+        Some(
+            serde_json::from_str::<AzureParams>(azure_params)
+                .map_err(|e| anyhow::anyhow!("Invalid azure_params: {e}"))?,
+        )
+    };
+
+    // Local OpenAI compatible servers may not require authorization
+    // and Azure deployments can use Entra client credentials instead of the token
+    let is_keyless = base_url.as_deref().is_some_and(is_local_base_url)
+        || azure
+            .as_ref()
+            .is_some_and(|azure| azure.client_secret.is_some());
+    if !is_keyless
+        && api_token == ""
+        && azure_entra_token == ""
         && OPENAI_TOKEN.get().is_none()
         && LLM_TOKEN.get().is_none()
         && OPENAI_AZURE_ENTRA_TOKEN.get().is_none()
@@ -43,16 +112,6 @@ pub fn get_openai_runtime_params(
         None
     };
 
-    let base_url = if base_url == "" {
-        if let Some(deployment_url) = LLM_DEPLOYMENT_URL.get() {
-            Some(deployment_url.to_str().unwrap().to_owned())
-        } else {
-            None
-        }
-    } else {
-        Some(base_url.to_owned())
-    };
-
     let mut api_token = if api_token != "" {
         Some(api_token.to_owned())
     } else {
@@ -91,6 +150,12 @@ pub fn get_openai_runtime_params(
         api_token,
         azure_entra_token,
         system_prompt: Some(system_prompt.to_owned()),
+        response_schema: get_response_schema(response_schema)?,
+        native_response_format: None,
+        batch_api: None,
+        batch_poll_interval: None,
+        batch_max_wait: None,
+        azure,
     })?;
 
     Ok(params)
@@ -99,6 +164,8 @@ pub fn get_openai_runtime_params(
 pub fn get_cohere_runtime_params(
     api_token: &str,
     input_type: &str,
+    system_prompt: &str,
+    response_schema: &str,
 ) -> Result<String, anyhow::Error> {
     if api_token == "" && LLM_TOKEN.get().is_none() {
         error!("'lantern_extras.llm_token' is required for 'cohere' runtime");
@@ -120,7 +187,9 @@ pub fn get_cohere_runtime_params(
 
     let runtime_params = serde_json::to_string(&CohereRuntimeParams {
         api_token,
-        input_type: Some(input_type.to_owned()),
+        input_type: Some(input_type.to_owned()).filter(|input_type| input_type != ""),
+        system_prompt: Some(system_prompt.to_owned()),
+        response_schema: get_response_schema(response_schema)?,
     })?;
 
     Ok(runtime_params)
@@ -135,18 +204,46 @@ fn llm_completion<'a>(
     api_token: default!(&'a str, "''"),
     azure_entra_token: default!(&'a str, "''"),
     runtime: default!(&'a str, "'openai'"),
+    response_schema: default!(&'a str, "''"),
+    azure_params: default!(&'a str, "''"),
 ) -> Result<String, anyhow::Error> {
-    let runtime_params =
-        get_openai_runtime_params(api_token, azure_entra_token, base_url, system_prompt, 0)?;
-
     let runtime = Runtime::try_from(runtime)?;
-    let embedding_runtime =
-        EmbeddingRuntime::new(&runtime, Some(&(notice_fn as LoggerFn)), &runtime_params)?;
+    let runtime_params = match runtime {
+        Runtime::OpenAi => get_openai_runtime_params(
+            api_token,
+            azure_entra_token,
+            base_url,
+            system_prompt,
+            response_schema,
+            0,
+            azure_params,
+        )?,
+        Runtime::Cohere => {
+            get_cohere_runtime_params(api_token, "", system_prompt, response_schema)?
+        }
+        Runtime::Ort => anyhow::bail!("Runtime ort does not support completion"),
+    };
 
     let rt = tokio::runtime::Builder::new_current_thread()
         .enable_all()
         .build()?;
 
+    if let Some(client) = get_embedding_server_client()? {
+        let mut res = rt.block_on(client.completions(
+            &runtime,
+            model,
+            &runtime_params,
+            vec![user_prompt.to_owned()],
+        ))?;
+        if let Some((_, error)) = res.failed_inputs.pop() {
+            anyhow::bail!("{error}");
+        }
+        return Ok(res.messages.pop().unwrap_or_default());
+    }
+
+    let embedding_runtime =
+        EmbeddingRuntime::new(&runtime, Some(&(notice_fn as LoggerFn)), &runtime_params)?;
+
     let res = rt.block_on(embedding_runtime.completion(model, user_prompt))?;
     Ok(res.message)
 }
@@ -159,33 +256,78 @@ fn llm_embedding<'a>(
     api_token: default!(&'a str, "''"),
     azure_entra_token: default!(&'a str, "''"),
     dimensions: default!(i32, 1536),
-    input_type: default!(&'a str, "'search_query'"),
+    input_type: default!(&'a str, "''"),
     runtime: default!(&'a str, "'openai'"),
+    azure_params: default!(&'a str, "''"),
 ) -> Result<Vec<f32>, anyhow::Error> {
     let runtime = Runtime::try_from(runtime)?;
+    // Input type is only used by ort (model instruction templates) and cohere runtimes
     let runtime_params = match runtime {
-        Runtime::Ort => ORT_RUNTIME_PARAMS.to_owned(),
-        Runtime::OpenAi => {
-            get_openai_runtime_params(api_token, azure_entra_token, base_url, "", dimensions)?
-        }
-        Runtime::Cohere => get_cohere_runtime_params(api_token, input_type)?,
+        Runtime::Ort => get_ort_runtime_params(input_type)?,
+        Runtime::OpenAi => get_openai_runtime_params(
+            api_token,
+            azure_entra_token,
+            base_url,
+            "",
+            "",
+            dimensions,
+            azure_params,
+        )?,
+        Runtime::Cohere => get_cohere_runtime_params(api_token, input_type, "", "")?,
     };
 
-    let embedding_runtime =
-        EmbeddingRuntime::new(&runtime, Some(&(notice_fn as LoggerFn)), &runtime_params)?;
-
     let rt = tokio::runtime::Builder::new_current_thread()
         .enable_all()
         .build()?;
 
-    let mut res = rt
-        .block_on(embedding_runtime.process(&get_clean_model_name(model, runtime), &vec![input]))?;
+    let model = get_clean_model_name(model, runtime);
+
+    if let Some(client) = get_embedding_server_client()? {
+        let mut res = rt.block_on(client.embeddings(
+            &runtime,
+            &model,
+            &runtime_params,
+            vec![input.to_owned()],
+        ))?;
+        if let Some((_, error)) = res.failed_inputs.pop() {
+            error!("{error}");
+        }
+        return Ok(res.embeddings.pop().unwrap_or_default());
+    }
+
+    let embedding_runtime =
+        EmbeddingRuntime::new(&runtime, Some(&(notice_fn as LoggerFn)), &runtime_params)?;
+
+    let mut res = rt.block_on(embedding_runtime.process(&model, &vec![input]))?;
+    if let Some((_, error)) = res.failed_inputs.pop() {
+        error!("{error}");
+    }
     Ok(res.embeddings.pop().unwrap())
 }
 
 #[pg_extern(immutable, parallel_safe)]
-fn text_embedding<'a>(model_name: &'a str, text: &'a str) -> Result<Vec<f32>, anyhow::Error> {
-    return llm_embedding(text, model_name, "", "", "", 0, "", "ort");
+fn text_embedding<'a>(
+    model_name: &'a str,
+    text: &'a str,
+    input_type: default!(&'a str, "''"),
+) -> Result<Vec<f32>, anyhow::Error> {
+    return llm_embedding(text, model_name, "", "", "", 0, input_type, "ort", "");
+}
+
+// Returns the token count of the text for the model's tokenizer
+// including special tokens, so it can be compared with the model's max sequence length
+#[pg_extern(immutable, parallel_safe, create_or_replace)]
+fn count_tokens<'a>(model: &'a str, text: &'a str) -> Result<i32, anyhow::Error> {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+
+    let count = rt.block_on(count_model_tokens(
+        &get_clean_model_name(model, Runtime::OpenAi),
+        text,
+        ORT_RUNTIME_PARAMS,
+    ))?;
+    Ok(count as i32)
 }
 
 #[pg_extern(immutable, parallel_safe, create_or_replace)]
@@ -195,14 +337,30 @@ fn openai_embedding<'a>(
     base_url: default!(&'a str, "''"),
     dimensions: default!(i32, 1536),
 ) -> Result<Vec<f32>, anyhow::Error> {
-    return llm_embedding(text, model_name, base_url, "", "", dimensions, "", "openai");
+    return llm_embedding(
+        text, model_name, base_url, "", "", dimensions, "", "openai", "",
+    );
 }
 
 #[pg_extern(immutable, parallel_safe, create_or_replace)]
 fn get_available_models<'a>(
     runtime: default!(&'a str, "'ort'"),
     job_type: default!(&'a str, "'embedding_generation'"),
-) -> Result<String, anyhow::Error> {
+) -> Result<
+    TableIterator<
+        'static,
+        (
+            name!(runtime, String),
+            name!(model, String),
+            name!(dimensions, Option<i32>),
+            name!(max_sequence_length, Option<i32>),
+            name!(visual, bool),
+            name!(downloaded, Option<bool>),
+            name!(default_batch_size, i32),
+        ),
+    >,
+    anyhow::Error,
+> {
     let runtime_name = Runtime::try_from(runtime)?;
     let runtime_params = get_dummy_runtime_params(&runtime_name);
     let job_type = EmbeddingJobType::try_from(job_type)?;
@@ -214,7 +372,20 @@ fn get_available_models<'a>(
     let rt = tokio::runtime::Builder::new_current_thread()
         .enable_all()
         .build()?;
-    return Ok(rt.block_on(runtime.get_available_models(job_type)).0);
+    let models = rt.block_on(runtime.get_available_models(job_type)).1;
+    let runtime_name = runtime_name.to_string();
+
+    return Ok(TableIterator::new(models.into_iter().map(move |model| {
+        (
+            runtime_name.clone(),
+            model.name,
+            model.dimensions.map(|d| d as i32),
+            model.max_sequence_length.map(|l| l as i32),
+            model.visual,
+            model.downloaded,
+            model.default_batch_size as i32,
+        )
+    })));
 }
 
 #[pg_extern(immutable, parallel_safe, create_or_replace)]
@@ -272,6 +443,51 @@ pub mod tests {
         assert!(distance2 < 0.01);
     }
 
+    #[pg_test]
+    fn test_count_tokens() {
+        let ort_count = Spi::get_one::<i32>(&format!(
+            "SELECT count_tokens('BAAI/bge-small-en', '{HELLO_WORLD_TEXT}');"
+        ))
+        .unwrap();
+        let openai_count = Spi::get_one::<i32>(&format!(
+            "SELECT count_tokens('openai/text-embedding-3-small', '{HELLO_WORLD_TEXT}');"
+        ))
+        .unwrap();
+        let long_text_count = Spi::get_one::<i32>(
+            "SELECT count_tokens('BAAI/bge-small-en', repeat('Hello world! ', 300));",
+        )
+        .unwrap();
+
+        // [CLS] hello world ! [SEP]
+        assert_eq!(ort_count, Some(5));
+        assert_eq!(openai_count, Some(3));
+        assert!(long_text_count.unwrap() > 512);
+    }
+
+    #[pg_test]
+    fn test_get_available_models() {
+        let (dimensions, max_sequence_length, default_batch_size) = Spi::get_three::<i32, i32, i32>(
+            "SELECT dimensions, max_sequence_length, default_batch_size FROM get_available_models() WHERE model = 'BAAI/bge-small-en' AND NOT visual;",
+        )
+        .unwrap();
+        assert_eq!(dimensions, Some(384));
+        assert_eq!(max_sequence_length, Some(512));
+        assert_eq!(default_batch_size, Some(300));
+
+        let (max_sequence_length, visual) = Spi::get_two::<i32, bool>(
+            "SELECT max_sequence_length, visual FROM get_available_models() WHERE model = 'clip/ViT-B-32-visual';",
+        )
+        .unwrap();
+        assert_eq!(max_sequence_length, None);
+        assert_eq!(visual, Some(true));
+
+        let openai_model_cnt = Spi::get_one::<i64>(
+            "SELECT COUNT(*) FROM get_available_models('openai') WHERE runtime = 'openai' AND model = 'text-embedding-3-small' AND dimensions = 1536;",
+        )
+        .unwrap();
+        assert_eq!(openai_model_cnt, Some(1));
+    }
+
     #[pg_test]
     fn test_cohere_embeddings() {
         static HELLO_WORLD_TEXT: &'static str = "Hello world!";
@@ -301,7 +517,7 @@ pub mod tests {
                 .select(
                     &format!(
                         "
-                         SELECT llm_embedding(model => 'cohere/embed-multilingual-light-v3.0', input => '{HELLO_WORLD_TEXT}', runtime => 'cohere') as embedding
+                         SELECT llm_embedding(model => 'cohere/embed-multilingual-light-v3.0', input => '{HELLO_WORLD_TEXT}', input_type => 'search_query', runtime => 'cohere') as embedding
                      "
                     ),
                     None,
@@ -390,6 +606,33 @@ pub mod tests {
         })
         .unwrap();
     }
+    #[pg_test]
+    #[should_panic(expected = "is required for 'openai' runtime")]
+    fn test_openai_completion_remote_url_without_token() {
+        Spi::run(
+            "SELECT llm_completion(user_prompt => 'hello', base_url => 'https://api.openai.com')",
+        )
+        .unwrap();
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "Invalid azure_params")]
+    fn test_openai_completion_invalid_azure_params() {
+        Spi::run(
+            "SELECT llm_completion(user_prompt => 'hello', api_token => 'xxx', azure_params => '{\"deployments\": []}')",
+        )
+        .unwrap();
+    }
+
+    #[pg_test]
+    #[should_panic(expected = "Invalid azure_params")]
+    fn test_openai_embedding_invalid_azure_params() {
+        Spi::run(
+            "SELECT llm_embedding(input => 'hello', api_token => 'xxx', azure_params => '{\"deployments\": []}')",
+        )
+        .unwrap();
+    }
+
     #[pg_test(volatile, create_or_replace)]
     fn test_openai_completion() {
         let openai_token = env::var("OPENAI_TOKEN");
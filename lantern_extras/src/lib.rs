@@ -35,8 +35,13 @@ pub static LLM_DEPLOYMENT_URL: GucSetting<Option<&'static CStr>> =
     GucSetting::<Option<&'static CStr>>::new(None);
 pub static OPENAI_AZURE_ENTRA_TOKEN: GucSetting<Option<&'static CStr>> =
     GucSetting::<Option<&'static CStr>>::new(None);
+pub static EMBEDDING_SERVER_URL: GucSetting<Option<&'static CStr>> =
+    GucSetting::<Option<&'static CStr>>::new(None);
 pub static ENABLE_DAEMON: GucSetting<bool> = GucSetting::<bool>::new(false);
 pub static ENABLE_INDEXING_SERVER: GucSetting<bool> = GucSetting::<bool>::new(true);
+pub static ENABLE_EMBEDDING_CACHE: GucSetting<bool> = GucSetting::<bool>::new(false);
+pub static EMBEDDING_CACHE_MAX_AGE: GucSetting<i32> = GucSetting::<i32>::new(0);
+pub static EMBEDDING_CACHE_MAX_ENTRIES: GucSetting<i32> = GucSetting::<i32>::new(0);
 
 pub static DAEMON_DATABASES: GucSetting<Option<&'static CStr>> =
     GucSetting::<Option<&'static CStr>>::new(None);
@@ -94,6 +99,14 @@ pub unsafe extern "C" fn _PG_init() {
         GucContext::Userset,
         GucFlags::NO_SHOW_ALL,
     );
+    GucRegistry::define_string_guc(
+        "lantern_extras.embedding_server_url",
+        "Embedding server URL.",
+        "Address of lantern-cli embedding server (http://host:port or unix:/path/to/socket) used for embedding and completion functions",
+        &EMBEDDING_SERVER_URL,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
     GucRegistry::define_string_guc(
         "lantern_extras.daemon_databases",
         "Databases to watch",
@@ -118,6 +131,34 @@ pub unsafe extern "C" fn _PG_init() {
         GucContext::Sighup,
         GucFlags::NO_SHOW_ALL,
     );
+    GucRegistry::define_bool_guc(
+        "lantern_extras.enable_embedding_cache",
+        "Enable embedding cache for daemon jobs",
+        "Reuse embeddings for the same input, model and dimensions across embedding jobs",
+        &ENABLE_EMBEDDING_CACHE,
+        GucContext::Sighup,
+        GucFlags::NO_SHOW_ALL,
+    );
+    GucRegistry::define_int_guc(
+        "lantern_extras.embedding_cache_max_age",
+        "Embedding cache entry max age in seconds",
+        "Cache entries which were not used for this many seconds will be removed (0 means no limit)",
+        &EMBEDDING_CACHE_MAX_AGE,
+        0,
+        i32::MAX,
+        GucContext::Sighup,
+        GucFlags::NO_SHOW_ALL,
+    );
+    GucRegistry::define_int_guc(
+        "lantern_extras.embedding_cache_max_entries",
+        "Embedding cache max entries",
+        "Least recently used cache entries above this count will be removed (0 means no limit)",
+        &EMBEDDING_CACHE_MAX_ENTRIES,
+        0,
+        i32::MAX,
+        GucContext::Sighup,
+        GucFlags::NO_SHOW_ALL,
+    );
     GucRegistry::define_float_guc(
         "lantern_extras.bm25_default_k1",
         "BM25 default k1",